@@ -0,0 +1,56 @@
+//! get_balances_batch against the one-call-per-script loop it replaces.
+//!
+//! Both run against FakeElectrs with a simulated network round trip; the
+//! loop is the same client talking to a server that rejects batches, which
+//! is exactly the sequential fallback. Run with `cargo bench --bench balance_batch`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::time::{Duration, Instant};
+
+use balancebridge_server::electrs::{address_script, ElectrsClient};
+use balancebridge_server::xpub::{derive_chain_range, AddressType};
+use bitcoin::{Network, ScriptBuf};
+use common::FakeElectrs;
+
+// Account key of the "abandon … about" test mnemonic (m/84h/0h/0h)
+const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+const ROUND_TRIP: Duration = Duration::from_millis(2);
+const RUNS: u32 = 5;
+
+/// Receive-chain scripts of the test xpub; all unfunded, so never cached
+fn scripts(count: u32) -> Vec<ScriptBuf> {
+    derive_chain_range(BIP84_XPUB, Network::Bitcoin, AddressType::NativeSegwit, 0, 0, count)
+        .unwrap()
+        .into_iter()
+        .map(|derived| address_script(&derived.address).unwrap())
+        .collect()
+}
+
+/// Mean time of one `get_balances_batch` over `scripts`
+async fn mean(server: &FakeElectrs, scripts: &[ScriptBuf]) -> Duration {
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        client.get_balances_batch(scripts).await.unwrap();
+    }
+    started.elapsed() / RUNS
+}
+
+#[tokio::main]
+async fn main() {
+    let batched = FakeElectrs::start(false).with_round_trip(ROUND_TRIP);
+    let sequential = FakeElectrs::start(false)
+        .with_round_trip(ROUND_TRIP)
+        .rejecting_batches();
+
+    println!("{:>8} {:>12} {:>12}", "scripts", "batch", "loop");
+    for count in [20, 100, 500] {
+        let scripts = scripts(count);
+        let batch = mean(&batched, &scripts).await;
+        let looped = mean(&sequential, &scripts).await;
+        println!("{:>8} {:>10}ms {:>10}ms", count, batch.as_millis(), looped.as_millis());
+    }
+}
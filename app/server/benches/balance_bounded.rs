@@ -0,0 +1,55 @@
+//! A 40-address get_balances_bounded scan, one lookup at a time against four.
+//!
+//! Runs against FakeElectrs at a few simulated round trips. The client spaces
+//! its calls at least 100ms apart, so concurrency only pays off once a lookup
+//! takes longer than that. Run with `cargo bench --bench balance_bounded`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::time::{Duration, Instant};
+
+use balancebridge_server::electrs::ElectrsClient;
+use balancebridge_server::xpub::{derive_chain_range, AddressType};
+use bitcoin::Network;
+use common::FakeElectrs;
+
+// Account key of the "abandon … about" test mnemonic (m/84h/0h/0h)
+const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+const ADDRESSES: u32 = 40;
+
+/// Unfunded receive addresses from `start`; each scan takes fresh ones so
+/// nothing is answered from the cache
+fn addresses(start: u32) -> Vec<String> {
+    derive_chain_range(BIP84_XPUB, Network::Bitcoin, AddressType::NativeSegwit, 0, start, ADDRESSES)
+        .unwrap()
+        .into_iter()
+        .map(|derived| derived.address)
+        .collect()
+}
+
+async fn scan(client: &ElectrsClient, start: u32, concurrency: usize) -> Duration {
+    let started = Instant::now();
+    client.get_balances_bounded(addresses(start), concurrency).await.unwrap();
+    started.elapsed()
+}
+
+#[tokio::main]
+async fn main() {
+    println!("{:>10} {:>12} {:>12}", "round trip", "1 at a time", "4 at a time");
+    for (run, millis) in [10, 100, 400].into_iter().enumerate() {
+        let server = FakeElectrs::start(false).with_round_trip(Duration::from_millis(millis));
+        let client = ElectrsClient::new(server.addr.clone()).unwrap().with_max_inflight(4);
+        let start = run as u32 * 2 * ADDRESSES;
+
+        let sequential = scan(&client, start, 1).await;
+        let concurrent = scan(&client, start + ADDRESSES, 4).await;
+        println!(
+            "{:>8}ms {:>10}ms {:>10}ms",
+            millis,
+            sequential.as_millis(),
+            concurrent.as_millis()
+        );
+    }
+}
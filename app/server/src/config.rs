@@ -2,8 +2,94 @@
 //! 
 //! Handles Umbrel-specific configuration and environment variables.
 
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+use bitcoin::Network;
+
+use crate::electrs::{self, ElectrsEndpoint};
+use crate::qr::QrEncoding;
+use crate::relays;
+use crate::xpub;
+
+/// Default Electrs address: the Umbrel electrs service
+const DEFAULT_ELECTRS_ADDR: &str = "electrs:50001";
+
+/// Old name of ELECTRS_ADDR, still read when ELECTRS_ADDR is unset
+const DEPRECATED_ELECTRS_ADDR_VAR: &str = "ELECTRS_URL";
+
+/// Default HTTP port the Umbrel app proxy forwards to
+const DEFAULT_LISTEN_PORT: u16 = 3829;
+
+/// Default Tokio runtime sizing (see get_worker_threads)
+const DEFAULT_WORKER_THREADS: usize = 2;
+const DEFAULT_MAX_BLOCKING_THREADS: usize = 4;
+
+const DEFAULT_WARM_UP_TIMEOUT_SECS: u64 = 60;
+
+const DEFAULT_BULK_CONCURRENCY: usize = 3;
+
+const DEFAULT_LIST_UTXOS_MAX: usize = 500;
+
+const DEFAULT_XPUB_WATCH_INTERVAL_SECS: u64 = 300;
+
+const DEFAULT_COMPRESS_THRESHOLD_BYTES: usize = 16384;
+
+const DEFAULT_REQUEST_RATE_LIMIT_PER_MINUTE: u32 = 10;
+
+/// Load a `.env` file for local development
+///
+/// Only active when RUST_ENV=development or BALANCEBRIDGE_DOTENV=true.
+/// Looks in `./.env`, then `$HOME/.config/balancebridge/.env`. Variables that
+/// are already set are never overridden. Returns how many were loaded.
+pub fn load_dotenv() -> u32 {
+    let enabled = env::var("RUST_ENV").map(|v| v == "development").unwrap_or(false)
+        || env::var("BALANCEBRIDGE_DOTENV").map(|v| v == "true").unwrap_or(false);
+    if !enabled {
+        return 0;
+    }
+
+    let mut candidates = vec![PathBuf::from(".env")];
+    if let Ok(home) = env::var("HOME") {
+        candidates.push(PathBuf::from(home).join(".config/balancebridge/.env"));
+    }
+
+    let content = match candidates.iter().find_map(|p| fs::read_to_string(p).ok()) {
+        Some(c) => c,
+        None => return 0,
+    };
+
+    let mut loaded = 0;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().trim_start_matches("export ").trim();
+        let value = value.trim().trim_matches('"').trim_matches('\'');
+
+        // Real environment always wins
+        if key.is_empty() || env::var_os(key).is_some() {
+            continue;
+        }
+
+        env::set_var(key, value);
+        loaded += 1;
+    }
+
+    loaded
+}
 
 /// Get the Umbrel app data directory
 /// 
@@ -18,6 +104,23 @@ pub fn get_data_dir() -> PathBuf {
         })
 }
 
+/// Existing Nostr secret key to adopt on first run (NOSTR_SECRET_NSEC, bech32 nsec1…)
+pub fn get_nostr_secret_nsec() -> Option<String> {
+    env::var("NOSTR_SECRET_NSEC")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// What the pairing QR encodes (PAIRING_QR_FORMAT: "json", the default, or
+/// "uri" for a balancebridge:// deep link a generic camera app can open)
+pub fn get_pairing_qr_format() -> QrEncoding {
+    env::var("PAIRING_QR_FORMAT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default()
+}
+
 /// Get the Umbrel app ID
 /// 
 /// Umbrel sets UMBREL_APP_ID to identify the app instance.
@@ -25,3 +128,352 @@ pub fn get_app_id() -> Option<String> {
     env::var("UMBREL_APP_ID").ok()
 }
 
+
+/// Get the Electrs address
+///
+/// Reads ELECTRS_ADDR (or the deprecated ELECTRS_URL), falls back to the
+/// Umbrel electrs service.
+pub fn get_electrs_addr() -> String {
+    env_electrs_addr().unwrap_or_else(|| DEFAULT_ELECTRS_ADDR.to_string())
+}
+
+/// ELECTRS_ADDR, or ELECTRS_URL (its old name) when only that is set
+fn env_electrs_addr() -> Option<String> {
+    env::var("ELECTRS_ADDR")
+        .ok()
+        .or_else(|| env::var(DEPRECATED_ELECTRS_ADDR_VAR).ok())
+}
+
+/// Whether the Electrs address comes from the deprecated ELECTRS_URL,
+/// so startup can warn about it once logging is up
+pub fn uses_deprecated_electrs_var() -> bool {
+    env::var_os("ELECTRS_ADDR").is_none() && env::var_os(DEPRECATED_ELECTRS_ADDR_VAR).is_some()
+}
+
+/// Skip certificate validation for ssl:// Electrs (ELECTRS_TLS_INSECURE=true).
+/// For self-signed electrs/Fulcrum certificates on the local network.
+pub fn get_electrs_tls_insecure() -> bool {
+    env::var("ELECTRS_TLS_INSECURE")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
+}
+
+/// Chain the node runs on (BITCOIN_NETWORK: mainnet, testnet, signet or
+/// regtest; default mainnet). Umbrel sets it from the Bitcoin app's network.
+///
+/// An unknown value is an error rather than mainnet: answering testnet
+/// queries against a mainnet reading would be silently wrong.
+pub fn get_bitcoin_network() -> Result<Network> {
+    match env::var("BITCOIN_NETWORK") {
+        Ok(v) if !v.trim().is_empty() => xpub::network_from_name(&v).ok_or_else(|| {
+            anyhow!(
+                "Invalid BITCOIN_NETWORK '{}': expected mainnet, testnet, signet or regtest",
+                v
+            )
+        }),
+        _ => Ok(Network::Bitcoin),
+    }
+}
+
+/// Drop a relay from the pool after this long unreachable
+/// (RELAY_DROP_AFTER_MINS, default 5; it is re-added once it answers again)
+pub fn get_relay_drop_after() -> Duration {
+    env::var("RELAY_DROP_AFTER_MINS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|&mins| mins > 0)
+        .map(|mins| Duration::from_secs(mins * 60))
+        .unwrap_or(relays::DEFAULT_RELAY_DROP_AFTER)
+}
+
+/// Requests one pubkey may send per minute before getting `rate_limited`
+/// (REQUEST_RATE_LIMIT_PER_MINUTE, default 10). server_ping, xpub_addresses
+/// and address_validate don't count.
+pub fn get_request_rate_limit() -> u32 {
+    env::var("REQUEST_RATE_LIMIT_PER_MINUTE")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_REQUEST_RATE_LIMIT_PER_MINUTE)
+}
+
+/// How long Electrs balances are served from cache (ELECTRS_CACHE_TTL_SECS,
+/// default 30; 0 disables the cache)
+pub fn get_electrs_cache_ttl() -> Duration {
+    env::var("ELECTRS_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(electrs::cache::DEFAULT_CACHE_TTL)
+}
+
+/// Get the HTTP listen address
+///
+/// Reads LISTEN_PORT, falls back to 3829 on all interfaces.
+pub fn get_listen_addr() -> SocketAddr {
+    let port = env::var("LISTEN_PORT")
+        .ok()
+        .and_then(|v| v.parse::<u16>().ok())
+        .unwrap_or(DEFAULT_LISTEN_PORT);
+
+    SocketAddr::from(([0, 0, 0, 0], port))
+}
+
+/// Tokio worker threads (TOKIO_WORKER_THREADS, default 2)
+///
+/// Umbrel boxes are often a 4-core Pi shared with Bitcoin Core and Electrs,
+/// so we don't take one thread per core.
+pub fn get_worker_threads() -> usize {
+    env::var("TOKIO_WORKER_THREADS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_WORKER_THREADS)
+}
+
+/// Tokio blocking pool size (TOKIO_MAX_BLOCKING_THREADS, default 4).
+/// Electrs calls run there.
+pub fn get_max_blocking_threads() -> usize {
+    env::var("TOKIO_MAX_BLOCKING_THREADS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_MAX_BLOCKING_THREADS)
+}
+
+/// Budget for the address warm-up at startup (WARM_UP_TIMEOUT_SECS, default 60)
+pub fn get_warm_up_timeout() -> Duration {
+    let secs = env::var("WARM_UP_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_WARM_UP_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Optional xpub whose addresses are fetched once at startup (WARM_UP_XPUB)
+pub fn get_warm_up_xpub() -> Option<String> {
+    env::var("WARM_UP_XPUB")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Electrs calls in flight at once across the server (ELECTRS_MAX_INFLIGHT, default 4).
+/// 1 restores strictly one-at-a-time lookups for a struggling Electrs.
+pub fn get_electrs_max_inflight() -> usize {
+    env::var("ELECTRS_MAX_INFLIGHT")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(electrs::DEFAULT_MAX_INFLIGHT)
+}
+
+/// Electrs balance/history timeouts and cooldown length
+/// (ELECTRS_BALANCE_TIMEOUT_SECS, default 90; ELECTRS_HISTORY_TIMEOUT_SECS,
+/// default 45; ELECTRS_COOLDOWN_SECS, default 10). Zero or unparsable values
+/// keep the default, with a warning.
+pub fn get_electrs_timeouts() -> electrs::ElectrsTimeouts {
+    let secs = |name: &str, default: Duration| {
+        let Ok(value) = env::var(name) else {
+            return default;
+        };
+        match value.trim().parse::<u64>() {
+            Ok(secs) if secs > 0 => Duration::from_secs(secs),
+            _ => {
+                warn!(
+                    "Ignoring {}={:?}: expected a positive number of seconds, using {}s",
+                    name,
+                    value,
+                    default.as_secs()
+                );
+                default
+            }
+        }
+    };
+
+    electrs::ElectrsTimeouts {
+        balance: secs("ELECTRS_BALANCE_TIMEOUT_SECS", electrs::DEFAULT_BALANCE_TIMEOUT),
+        history: secs("ELECTRS_HISTORY_TIMEOUT_SECS", electrs::DEFAULT_HISTORY_TIMEOUT),
+        cooldown: secs("ELECTRS_COOLDOWN_SECS", electrs::DEFAULT_COOLDOWN),
+    }
+}
+
+/// Concurrent Electrs calls for one bulk_balance request (ELECTRS_BULK_CONCURRENCY, default 3)
+pub fn get_bulk_concurrency() -> usize {
+    env::var("ELECTRS_BULK_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_BULK_CONCURRENCY)
+}
+
+/// Most UTXOs one list_utxos response may carry (LIST_UTXOS_MAX, default 500)
+pub fn get_list_utxos_max() -> usize {
+    env::var("LIST_UTXOS_MAX")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_LIST_UTXOS_MAX)
+}
+
+/// Time between re-scans of watched xpubs (XPUB_WATCH_INTERVAL_SECS, default 300)
+pub fn get_xpub_watch_interval() -> Duration {
+    let secs = env::var("XPUB_WATCH_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_XPUB_WATCH_INTERVAL_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Consecutive unused addresses that end an xpub chain scan (XPUB_GAP_LIMIT, default 20)
+pub fn get_gap_limit() -> u32 {
+    env::var("XPUB_GAP_LIMIT")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(xpub::DEFAULT_GAP_LIMIT)
+}
+
+/// Lifetime of new pairings (PAIRING_TTL_DAYS, default: no expiry)
+pub fn get_pairing_ttl() -> Option<Duration> {
+    env::var("PAIRING_TTL_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|n| *n > 0)
+        .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+}
+
+/// Responses larger than this are sent gzip+base64 encoded (COMPRESS_THRESHOLD_BYTES, default 16384)
+pub fn get_compress_threshold() -> usize {
+    env::var("COMPRESS_THRESHOLD_BYTES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_COMPRESS_THRESHOLD_BYTES)
+}
+
+/// Command line options (development convenience; Umbrel uses env vars)
+///
+/// Precedence: command line > environment > --config-file > built-in default.
+#[derive(Debug, Default, Parser)]
+#[command(name = "balancebridge-server", version, about = "BalanceBridge Umbrel server")]
+pub struct CliArgs {
+    /// Electrum server, host:port, tcp://host:port or ssl://host:port [env: ELECTRS_ADDR] [default: electrs:50001]
+    #[arg(long)]
+    pub electrs_addr: Option<String>,
+
+    /// HTTP port for the web UI and health endpoints [env: LISTEN_PORT] [default: 3829]
+    #[arg(long)]
+    pub listen_port: Option<u16>,
+
+    /// Persistent data directory [env: UMBREL_APP_DATA_DIR] [default: ./data]
+    #[arg(long)]
+    pub data_dir: Option<PathBuf>,
+
+    /// Nostr relay URL, repeatable [env: NOSTR_RELAYS, comma-separated] [default: built-in public list]
+    #[arg(long = "relay")]
+    pub relays: Vec<String>,
+
+    /// TOML file with any of: electrs_addr, listen_port, data_dir, relays (lowest precedence)
+    #[arg(long)]
+    pub config_file: Option<PathBuf>,
+}
+
+/// Contents of --config-file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    electrs_addr: Option<String>,
+    listen_port: Option<u16>,
+    data_dir: Option<PathBuf>,
+    relays: Option<Vec<String>>,
+}
+
+impl FileConfig {
+    fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+}
+
+/// Effective server configuration, resolved once at startup
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub data_dir: PathBuf,
+    pub electrs_addr: String,
+    pub electrs_tls_insecure: bool,
+    pub network: Network,
+    pub relays: Vec<String>,
+    pub gap_limit: u32,
+    pub listen_addr: SocketAddr,
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            data_dir: get_data_dir(),
+            electrs_addr: get_electrs_addr(),
+            electrs_tls_insecure: get_electrs_tls_insecure(),
+            network: get_bitcoin_network()?,
+            relays: relays::get_relays(),
+            gap_limit: get_gap_limit(),
+            listen_addr: get_listen_addr(),
+        })
+    }
+
+    /// Resolve the configuration from the command line, environment, optional
+    /// TOML file and defaults, in that order of precedence. Exits on --help.
+    pub fn from_args_and_env() -> Result<Self> {
+        Self::resolve(CliArgs::parse())
+    }
+
+    fn resolve(args: CliArgs) -> Result<Self> {
+        let file = match &args.config_file {
+            Some(path) => FileConfig::load(path)?,
+            None => FileConfig::default(),
+        };
+
+        let data_dir = args
+            .data_dir
+            .or_else(|| env::var("UMBREL_APP_DATA_DIR").ok().map(PathBuf::from))
+            .or(file.data_dir)
+            .unwrap_or_else(|| PathBuf::from("./data"));
+
+        let electrs_addr = args
+            .electrs_addr
+            .or_else(env_electrs_addr)
+            .or(file.electrs_addr)
+            .unwrap_or_else(|| DEFAULT_ELECTRS_ADDR.to_string());
+
+        let relays = Some(args.relays)
+            .filter(|r| !r.is_empty())
+            .or_else(relays::env_relays)
+            .or(file.relays.filter(|r| !r.is_empty()))
+            .unwrap_or_else(relays::default_relays);
+
+        let port = args
+            .listen_port
+            .or_else(|| env::var("LISTEN_PORT").ok().and_then(|v| v.parse().ok()))
+            .or(file.listen_port)
+            .unwrap_or(DEFAULT_LISTEN_PORT);
+
+        Ok(Self {
+            data_dir,
+            electrs_addr,
+            electrs_tls_insecure: get_electrs_tls_insecure(),
+            network: get_bitcoin_network()?,
+            relays,
+            gap_limit: get_gap_limit(),
+            listen_addr: SocketAddr::from(([0, 0, 0, 0], port)),
+        })
+    }
+
+    /// Whether the Electrs connection is TLS (ssl://) rather than plaintext
+    pub fn electrs_tls(&self) -> bool {
+        ElectrsEndpoint::parse(&self.electrs_addr)
+            .map(|e| e.tls)
+            .unwrap_or(false)
+    }
+}
@@ -1,49 +1,609 @@
 use anyhow::{anyhow, Result};
-use electrum_client::bitcoin::{Address, Network, ScriptBuf};
-use electrum_client::{Client, ElectrumApi};
+use electrum_client::bitcoin::{Address, Network, Script, ScriptBuf, Transaction, Txid};
+use std::collections::HashMap;
+use electrum_client::{Client, ElectrumApi, Param};
+use serde::Serialize;
 use std::net::ToSocketAddrs;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::sync::Semaphore;
-use tracing::{info, warn};
+use tokio::sync::{watch, Semaphore};
+use tracing::{debug, info, warn};
+
+use crate::protocol::Utxo;
+use crate::xpub::AddressType;
+
+pub mod cache;
+#[cfg(feature = "testing")]
+pub mod mock;
+
+use cache::{BalanceCache, CachedBalance};
+
+/// Roughly one block worth of transactions
+pub const BLOCK_VSIZE: u64 = 1_000_000;
+
+/// Concurrent Electrs calls inside one parallel batch (each call also takes
+/// a permit of the global gate, so the gate still caps what Electrs sees)
+const PARALLEL_CALLS: usize = 4;
+
+/// Electrs calls in flight at once across the whole server (ELECTRS_MAX_INFLIGHT overrides)
+pub const DEFAULT_MAX_INFLIGHT: usize = 4;
+
+/// Per-attempt timeout of a balance lookup (ELECTRS_BALANCE_TIMEOUT_SECS overrides)
+pub const DEFAULT_BALANCE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Timeout of a history lookup (ELECTRS_HISTORY_TIMEOUT_SECS overrides)
+pub const DEFAULT_HISTORY_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Fail-fast period after a timeout, doubled once retries are exhausted
+/// (ELECTRS_COOLDOWN_SECS overrides)
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Default minimum relay fee
+const MIN_RELAY_FEE_SAT_VBYTE: f64 = 1.0;
+
+/// Best block Electrs knows about
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChainTip {
+    pub height: u32,
+    /// Block hash, hex (display byte order)
+    pub header_hash: String,
+}
+
+/// One transaction of an address history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxHistoryEntry {
+    pub txid: String,
+    /// Block height; 0 (or -1 with unconfirmed parents) while in the mempool
+    pub height: i32,
+}
+
+impl TxHistoryEntry {
+    /// Confirmed block height, None while in the mempool
+    pub fn confirmed_height(&self) -> Option<u32> {
+        u32::try_from(self.height).ok().filter(|h| *h > 0)
+    }
+
+    /// Confirmations at chain tip `tip_height` (0 while in the mempool)
+    pub fn confirmations(&self, tip_height: u32) -> u32 {
+        match self.confirmed_height() {
+            Some(height) => tip_height.saturating_sub(height).saturating_add(1),
+            None => 0,
+        }
+    }
+}
+
+/// What a transaction did, worked out from the transactions it spends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TxAmounts {
+    /// Inputs minus outputs; None for coinbase or when a spent output is unknown
+    pub fee_sats: Option<u64>,
+    /// Received by minus spent from the queried script; None without a
+    /// script or when a spent output is unknown
+    pub net_amount_sats: Option<i64>,
+}
+
+/// Fee and net amount of `tx` for `script`. `prevouts` holds the transactions
+/// whose outputs `tx` spends, by txid.
+pub fn tx_amounts(
+    tx: &Transaction,
+    prevouts: &HashMap<Txid, Transaction>,
+    script: Option<&Script>,
+) -> TxAmounts {
+    let spent: Option<Vec<_>> = if tx.is_coinbase() {
+        Some(Vec::new())
+    } else {
+        tx.input
+            .iter()
+            .map(|input| {
+                prevouts
+                    .get(&input.previous_output.txid)
+                    .and_then(|prev| prev.output.get(input.previous_output.vout as usize))
+            })
+            .collect()
+    };
+    let Some(spent) = spent else {
+        return TxAmounts::default();
+    };
+
+    let value_out: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+    let value_in: u64 = spent.iter().map(|o| o.value.to_sat()).sum();
+    let fee_sats = (!tx.is_coinbase()).then(|| value_in.saturating_sub(value_out));
+
+    let net_amount_sats = script.map(|script| {
+        let received: u64 = tx
+            .output
+            .iter()
+            .filter(|o| o.script_pubkey.as_script() == script)
+            .map(|o| o.value.to_sat())
+            .sum();
+        let sent: u64 = spent
+            .iter()
+            .filter(|o| o.script_pubkey.as_script() == script)
+            .map(|o| o.value.to_sat())
+            .sum();
+        received as i64 - sent as i64
+    });
+
+    TxAmounts {
+        fee_sats,
+        net_amount_sats,
+    }
+}
+
+/// One cumulative bucket of the mempool fee histogram
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeHistogramBucket {
+    pub fee_rate_sat_vbyte: f64,
+    /// vbytes of mempool transactions paying at least `fee_rate_sat_vbyte`
+    pub mempool_size_bytes: u64,
+}
+
+/// Balance of one address found by `scan_xpub_parallel`
+#[derive(Debug, Clone, Serialize)]
+pub struct ScannedAddress {
+    pub address: String,
+    pub confirmed: u64,
+    pub unconfirmed: u64,
+}
+
+/// Totals of a full (non-paginated) xpub scan
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct XpubScanResult {
+    pub confirmed: u64,
+    pub unconfirmed: u64,
+    /// Confirmed balance on the receiving chain (m/0)
+    pub external_confirmed: u64,
+    /// Confirmed balance on the change chain (m/1)
+    pub internal_confirmed: u64,
+    /// Addresses with a non-zero balance
+    pub funded: Vec<ScannedAddress>,
+}
+
+/// Outcome of a balance query that reached Electrs
+#[derive(Debug)]
+pub enum ElectrsQueryResult {
+    /// Address has history; balance from its UTXOs
+    Found {
+        confirmed: u64,
+        unconfirmed: u64,
+        /// Value of each UTXO (confirmed or not), for dust accounting
+        utxo_values: Vec<u64>,
+    },
+    /// Address was never used: a valid, empty answer
+    NotFound,
+    /// Electrs answered, but with an error for this query (bad input, protocol error)
+    Error(anyhow::Error),
+}
+
+impl ElectrsQueryResult {
+    /// (confirmed, unconfirmed); `NotFound` is (0, 0)
+    pub fn balance(&self) -> Option<(u64, u64)> {
+        match self {
+            Self::Found {
+                confirmed,
+                unconfirmed,
+                ..
+            } => Some((*confirmed, *unconfirmed)),
+            Self::NotFound => Some((0, 0)),
+            Self::Error(_) => None,
+        }
+    }
+}
+
+/// (confirmed, unconfirmed) total of listunspent entries
+fn unspent_balance(unspent: &[electrum_client::ListUnspentRes]) -> (u64, u64) {
+    // Convention: height == 0 => mempool/unconfirmed
+    unspent.iter().fold((0u64, 0u64), |(confirmed, unconfirmed), u| {
+        if u.height > 0 {
+            (confirmed.saturating_add(u.value), unconfirmed)
+        } else {
+            (confirmed, unconfirmed.saturating_add(u.value))
+        }
+    })
+}
+
+/// How `get_address_balance` retries failed attempts
+#[derive(Debug, Clone, Copy)]
+pub struct RetryStrategy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// Rebuild the Electrum connection before retrying after an I/O error
+    pub reconnect_before_retry: bool,
+}
+
+impl Default for RetryStrategy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            reconnect_before_retry: true,
+        }
+    }
+}
+
+/// How long Electrs calls may take, and how long to back off after one didn't
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElectrsTimeouts {
+    pub balance: Duration,
+    /// Every other read: histories, transactions, fees and the chain tip
+    pub history: Duration,
+    pub cooldown: Duration,
+}
+
+impl Default for ElectrsTimeouts {
+    fn default() -> Self {
+        Self {
+            balance: DEFAULT_BALANCE_TIMEOUT,
+            history: DEFAULT_HISTORY_TIMEOUT,
+            cooldown: DEFAULT_COOLDOWN,
+        }
+    }
+}
+
+/// Error returned while the post-timeout cooldown is active
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Electrs cooling down ({remaining_ms}ms remaining)")]
+pub struct PendingCooldown {
+    pub remaining_ms: u64,
+}
+
+/// Rough classification of a failed Electrs call, used to pick a retry policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureKind {
+    /// Socket-level failure: the connection is probably dead, reconnect then retry
+    Io,
+    /// Electrum protocol error: the connection is fine, retry as-is
+    Protocol,
+    /// Call didn't complete in time: retry as-is (after cooldown)
+    Timeout,
+    /// Bad input (e.g. invalid address): retrying can't help
+    Invalid,
+}
+
+fn classify_error(e: &anyhow::Error) -> FailureKind {
+    if e.downcast_ref::<electrum_client::bitcoin::address::ParseError>().is_some() {
+        return FailureKind::Invalid;
+    }
+
+    match e.downcast_ref::<electrum_client::Error>() {
+        Some(electrum_client::Error::IOError(_))
+        | Some(electrum_client::Error::SharedIOError(_))
+        | Some(electrum_client::Error::AllAttemptsErrored(_))
+        | Some(electrum_client::Error::CouldntLockReader)
+        | Some(electrum_client::Error::Mpsc) => FailureKind::Io,
+        _ => FailureKind::Protocol,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ElectrsStatus {
+    Connected,
+    CoolingDown,
+    Error,
+}
+
+/// Connection state pushed to subscribers whenever it changes
+#[derive(Debug, Clone, Serialize)]
+pub struct ElectrsConnectionState {
+    pub status: ElectrsStatus,
+    pub last_error: Option<String>,
+    pub cooldown_remaining_ms: u64,
+}
+
+impl ElectrsConnectionState {
+    fn connected() -> Self {
+        Self {
+            status: ElectrsStatus::Connected,
+            last_error: None,
+            cooldown_remaining_ms: 0,
+        }
+    }
+}
+
+/// Oldest Electrum protocol version with everything we use
+const MIN_PROTOCOL_VERSION: (u32, u32) = (1, 4);
+
+/// What the Electrum server reported via `server.features`
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerFeatures {
+    pub server_version: String,
+    pub protocol_min: String,
+    pub protocol_max: String,
+    pub genesis_hash: String,
+    pub hash_function: String,
+}
+
+/// Circuit breaker view of the connection state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Calls go through
+    Healthy,
+    /// Open after a timeout: calls fail fast for `remaining_ms` more
+    CoolingDown { remaining_ms: u64 },
+    /// The last call failed; the next one tries again
+    Down,
+}
+
+impl BreakerState {
+    pub fn is_healthy(&self) -> bool {
+        *self == BreakerState::Healthy
+    }
+}
+
+impl From<&ElectrsConnectionState> for BreakerState {
+    /// A cooldown that ran out counts as closed: the next call goes through
+    fn from(state: &ElectrsConnectionState) -> Self {
+        match state.status {
+            ElectrsStatus::Connected => BreakerState::Healthy,
+            ElectrsStatus::CoolingDown if state.cooldown_remaining_ms > 0 => {
+                BreakerState::CoolingDown {
+                    remaining_ms: state.cooldown_remaining_ms,
+                }
+            }
+            ElectrsStatus::CoolingDown => BreakerState::Healthy,
+            ElectrsStatus::Error => BreakerState::Down,
+        }
+    }
+}
+
+/// `GET /health/electrs` body
+#[derive(Debug, Clone, Serialize)]
+pub struct ElectrsHealth {
+    #[serde(flatten)]
+    pub state: ElectrsConnectionState,
+    pub breaker: BreakerState,
+    pub server_features: Option<ServerFeatures>,
+}
 
 #[derive(Clone)]
 pub struct ElectrsClient {
-    client: Arc<Client>,
+    // Swappable so a dead connection can be rebuilt without restarting the app
+    client: Arc<Mutex<Arc<Client>>>,
+    // Bumped on every rebuild, so concurrent callers that saw the same dead
+    // connection fail reconnect once between them
+    generation: Arc<Mutex<u64>>,
     addr: String,
+    endpoint: ElectrsEndpoint,
+    // ssl:// only: accept any certificate (self-signed electrs/Fulcrum)
+    tls_insecure: bool,
 
     // Soft rate limit between individual RPC calls
     last_call: Arc<Mutex<Instant>>,
 
-    // Hard global gate: only one in-flight Electrs request at a time
+    // Hard global gate: at most max_inflight Electrs requests at a time
     gate: Arc<Semaphore>,
 
     // Cooldown until this time (set when a timeout happens)
     cooldown_until: Arc<Mutex<Option<Instant>>>,
+
+    // Per-call timeouts and the cooldown length
+    timeouts: ElectrsTimeouts,
+
+    // Connection state observable (see subscribe_to_connection_events)
+    state_tx: Arc<watch::Sender<ElectrsConnectionState>>,
+
+    // Fetched once at startup; None if the server didn't answer
+    server_features: Option<ServerFeatures>,
+
+    // Recent balances by script_pubkey (see electrs::cache)
+    balance_cache: Arc<BalanceCache>,
+
+    // Chain the Electrs server indexes; addresses for any other are rejected
+    network: Network,
 }
 
 impl ElectrsClient {
-    pub fn new() -> Result<Self> {
-        let addr = std::env::var("ELECTRS_ADDR").unwrap_or_else(|_| "electrs:50001".to_string());
+    pub fn new(addr: String) -> Result<Self> {
+        Self::connect(addr, false)
+    }
+
+    /// Connect to `addr` (`host:port`, `tcp://host:port` or `ssl://host:port`).
+    /// With `tls_insecure`, an ssl:// server's certificate is not validated.
+    pub fn connect(addr: String, tls_insecure: bool) -> Result<Self> {
         info!("ElectrsClient using ELECTRS_ADDR={}", addr);
 
-        preflight_tcp(&addr)?;
+        let endpoint = ElectrsEndpoint::parse(&addr)?;
+        if endpoint.tls && tls_insecure {
+            warn!("ELECTRS_TLS_INSECURE is set: the Electrs certificate will not be validated");
+        }
+
+        // Raw TCP connect for either scheme; the TLS handshake happens in Client
+        preflight_tcp(&endpoint.host_port())?;
 
-        let client = Client::new(&addr)
+        let client = open_client(&endpoint, tls_insecure)
             .map_err(|e| anyhow!("Failed to create electrum client for {}: {}", addr, e))?;
 
-        Ok(Self {
-            client: Arc::new(client),
+        let mut this = Self {
+            client: Arc::new(Mutex::new(Arc::new(client))),
+            generation: Arc::new(Mutex::new(0)),
             addr,
+            endpoint,
+            tls_insecure,
             last_call: Arc::new(Mutex::new(Instant::now())),
-            gate: Arc::new(Semaphore::new(1)),
+            gate: Arc::new(Semaphore::new(DEFAULT_MAX_INFLIGHT)),
             cooldown_until: Arc::new(Mutex::new(None)),
+            timeouts: ElectrsTimeouts::default(),
+            state_tx: Arc::new(watch::channel(ElectrsConnectionState::connected()).0),
+            server_features: None,
+            balance_cache: Arc::new(BalanceCache::default()),
+            network: Network::Bitcoin,
+        };
+
+        match this.get_server_features() {
+            Ok(features) => {
+                info!(
+                    "Electrum server: {} (protocol {} - {})",
+                    features.server_version, features.protocol_min, features.protocol_max
+                );
+                if !protocol_at_least(&features.protocol_max, MIN_PROTOCOL_VERSION) {
+                    warn!(
+                        "Electrum server protocol_max {} is below {}.{}; some features may be unavailable",
+                        features.protocol_max, MIN_PROTOCOL_VERSION.0, MIN_PROTOCOL_VERSION.1
+                    );
+                }
+                this.server_features = Some(features);
+            }
+            Err(e) => warn!("Failed to query Electrum server features: {}", e),
+        }
+
+        Ok(this)
+    }
+
+    /// Serve balances from cache for `ttl` (zero disables the cache)
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.balance_cache = Arc::new(BalanceCache::new(ttl, cache::DEFAULT_CACHE_CAPACITY));
+        self
+    }
+
+    /// Chain the Electrs server indexes (mainnet unless BITCOIN_NETWORK says otherwise)
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Balance/history timeouts and cooldown length (zero durations are ignored)
+    pub fn with_timeouts(mut self, timeouts: ElectrsTimeouts) -> Self {
+        let defaults = ElectrsTimeouts::default();
+        let positive = |d: Duration, default: Duration| if d.is_zero() { default } else { d };
+        self.timeouts = ElectrsTimeouts {
+            balance: positive(timeouts.balance, defaults.balance),
+            history: positive(timeouts.history, defaults.history),
+            cooldown: positive(timeouts.cooldown, defaults.cooldown),
+        };
+        self
+    }
+
+    pub fn timeouts(&self) -> ElectrsTimeouts {
+        self.timeouts
+    }
+
+    /// Allow `max_inflight` concurrent Electrs calls (at least one)
+    pub fn with_max_inflight(mut self, max_inflight: usize) -> Self {
+        self.gate = Arc::new(Semaphore::new(max_inflight.max(1)));
+        self
+    }
+
+    /// `server.features` of the connected Electrum server. BLOCKING.
+    pub fn get_server_features(&self) -> Result<ServerFeatures> {
+        let f = self.client().server_features()?;
+        Ok(ServerFeatures {
+            server_version: f.server_version,
+            protocol_min: f.protocol_min,
+            protocol_max: f.protocol_max,
+            genesis_hash: hex::encode(f.genesis_hash),
+            hash_function: f.hash_function.unwrap_or_default(),
         })
     }
 
+    /// Connection state plus server features, for the health endpoint
+    pub fn health(&self) -> ElectrsHealth {
+        let state = self.connection_state();
+        ElectrsHealth {
+            breaker: BreakerState::from(&state),
+            state,
+            server_features: self.server_features.clone(),
+        }
+    }
+
+    /// Circuit breaker state derived from the connection state (O(1), no network)
+    pub fn breaker_state(&self) -> BreakerState {
+        BreakerState::from(&self.connection_state())
+    }
+
+    /// Receive a notification every time the connection state changes
+    pub fn subscribe_to_connection_events(&self) -> watch::Receiver<ElectrsConnectionState> {
+        self.state_tx.subscribe()
+    }
+
+    /// Current connection state, with the cooldown countdown refreshed (O(1), no network)
+    pub fn connection_state(&self) -> ElectrsConnectionState {
+        let mut state = self.state_tx.borrow().clone();
+        if state.status == ElectrsStatus::CoolingDown {
+            let remaining = self
+                .cooldown_until
+                .lock()
+                .unwrap()
+                .map(|until| until.saturating_duration_since(Instant::now()))
+                .unwrap_or_default();
+            state.cooldown_remaining_ms = remaining.as_millis() as u64;
+        }
+        state
+    }
+
+    /// Record a successful call. A call that was already in flight when
+    /// another one timed out doesn't end that cooldown early.
+    fn mark_connected(&self) {
+        if self.cooldown_active() {
+            return;
+        }
+        self.state_tx.send_if_modified(|s| {
+            if s.status == ElectrsStatus::Connected {
+                return false;
+            }
+            info!("Electrs state: {:?} -> Connected", s.status);
+            *s = ElectrsConnectionState::connected();
+            true
+        });
+    }
+
+    fn mark_error(&self, error: &anyhow::Error) {
+        self.state_tx.send_modify(|s| {
+            s.status = ElectrsStatus::Error;
+            s.last_error = Some(error.to_string());
+            s.cooldown_remaining_ms = 0;
+        });
+    }
+
+    fn client(&self) -> Arc<Client> {
+        Arc::clone(&self.client.lock().unwrap())
+    }
+
+    /// Replace the underlying Electrum connection with a fresh one.
+    /// BLOCKING (TCP connect).
+    pub fn reconnect(&self) -> Result<()> {
+        let mut generation = self.generation.lock().unwrap();
+        self.replace_client()?;
+        *generation += 1;
+        Ok(())
+    }
+
+    fn connection_generation(&self) -> u64 {
+        *self.generation.lock().unwrap()
+    }
+
+    /// Reconnect after a call on connection `seen` failed with an I/O error,
+    /// unless another caller already replaced that connection. BLOCKING.
+    fn reconnect_after_failure(&self, seen: u64) -> Result<()> {
+        let mut generation = self.generation.lock().unwrap();
+        if *generation != seen {
+            debug!("Electrs connection already rebuilt by another caller");
+            return Ok(());
+        }
+        self.replace_client()?;
+        *generation += 1;
+        Ok(())
+    }
+
+    /// Caller holds the generation lock
+    fn replace_client(&self) -> Result<()> {
+        info!("Reconnecting to Electrs at {}", self.addr);
+
+        let client = open_client(&self.endpoint, self.tls_insecure)
+            .map_err(|e| anyhow!("Failed to reconnect electrum client to {}: {}", self.addr, e))?;
+
+        *self.client.lock().unwrap() = Arc::new(client);
+        info!("Electrs reconnected");
+        Ok(())
+    }
+
     pub fn test_connectivity(&self) -> Result<()> {
-        self.client
+        self.client()
             .ping()
             .map_err(|e| anyhow!("Electrs ping failed ({}) : {}", self.addr, e))?;
         Ok(())
@@ -58,6 +618,57 @@ impl ElectrsClient {
         Ok(())
     }
 
+    /// Warm-up of known addresses: history only (no script_list_unspent),
+    /// which is enough to make Electrs index the scripts. BLOCKING, like
+    /// `warm_up`; stops early once `timeout` is exceeded.
+    ///
+    /// Returns how many addresses have history (i.e. are active). Failures
+    /// are logged and skipped; only fails if every address failed. History
+    /// alone gives no balance, so the balance cache is left untouched.
+    pub fn preload_scripts(&self, addresses: &[String], timeout: Duration) -> Result<u32> {
+        let started = Instant::now();
+        let mut active = 0u32;
+        let mut failures = 0usize;
+        let mut last_error = None;
+
+        for (i, address) in addresses.iter().enumerate() {
+            if started.elapsed() >= timeout {
+                warn!(
+                    "Electrs script preload timed out after {}/{} addresses",
+                    i,
+                    addresses.len()
+                );
+                break;
+            }
+
+            match self.has_transactions_blocking(address) {
+                Ok(true) => active += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!("Electrs script preload failed for {}: {}", address, e);
+                    failures += 1;
+                    last_error = Some(e);
+                }
+            }
+
+            if (i + 1) % 10 == 0 {
+                info!(
+                    "Electrs script preload progress: {}/{} addresses ({}ms)",
+                    i + 1,
+                    addresses.len(),
+                    started.elapsed().as_millis()
+                );
+            }
+        }
+
+        match last_error {
+            Some(e) if failures == addresses.len() => {
+                Err(e.context("Electrs script preload failed for every address"))
+            }
+            _ => Ok(active),
+        }
+    }
+
     fn rate_limit(&self) {
         let mut last = self.last_call.lock().unwrap();
         let elapsed = last.elapsed();
@@ -76,56 +687,113 @@ impl ElectrsClient {
             let now = Instant::now();
             if now < until {
                 let remaining = until.duration_since(now);
-                return Err(anyhow!(
-                    "Electrs cooling down ({}ms remaining)",
-                    remaining.as_millis()
-                ));
+                return Err(PendingCooldown {
+                    remaining_ms: remaining.as_millis() as u64,
+                }
+                .into());
             } else {
                 *cd = None;
+                drop(cd);
+                self.mark_connected();
             }
         }
         Ok(())
     }
 
-    fn set_cooldown(&self, seconds: u64) {
+    /// Fail fast for `duration`. With several calls in flight, a shorter
+    /// cooldown never cuts a longer one short.
+    fn set_cooldown(&self, duration: Duration) {
         let mut cd = self.cooldown_until.lock().unwrap();
-        *cd = Some(Instant::now() + Duration::from_secs(seconds));
+        let until = Instant::now() + duration;
+        let until = cd.map_or(until, |current| current.max(until));
+        *cd = Some(until);
+
+        let remaining_ms = until.saturating_duration_since(Instant::now()).as_millis() as u64;
+        self.state_tx.send_modify(|s| {
+            s.status = ElectrsStatus::CoolingDown;
+            s.cooldown_remaining_ms = remaining_ms;
+        });
+    }
+
+    fn cooldown_active(&self) -> bool {
+        self.cooldown_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| Instant::now() < until)
     }
 
     /// BLOCKING tx history lookup
     fn get_address_txs_blocking(&self, address: &str) -> Result<Vec<String>> {
         self.rate_limit();
 
-        let addr = Address::from_str(address)?.require_network(Network::Bitcoin)?;
+        let addr = Address::from_str(address)?.require_network(self.network)?;
         let script: ScriptBuf = addr.script_pubkey();
 
-        let history = self.client.script_get_history(&script)?;
+        let history = self.client().script_get_history(&script)?;
         Ok(history.into_iter().map(|h| h.tx_hash.to_string()).collect())
     }
 
+    /// BLOCKING "is this address used" check (see `has_transactions`)
+    fn has_transactions_blocking(&self, address: &str) -> Result<bool> {
+        self.rate_limit();
+
+        let addr = Address::from_str(address)?.require_network(self.network)?;
+        let script: ScriptBuf = addr.script_pubkey();
+
+        let history = self.client().script_get_history(&script)?;
+        Ok(!history.is_empty())
+    }
+
+    /// BLOCKING history lookup with block heights
+    fn get_address_history_blocking(&self, address: &str) -> Result<Vec<TxHistoryEntry>> {
+        self.rate_limit();
+
+        let addr = Address::from_str(address)?.require_network(self.network)?;
+        let script: ScriptBuf = addr.script_pubkey();
+
+        let history = self.client().script_get_history(&script)?;
+        Ok(history
+            .into_iter()
+            .map(|h| TxHistoryEntry {
+                txid: h.tx_hash.to_string(),
+                height: h.height,
+            })
+            .collect())
+    }
+
     /// BLOCKING balance lookup with history fast-path:
     /// 1) Call script_get_history first
-    ///    - if empty => immediately return (0,0) (avoids listunspent cost/blocking)
+    ///    - if empty => immediately return NotFound (avoids listunspent cost/blocking)
     /// 2) If non-empty => call script_list_unspent and sum values
     ///
     /// This keeps the service stateless while avoiding listunspent calls for unused addresses.
-    fn get_address_balance_blocking(&self, address: &str) -> Result<(u64, u64)> {
-        let addr = Address::from_str(address)?.require_network(Network::Bitcoin)?;
+    ///
+    /// `Address::from_str` (bitcoin 0.32) decodes Bech32m, so Taproot (bc1p)
+    /// addresses get their P2TR script_pubkey just like bc1q gets P2WPKH/P2WSH.
+    fn get_address_balance_blocking(&self, address: &str) -> Result<ElectrsQueryResult> {
+        let addr = Address::from_str(address)?.require_network(self.network)?;
         let script: ScriptBuf = addr.script_pubkey();
+        let client = self.client();
 
         // ---- Fast-path: check history first ----
         self.rate_limit();
-        let history = self.client.script_get_history(&script)?;
+        let history = client.script_get_history(&script)?;
         if history.is_empty() {
-            return Ok((0, 0));
+            return Ok(ElectrsQueryResult::NotFound);
         }
 
         // ---- Only if there is history, compute balance from UTXOs ----
+        // No early (0, 0) for fully spent addresses: history entries don't say
+        // whether a tx paid to or spent from the script, so proving it takes a
+        // transaction fetch per entry, and batching listunspent with the
+        // history would cost every fresh address a listunspent. This one call
+        // answers it; the result is Found with (0, 0), not NotFound.
         self.rate_limit();
-        let utxos = self.client.script_list_unspent(&script)?;
+        let utxos = client.script_list_unspent(&script)?;
 
         let mut confirmed: u64 = 0;
         let mut unconfirmed: u64 = 0;
+        let mut utxo_values = Vec::with_capacity(utxos.len());
 
         for u in utxos {
             // Convention: height == 0 => mempool/unconfirmed
@@ -134,109 +802,987 @@ impl ElectrsClient {
             } else {
                 unconfirmed = unconfirmed.saturating_add(u.value);
             }
+            utxo_values.push(u.value);
         }
 
-        Ok((confirmed, unconfirmed))
+        Ok(ElectrsQueryResult::Found {
+            confirmed,
+            unconfirmed,
+            utxo_values,
+        })
+    }
+
+    /// Balance lookup, recorded in the `metrics::metrics().electrs` counters
+    /// (see `get_address_balance_inner` for the retry behaviour).
+    ///
+    /// Answers from the balance cache while the last lookup of the same
+    /// script is younger than the cache TTL; only misses reach Electrs.
+    pub async fn get_address_balance(
+        &self,
+        address: &str,
+        strategy: RetryStrategy,
+    ) -> Result<ElectrsQueryResult> {
+        let m = &crate::metrics::metrics().electrs;
+        m.calls_total.inc();
+
+        // Invalid addresses skip the cache and fail in the lookup below
+        let script = address_script(address).ok();
+        let cached = script
+            .as_ref()
+            .and_then(|s| self.balance_cache.get(s, Instant::now()));
+        if let Some(cached) = cached {
+            m.cache_hits.inc();
+            return Ok(cached.into());
+        }
+        m.cache_misses.inc();
+
+        m.active_calls.inc();
+        let started = std::time::Instant::now();
+
+        let result = self.get_address_balance_inner(address, strategy).await;
+
+        m.duration_seconds.observe(started.elapsed().as_secs_f64());
+        m.active_calls.dec();
+        if matches!(result, Err(_) | Ok(ElectrsQueryResult::Error(_))) {
+            m.errors_total.inc();
+        }
+
+        if let (Some(script), Ok(answer)) = (script, &result) {
+            if let Some(balance) = CachedBalance::from_result(answer, Instant::now()) {
+                self.balance_cache.insert(script, balance);
+            }
+        }
+
+        result
     }
 
     /// Balance lookup:
-    /// - single-flight gate (global)
+    /// - global in-flight gate
     /// - cooldown after timeout
-    /// - 90s timeout + 1 retry
-    pub async fn get_address_balance(&self, address: &str) -> Result<(u64, u64)> {
+    /// - balance timeout (90s by default) per attempt, retried according to `strategy`:
+    ///   I/O errors reconnect first (if enabled), protocol errors and timeouts
+    ///   retry as-is, invalid input is never retried
+    ///
+    /// `Err` only for infrastructure failures (cooldown, I/O, timeout); an
+    /// answer from Electrs, even an error one, is `Ok(ElectrsQueryResult)`.
+    async fn get_address_balance_inner(
+        &self,
+        address: &str,
+        strategy: RetryStrategy,
+    ) -> Result<ElectrsQueryResult> {
         use tokio::task::spawn_blocking;
-        use tokio::time::{timeout, Duration};
+        use tokio::time::timeout;
 
         // Respect cooldown (fast-fail instead of wedging Electrs)
         self.check_cooldown()?;
 
-        // Global single-flight gate
+        // Global in-flight gate
         let _permit = self.gate.acquire().await.unwrap();
 
         // Re-check cooldown after acquiring (someone else might have set it)
         self.check_cooldown()?;
 
-        // ---- First attempt (90s) ----
-        let addr1 = address.to_string();
-        let this1 = self.clone();
+        let mut attempt: u32 = 0;
+
+        loop {
+            let addr = address.to_string();
+            let this = self.clone();
+            let generation = self.connection_generation();
+
+            let res = timeout(
+                self.timeouts.balance,
+                spawn_blocking(move || this.get_address_balance_blocking(&addr)),
+            )
+            .await;
 
-        let first = timeout(
-            Duration::from_secs(90),
-            spawn_blocking(move || this1.get_address_balance_blocking(&addr1)),
-        )
-        .await;
+            let failure = match res {
+                Ok(Ok(Ok(v))) => {
+                    self.mark_connected();
+                    return Ok(v);
+                }
+                Ok(Ok(Err(e))) => {
+                    let kind = classify_error(&e);
+                    if kind == FailureKind::Invalid {
+                        return Ok(ElectrsQueryResult::Error(e));
+                    }
+                    self.mark_error(&e);
+                    if attempt >= strategy.max_retries {
+                        return match kind {
+                            FailureKind::Protocol => Ok(ElectrsQueryResult::Error(e)),
+                            _ => Err(anyhow!("Electrs balance error: {}", e)),
+                        };
+                    }
+                    warn!(
+                        "Electrs balance error ({:?}, attempt {}): {} — retrying",
+                        kind,
+                        attempt + 1,
+                        e
+                    );
+                    kind
+                }
+                Ok(Err(e)) => return Err(anyhow!("Electrs join error: {}", e)),
+                Err(_) => {
+                    if attempt >= strategy.max_retries {
+                        warn!("Electrs balance timed out after retry; setting longer cooldown");
+                        self.set_cooldown(self.timeouts.cooldown * 2);
+                        return Err(anyhow!("Electrs balance timeout (after retry)"));
+                    }
+                    warn!("Electrs balance timed out, setting cooldown + retrying...");
+                    // cooldown helps the whole system recover (wallet + UI)
+                    self.set_cooldown(self.timeouts.cooldown);
+                    FailureKind::Timeout
+                }
+            };
 
-        match first {
-            Ok(Ok(Ok(v))) => return Ok(v),
-            Ok(Ok(Err(e))) => return Err(anyhow!("Electrs balance error: {}", e)),
-            Ok(Err(e)) => return Err(anyhow!("Electrs join error: {}", e)),
-            Err(_) => {
-                warn!("Electrs balance timed out, setting cooldown + retrying once...");
-                // cooldown helps the whole system recover (wallet + UI)
-                self.set_cooldown(10);
+            if failure == FailureKind::Io && strategy.reconnect_before_retry {
+                let this = self.clone();
+                match spawn_blocking(move || this.reconnect_after_failure(generation)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("Electrs reconnect failed: {}", e),
+                    Err(e) => warn!("Electrs reconnect join error: {}", e),
+                }
             }
+
+            attempt += 1;
+        }
+    }
+
+    /// Balances of many addresses, up to PARALLEL_CALLS at a time.
+    ///
+    /// Each call takes its own permit of the global gate; any error fails the batch
+    /// (no retries), a timeout also sets the cooldown. Same order as `addresses`.
+    /// Fresh cached balances are used as is; the rest are fetched and cached.
+    pub async fn get_balances_parallel(&self, addresses: Vec<String>) -> Result<Vec<(u64, u64)>> {
+        self.get_balances_bounded(addresses, PARALLEL_CALLS).await
+    }
+
+    /// `get_balances_parallel` with an explicit number of concurrent calls
+    pub async fn get_balances_bounded(
+        &self,
+        addresses: Vec<String>,
+        concurrency: usize,
+    ) -> Result<Vec<(u64, u64)>> {
+        use futures_util::stream::{self, StreamExt};
+        use tokio::task::spawn_blocking;
+        use tokio::time::timeout;
+
+        if addresses.is_empty() {
+            return Ok(Vec::new());
         }
 
-        // ---- Second attempt (retry, 90s) ----
-        let addr2 = address.to_string();
-        let this2 = self.clone();
+        // Invalid addresses skip the cache and fail in the lookup below
+        let scripts: Vec<Option<ScriptBuf>> =
+            addresses.iter().map(|a| address_script(a).ok()).collect();
+        let cached = self.cached_balances(&scripts);
+        if cached.iter().all(Option::is_some) {
+            return Ok(cached.into_iter().flatten().collect());
+        }
+
+        self.check_cooldown()?;
+
+        let mut balances: Vec<(u64, u64)> =
+            cached.iter().map(|c| c.unwrap_or_default()).collect();
+        let balance_timeout = self.timeouts.balance;
+        let misses = addresses.into_iter().enumerate().filter(|(i, _)| cached[*i].is_none());
 
-        let second = timeout(
-            Duration::from_secs(90),
-            spawn_blocking(move || this2.get_address_balance_blocking(&addr2)),
-        )
-        .await;
+        // Returning early drops the stream, so lookups not yet started never run
+        let mut lookups = stream::iter(misses)
+            .map(|(i, address)| {
+                let this = self.clone();
+                let gate = Arc::clone(&self.gate);
+                async move {
+                    let _permit = gate.acquire_owned().await.unwrap();
+                    let res = timeout(
+                        balance_timeout,
+                        spawn_blocking(move || this.get_address_balance_blocking(&address)),
+                    )
+                    .await;
+                    (i, res)
+                }
+            })
+            .buffer_unordered(concurrency.max(1));
 
-        match second {
-            Ok(Ok(Ok(v))) => Ok(v),
-            Ok(Ok(Err(e))) => Err(anyhow!("Electrs balance error (retry): {}", e)),
-            Ok(Err(e)) => Err(anyhow!("Electrs join error (retry): {}", e)),
-            Err(_) => {
-                warn!("Electrs balance timed out after retry; setting longer cooldown");
-                self.set_cooldown(20);
-                Err(anyhow!("Electrs balance timeout (after retry)"))
+        while let Some((i, res)) = lookups.next().await {
+            match res {
+                Ok(Ok(Ok(v))) => {
+                    balances[i] = v.balance().unwrap_or_default();
+                    if let (Some(script), Some(balance)) =
+                        (&scripts[i], CachedBalance::from_result(&v, Instant::now()))
+                    {
+                        self.balance_cache.insert(script.clone(), balance);
+                    }
+                }
+                Ok(Ok(Err(e))) => {
+                    if classify_error(&e) != FailureKind::Invalid {
+                        self.mark_error(&e);
+                    }
+                    return Err(anyhow!("Electrs balance error: {}", e));
+                }
+                Ok(Err(e)) => return Err(anyhow!("Electrs join error: {}", e)),
+                Err(_) => {
+                    warn!("Electrs parallel balance timed out; setting cooldown");
+                    self.set_cooldown(self.timeouts.cooldown);
+                    return Err(anyhow!("Electrs balance timeout"));
+                }
             }
         }
+
+        self.mark_connected();
+        Ok(balances)
+    }
+
+    /// Gap-limit scan of both chains of an xpub, then the balances of every
+    /// scanned address with up to min(free gate permits, PARALLEL_CALLS)
+    /// lookups at once
+    pub async fn scan_xpub_parallel(&self, xpub: &str, gap_limit: u32) -> Result<XpubScanResult> {
+        let concurrency = self.gate.available_permits().clamp(1, PARALLEL_CALLS);
+        scan_xpub_bounded(self, xpub, gap_limit, concurrency).await
     }
 
     /// History lookup (used only for xpub path):
-    /// - single-flight gate (global)
+    /// - global in-flight gate
     /// - cooldown after timeout
-    /// - 45s timeout (no retries here by default)
+    /// - history timeout, 45s by default (no retries here by default)
     pub async fn get_address_txs(&self, address: &str) -> Result<Vec<String>> {
+        let addr = address.to_string();
+        self.call_blocking("history", self.timeouts.history, move |this| {
+            this.get_address_txs_blocking(&addr)
+        })
+        .await
+    }
+
+    /// Like `get_address_txs`, with the block height of each transaction
+    pub async fn get_address_history(&self, address: &str) -> Result<Vec<TxHistoryEntry>> {
+        let addr = address.to_string();
+        self.call_blocking("history", self.timeouts.history, move |this| {
+            this.get_address_history_blocking(&addr)
+        })
+        .await
+    }
+
+    /// Whether the address has any history (gap-limit "used" check).
+    ///
+    /// Same answer as `get_address_txs(addr).map(|v| !v.is_empty())`, but says
+    /// what the caller wants and skips the script_list_unspent call a balance
+    /// lookup would make. Electrum has no "first result only" call, so the
+    /// server still sends the full history.
+    pub async fn has_transactions(&self, address: &str) -> Result<bool> {
+        let addr = address.to_string();
+        self.call_blocking("history", self.timeouts.history, move |this| {
+            this.has_transactions_blocking(&addr)
+        })
+        .await
+    }
+
+    /// Histories (tx hashes) for many scripts in one round trip.
+    ///
+    /// Sends a single JSON-RPC batch of `blockchain.scripthash.get_history`
+    /// calls; if the server rejects the batch, falls back to one call per script.
+    /// Results are in the same order as `scripts`.
+    pub async fn batch_get_histories(&self, scripts: &[ScriptBuf]) -> Result<Vec<Vec<String>>> {
+        let scripts = scripts.to_vec();
+        self.call_blocking("batch history", self.timeouts.history, move |this| {
+            this.batch_get_histories_blocking(&scripts)
+        })
+        .await
+    }
+
+    /// BLOCKING batch history lookup (see `batch_get_histories`)
+    fn batch_get_histories_blocking(&self, scripts: &[ScriptBuf]) -> Result<Vec<Vec<String>>> {
+        if scripts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let client = self.client();
+        let started = Instant::now();
+
+        self.rate_limit();
+        match client.batch_script_get_history(scripts.iter().map(|s| s.as_script())) {
+            Ok(histories) => {
+                debug!(
+                    "Electrs batch history: {} scripts in {}ms",
+                    scripts.len(),
+                    started.elapsed().as_millis()
+                );
+                return Ok(histories
+                    .into_iter()
+                    .map(|h| h.into_iter().map(|e| e.tx_hash.to_string()).collect())
+                    .collect());
+            }
+            // An I/O failure won't get better by sending more requests down the same socket
+            Err(e @ electrum_client::Error::IOError(_)) => return Err(e.into()),
+            Err(e) => warn!("Electrs batch history failed, falling back to sequential: {}", e),
+        }
+
+        let mut histories = Vec::with_capacity(scripts.len());
+        for script in scripts {
+            self.rate_limit();
+            let history = client.script_get_history(script)?;
+            histories.push(history.into_iter().map(|h| h.tx_hash.to_string()).collect());
+        }
+
+        debug!(
+            "Electrs sequential history: {} scripts in {}ms",
+            scripts.len(),
+            started.elapsed().as_millis()
+        );
+        Ok(histories)
+    }
+
+    /// Balances (confirmed, unconfirmed) of many scripts in one round trip.
+    ///
+    /// One JSON-RPC batch of `blockchain.scripthash.listunspent` under a
+    /// single gate permit; if the server rejects the batch, falls back to one
+    /// call per script. Results are in the same order as `scripts`. Unlike
+    /// `get_address_balance`, there is no history check first.
+    ///
+    /// Only cache misses are sent to Electrs. Scripts with unspent outputs
+    /// are cached from the answer; empty ones are not, since without the
+    /// history there is no telling a never-used script from an emptied one.
+    pub async fn get_balances_batch(&self, scripts: &[ScriptBuf]) -> Result<Vec<(u64, u64)>> {
+        let keys: Vec<Option<ScriptBuf>> = scripts.iter().cloned().map(Some).collect();
+        let cached = self.cached_balances(&keys);
+        let misses: Vec<ScriptBuf> = scripts
+            .iter()
+            .zip(&cached)
+            .filter(|(_, c)| c.is_none())
+            .map(|(script, _)| script.clone())
+            .collect();
+        if misses.is_empty() {
+            return Ok(cached.into_iter().flatten().collect());
+        }
+
+        let fetched = {
+            let misses = misses.clone();
+            self.call_blocking("batch balance", self.timeouts.balance, move |this| {
+                this.get_balances_batch_blocking(&misses)
+            })
+            .await?
+        };
+
+        let now = Instant::now();
+        let mut fetched = misses.into_iter().zip(fetched).map(|(script, unspent)| {
+            let (confirmed, unconfirmed) = unspent_balance(&unspent);
+            if !unspent.is_empty() {
+                let balance = CachedBalance {
+                    confirmed,
+                    unconfirmed,
+                    utxo_values: unspent.iter().map(|u| u.value).collect(),
+                    used: true,
+                    fetched_at: now,
+                };
+                self.balance_cache.insert(script, balance);
+            }
+            (confirmed, unconfirmed)
+        });
+
+        Ok(cached
+            .into_iter()
+            .map(|c| c.or_else(|| fetched.next()).unwrap_or_default())
+            .collect())
+    }
+
+    /// Fresh cached balances of `scripts` (None for misses and unknown
+    /// scripts), counted in the cache metrics
+    fn cached_balances(&self, scripts: &[Option<ScriptBuf>]) -> Vec<Option<(u64, u64)>> {
+        let m = &crate::metrics::metrics().electrs;
+        let now = Instant::now();
+        scripts
+            .iter()
+            .map(|script| {
+                let cached = script.as_ref().and_then(|s| self.balance_cache.get(s, now));
+                match cached {
+                    Some(c) => {
+                        m.cache_hits.inc();
+                        Some((c.confirmed, c.unconfirmed))
+                    }
+                    None => {
+                        m.cache_misses.inc();
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// BLOCKING batch listunspent (see `get_balances_batch`); same order as `scripts`
+    fn get_balances_batch_blocking(
+        &self,
+        scripts: &[ScriptBuf],
+    ) -> Result<Vec<Vec<electrum_client::ListUnspentRes>>> {
+        if scripts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let client = self.client();
+        let started = Instant::now();
+
+        self.rate_limit();
+        match client.batch_script_list_unspent(scripts.iter().map(|s| s.as_script())) {
+            Ok(unspent) => {
+                debug!(
+                    "Electrs batch balance: {} scripts in {}ms",
+                    scripts.len(),
+                    started.elapsed().as_millis()
+                );
+                return Ok(unspent);
+            }
+            // An I/O failure won't get better by sending more requests down the same socket
+            Err(e @ electrum_client::Error::IOError(_)) => return Err(e.into()),
+            Err(e) => warn!("Electrs batch balance failed, falling back to sequential: {}", e),
+        }
+
+        let mut balances = Vec::with_capacity(scripts.len());
+        for script in scripts {
+            self.rate_limit();
+            balances.push(client.script_list_unspent(script)?);
+        }
+
+        debug!(
+            "Electrs sequential balance: {} scripts in {}ms",
+            scripts.len(),
+            started.elapsed().as_millis()
+        );
+        Ok(balances)
+    }
+
+    /// Unspent outputs of `scripts` (`blockchain.scripthash.listunspent`),
+    /// in one batched round trip; same order as `scripts`.
+    ///
+    /// Confirmations are only set for mempool outputs; `Utxo::with_tip`
+    /// fills in the rest once the caller has the chain tip.
+    pub async fn list_unspent(&self, scripts: &[ScriptBuf]) -> Result<Vec<Utxo>> {
+        let scripts = scripts.to_vec();
+        self.call_blocking("list unspent", self.timeouts.balance, move |this| {
+            this.list_unspent_blocking(&scripts)
+        })
+        .await
+    }
+
+    /// BLOCKING UTXO lookup (see `list_unspent`), falling back to one
+    /// request per script like `get_balances_batch_blocking`
+    fn list_unspent_blocking(&self, scripts: &[ScriptBuf]) -> Result<Vec<Utxo>> {
+        if scripts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let client = self.client();
+        self.rate_limit();
+        let batch = client.batch_script_list_unspent(scripts.iter().map(|s| s.as_script()));
+        let unspent = match batch {
+            Ok(unspent) => unspent,
+            // An I/O failure won't get better by sending more requests down the same socket
+            Err(e @ electrum_client::Error::IOError(_)) => return Err(e.into()),
+            Err(e) => {
+                warn!("Electrs batch listunspent failed, falling back to sequential: {}", e);
+                let mut unspent = Vec::with_capacity(scripts.len());
+                for script in scripts {
+                    self.rate_limit();
+                    unspent.push(client.script_list_unspent(script)?);
+                }
+                unspent
+            }
+        };
+
+        let mut utxos = Vec::new();
+        for (script, unspent) in scripts.iter().zip(unspent) {
+            utxos.extend(self.to_utxos(script, unspent)?);
+        }
+        Ok(utxos)
+    }
+
+    /// Electrum's listunspent entries of `script` as `Utxo`s
+    fn to_utxos(
+        &self,
+        script: &Script,
+        unspent: Vec<electrum_client::ListUnspentRes>,
+    ) -> Result<Vec<Utxo>> {
+        let address = Address::from_script(script, self.network)?.to_string();
+        unspent
+            .into_iter()
+            .map(|u| -> Result<Utxo> {
+                Ok(Utxo::new(
+                    u.tx_hash.to_string(),
+                    u32::try_from(u.tx_pos)?,
+                    u.value,
+                    // Convention: height == 0 => mempool/unconfirmed
+                    u32::try_from(u.height)?,
+                    address.clone(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Mempool fee histogram (`mempool.get_fee_histogram`), cumulative:
+    /// bucket N says how many vbytes pay at least bucket N's fee rate.
+    /// Sorted from highest to lowest fee rate.
+    pub async fn get_fee_histogram(&self) -> Result<Vec<FeeHistogramBucket>> {
+        self.call_blocking("fee histogram", self.timeouts.history, |this| {
+            this.get_fee_histogram_blocking()
+        })
+        .await
+    }
+
+    /// Raw transaction by txid (`blockchain.transaction.get`); None if
+    /// Electrs doesn't know it
+    pub async fn get_transaction(&self, txid: Txid) -> Result<Option<Transaction>> {
+        self.call_blocking("transaction", self.timeouts.history, move |this| {
+            this.get_transaction_blocking(&txid)
+        })
+        .await
+    }
+
+    /// Several raw transactions in one round trip; fails if any is unknown.
+    /// Same order as `txids`.
+    pub async fn get_transactions(&self, txids: &[Txid]) -> Result<Vec<Transaction>> {
+        let txids = txids.to_vec();
+        self.call_blocking("transactions", self.timeouts.history, move |this| {
+            this.get_transactions_blocking(&txids)
+        })
+        .await
+    }
+
+    /// BLOCKING transaction lookup (see `get_transaction`)
+    fn get_transaction_blocking(&self, txid: &Txid) -> Result<Option<Transaction>> {
+        self.rate_limit();
+
+        match self.client().transaction_get(txid) {
+            Ok(tx) => Ok(Some(tx)),
+            // Electrs answers an unknown txid with a JSON-RPC error
+            Err(electrum_client::Error::Protocol(e)) => {
+                debug!("Electrs has no transaction {}: {}", txid, e);
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// BLOCKING batch transaction lookup (see `get_transactions`)
+    fn get_transactions_blocking(&self, txids: &[Txid]) -> Result<Vec<Transaction>> {
+        if txids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.rate_limit();
+        Ok(self.client().batch_transaction_get(txids)?)
+    }
+
+    /// Fee rate (BTC/kB) to confirm within `blocks` blocks
+    /// (`blockchain.estimatefee`); -1 when the server can't estimate
+    pub async fn estimate_fee(&self, blocks: usize) -> Result<f64> {
+        self.call_blocking("fee estimate", self.timeouts.history, move |this| {
+            this.rate_limit();
+            Ok(this.client().estimate_fee(blocks)?)
+        })
+        .await
+    }
+
+    /// Current chain tip (`blockchain.headers.subscribe`), through the same
+    /// gate/cooldown/timeout machinery as the other calls
+    pub async fn get_tip_height(&self) -> Result<ChainTip> {
+        self.call_blocking("tip", self.timeouts.history, |this| this.get_tip_blocking()).await
+    }
+
+    /// BLOCKING chain tip lookup
+    fn get_tip_blocking(&self) -> Result<ChainTip> {
+        self.rate_limit();
+
+        let notification = self.client().block_headers_subscribe()?;
+        let height = u32::try_from(notification.height).map_err(|_| {
+            anyhow!("Electrs reported an impossible tip height {}", notification.height)
+        })?;
+
+        Ok(ChainTip {
+            height,
+            header_hash: notification.header.block_hash().to_string(),
+        })
+    }
+
+    /// BLOCKING fee histogram lookup
+    fn get_fee_histogram_blocking(&self) -> Result<Vec<FeeHistogramBucket>> {
+        self.rate_limit();
+
+        let raw = self
+            .client()
+            .raw_call("mempool.get_fee_histogram", Vec::<Param>::new())?;
+
+        let entries = raw
+            .as_array()
+            .ok_or_else(|| anyhow!("unexpected fee histogram response: {}", raw))?;
+
+        // Electrum returns [[fee_rate, vsize], …] per bucket, highest fee first
+        let mut buckets = Vec::with_capacity(entries.len());
+        let mut cumulative: u64 = 0;
+        for entry in entries {
+            let fee_rate = entry.get(0).and_then(|v| v.as_f64());
+            let vsize = entry.get(1).and_then(|v| v.as_u64());
+            let (Some(fee_rate), Some(vsize)) = (fee_rate, vsize) else {
+                return Err(anyhow!("malformed fee histogram entry: {}", entry));
+            };
+
+            cumulative = cumulative.saturating_add(vsize);
+            buckets.push(FeeHistogramBucket {
+                fee_rate_sat_vbyte: fee_rate,
+                mempool_size_bytes: cumulative,
+            });
+        }
+
+        Ok(buckets)
+    }
+
+    /// Run one blocking Electrs call through the shared machinery:
+    /// - global in-flight gate
+    /// - cooldown after timeout
+    /// - `call_timeout` per attempt
+    /// - after an I/O error (dropped connection), rebuild the connection and
+    ///   retry once; concurrent callers that hit the same dead connection
+    ///   rebuild it only once
+    async fn call_blocking<T, F>(&self, what: &'static str, call_timeout: Duration, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: Fn(&ElectrsClient) -> Result<T> + Send + Sync + 'static,
+    {
         use tokio::task::spawn_blocking;
-        use tokio::time::{timeout, Duration};
+        use tokio::time::timeout;
 
         self.check_cooldown()?;
         let _permit = self.gate.acquire().await.unwrap();
         self.check_cooldown()?;
 
-        let addr = address.to_string();
-        let this = self.clone();
+        let f = Arc::new(f);
+        let mut reconnected = false;
 
-        let res = timeout(
-            Duration::from_secs(45),
-            spawn_blocking(move || this.get_address_txs_blocking(&addr)),
-        )
-        .await;
+        loop {
+            let this = self.clone();
+            let call = Arc::clone(&f);
+            let generation = self.connection_generation();
 
-        match res {
-            Ok(Ok(Ok(v))) => Ok(v),
-            Ok(Ok(Err(e))) => Err(anyhow!("Electrs tx error: {}", e)),
-            Ok(Err(e)) => Err(anyhow!("Electrs join error: {}", e)),
-            Err(_) => {
-                warn!("Electrs history timed out; setting cooldown");
-                self.set_cooldown(10);
-                Err(anyhow!("Electrs history timeout"))
+            let res = timeout(call_timeout, spawn_blocking(move || call(&this))).await;
+
+            match res {
+                Ok(Ok(Ok(v))) => {
+                    self.mark_connected();
+                    return Ok(v);
+                }
+                Ok(Ok(Err(e))) => {
+                    let kind = classify_error(&e);
+                    if kind == FailureKind::Io && !reconnected {
+                        warn!("Electrs {} I/O error: {} — reconnecting and retrying once", what, e);
+                        reconnected = true;
+
+                        let this = self.clone();
+                        match spawn_blocking(move || this.reconnect_after_failure(generation)).await {
+                            Ok(Ok(())) => continue,
+                            Ok(Err(re)) => warn!("Electrs reconnect failed: {}", re),
+                            Err(re) => warn!("Electrs reconnect join error: {}", re),
+                        }
+                    }
+                    if kind != FailureKind::Invalid {
+                        self.mark_error(&e);
+                    }
+                    return Err(anyhow!("Electrs {} error: {}", what, e));
+                }
+                Ok(Err(e)) => return Err(anyhow!("Electrs join error: {}", e)),
+                Err(_) => {
+                    warn!("Electrs {} timed out; setting cooldown", what);
+                    self.set_cooldown(self.timeouts.cooldown);
+                    return Err(anyhow!("Electrs {} timeout", what));
+                }
             }
         }
     }
 }
 
+/// Fee rate at which roughly `vbytes` of mempool would be mined first
+///
+/// Walks the cumulative histogram until it covers `vbytes`; if the whole
+/// mempool is smaller than that, anything above the floor gets in.
+pub fn fee_rate_for_depth(buckets: &[FeeHistogramBucket], vbytes: u64) -> f64 {
+    buckets
+        .iter()
+        .find(|b| b.mempool_size_bytes >= vbytes)
+        .map(|b| b.fee_rate_sat_vbyte)
+        .unwrap_or(MIN_RELAY_FEE_SAT_VBYTE)
+        .max(MIN_RELAY_FEE_SAT_VBYTE)
+}
+
+/// Convert an Electrum `blockchain.estimatefee` answer (BTC/kB) to sat/vB.
+/// None when the server has too little data to estimate (it answers -1).
+pub fn btc_per_kb_to_sat_per_vb(btc_per_kb: f64) -> Option<f64> {
+    // 1e8 sat per BTC, 1000 vbytes per kB
+    (btc_per_kb.is_finite() && btc_per_kb > 0.0).then(|| btc_per_kb * 100_000.0)
+}
+
+/// Bitcoin Core's dust limit: an output is dust when spending it costs more
+/// than a third of its value, i.e. below 3 × (output + spending input size) × fee rate
+pub struct DustThreshold;
+
+impl DustThreshold {
+    /// 546 sat for P2PKH, 540 for P2SH, 294 for P2WPKH and 330 for P2TR at 1 sat/vB
+    pub fn for_script_type(script_type: AddressType, fee_rate_sat_vbyte: f64) -> u64 {
+        // (output size, input size) in vbytes; witness inputs count 1/4 for signatures
+        let (output, input) = match script_type {
+            AddressType::P2PKH => (34, 148),
+            AddressType::P2shSegwit => (32, 148),
+            AddressType::NativeSegwit => (31, 67),
+            AddressType::Taproot => (43, 67),
+        };
+        (3.0 * f64::from(output + input) * fee_rate_sat_vbyte.max(MIN_RELAY_FEE_SAT_VBYTE)) as u64
+    }
+
+    /// (number of dust UTXOs, their total value)
+    pub fn count(utxo_values: &[u64], threshold: u64) -> (u32, u64) {
+        utxo_values
+            .iter()
+            .filter(|v| **v < threshold)
+            .fold((0, 0), |(n, total), v| (n + 1, total.saturating_add(*v)))
+    }
+}
+
+/// Script type of an address for dust purposes; P2WSH and other
+/// non-standard types get the legacy (highest) threshold
+pub fn dust_script_type(address: &str) -> AddressType {
+    use electrum_client::bitcoin::AddressType as Kind;
+
+    match Address::from_str(address).map(|a| a.assume_checked().address_type()) {
+        Ok(Some(Kind::P2sh)) => AddressType::P2shSegwit,
+        Ok(Some(Kind::P2wpkh)) => AddressType::NativeSegwit,
+        Ok(Some(Kind::P2tr)) => AddressType::Taproot,
+        _ => AddressType::P2PKH,
+    }
+}
+
+/// The Electrs operations `NostrHandler` needs, so it can run against
+/// `mock::MockElectrsClient` (feature "testing") instead of a live server
+#[async_trait::async_trait]
+pub trait ElectrsClientTrait: Send + Sync {
+    /// Chain the server indexes; derived and submitted addresses must match it
+    fn network(&self) -> Network;
+
+    async fn get_address_balance(
+        &self,
+        address: &str,
+        strategy: RetryStrategy,
+    ) -> Result<ElectrsQueryResult>;
+
+    async fn get_address_txs(&self, address: &str) -> Result<Vec<String>>;
+
+    async fn get_address_history(&self, address: &str) -> Result<Vec<TxHistoryEntry>>;
+
+    async fn has_transactions(&self, address: &str) -> Result<bool>;
+
+    async fn batch_get_histories(&self, scripts: &[ScriptBuf]) -> Result<Vec<Vec<String>>>;
+
+    async fn get_balances_batch(&self, scripts: &[ScriptBuf]) -> Result<Vec<(u64, u64)>>;
+
+    async fn list_unspent(&self, scripts: &[ScriptBuf]) -> Result<Vec<Utxo>>;
+
+    async fn get_balances_bounded(
+        &self,
+        addresses: Vec<String>,
+        concurrency: usize,
+    ) -> Result<Vec<(u64, u64)>>;
+
+    async fn get_balances_parallel(&self, addresses: Vec<String>) -> Result<Vec<(u64, u64)>> {
+        self.get_balances_bounded(addresses, PARALLEL_CALLS).await
+    }
+
+    async fn scan_xpub_parallel(&self, xpub: &str, gap_limit: u32) -> Result<XpubScanResult>;
+
+    async fn get_fee_histogram(&self) -> Result<Vec<FeeHistogramBucket>>;
+
+    async fn get_tip_height(&self) -> Result<ChainTip>;
+
+    async fn estimate_fee(&self, blocks: usize) -> Result<f64>;
+
+    async fn get_transaction(&self, txid: Txid) -> Result<Option<Transaction>>;
+
+    async fn get_transactions(&self, txids: &[Txid]) -> Result<Vec<Transaction>>;
+}
+
+/// `scan_xpub_parallel` against any client: the addresses come from the
+/// gap scan, their balances from `get_balances_bounded`
+pub async fn scan_xpub_bounded(
+    electrs: &dyn ElectrsClientTrait,
+    xpub: &str,
+    gap_limit: u32,
+    concurrency: usize,
+) -> Result<XpubScanResult> {
+    let address_type = crate::xpub::prefix_address_type(xpub)?;
+    let sets =
+        crate::xpub::derive_addresses_until_gap(xpub, address_type, gap_limit, electrs).await?;
+    let external_count = sets.external.len();
+
+    let mut addresses = sets.external;
+    addresses.extend(sets.internal);
+    let balances = electrs.get_balances_bounded(addresses.clone(), concurrency).await?;
+
+    let mut result = XpubScanResult::default();
+    for (i, (address, (confirmed, unconfirmed))) in addresses.into_iter().zip(balances).enumerate() {
+        result.confirmed = result.confirmed.saturating_add(confirmed);
+        result.unconfirmed = result.unconfirmed.saturating_add(unconfirmed);
+        if i < external_count {
+            result.external_confirmed = result.external_confirmed.saturating_add(confirmed);
+        } else {
+            result.internal_confirmed = result.internal_confirmed.saturating_add(confirmed);
+        }
+        if confirmed > 0 || unconfirmed > 0 {
+            result.funded.push(ScannedAddress {
+                address,
+                confirmed,
+                unconfirmed,
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+#[async_trait::async_trait]
+impl ElectrsClientTrait for ElectrsClient {
+    fn network(&self) -> Network {
+        ElectrsClient::network(self)
+    }
+
+    async fn get_address_balance(
+        &self,
+        address: &str,
+        strategy: RetryStrategy,
+    ) -> Result<ElectrsQueryResult> {
+        ElectrsClient::get_address_balance(self, address, strategy).await
+    }
+
+    async fn get_address_txs(&self, address: &str) -> Result<Vec<String>> {
+        ElectrsClient::get_address_txs(self, address).await
+    }
+
+    async fn get_address_history(&self, address: &str) -> Result<Vec<TxHistoryEntry>> {
+        ElectrsClient::get_address_history(self, address).await
+    }
+
+    async fn has_transactions(&self, address: &str) -> Result<bool> {
+        ElectrsClient::has_transactions(self, address).await
+    }
+
+    async fn batch_get_histories(&self, scripts: &[ScriptBuf]) -> Result<Vec<Vec<String>>> {
+        ElectrsClient::batch_get_histories(self, scripts).await
+    }
+
+    async fn get_balances_batch(&self, scripts: &[ScriptBuf]) -> Result<Vec<(u64, u64)>> {
+        ElectrsClient::get_balances_batch(self, scripts).await
+    }
+
+    async fn list_unspent(&self, scripts: &[ScriptBuf]) -> Result<Vec<Utxo>> {
+        ElectrsClient::list_unspent(self, scripts).await
+    }
+
+    async fn get_balances_bounded(
+        &self,
+        addresses: Vec<String>,
+        concurrency: usize,
+    ) -> Result<Vec<(u64, u64)>> {
+        ElectrsClient::get_balances_bounded(self, addresses, concurrency).await
+    }
+
+    async fn scan_xpub_parallel(&self, xpub: &str, gap_limit: u32) -> Result<XpubScanResult> {
+        ElectrsClient::scan_xpub_parallel(self, xpub, gap_limit).await
+    }
+
+    async fn get_fee_histogram(&self) -> Result<Vec<FeeHistogramBucket>> {
+        ElectrsClient::get_fee_histogram(self).await
+    }
+
+    async fn get_tip_height(&self) -> Result<ChainTip> {
+        ElectrsClient::get_tip_height(self).await
+    }
+
+    async fn estimate_fee(&self, blocks: usize) -> Result<f64> {
+        ElectrsClient::estimate_fee(self, blocks).await
+    }
+
+    async fn get_transaction(&self, txid: Txid) -> Result<Option<Transaction>> {
+        ElectrsClient::get_transaction(self, txid).await
+    }
+
+    async fn get_transactions(&self, txids: &[Txid]) -> Result<Vec<Transaction>> {
+        ElectrsClient::get_transactions(self, txids).await
+    }
+}
+
+/// script_pubkey for an address we derived ourselves (network already known to match)
+pub fn address_script(address: &str) -> Result<ScriptBuf> {
+    Ok(Address::from_str(address)?.assume_checked().script_pubkey())
+}
+
+/// Compare a dotted protocol version ("1.4.2") against `(major, minor)`
+fn protocol_at_least(version: &str, min: (u32, u32)) -> bool {
+    let mut parts = version.split('.').map(|p| p.parse::<u32>().unwrap_or(0));
+    let major = parts.next().unwrap_or(0);
+    let minor = parts.next().unwrap_or(0);
+    (major, minor) >= min
+}
+
+const ELECTRS_ADDR_FORMATS: &str = "expected host:port, tcp://host:port or ssl://host:port";
+
+/// ELECTRS_ADDR split into scheme, host and port
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElectrsEndpoint {
+    /// Host name or IP (IPv6 keeps its brackets)
+    pub host: String,
+    pub port: u16,
+    /// ssl:// rather than plaintext
+    pub tls: bool,
+}
+
+impl ElectrsEndpoint {
+    /// Parse `host:port` (plaintext), `tcp://host:port` or `ssl://host:port`
+    pub fn parse(addr: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid ELECTRS_ADDR '{}': {}", addr, ELECTRS_ADDR_FORMATS);
+
+        let (tls, rest) = match addr.trim().split_once("://") {
+            Some(("tcp", rest)) => (false, rest),
+            Some(("ssl", rest)) => (true, rest),
+            Some(_) => return Err(invalid()),
+            None => (false, addr.trim()),
+        };
+
+        let (host, port) = rest.rsplit_once(':').ok_or_else(invalid)?;
+        let port = port.parse::<u16>().map_err(|_| invalid())?;
+        if host.is_empty() || host.contains('/') {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            tls,
+        })
+    }
+
+    /// `host:port`, for the raw TCP preflight
+    pub fn host_port(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// URL in the form electrum_client expects
+    pub fn url(&self) -> String {
+        let scheme = if self.tls { "ssl" } else { "tcp" };
+        format!("{}://{}", scheme, self.host_port())
+    }
+}
+
+/// Open an Electrum connection. BLOCKING (TCP connect + TLS handshake).
+fn open_client(endpoint: &ElectrsEndpoint, tls_insecure: bool) -> Result<Client> {
+    let config = electrum_client::ConfigBuilder::new()
+        .validate_domain(!tls_insecure)
+        .build();
+    Ok(Client::from_config(&endpoint.url(), config)?)
+}
+
 fn preflight_tcp(addr: &str) -> Result<()> {
     let mut addrs = addr
         .to_socket_addrs()
-        .map_err(|e| anyhow!("Invalid ELECTRS_ADDR '{}': {}", addr, e))?;
+        .map_err(|e| anyhow!("Invalid ELECTRS_ADDR '{}' ({}): {}", addr, ELECTRS_ADDR_FORMATS, e))?;
 
     let sock = addrs
         .next()
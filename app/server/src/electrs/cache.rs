@@ -0,0 +1,182 @@
+//! Short-lived balance cache in front of Electrs
+//!
+//! A phone polling the same xpub every minute would otherwise queue every
+//! address behind the single-flight gate again. Entries are keyed by
+//! script_pubkey, expire after a TTL and are evicted least-recently-used
+//! once the cache is full, so scanning large xpubs can't grow it unbounded.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use electrum_client::bitcoin::ScriptBuf;
+
+use super::ElectrsQueryResult;
+
+/// How long a balance is served from cache (ELECTRS_CACHE_TTL_SECS overrides)
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Scripts kept at most; a few large xpubs' worth of addresses
+pub const DEFAULT_CACHE_CAPACITY: usize = 5_000;
+
+/// Balance of one script as Electrs last reported it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedBalance {
+    pub confirmed: u64,
+    pub unconfirmed: u64,
+    pub utxo_values: Vec<u64>,
+    /// false for never-used scripts (ElectrsQueryResult::NotFound)
+    pub used: bool,
+    pub fetched_at: Instant,
+}
+
+impl CachedBalance {
+    /// Cacheable form of an Electrs answer; error answers are not cached
+    pub fn from_result(result: &ElectrsQueryResult, fetched_at: Instant) -> Option<Self> {
+        match result {
+            ElectrsQueryResult::Found {
+                confirmed,
+                unconfirmed,
+                utxo_values,
+            } => Some(Self {
+                confirmed: *confirmed,
+                unconfirmed: *unconfirmed,
+                utxo_values: utxo_values.clone(),
+                used: true,
+                fetched_at,
+            }),
+            ElectrsQueryResult::NotFound => Some(Self {
+                confirmed: 0,
+                unconfirmed: 0,
+                utxo_values: Vec::new(),
+                used: false,
+                fetched_at,
+            }),
+            ElectrsQueryResult::Error(_) => None,
+        }
+    }
+
+    fn is_fresh(&self, now: Instant, ttl: Duration) -> bool {
+        now.saturating_duration_since(self.fetched_at) < ttl
+    }
+}
+
+impl From<CachedBalance> for ElectrsQueryResult {
+    fn from(balance: CachedBalance) -> Self {
+        if !balance.used {
+            return ElectrsQueryResult::NotFound;
+        }
+        ElectrsQueryResult::Found {
+            confirmed: balance.confirmed,
+            unconfirmed: balance.unconfirmed,
+            utxo_values: balance.utxo_values,
+        }
+    }
+}
+
+pub struct BalanceCache {
+    ttl: Duration,
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// script -> (balance, last use)
+    entries: HashMap<ScriptBuf, (CachedBalance, u64)>,
+    /// last use -> script, oldest first
+    recency: BTreeMap<u64, ScriptBuf>,
+    clock: u64,
+}
+
+impl Inner {
+    fn touch(&mut self, script: &ScriptBuf) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some((_, used)) = self.entries.get_mut(script) {
+            self.recency.remove(used);
+            *used = clock;
+            self.recency.insert(clock, script.clone());
+        }
+    }
+
+    fn remove(&mut self, script: &ScriptBuf) {
+        if let Some((_, used)) = self.entries.remove(script) {
+            self.recency.remove(&used);
+        }
+    }
+}
+
+impl BalanceCache {
+    /// A zero `ttl` or `capacity` disables the cache
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn enabled(&self) -> bool {
+        !self.ttl.is_zero() && self.capacity > 0
+    }
+
+    /// Cached balance of `script` if it was fetched less than the TTL before `now`
+    pub fn get(&self, script: &ScriptBuf, now: Instant) -> Option<CachedBalance> {
+        if !self.enabled() {
+            return None;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let balance = match inner.entries.get(script) {
+            Some((balance, _)) if balance.is_fresh(now, self.ttl) => balance.clone(),
+            Some(_) => {
+                inner.remove(script);
+                return None;
+            }
+            None => return None,
+        };
+        inner.touch(script);
+        Some(balance)
+    }
+
+    /// Store a fresh balance, evicting the least recently used entry when full
+    pub fn insert(&self, script: ScriptBuf, balance: CachedBalance) {
+        if !self.enabled() {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(&script);
+
+        while inner.entries.len() >= self.capacity {
+            let Some((_, oldest)) = inner.recency.pop_first() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+        }
+
+        inner.clock += 1;
+        let clock = inner.clock;
+        inner.recency.insert(clock, script.clone());
+        inner.entries.insert(script, (balance, clock));
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for BalanceCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_TTL, DEFAULT_CACHE_CAPACITY)
+    }
+}
@@ -0,0 +1,200 @@
+//! In-memory Electrs stand-in (feature "testing")
+//!
+//! Answers from fixed maps instead of the network, so `NostrHandler` can be
+//! exercised without a running Electrs.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use electrum_client::bitcoin::{Network, ScriptBuf, Transaction, Txid};
+
+use super::{
+    address_script, ChainTip, ElectrsClientTrait, ElectrsQueryResult, FeeHistogramBucket,
+    RetryStrategy, TxHistoryEntry, XpubScanResult,
+};
+use crate::protocol::Utxo;
+
+/// Tip reported unless `with_tip` says otherwise (block 840,000)
+const DEFAULT_TIP_HEIGHT: u32 = 840_000;
+const DEFAULT_TIP_HASH: &str = "0000000000000000000320283a032748cef8227873ff4872689bf23f1cda83a5";
+
+pub struct MockElectrsClient {
+    /// address -> (confirmed, unconfirmed)
+    balances: HashMap<String, (u64, u64)>,
+    /// address -> txids
+    txs: HashMap<String, Vec<String>>,
+    /// script_pubkey -> address, for the script-based calls
+    scripts: HashMap<ScriptBuf, String>,
+    tip: ChainTip,
+    transactions: HashMap<Txid, Transaction>,
+    network: Network,
+}
+
+impl MockElectrsClient {
+    /// Addresses missing from both maps behave like never-used addresses
+    pub fn new(balances: HashMap<String, (u64, u64)>, txs: HashMap<String, Vec<String>>) -> Self {
+        let scripts = balances
+            .keys()
+            .chain(txs.keys())
+            .filter_map(|address| Some((address_script(address).ok()?, address.clone())))
+            .collect();
+
+        Self {
+            balances,
+            txs,
+            scripts,
+            tip: ChainTip {
+                height: DEFAULT_TIP_HEIGHT,
+                header_hash: DEFAULT_TIP_HASH.to_string(),
+            },
+            transactions: HashMap::new(),
+            network: Network::Bitcoin,
+        }
+    }
+
+    /// Raw transactions served by `get_transaction(s)`
+    pub fn with_transactions(mut self, transactions: Vec<Transaction>) -> Self {
+        self.transactions
+            .extend(transactions.into_iter().map(|tx| (tx.compute_txid(), tx)));
+        self
+    }
+
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn with_tip(mut self, tip: ChainTip) -> Self {
+        self.tip = tip;
+        self
+    }
+
+    fn balance(&self, address: &str) -> (u64, u64) {
+        self.balances.get(address).copied().unwrap_or((0, 0))
+    }
+
+    fn txs(&self, address: &str) -> Vec<String> {
+        self.txs.get(address).cloned().unwrap_or_default()
+    }
+}
+
+#[async_trait::async_trait]
+impl ElectrsClientTrait for MockElectrsClient {
+    fn network(&self) -> Network {
+        self.network
+    }
+
+    async fn get_address_balance(
+        &self,
+        address: &str,
+        _strategy: RetryStrategy,
+    ) -> Result<ElectrsQueryResult> {
+        Ok(match self.balances.get(address) {
+            Some(&(confirmed, unconfirmed)) => ElectrsQueryResult::Found {
+                confirmed,
+                unconfirmed,
+                // One UTXO per non-zero amount
+                utxo_values: [confirmed, unconfirmed].into_iter().filter(|v| *v > 0).collect(),
+            },
+            None => ElectrsQueryResult::NotFound,
+        })
+    }
+
+    async fn get_address_txs(&self, address: &str) -> Result<Vec<String>> {
+        Ok(self.txs(address))
+    }
+
+    /// Every transaction is reported as still in the mempool
+    async fn get_address_history(&self, address: &str) -> Result<Vec<TxHistoryEntry>> {
+        Ok(self
+            .txs(address)
+            .into_iter()
+            .map(|txid| TxHistoryEntry { txid, height: 0 })
+            .collect())
+    }
+
+    async fn has_transactions(&self, address: &str) -> Result<bool> {
+        Ok(!self.txs(address).is_empty())
+    }
+
+    async fn batch_get_histories(&self, scripts: &[ScriptBuf]) -> Result<Vec<Vec<String>>> {
+        Ok(scripts
+            .iter()
+            .map(|script| match self.scripts.get(script) {
+                Some(address) => self.txs(address),
+                None => Vec::new(),
+            })
+            .collect())
+    }
+
+    async fn get_balances_batch(&self, scripts: &[ScriptBuf]) -> Result<Vec<(u64, u64)>> {
+        Ok(scripts
+            .iter()
+            .map(|script| match self.scripts.get(script) {
+                Some(address) => self.balance(address),
+                None => (0, 0),
+            })
+            .collect())
+    }
+
+    /// One UTXO per non-zero amount, paid by the address's first txid: the
+    /// confirmed one mined at the tip, the unconfirmed one in the mempool
+    async fn list_unspent(&self, scripts: &[ScriptBuf]) -> Result<Vec<Utxo>> {
+        let mut utxos = Vec::new();
+        for address in scripts.iter().filter_map(|script| self.scripts.get(script)) {
+            let (confirmed, unconfirmed) = self.balance(address);
+            let txid = self.txs(address).into_iter().next().unwrap_or_else(|| "0".repeat(64));
+
+            utxos.extend(
+                [(0, confirmed, self.tip.height), (1, unconfirmed, 0)]
+                    .into_iter()
+                    .filter(|(_, value, _)| *value > 0)
+                    .map(|(vout, value, height)| {
+                        Utxo::new(txid.clone(), vout, value, height, address.clone())
+                    }),
+            );
+        }
+        Ok(utxos)
+    }
+
+    async fn get_balances_bounded(
+        &self,
+        addresses: Vec<String>,
+        _concurrency: usize,
+    ) -> Result<Vec<(u64, u64)>> {
+        Ok(addresses.iter().map(|a| self.balance(a)).collect())
+    }
+
+    async fn scan_xpub_parallel(&self, xpub: &str, gap_limit: u32) -> Result<XpubScanResult> {
+        super::scan_xpub_bounded(self, xpub, gap_limit, super::PARALLEL_CALLS).await
+    }
+
+    async fn get_fee_histogram(&self) -> Result<Vec<FeeHistogramBucket>> {
+        Ok(Vec::new())
+    }
+
+    async fn get_tip_height(&self) -> Result<ChainTip> {
+        Ok(self.tip.clone())
+    }
+
+    /// Always "not enough data", like a freshly started Electrs
+    async fn estimate_fee(&self, _blocks: usize) -> Result<f64> {
+        Ok(-1.0)
+    }
+
+    async fn get_transaction(&self, txid: Txid) -> Result<Option<Transaction>> {
+        Ok(self.transactions.get(&txid).cloned())
+    }
+
+    async fn get_transactions(&self, txids: &[Txid]) -> Result<Vec<Transaction>> {
+        txids
+            .iter()
+            .map(|txid| {
+                self.transactions
+                    .get(txid)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("unknown transaction {}", txid))
+            })
+            .collect()
+    }
+}
@@ -1,5 +1,6 @@
 //! Error types for the BalanceBridge server
 
+use serde::{Serialize, Serializer};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -19,3 +20,71 @@ pub enum ServerError {
 
 pub type ServerResult<T> = Result<T, ServerError>;
 
+
+/// Stable `code` of an error response, one per `protocol::LookupError` kind.
+///
+/// The strings are part of the wire protocol: the Android app branches on
+/// them, so a variant may be added but never renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LookupErrorCode {
+    /// Sender is not a paired device
+    NotPaired,
+    RateLimited,
+    /// Request content is not valid JSON for a request
+    InvalidJson,
+    /// Unknown request `type`
+    InvalidType,
+    /// `query` is missing or blank
+    EmptyQuery,
+    /// `query` is not an address, key or descriptor
+    InvalidQuery,
+    InvalidParams,
+    InvalidAddress,
+    InvalidXpub,
+    WrongNetwork,
+    UnsupportedVersion,
+    TxNotFound,
+    FeeUnavailable,
+    /// list_utxos found more unspent outputs than LIST_UTXOS_MAX
+    TooManyUtxos,
+    /// watch_xpub from a device already watching MAX_WATCHED_XPUBS_PER_DEVICE
+    WatchLimitReached,
+    /// Electrs failed or is unreachable
+    ElectrsUnavailable,
+    ElectrsCoolingDown,
+    ElectrsTimeout,
+    InternalError,
+}
+
+impl LookupErrorCode {
+    /// The string sent as `code`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LookupErrorCode::NotPaired => "not_paired",
+            LookupErrorCode::RateLimited => "rate_limited",
+            LookupErrorCode::InvalidJson => "invalid_json",
+            LookupErrorCode::InvalidType => "invalid_type",
+            LookupErrorCode::EmptyQuery => "empty_query",
+            LookupErrorCode::InvalidQuery => "invalid_query",
+            LookupErrorCode::InvalidParams => "invalid_params",
+            LookupErrorCode::InvalidAddress => "invalid_address",
+            LookupErrorCode::InvalidXpub => "invalid_xpub",
+            LookupErrorCode::WrongNetwork => "wrong_network",
+            LookupErrorCode::UnsupportedVersion => "unsupported_version",
+            LookupErrorCode::TxNotFound => "tx_not_found",
+            LookupErrorCode::FeeUnavailable => "fee_unavailable",
+            LookupErrorCode::TooManyUtxos => "too_many_utxos",
+            LookupErrorCode::WatchLimitReached => "watch_limit_reached",
+            LookupErrorCode::ElectrsUnavailable => "electrs_unavailable",
+            LookupErrorCode::ElectrsCoolingDown => "electrs_cooling_down",
+            LookupErrorCode::ElectrsTimeout => "electrs_timeout",
+            LookupErrorCode::InternalError => "internal_error",
+        }
+    }
+}
+
+impl Serialize for LookupErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
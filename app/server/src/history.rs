@@ -0,0 +1,96 @@
+//! Balance history
+//!
+//! Records a balance snapshot every time an address is looked up, so the
+//! Android app can chart balance over time.
+
+use anyhow::{Context, Result};
+use blake2::{Blake2s256, Digest};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const HISTORY_DIRNAME: &str = "balance_history";
+
+/// Snapshots kept per address (oldest dropped first)
+const MAX_SNAPSHOTS_PER_ADDRESS: usize = 1000;
+
+/// Snapshots returned when the request doesn't say
+pub const DEFAULT_HISTORY_LIMIT: usize = 30;
+
+/// Upper bound on snapshots returned per request
+pub const MAX_HISTORY_LIMIT: usize = 90;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceSnapshot {
+    pub timestamp_utc: String,
+    pub confirmed_sat: u64,
+    pub unconfirmed_sat: u64,
+    /// Chain tip when the snapshot was taken; unset if it couldn't be fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u32>,
+}
+
+/// Per-address snapshot files under `data_dir/balance_history/`
+#[derive(Clone)]
+pub struct BalanceHistoryStore {
+    dir: PathBuf,
+}
+
+impl BalanceHistoryStore {
+    pub fn new(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = data_dir.as_ref().join(HISTORY_DIRNAME);
+        fs::create_dir_all(&dir)
+            .context("Failed to create balance history directory")?;
+
+        Ok(Self { dir })
+    }
+
+    /// Append a snapshot for `address`
+    pub fn record(&self, address: &str, snapshot: BalanceSnapshot) -> Result<()> {
+        let mut snapshots = self.load(address)?;
+        snapshots.push(snapshot);
+
+        if snapshots.len() > MAX_SNAPSHOTS_PER_ADDRESS {
+            let excess = snapshots.len() - MAX_SNAPSHOTS_PER_ADDRESS;
+            snapshots.drain(..excess);
+        }
+
+        let json = serde_json::to_string(&snapshots)
+            .context("Failed to serialize balance history")?;
+
+        let path = self.path_for(address);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .context("Failed to write balance history")?;
+        fs::rename(&tmp_path, &path)
+            .context("Failed to replace balance history")?;
+
+        Ok(())
+    }
+
+    /// Most recent `limit` snapshots (capped at MAX_HISTORY_LIMIT), oldest first
+    pub fn get_history(&self, address: &str, limit: usize) -> Result<Vec<BalanceSnapshot>> {
+        let limit = limit.min(MAX_HISTORY_LIMIT);
+        let snapshots = self.load(address)?;
+        let skip = snapshots.len().saturating_sub(limit);
+        Ok(snapshots.into_iter().skip(skip).collect())
+    }
+
+    fn load(&self, address: &str) -> Result<Vec<BalanceSnapshot>> {
+        let path = self.path_for(address);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&path)
+            .context("Failed to read balance history")?;
+        serde_json::from_str(&content)
+            .context("Invalid balance history file format")
+    }
+
+    /// Hash the address so arbitrary input can't escape the directory
+    fn path_for(&self, address: &str) -> PathBuf {
+        let digest = Blake2s256::digest(address.trim().as_bytes());
+        self.dir.join(format!("{}.json", hex::encode(digest)))
+    }
+}
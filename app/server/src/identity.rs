@@ -2,51 +2,214 @@
 //!
 //! Handles generation and persistence of Nostr keypairs for the Umbrel node.
 
-use nostr_sdk::{Keys, SecretKey};
+use anyhow::{Context, Result};
+use nostr_sdk::{FromBech32, Keys, SecretKey, ToBech32};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-const DATA_DIR: &str = "/data";
-const KEY_FILE: &str = "/data/nostr_secret.hex";
+/// Secret key file inside the data directory (64 hex characters)
+const KEY_FILENAME: &str = "nostr_secret.hex";
 
-pub fn load_or_create_keys() -> Keys {
-    fs::create_dir_all(DATA_DIR).ok();
+fn load_keys(path: &Path) -> Result<Keys> {
+    // A truncated or corrupted file would silently give us a different
+    // identity than the one every paired device knows
+    if let Err(e) = check_key_contents(path) {
+        anyhow::bail!(
+            "Refusing to start with a damaged Nostr key file {}: {:#}. \
+             Restore it from backup (or delete it to create a new identity and re-pair).",
+            path.display(),
+            e
+        );
+    }
 
-    if Path::new(KEY_FILE).exists() {
-        let hex_str = fs::read_to_string(KEY_FILE)
-            .expect("Failed to read nostr secret key file")
-            .trim()
-            .to_string();
+    // A loose mode is reported before it is fixed, so the exposure shows up in the log
+    if let Err(e) = check_key_mode(path) {
+        log::warn!("{}: {}; restricting it to 0600", path.display(), e);
+        restrict_permissions(path);
+    }
 
-        let bytes = hex::decode(&hex_str)
-            .expect("Invalid hex in nostr_secret.hex");
+    let hex_str = fs::read_to_string(path)
+        .context("Failed to read nostr secret key file")?;
+    let bytes = hex::decode(hex_str.trim()).context("Invalid hex in nostr secret key file")?;
+    let secret_key = SecretKey::from_slice(&bytes).context("Invalid secret key bytes")?;
 
-        let secret_key = SecretKey::from_slice(&bytes)
-            .expect("Invalid secret key bytes");
+    let keys = Keys::new(secret_key);
+    log::info!("Loaded persisted Nostr pubkey: {}", keys.public_key().to_hex());
+    Ok(keys)
+}
 
-        let keys = Keys::new(secret_key);
+/// Parse an imported `nsec1…` secret key. The error never echoes the input.
+pub fn parse_nsec(nsec: &str) -> Result<Keys> {
+    let nsec = nsec.trim();
+    if !nsec.starts_with("nsec1") {
+        anyhow::bail!("NOSTR_SECRET_NSEC must be a bech32 nsec1… secret key");
+    }
+    let secret_key = SecretKey::from_bech32(nsec)
+        .map_err(|e| anyhow::anyhow!("NOSTR_SECRET_NSEC is not a valid nsec: {}", e))?;
+    Ok(Keys::new(secret_key))
+}
 
-        log::info!(
-            "Loaded persisted Nostr pubkey: {}",
-            keys.public_key().to_hex()
-        );
+fn persist_keys(path: &Path, keys: &Keys) -> Result<()> {
+    let hex_str = hex::encode(keys.secret_key().as_secret_bytes());
+    write_private(path, hex_str.as_bytes()).context("Failed to persist nostr secret key")?;
+    restrict_permissions(path);
+    Ok(())
+}
 
-        keys
-    } else {
-        let keys = Keys::generate();
+/// Write a file that is owner-only (0600) from the moment it exists, instead
+/// of briefly world-readable under the usual 022 umask
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
 
-        let secret = keys.secret_key();
-        let hex_str = hex::encode(secret.as_secret_bytes());
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
 
-        fs::write(KEY_FILE, &hex_str)
-            .expect("Failed to persist nostr secret key");
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    fs::write(path, contents)
+}
 
-        log::info!(
-            "Generated NEW Nostr pubkey (persisted): {}",
-            keys.public_key().to_hex()
-        );
+fn create_keys(path: &Path, imported_nsec: Option<&str>) -> Result<Keys> {
+    let keys = match imported_nsec {
+        Some(nsec) => {
+            let keys = parse_nsec(nsec)?;
+            log::info!(
+                "Imported Nostr pubkey from NOSTR_SECRET_NSEC (persisted): {}",
+                keys.public_key().to_hex()
+            );
+            keys
+        }
+        None => {
+            let keys = Keys::generate();
+            log::info!(
+                "Generated NEW Nostr pubkey (persisted): {}",
+                keys.public_key().to_hex()
+            );
+            keys
+        }
+    };
+
+    persist_keys(path, &keys)?;
+    Ok(keys)
+}
+
+/// 64 hex characters (surrounding whitespace allowed) that make a valid
+/// secp256k1 scalar
+fn check_key_contents(path: &Path) -> Result<()> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let hex_str = content.trim();
+
+    if hex_str.len() != 64 {
+        anyhow::bail!("expected 64 hex characters, found {}", hex_str.len());
+    }
+
+    let bytes = hex::decode(hex_str).context("key file is not valid hex")?;
+    SecretKey::from_slice(&bytes).context("key file does not hold a valid secret key")?;
+    Ok(())
+}
+
+/// No group/other permission bits
+#[cfg(unix)]
+fn check_key_mode(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        anyhow::bail!("key file is accessible by other users (mode {:o})", mode & 0o777);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_key_mode(_path: &Path) -> Result<()> {
+    Ok(())
+}
 
-        keys
+/// Make the key file owner-only (0600)
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(0o600)) {
+        log::warn!("Failed to restrict permissions of {}: {}", path.display(), e);
     }
 }
 
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) {}
+
+/// Owns the server's Nostr identity, stored in `data_dir/nostr_secret.hex`
+#[derive(Clone)]
+pub struct IdentityManager {
+    keys: Keys,
+    key_path: PathBuf,
+}
+
+impl IdentityManager {
+    /// Load the persisted keypair from `data_dir`. On first run, persist
+    /// `imported_nsec` (NOSTR_SECRET_NSEC) if given, or a freshly generated key.
+    ///
+    /// A key already on disk always wins, so leaving the env var set doesn't
+    /// rotate the identity on every boot.
+    pub fn load_or_create(data_dir: impl AsRef<Path>, imported_nsec: Option<&str>) -> Result<Self> {
+        let data_dir = data_dir.as_ref();
+        fs::create_dir_all(data_dir).context("Failed to create data directory")?;
+
+        let key_path = data_dir.join(KEY_FILENAME);
+        let keys = if key_path.exists() {
+            let keys = load_keys(&key_path)?;
+            if let Some(nsec) = imported_nsec {
+                let imported = parse_nsec(nsec)?;
+                if imported.public_key() != keys.public_key() {
+                    log::warn!(
+                        "NOSTR_SECRET_NSEC ignored: {} already holds a different key",
+                        key_path.display()
+                    );
+                }
+            }
+            keys
+        } else {
+            create_keys(&key_path, imported_nsec)?
+        };
+
+        Ok(Self { keys, key_path })
+    }
+
+    /// Check that a key file holds exactly one valid secret key and is private:
+    /// 64 hex characters (surrounding whitespace allowed), a valid secp256k1
+    /// scalar, and (on unix) no group/other permission bits
+    pub fn verify_key_file(path: &Path) -> Result<()> {
+        check_key_contents(path)?;
+        check_key_mode(path)
+    }
+
+    pub fn keys(&self) -> &Keys {
+        &self.keys
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        self.keys.public_key().to_hex()
+    }
+
+    pub fn key_path(&self) -> &Path {
+        &self.key_path
+    }
+
+    /// Secret key as bech32 `nsec1…`, for backup to a password manager.
+    /// Callers must gate this behind authentication.
+    pub fn nsec_export_bech32(&self) -> Result<String> {
+        self.keys
+            .secret_key()
+            .to_bech32()
+            .context("Failed to encode secret key as nsec")
+    }
+}
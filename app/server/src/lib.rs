@@ -4,12 +4,18 @@
 
 pub mod config;
 pub mod error;
+pub mod history;
 pub mod identity;
+pub mod metrics;
 pub mod relays;
+pub mod shutdown;
+pub mod startup;
 pub mod qr;
+pub mod protocol;
 pub mod pairing;
 pub mod nostr_handler;
 pub mod nostr;
 pub mod electrs;
+pub mod watch;
 pub mod xpub;
 
@@ -3,37 +3,87 @@ use rustls::crypto::ring::default_provider;
 use tracing::{error, info, warn};
 
 use axum::{
-    routing::get,
+    body::Body,
+    extract::Path,
+    routing::{delete, get, post},
     Router,
     response::{IntoResponse, Response},
-    http::{StatusCode, header},
+    http::{HeaderMap, Request, StatusCode, header},
     Json,
 };
 use tokio::net::TcpListener;
-use std::net::SocketAddr;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 mod config;
 mod error;
+mod history;
 mod identity;
+mod metrics;
 mod relays;
+mod shutdown;
+mod startup;
 mod qr;
 mod protocol;
 mod pairing;
 mod nostr_handler;
 mod nostr;
 mod electrs;
+mod watch;
 mod xpub;
 
+/// Number of entries returned by GET /events/recent
+const RECENT_EVENTS_LIMIT: usize = 20;
+
+/// A Nostr handler run this long counts as healthy: the restart backoff starts over
+const HANDLER_STABLE_AFTER: std::time::Duration = std::time::Duration::from_secs(300);
+
+#[derive(serde::Serialize)]
+struct RecentEvent {
+    event_id: String,
+    req_id: String,
+    published_at: String,
+}
+
+/// Newest first, at most RECENT_EVENTS_LIMIT
+fn recent_events(published: &nostr::PublishedEvents) -> Vec<RecentEvent> {
+    let now = chrono::Utc::now();
+    published
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .take(RECENT_EVENTS_LIMIT)
+        .map(|(event_id, req_id, at)| {
+            let age = chrono::Duration::from_std(at.elapsed()).unwrap_or_default();
+            RecentEvent {
+                event_id: event_id.to_hex(),
+                req_id: req_id.clone(),
+                published_at: (now - age).to_rfc3339(),
+            }
+        })
+        .collect()
+}
+
 fn install_crypto_provider() {
     let _ = default_provider().install_default();
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     println!("=== BALANCEBRIDGE MAIN STARTED ===");
+    metrics::mark_start();
 
     install_crypto_provider();
+
+    // Before the subscriber so RUST_LOG from .env applies
+    let dotenv_loaded = config::load_dotenv();
+
+    // Command line > env > --config-file > defaults (exits here on --help)
+    let config = config::ServerConfig::from_args_and_env()?;
+
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
@@ -42,66 +92,190 @@ async fn main() -> Result<()> {
 
     info!("BalanceBridge Umbrel Server starting...");
 
-    let data_dir = config::get_data_dir();
+    if dotenv_loaded > 0 {
+        info!("Loaded {} variable(s) from .env (development mode)", dotenv_loaded);
+    }
+    if config::uses_deprecated_electrs_var() {
+        warn!("ELECTRS_URL is deprecated; rename it to ELECTRS_ADDR");
+    }
+
+    // Explicit sizing instead of #[tokio::main]: don't starve the other Umbrel apps
+    let worker_threads = config::get_worker_threads();
+    let max_blocking_threads = config::get_max_blocking_threads();
+    info!(
+        "Tokio runtime: worker_threads={} max_blocking_threads={}",
+        worker_threads, max_blocking_threads
+    );
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        .max_blocking_threads(max_blocking_threads)
+        .enable_all()
+        .build()
+        .context("Failed to build Tokio runtime")?;
+
+    runtime.block_on(run(config))
+}
+
+async fn run(config: config::ServerConfig) -> Result<()> {
+    let data_dir = config.data_dir.clone();
     info!("Using data dir: {}", data_dir.display());
 
-    let keys = identity::load_or_create_keys();
-    let pubkey = keys.public_key().to_hex();
-    let relay_list = relays::get_relays();
-    let nostr_state = nostr::NostrState::new(keys.clone(), relay_list.clone()).await?;
+    let nsec = config::get_nostr_secret_nsec();
+    let identity = identity::IdentityManager::load_or_create(&data_dir, nsec.as_deref())
+        .context("Failed to load Nostr identity")?;
+    let keys = identity.keys().clone();
+    let pubkey = identity.public_key_hex();
+    // Relays added/removed over HTTP win over the configured ones
+    let persistent_relays = relays::PersistentRelayList::new(&data_dir, config.relays.clone());
+    let relay_list = persistent_relays.load();
+
+    // Initialize pairing manager
+    let pairing_manager = pairing::PairingManager::new(&data_dir)
+        .context("Failed to init pairing manager")?
+        .with_pairing_ttl(config::get_pairing_ttl());
+
+    startup::print_banner(&config, &keys.public_key(), pairing_manager.has_pairing());
+
+    // Also connect to every relay a paired device asked for
+    let mut connect_relays = relay_list.clone();
+    match pairing_manager.get_all_known_relays() {
+        Ok(known) => {
+            for relay in known {
+                if !connect_relays.contains(&relay) {
+                    connect_relays.push(relay);
+                }
+            }
+        }
+        Err(e) => warn!("Failed to read relays of paired devices: {}", e),
+    }
+
+    // Historically healthy relays first, until the probes have measured again
+    let relay_health = relays::RelayHealthMonitor::new(&data_dir)
+        .with_drop_after(config::get_relay_drop_after());
+    let connect_relays = relay_health.rank(connect_relays);
+    relay_health.spawn_persist();
+
+    let nostr_state = nostr::NostrState::new(keys.clone(), connect_relays.clone()).await?;
+    relays::spawn_relay_failover(Arc::clone(&nostr_state.client), relay_health.clone());
+    relays::spawn_latency_monitor(
+        connect_relays,
+        nostr_state.relay_latencies.clone(),
+        relay_health.clone(),
+    );
 
     // ✅ Electrs MUST be initialized before Nostr handler
     info!("Initializing Electrs client...");
     let electrs_client = Arc::new(
-        electrs::ElectrsClient::new()
+        electrs::ElectrsClient::connect(config.electrs_addr.clone(), config.electrs_tls_insecure)
             .context("Failed to initialize Electrs client")?
+            .with_cache_ttl(config::get_electrs_cache_ttl())
+            .with_max_inflight(config::get_electrs_max_inflight())
+            .with_timeouts(config::get_electrs_timeouts())
+            .with_network(config.network),
     );
     info!("Electrs client initialized successfully");
-    info!("Warming up Electrs...");
-    match electrs_client.warm_up() {
-        Ok(_) => info!("Electrs warm-up successful"),
-        Err(e) => warn!("Electrs warm-up failed: {}", e),
-    }
 
-    // Initialize pairing manager
-    let pairing_manager = pairing::PairingManager::new(&data_dir)
-        .context("Failed to init pairing manager")?;
+    let coordinator = shutdown::ShutdownCoordinator::new();
+    // Requests get a "not_ready" answer until warm-up succeeds, or at most 30s
+    let is_ready = Arc::new(AtomicBool::new(false));
 
-    // Generate QR code for pairing
-    let payload = qr::PairingPayload::new(pubkey.clone(), relay_list.clone());
-    let pairing_json = payload.to_json()?;
-    let qr_svg = payload.generate_qr_svg()?;
+    // SIGTERM: stop taking requests, then let the HTTP server wind down.
+    // Installed before the (slow) warm-up so a stop during startup is clean too.
+    tokio::spawn({
+        let coordinator = coordinator.clone();
+        let is_ready = Arc::clone(&is_ready);
+        async move {
+            shutdown::termination_signal().await;
+            info!("Shutdown signal received; no longer accepting requests");
+            is_ready.store(false, Ordering::Release);
+            coordinator.trigger();
 
-    let pairing_json_clone = pairing_json.clone();
-    let qr_svg_clone = qr_svg.clone();
+            // Exit 0 even if the cleanup in run() hangs, before Umbrel has to SIGKILL us
+            tokio::time::sleep(shutdown::SHUTDOWN_TIMEOUT).await;
+            warn!("forced shutdown after timeout");
+            std::process::exit(0);
+        }
+    });
 
-    // Spawn lightweight BalanceBridge Nostr loop (request/response)
+    // Warm-up blocks on Electrs, so it runs off the runtime and shutdown
+    // doesn't wait for it
+    tokio::spawn({
+        let electrs_client = Arc::clone(&electrs_client);
+        let is_ready = Arc::clone(&is_ready);
+        let coordinator = coordinator.clone();
+        let network = config.network;
+        async move {
+            let token = coordinator.token();
+            let warm_up = tokio::task::spawn_blocking({
+                let token = token.clone();
+                let is_ready = Arc::clone(&is_ready);
+                move || warm_up_electrs(&electrs_client, network, &is_ready, &token)
+            });
+            tokio::select! {
+                _ = warm_up => {}
+                _ = token.cancelled() => {
+                    info!("Shutdown during Electrs warm-up; not waiting for it");
+                    return;
+                }
+            }
+
+            if !is_ready.load(Ordering::Acquire) {
+                tokio::select! {
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(30)) => {
+                        is_ready.store(true, Ordering::Release);
+                        info!("Readiness grace period over; accepting requests");
+                    }
+                    _ = token.cancelled() => {}
+                }
+            }
+        }
+    });
+
+    // Log Electrs state transitions as they happen
     {
-        let client = nostr_state.client.clone();
-        let electrs_for_nostr = Arc::clone(&electrs_client);
+        let mut electrs_state = electrs_client.subscribe_to_connection_events();
         tokio::spawn(async move {
-            loop {
-                if let Err(e) =
-                    crate::nostr::run_balancebridge_nostr_loop(client.clone(), electrs_for_nostr.clone()).await
-                {
-                    error!("BB_NOSTR: loop crashed: {e:?} — restarting in 2s");
-                    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+            while electrs_state.changed().await.is_ok() {
+                let state = electrs_state.borrow_and_update().clone();
+                match state.status {
+                    electrs::ElectrsStatus::Connected => info!("Electrs state: connected"),
+                    _ => warn!(
+                        "Electrs state: {:?} (cooldown={}ms, last_error={:?})",
+                        state.status, state.cooldown_remaining_ms, state.last_error
+                    ),
                 }
             }
         });
     }
 
+    // Generate QR code for pairing (with a fresh, time-limited challenge)
+    let pairing_qr = Arc::new(std::sync::RwLock::new(qr::PairingQr::build(
+        &keys,
+        relay_list.clone(),
+        pairing_manager.generate_challenge(),
+        config::get_pairing_qr_format(),
+    )?));
+
     // Start Nostr handler
     info!("Server pubkey: {}", pubkey);
     info!("BalanceBridge request kind: {}", crate::nostr_handler::BALANCEBRIDGE_REQUEST_KIND);
     info!("BalanceBridge response kind: {}", crate::nostr_handler::BALANCEBRIDGE_RESPONSE_KIND);
     info!("Nostr relays: {}", relay_list.join(", "));
 
+    let balance_history = history::BalanceHistoryStore::new(&data_dir)
+        .context("Failed to init balance history store")?;
+
+    let xpub_watch = watch::XpubWatchList::new(&data_dir)
+        .context("Failed to load xpub watch list")?;
+
     let nostr_task = tokio::spawn({
         let keys_clone = keys.clone();
         let pairing_manager_clone = pairing_manager.clone();
         let electrs_client_clone = Arc::clone(&electrs_client);
         let nostr_state_clone = nostr_state.clone();
+        let is_ready_clone = Arc::clone(&is_ready);
+        let coordinator_clone = coordinator.clone();
 
         async move {
             match nostr_handler::NostrHandler::new(
@@ -109,12 +283,41 @@ async fn main() -> Result<()> {
                 keys_clone,
                 pairing_manager_clone,
                 electrs_client_clone,
+                balance_history,
+                xpub_watch,
+                is_ready_clone,
+                coordinator_clone,
             )
             .await
             {
+                // NostrHandler is the only request consumer; restart it if the
+                // notification stream ever dies (backoff + jitter between restarts)
                 Ok(handler) => {
-                    if let Err(e) = handler.start_listening().await {
-                        eprintln!("Nostr handler exited with error: {}", e);
+                    let handler = Arc::new(handler);
+                    tokio::spawn({
+                        let handler = Arc::clone(&handler);
+                        async move { handler.run_xpub_watcher().await }
+                    });
+
+                    let mut attempt: u32 = 0;
+                    loop {
+                        let started = std::time::Instant::now();
+                        let result = handler.start_listening().await;
+                        if started.elapsed() >= HANDLER_STABLE_AFTER {
+                            attempt = 0;
+                        }
+                        let delay = relays::jitter_delay(attempt, 2000, 60_000);
+                        match result {
+                            // Only returns Ok on shutdown
+                            Ok(()) => break,
+                            Err(e) => error!(
+                                "Nostr handler exited with error: {} — restarting in {}ms",
+                                e,
+                                delay.as_millis()
+                            ),
+                        }
+                        tokio::time::sleep(delay).await;
+                        attempt = attempt.saturating_add(1);
                     }
                 }
                 Err(e) => {
@@ -132,31 +335,194 @@ async fn main() -> Result<()> {
 
     let electrs_client_health = Arc::clone(&electrs_client);
 
+    let identity_http = identity.clone();
+    let published_events = nostr_state.published_events.clone();
+
     let app_state = nostr_state.clone();
+    let is_ready_http = Arc::clone(&is_ready);
     let app = Router::new()
         .route("/", get(|| async { "BalanceBridge is running" }))
-        .route("/pairing", get(move || async move { pairing_json_clone.clone() }))
-        .route("/qr", get(move || async move { serve_svg(qr_svg_clone.clone()) }))
-        .route("/health", get(|| async {
-            info!("HTTP GET /health request received");
-            (StatusCode::OK, "OK").into_response()
+        .route("/pairing", get({
+            let pairing_qr = Arc::clone(&pairing_qr);
+            move || {
+                let json = pairing_qr.read().unwrap().json.clone();
+                async move { json }
+            }
+        }))
+        .route("/pairing/refresh", get({
+            let pairing_qr = Arc::clone(&pairing_qr);
+            let pairing_manager = pairing_manager.clone();
+            let keys = keys.clone();
+            let persistent_relays = persistent_relays.clone();
+            move || {
+                // The current list: POST /relays may have changed it since startup
+                let result = qr::PairingQr::build(
+                    &keys,
+                    persistent_relays.load(),
+                    pairing_manager.generate_challenge(),
+                    config::get_pairing_qr_format(),
+                );
+                let response = match result {
+                    Ok(fresh) => {
+                        info!("Pairing challenge refreshed");
+                        let json = fresh.json.clone();
+                        *pairing_qr.write().unwrap() = fresh;
+                        (StatusCode::OK, json).into_response()
+                    }
+                    Err(e) => {
+                        error!("Failed to refresh pairing QR: {}", e);
+                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    }
+                };
+                async move { response }
+            }
+        }))
+        .route("/qr", get({
+            let pairing_qr = Arc::clone(&pairing_qr);
+            move || {
+                let svg = pairing_qr.read().unwrap().svg.clone();
+                async move { serve_svg(svg) }
+            }
+        }))
+        .route("/qr.png", get({
+            let pairing_qr = Arc::clone(&pairing_qr);
+            move || {
+                let png = pairing_qr.read().unwrap().png.clone();
+                async move { ([(header::CONTENT_TYPE, "image/png")], png).into_response() }
+            }
+        }))
+        .route("/qr/animated", get({
+            let pairing_qr = Arc::clone(&pairing_qr);
+            move || {
+                let frames = pairing_qr.read().unwrap().frames.clone();
+                async move { Json(frames) }
+            }
+        }))
+        .route("/relays", get({
+            let state = nostr_state.clone();
+            move || {
+                let state = state.clone();
+                async move { Json(serde_json::json!({ "relays": state.relay_connections().await })) }
+            }
+        }).post({
+            let persistent_relays = persistent_relays.clone();
+            let state = nostr_state.clone();
+            let relay_health = relay_health.clone();
+            move |Json(relays): Json<Vec<String>>| {
+                let persistent_relays = persistent_relays.clone();
+                let state = state.clone();
+                let relay_health = relay_health.clone();
+                async move { replace_relays(&persistent_relays, &state, &relay_health, relays).await }
+            }
+        }))
+        .route("/relay/add", post({
+            let persistent_relays = persistent_relays.clone();
+            let state = nostr_state.clone();
+            let relay_health = relay_health.clone();
+            move |Json(body): Json<RelayRequest>| {
+                let persistent_relays = persistent_relays.clone();
+                let state = state.clone();
+                let relay_health = relay_health.clone();
+                async move {
+                    add_relay(&persistent_relays, &state, &relay_health, &body.url).await
+                }
+            }
+        }))
+        .route("/relay/:url", delete({
+            let persistent_relays = persistent_relays.clone();
+            let state = nostr_state.clone();
+            let relay_health = relay_health.clone();
+            move |Path(url): Path<String>| {
+                let persistent_relays = persistent_relays.clone();
+                let state = state.clone();
+                let relay_health = relay_health.clone();
+                async move {
+                    remove_relay(&persistent_relays, &state, &relay_health, &url).await
+                }
+            }
+        }))
+        .route("/identity/nsec", get(move |headers: HeaderMap| {
+            let identity = identity_http.clone();
+            async move { serve_nsec_export(&identity, &headers) }
+        }))
+        .route("/metrics", get(|| async {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                metrics::render(),
+            )
+        }))
+        .route("/events/recent", get(move || {
+            let recent = recent_events(&published_events);
+            async move { Json(recent) }
+        }))
+        .route("/ready", get(move || {
+            let ready = is_ready_http.load(Ordering::Acquire);
+            async move {
+                let status = if ready {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                (status, Json(serde_json::json!({ "ready": ready })))
+            }
         }))
         .route("/health/electrs", get(move || {
-            let electrs_client = Arc::clone(&electrs_client_health);
+            // O(1): read the pushed connection state instead of pinging Electrs
+            let health = electrs_client_health.health();
             async move {
                 info!("HTTP GET /health/electrs request received");
-                match tokio::task::spawn_blocking(move || electrs_client.test_connectivity()).await {
-                    Ok(_) => (StatusCode::OK, "OK"),
-                    Err(e) => {
-                        error!("Electrs health check failed: {}", e);
-                        (StatusCode::SERVICE_UNAVAILABLE, "Electrs unavailable")
+                let status = if health.breaker.is_healthy() {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                let retry_after = match health.breaker {
+                    electrs::BreakerState::CoolingDown { remaining_ms } => {
+                        Some(remaining_ms.div_ceil(1000))
                     }
+                    _ => None,
+                };
+
+                let mut response = (status, Json(health)).into_response();
+                if let Some(secs) = retry_after {
+                    response.headers_mut().insert(header::RETRY_AFTER, secs.into());
                 }
+                response
             }
         }))
+        // Layers run bottom-up: assign the id, log with it, copy it to the response
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &Request<Body>| {
+                    let request_id = request
+                        .headers()
+                        .get("x-request-id")
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or("-");
+                    tracing::info_span!(
+                        "http",
+                        method = %request.method(),
+                        path = %request.uri().path(),
+                        request_id = %request_id,
+                    )
+                })
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(tracing::Level::INFO)
+                        .latency_unit(LatencyUnit::Millis),
+                ),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        // Merged after the layers so liveness probes stay out of the log.
+        // Always 200: a dead relay is worth reporting, not worth a restart
+        .merge(Router::new().route("/health", get(move || {
+            let relays = relay_health.snapshot();
+            async move { Json(serde_json::json!({ "status": "ok", "relays": relays })) }
+        })))
         .with_state(app_state);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3829));
+    let addr = config.listen_addr;
     info!("Listening on http://{}", addr);
 
     let listener = TcpListener::bind(addr)
@@ -164,10 +530,129 @@ async fn main() -> Result<()> {
         .context("Failed to bind")?;
 
     info!("Server ready. Waiting for Android app pairing...");
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(coordinator.wait())
+        .await?;
+
+    let in_flight = coordinator.in_flight();
+    if in_flight > 0 {
+        info!("Waiting for {} in-flight request(s)", in_flight);
+    }
+    if !coordinator.wait_for_in_flight(shutdown::IN_FLIGHT_TIMEOUT).await {
+        warn!(
+            "{} request(s) still in flight after {:?}",
+            coordinator.in_flight(),
+            shutdown::IN_FLIGHT_TIMEOUT
+        );
+    }
+
+    nostr_state.client.unsubscribe_all().await;
+    nostr_state.client.disconnect().await;
+
+    if let Err(e) = pairing_manager.flush() {
+        warn!("Failed to flush pairing file: {}", e);
+    }
+
+    info!("clean shutdown complete");
+
     Ok(())
 }
 
+#[derive(serde::Deserialize)]
+struct RelayRequest {
+    url: String,
+}
+
+/// Ping Electrs, then have it index WARM_UP_XPUB's addresses. BLOCKING.
+/// Marks the server ready once the ping answers, unless shutdown started.
+fn warm_up_electrs(
+    electrs_client: &electrs::ElectrsClient,
+    network: bitcoin::Network,
+    is_ready: &AtomicBool,
+    shutdown: &shutdown::ShutdownToken,
+) {
+    info!("Warming up Electrs...");
+    match electrs_client.warm_up() {
+        Ok(_) if !shutdown.is_cancelled() => {
+            info!("Electrs warm-up successful");
+            is_ready.store(true, Ordering::Release);
+        }
+        Ok(_) => return,
+        Err(e) => warn!("Electrs warm-up failed: {} — marking ready in 30s anyway", e),
+    }
+
+    if let Some(warm_up_xpub) = config::get_warm_up_xpub() {
+        match xpub::derive_addresses(&warm_up_xpub, network, xpub::DEFAULT_GAP_LIMIT) {
+            Ok(addresses) => {
+                let started = std::time::Instant::now();
+                match electrs_client.preload_scripts(&addresses, config::get_warm_up_timeout()) {
+                    Ok(active) => info!(
+                        "Electrs address warm-up: {} of {} addresses active ({}ms)",
+                        active,
+                        addresses.len(),
+                        started.elapsed().as_millis()
+                    ),
+                    Err(e) => warn!("Electrs address warm-up failed: {}", e),
+                }
+            }
+            Err(e) => warn!("WARM_UP_XPUB is not a usable xpub: {}", e),
+        }
+    }
+}
+
+/// POST /relay/add — append the relay to the persisted list and apply it
+/// through `replace_relays`, which connects to it now
+async fn add_relay(
+    persistent: &relays::PersistentRelayList,
+    state: &nostr::NostrState,
+    health: &relays::RelayHealthMonitor,
+    url: &str,
+) -> Response {
+    let mut next = persistent.load();
+    next.push(url.to_string());
+    replace_relays(persistent, state, health, next).await
+}
+
+/// POST /relays — replace the relay list (JSON array of ws:// or wss:// URLs)
+/// and move the pool over to it without a restart
+async fn replace_relays(
+    persistent: &relays::PersistentRelayList,
+    state: &nostr::NostrState,
+    health: &relays::RelayHealthMonitor,
+    relays: Vec<String>,
+) -> Response {
+    let previous = persistent.load();
+    let next = match persistent.replace(&relays) {
+        Ok(next) => next,
+        Err(e) => {
+            warn!("Rejected relay list: {}", e);
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+
+    for url in previous.iter().filter(|url| !next.contains(url)) {
+        health.forget(url);
+    }
+    state.apply_relays(&previous, &next).await;
+
+    info!("Relays replaced via HTTP: {}", next.join(", "));
+    (StatusCode::OK, Json(serde_json::json!({ "relays": state.relay_connections().await })))
+        .into_response()
+}
+
+/// DELETE /relay/:url (URL-encoded) — forget the relay and disconnect from it
+async fn remove_relay(
+    persistent: &relays::PersistentRelayList,
+    state: &nostr::NostrState,
+    health: &relays::RelayHealthMonitor,
+    url: &str,
+) -> Response {
+    let normalized = relays::validate_relay_url(url).ok();
+    let mut next = persistent.load();
+    next.retain(|r| r != url && Some(r) != normalized.as_ref());
+    replace_relays(persistent, state, health, next).await
+}
+
 fn serve_svg(svg: String) -> Response {
     (
         StatusCode::OK,
@@ -176,3 +661,70 @@ fn serve_svg(svg: String) -> Response {
     )
         .into_response()
 }
+
+/// GET /identity/nsec — nsec backup export behind HTTP Basic Auth
+///
+/// Disabled (503) unless NSEC_EXPORT_PASSWORD is set. Every access is
+/// logged at WARN regardless of outcome.
+fn serve_nsec_export(identity: &identity::IdentityManager, headers: &HeaderMap) -> Response {
+    warn!("SECURITY: GET /identity/nsec accessed");
+
+    let expected = match std::env::var("NSEC_EXPORT_PASSWORD") {
+        Ok(p) if !p.is_empty() => p,
+        _ => {
+            warn!("SECURITY: nsec export refused (NSEC_EXPORT_PASSWORD not set)");
+            return (StatusCode::SERVICE_UNAVAILABLE, "nsec export not configured").into_response();
+        }
+    };
+
+    let authorized = basic_auth_password(headers)
+        .map(|given| constant_time_eq(given.as_bytes(), expected.as_bytes()))
+        .unwrap_or(false);
+
+    if !authorized {
+        warn!("SECURITY: nsec export refused (bad or missing credentials)");
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"balancebridge\"")],
+            "unauthorized",
+        )
+            .into_response();
+    }
+
+    match identity.nsec_export_bech32() {
+        Ok(nsec) => {
+            warn!("SECURITY: nsec exported");
+            (
+                StatusCode::OK,
+                [
+                    (header::CONTENT_SECURITY_POLICY, "default-src 'none'"),
+                    (header::CACHE_CONTROL, "no-store"),
+                ],
+                nsec,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("nsec export failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "nsec export failed").into_response()
+        }
+    }
+}
+
+/// Extract the password from an `Authorization: Basic …` header (username is ignored)
+fn basic_auth_password(headers: &HeaderMap) -> Option<String> {
+    use bitcoin::base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (_user, password) = decoded.split_once(':')?;
+    Some(password.to_string())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
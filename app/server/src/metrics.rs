@@ -0,0 +1,182 @@
+//! Prometheus metrics
+//!
+//! Process-wide registry exposed at `GET /metrics`.
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the Electrs call duration buckets, seconds: from
+/// near-instant cached answers up to the 90s per-attempt timeout
+const ELECTRS_DURATION_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 90.0];
+
+pub struct Metrics {
+    registry: Registry,
+    /// Requests received, by the relay that delivered them
+    pub requests_by_relay: IntCounterVec,
+    /// Responses published, errors included
+    pub responses_published: IntCounter,
+    /// Error responses sent
+    pub error_responses: IntCounter,
+    /// Responses bigger than the NIP-11 max_message_length of a relay they went to
+    pub oversized_responses: IntCounter,
+    pub electrs: ElectrsMetrics,
+}
+
+/// Balance lookups made by `ElectrsClient::get_address_balance`
+pub struct ElectrsMetrics {
+    pub calls_total: IntCounter,
+    /// Calls that ended in an error, from Electrs or the connection
+    pub errors_total: IntCounter,
+    /// Wall time per call, including gate wait and retries
+    pub duration_seconds: Histogram,
+    pub cache_hits: IntCounter,
+    pub cache_misses: IntCounter,
+    /// Calls in flight; stuck near the gate size means Electrs is saturated
+    pub active_calls: IntGauge,
+}
+
+impl ElectrsMetrics {
+    fn register(registry: &Registry) -> Self {
+        let counter = |name: &str, help: &str| {
+            let c = IntCounter::new(name, help).expect("valid metric");
+            registry
+                .register(Box::new(c.clone()))
+                .expect("metric registered once");
+            c
+        };
+
+        let calls_total = counter(
+            "balancebridge_electrs_calls_total",
+            "Electrs balance lookups",
+        );
+        let errors_total = counter(
+            "balancebridge_electrs_errors_total",
+            "Electrs balance lookups that failed",
+        );
+        let cache_hits = counter(
+            "balancebridge_electrs_cache_hits_total",
+            "Balance lookups answered from cache",
+        );
+        let cache_misses = counter(
+            "balancebridge_electrs_cache_misses_total",
+            "Balance lookups that had to query Electrs",
+        );
+
+        let duration_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "balancebridge_electrs_duration_seconds",
+                "Electrs balance lookup duration",
+            )
+            .buckets(ELECTRS_DURATION_BUCKETS.to_vec()),
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(duration_seconds.clone()))
+            .expect("metric registered once");
+
+        let active_calls = IntGauge::new(
+            "balancebridge_electrs_active_calls",
+            "Electrs balance lookups in flight",
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(active_calls.clone()))
+            .expect("metric registered once");
+
+        Self {
+            calls_total,
+            errors_total,
+            duration_seconds,
+            cache_hits,
+            cache_misses,
+            active_calls,
+        }
+    }
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let requests_by_relay = IntCounterVec::new(
+            Opts::new(
+                "balancebridge_requests_by_relay",
+                "BalanceBridge requests received, by delivering relay",
+            ),
+            &["relay"],
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(requests_by_relay.clone()))
+            .expect("metric registered once");
+
+        let responses_published = IntCounter::new(
+            "balancebridge_responses_published_total",
+            "BalanceBridge responses published",
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(responses_published.clone()))
+            .expect("metric registered once");
+
+        let error_responses = IntCounter::new(
+            "balancebridge_error_responses_total",
+            "BalanceBridge error responses sent",
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(error_responses.clone()))
+            .expect("metric registered once");
+
+        let oversized_responses = IntCounter::new(
+            "balancebridge_oversized_responses_total",
+            "BalanceBridge responses too large for at least one relay",
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(oversized_responses.clone()))
+            .expect("metric registered once");
+
+        let electrs = ElectrsMetrics::register(&registry);
+
+        Self {
+            registry,
+            requests_by_relay,
+            responses_published,
+            error_responses,
+            oversized_responses,
+            electrs,
+        }
+    }
+}
+
+/// The global metrics instance
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+static SERVER_START_TIME: OnceLock<Instant> = OnceLock::new();
+
+/// Remember when the server started (first call wins); call early in main
+pub fn mark_start() {
+    SERVER_START_TIME.get_or_init(Instant::now);
+}
+
+/// Time since `mark_start`
+pub fn uptime() -> Duration {
+    SERVER_START_TIME.get_or_init(Instant::now).elapsed()
+}
+
+/// Prometheus text exposition of every registered metric
+pub fn render() -> String {
+    let mut buf = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&metrics().registry.gather(), &mut buf) {
+        tracing::warn!("Failed to encode metrics: {}", e);
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
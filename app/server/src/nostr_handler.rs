@@ -1,24 +1,159 @@
 use anyhow::{anyhow, Result};
+use futures_util::future::BoxFuture;
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use nostr_sdk::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+#[cfg(feature = "testing")]
+use tokio::sync::mpsc;
+use tokio::sync::Semaphore;
 use tokio::time::{timeout, Duration};
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
-use crate::electrs::ElectrsClient;
-use crate::nostr::NostrState;
-use crate::pairing::PairingManager;
+use crate::electrs::{
+    self, ElectrsClientTrait, ElectrsQueryResult, FeeHistogramBucket, RetryStrategy,
+};
+use crate::config;
+use crate::history::{self, BalanceHistoryStore, BalanceSnapshot};
+use crate::metrics;
+use crate::nostr::{NostrState, PublishedEvents};
+use crate::pairing::{self, PairingManager};
+use crate::protocol::{
+    self, BlockHeightResponse, ErrorResponse, FeeEstimateResponse,
+    LookupError, TransactionInfo, TxDetailResponse, UtxoListResponse, PROTOCOL_VERSION,
+};
+use crate::relays::{self, RelayLatencies};
+use crate::shutdown::{ShutdownCoordinator, ShutdownToken};
+use crate::watch::{WatchAdd, WatchedXpub, XpubSnapshot, XpubWatchList, MAX_WATCHED_XPUBS_PER_DEVICE};
+use crate::xpub;
 
 pub const BALANCEBRIDGE_REQUEST_KIND: u16 = 30078;
 pub const BALANCEBRIDGE_RESPONSE_KIND: u16 = 30079;
 
+/// Addresses scanned per xpub response page
+const XPUB_PAGE_SIZE: u32 = 20;
+
+/// How long an unfinished paginated xpub scan is kept around
+const XPUB_SCAN_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How long a (pubkey, req id) is remembered to skip relay redeliveries
+const SEEN_REQUEST_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// server_ping is cheap but still gets its own per-pubkey limit
+const PING_RATE_LIMIT_PER_MINUTE: u32 = 10;
+
+/// How long the dust accounting fee rate is reused before asking Electrs again
+const DUST_FEE_RATE_TTL: Duration = Duration::from_secs(60);
+
+/// Maximum addresses in one bulk_balance request
+const BULK_BALANCE_MAX_ADDRESSES: usize = 10;
+
+/// "fee_estimate" target when the request names none, and the largest accepted
+/// (Bitcoin Core's estimatesmartfee limit)
+const DEFAULT_FEE_TARGET_BLOCKS: usize = 6;
+const MAX_FEE_TARGET_BLOCKS: usize = 1008;
+
+/// Published responses remembered for GET /events/recent
+const MAX_PUBLISHED_EVENTS: usize = 100;
+
+/// Normal and Low priority requests handled at once (they query Electrs)
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+
+/// Low priority requests (xpub scans) handled at once
+const MAX_CONCURRENT_LOW_PRIORITY: usize = 1;
+
+/// Requests waiting for a slot; past this, new ones are turned away
+const MAX_QUEUED_REQUESTS: usize = 256;
+
+/// How long start_listening waits for in-flight requests once shut down
+const NOSTR_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a response may take to be accepted by the relays
+const PUBLISH_CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Relays that must accept a response for the publish to count as delivered
+const PUBLISH_REQUIRED_CONFIRMATIONS: usize = 1;
+
+/// Suggested retry delay for requests that arrive before Electrs is warmed up
+const NOT_READY_RETRY_AFTER_MS: u64 = 5000;
+
+/// xpub_addresses never touches Electrs, so it may be called a bit more often
+const XPUB_ADDRESSES_RATE_LIMIT_PER_MINUTE: u32 = 20;
+
+/// Addresses returned by xpub_addresses when `count` is omitted, and the cap
+const XPUB_ADDRESSES_DEFAULT_COUNT: u32 = 5;
+const XPUB_ADDRESSES_MAX_COUNT: u32 = 20;
+
 /* -------------------- Request / Response -------------------- */
 
 #[derive(Debug, Serialize, Deserialize)]
 struct BitcoinLookupRequest {
     #[serde(rename = "type")]
     req_type: String,
+    /// Request version; absent for apps predating version negotiation
+    #[serde(default)]
+    v: Option<u32>,
+    #[serde(default)]
     query: String,
+    /// Only used by "update_relays"
+    #[serde(default)]
+    relays: Vec<String>,
+    /// Continuation point of a paginated xpub scan, e.g. "m/0/21"
+    #[serde(default)]
+    cursor: Option<String>,
+    /// Number of snapshots for "balance_history"
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Number of addresses for "xpub_addresses"
+    #[serde(default)]
+    count: Option<u32>,
+    /// Challenge from the pairing QR code, for "pair"
+    #[serde(default)]
+    challenge: Option<String>,
+    /// Optional HMAC key for "pair" (32 bytes, hex); once set, every request
+    /// of the device must carry a valid `["hmac", …]` tag (see `verify_hmac`)
+    #[serde(default)]
+    shared_secret: Option<String>,
+    /// Addresses for "bulk_balance" (older clients send them newline-separated in `query`)
+    #[serde(default)]
+    addresses: Vec<String>,
+    /// Derivation standard of an xpub query ("bip44", "bip49", "bip84", "bip86")
+    #[serde(default)]
+    standard: Option<String>,
+    /// Account index of an xpub query (0..=100), checked against the xpub
+    #[serde(default)]
+    account: Option<u32>,
+    /// Gap limit of an xpub query (1..=200); XPUB_GAP_LIMIT when absent
+    #[serde(default)]
+    gap_limit: Option<u32>,
+    /// xpub queries: also return the funded addresses with their paths
+    #[serde(default)]
+    include_addresses: bool,
+    /// Transaction for "tx_detail" (older clients send it in `query`)
+    #[serde(default)]
+    txid: Option<String>,
+    /// Address whose net amount "tx_detail" reports
+    #[serde(default)]
+    address: Option<String>,
+    /// Confirmation target in blocks for "fee_estimate"
+    #[serde(default)]
+    target: Option<usize>,
+}
+
+impl BitcoinLookupRequest {
+    /// Requested gap limit, XPUB_GAP_LIMIT when absent; None if out of range
+    fn checked_gap_limit(&self) -> Option<u32> {
+        match self.gap_limit {
+            Some(gap_limit) => xpub::valid_gap_limit(gap_limit).then_some(gap_limit),
+            None => Some(config::get_gap_limit()),
+        }
+    }
 }
 
 /*
@@ -30,47 +165,619 @@ struct BitcoinLookupRequest {
 struct BitcoinLookupResponse {
     // Android MVP fields
     req: String,
+    protocol_version: &'static str,
     confirmedBalance: u64,
     unconfirmedBalance: u64,
     confirmations: u64,
     amount: u64,
+    /// Relay that delivered the request (diagnostics)
+    source_relay: Option<String>,
 
     // Modern fields
     confirmed_balance: u64,
     unconfirmed_balance: u64,
     transactions: Vec<TransactionInfo>,
+    /// UTXOs below the dust threshold at the current fee rate, and their total
+    dust_utxo_count: u32,
+    dust_total_sat: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct XpubLookupResponse {
+    req: String,
+    protocol_version: &'static str,
+    // Android MVP fields
+    confirmedBalance: u64,
+    unconfirmedBalance: u64,
+
+    // Running totals across all pages scanned so far
+    confirmed_balance: u64,
+    unconfirmed_balance: u64,
+    /// Confirmed running totals split by chain: receiving (m/0) vs change (m/1)
+    external_confirmed_sat: u64,
+    internal_confirmed_sat: u64,
+    /// Used addresses found in this page only
+    address_breakdown: Vec<AddressBalance>,
+    /// The funded entries of `address_breakdown`, only with `include_addresses`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addresses: Option<Vec<AddressBalance>>,
+    next_cursor: Option<String>,
+    has_more: bool,
+}
+
+/// Per-request options of a paginated xpub scan
+struct XpubPageRequest {
+    gap_limit: u32,
+    cursor: Option<String>,
+    include_addresses: bool,
+}
+
+/// Sent instead of a result while the server is still starting up
+#[derive(Debug, Serialize)]
+struct NotReadyResponse {
+    req: String,
+    status: &'static str,
+    retry_after_ms: u64,
+}
+
+/// Sent before a fresh xpub scan so the client can show progress
+#[derive(Debug, Serialize)]
+struct ScanProgressResponse {
+    req: String,
+    status: &'static str,
+    estimated_address_count: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct AddressBalance {
+    path: String,
+    address: String,
+    confirmed: u64,
+    unconfirmed: u64,
+}
+
+impl AddressBalance {
+    /// Used addresses that have since been emptied are not funded
+    fn is_funded(&self) -> bool {
+        self.confirmed > 0 || self.unconfirmed > 0
+    }
+}
+
+/// The parts of an authenticated request the handler needs: a signed
+/// event's fields, or those of a gift-wrapped rumor
+struct RequestEnvelope<'a> {
+    pubkey: PublicKey,
+    req_id: String,
+    tags: &'a Tags,
+    created_at: Timestamp,
+    content: &'a str,
+}
+
+/// Processing order of queued requests: status checks must not wait behind
+/// a 40-address xpub scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum RequestPriority {
+    /// No Electrs access: never waits for a request slot
+    High,
+    /// One Electrs lookup: takes a request slot
+    Normal,
+    /// Gap-limit scans: a request slot, and at most MAX_CONCURRENT_LOW_PRIORITY at once
+    Low,
+    /// Sender isn't paired, so only "pair" can succeed: handled after
+    /// everything else, content still encrypted, no request slot
+    Unpaired,
+}
+
+impl RequestPriority {
+    fn for_request(req_type: &str, query: &str) -> Self {
+        match req_type {
+            "server_ping" | "server_stats" | "address_validate" | "balance_history"
+            | "xpub_info" | "xpub_addresses" | "update_relays" | "pair" | "unpair"
+            | "unwatch_xpub" => {
+                RequestPriority::High
+            }
+            "watch_xpub" => RequestPriority::Low,
+            "bitcoin_lookup" | "list_utxos" => {
+                let query = query.trim();
+                if xpub::is_xpub(query) || xpub::is_descriptor(query) {
+                    RequestPriority::Low
+                } else {
+                    RequestPriority::Normal
+                }
+            }
+            _ => RequestPriority::Normal,
+        }
+    }
+
+    /// Unparseable content gets Normal; handle_request rejects it anyway
+    fn of_content(content: &str) -> Self {
+        match serde_json::from_str::<BitcoinLookupRequest>(content) {
+            Ok(request) => Self::for_request(&request.req_type, &request.query),
+            Err(_) => RequestPriority::Normal,
+        }
+    }
+}
+
+/// Request types with a per-pubkey limit of their own (server_ping,
+/// xpub_addresses) or none at all (address_validate, a local check), so
+/// they don't use up the shared REQUEST_RATE_LIMIT_PER_MINUTE budget
+fn has_own_rate_limit(content: &str) -> bool {
+    serde_json::from_str::<BitcoinLookupRequest>(content).is_ok_and(|request| {
+        matches!(request.req_type.as_str(), "server_ping" | "xpub_addresses" | "address_validate")
+    })
+}
+
+/// Content of a queued request
+enum RequestContent {
+    Plain(String),
+    /// NIP-44 ciphertext of an unpaired sender, decrypted when its turn comes
+    Encrypted(String),
+}
+
+/// A request from an authenticated sender, waiting for its turn
+struct IncomingRequest {
+    sender: PublicKey,
+    req_id: String,
+    tags: Tags,
+    created_at: Timestamp,
+    content: RequestContent,
+    relay_url: RelayUrl,
+    received_at: Instant,
+}
+
+/// What `NostrHandler::admit` decided for a request event
+enum Admission {
+    Queue(RequestPriority, IncomingRequest),
+    /// Over the sender's limit: answer right away, don't let it wait in the queue
+    RateLimited(PublicKey, String),
+    /// Forged, undecryptable, without req tag, or a redelivery
+    Drop,
+}
+
+/// Queue entry: highest priority first, then first come first served
+struct QueuedRequest {
+    priority: RequestPriority,
+    seq: u64,
+    request: IncomingRequest,
+}
+
+impl QueuedRequest {
+    fn key(&self) -> (Reverse<RequestPriority>, Reverse<u64>) {
+        (Reverse(self.priority), Reverse(self.seq))
+    }
+}
+
+impl PartialEq for QueuedRequest {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for QueuedRequest {}
+
+impl PartialOrd for QueuedRequest {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedRequest {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Outcome of `NostrHandler::publish_to_all_relays`
+#[derive(Debug)]
+struct PublishResult {
+    event_id: EventId,
+    confirmed_by: Vec<String>,
+    /// (relay, error message)
+    failed_on: Vec<(String, String)>,
+}
+
+/// Progress of a paginated xpub scan, resumed via `cursor`
+#[derive(Debug, Clone)]
+struct ScanState {
+    scan: xpub::GapScan,
+    confirmed: u64,
+    unconfirmed: u64,
+    external_confirmed: u64,
+    internal_confirmed: u64,
+    updated_at: Instant,
+}
+
+impl ScanState {
+    fn new(gap_limit: u32) -> Self {
+        Self {
+            scan: xpub::GapScan::new(gap_limit),
+            confirmed: 0,
+            unconfirmed: 0,
+            external_confirmed: 0,
+            internal_confirmed: 0,
+            updated_at: Instant::now(),
+        }
+    }
+
+    fn cursor(&self) -> String {
+        self.scan.cursor()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct XpubInfoResponse {
+    req: String,
+    network: String,
+    depth: u8,
+    parent_fingerprint: String,
+    child_number: u32,
+    chain_code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct XpubAddressesResponse {
+    req: String,
+    addresses: Vec<xpub::TypedAddress>,
+}
+
+#[derive(Debug, Serialize)]
+struct BalanceHistoryResponse {
+    req: String,
+    address: String,
+    snapshots: Vec<BalanceSnapshot>,
+}
+
+#[derive(Debug, Serialize)]
+struct AddressValidateResponse {
+    req: String,
+    #[serde(flatten)]
+    validation: xpub::AddressValidation,
+}
+
+#[derive(Debug, Serialize)]
+struct ServerPongResponse {
+    #[serde(rename = "type")]
+    resp_type: &'static str,
+    req: String,
+    server_time_utc: String,
+    /// Event receipt to response publication
+    processing_time_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ServerStatsResponse {
+    #[serde(rename = "type")]
+    resp_type: &'static str,
+    req: String,
+    uptime_secs: u64,
+    requests_served: u64,
+    errors_total: u64,
+    electrs_calls: u64,
+    electrs_errors: u64,
+    /// 0.0 when nothing was looked up yet
+    cache_hit_rate: f64,
+    relay_connection_count: u32,
+    pairing_count: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct FeeHistogramResponse {
+    req: String,
+    buckets: Vec<FeeHistogramBucket>,
+    /// Next block
+    fast_fee: f64,
+    /// Within ~3 blocks
+    normal_fee: f64,
+    /// Within ~6 blocks
+    slow_fee: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct BulkBalanceResponse {
+    req: String,
+    balances: Vec<BulkBalanceEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct BulkBalanceEntry {
+    address: String,
+    confirmed_sat: u64,
+    unconfirmed_sat: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct PairResponse {
+    req: String,
+    #[serde(rename = "type")]
+    resp_type: &'static str,
+    status: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct UnpairResponse {
+    req: String,
+    #[serde(rename = "type")]
+    resp_type: &'static str,
+    status: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct UpdateRelaysResponse {
+    req: String,
+    #[serde(rename = "type")]
+    resp_type: &'static str,
+    /// Android's relays merged with the server's own relays
+    relays: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct WatchXpubResponse {
+    req: String,
+    #[serde(rename = "type")]
+    resp_type: &'static str,
+    /// "watching" or "already_watching"
+    status: &'static str,
+    watched_count: usize,
+    /// Baseline the watcher compares against (None if the first scan failed)
+    snapshot: Option<XpubSnapshot>,
+}
+
+#[derive(Debug, Serialize)]
+struct UnwatchXpubResponse {
+    req: String,
+    #[serde(rename = "type")]
+    resp_type: &'static str,
+    /// "removed" or "not_watched"
+    status: &'static str,
+    watched_count: usize,
 }
 
+/// Pushed by the xpub watcher when a watched xpub's totals change
 #[derive(Debug, Serialize)]
-struct TransactionInfo {
-    txid: String,
+struct XpubChangedNotification {
+    /// Request id of the original watch_xpub request
+    req: String,
+    #[serde(rename = "type")]
+    resp_type: &'static str,
+    xpub: String,
+    previous: XpubSnapshot,
+    current: XpubSnapshot,
+}
+
+/* -------------------- Rate limiting -------------------- */
+
+/// Tokens a requester has left as of `refilled_at`
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token bucket per requester pubkey, over all request types.
+///
+/// A pubkey may burst `capacity` requests and then gets one more every
+/// `window / capacity`. Buckets idle for a whole window are full again, so
+/// they are dropped (at most once per window) instead of kept forever.
+pub struct RequestRateLimiter {
+    capacity: u32,
+    window: Duration,
+    buckets: Mutex<HashMap<PublicKey, Bucket>>,
+    last_cleanup: Mutex<Instant>,
+}
+
+impl RequestRateLimiter {
+    pub fn new(capacity: u32, window: Duration) -> Self {
+        Self {
+            capacity,
+            window,
+            buckets: Mutex::new(HashMap::new()),
+            last_cleanup: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Take a token for `pubkey`; false if its bucket is empty
+    pub fn check(&self, pubkey: &PublicKey) -> bool {
+        self.check_at(pubkey, Instant::now())
+    }
+
+    pub fn check_at(&self, pubkey: &PublicKey, now: Instant) -> bool {
+        self.cleanup_if_due(now);
+
+        let capacity = f64::from(self.capacity);
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(*pubkey).or_insert(Bucket {
+            tokens: capacity,
+            refilled_at: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        let refill = elapsed.as_secs_f64() * capacity / self.window.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drop the buckets of pubkeys that sent nothing for a whole window
+    pub fn cleanup(&self, now: Instant) {
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, b| now.saturating_duration_since(b.refilled_at) < self.window);
+    }
+
+    /// Pubkeys currently tracked
+    pub fn tracked(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+
+    fn cleanup_if_due(&self, now: Instant) {
+        let mut last_cleanup = self.last_cleanup.lock().unwrap();
+        if now.saturating_duration_since(*last_cleanup) >= self.window {
+            *last_cleanup = now;
+            drop(last_cleanup);
+            self.cleanup(now);
+        }
+    }
+}
+
+/// Recently started requests by (sender, req id).
+///
+/// Every relay we read from delivers the same request, so without this each
+/// copy would run the full lookup and publish its own response. Entries
+/// expire after `ttl`, counted from the first delivery.
+pub struct SeenRequests {
+    ttl: Duration,
+    seen: Mutex<HashMap<(PublicKey, String), Instant>>,
+}
+
+impl SeenRequests {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remember the request; false if it was already seen within the TTL
+    pub fn first_seen(&self, pubkey: &PublicKey, req_id: &str, now: Instant) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, at| now.saturating_duration_since(*at) < self.ttl);
+
+        match seen.entry((*pubkey, req_id.to_string())) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(slot) => {
+                slot.insert(now);
+                true
+            }
+        }
+    }
+
+    /// Let a later delivery of the request run again (e.g. after not_ready)
+    pub fn forget(&self, pubkey: &PublicKey, req_id: &str) {
+        self.seen
+            .lock()
+            .unwrap()
+            .remove(&(*pubkey, req_id.to_string()));
+    }
 }
 
 /* -------------------- Handler -------------------- */
 
 pub struct NostrHandler {
     client: Arc<Client>,
+    relay_latencies: RelayLatencies,
+    published_events: PublishedEvents,
     keys: Keys,
-    electrs_client: Arc<ElectrsClient>,
+    pairing_manager: PairingManager,
+    electrs_client: Arc<dyn ElectrsClientTrait>,
+    balance_history: BalanceHistoryStore,
+    xpub_watch: XpubWatchList,
+    // false until Electrs warm-up is done (see main)
+    is_ready: Arc<AtomicBool>,
+    // Counts requests being handled so shutdown can wait for them
+    shutdown: ShutdownCoordinator,
+    // Cancelled on SIGTERM; ends start_listening
+    shutdown_token: ShutdownToken,
+    // Unfinished xpub scans keyed by "<requester pubkey>:<xpub>"
+    xpub_scans: Arc<Mutex<HashMap<String, ScanState>>>,
+    // Requests already started, so relay redeliveries are skipped
+    seen_requests: SeenRequests,
+    // Every request without a limit of its own, checked before it is queued
+    request_limiter: RequestRateLimiter,
+    ping_limiter: RequestRateLimiter,
+    xpub_addresses_limiter: RequestRateLimiter,
+    // Slots for Normal/Low priority requests, and the extra limit on Low ones
+    request_slots: Arc<Semaphore>,
+    low_priority_slots: Arc<Semaphore>,
+    // Last dust fee rate and when it was fetched (see dust_fee_rate)
+    dust_fee: Mutex<Option<(f64, Instant)>>,
+    // Responses go here instead of to the relays (see with_outbox)
+    #[cfg(feature = "testing")]
+    outbox: Option<mpsc::UnboundedSender<(PublicKey, String)>>,
 }
 
 impl NostrHandler {
     pub async fn new(
         nostr_state: NostrState,
         keys: Keys,
-        _pairing_manager: PairingManager,
-        electrs_client: Arc<ElectrsClient>,
+        pairing_manager: PairingManager,
+        electrs_client: Arc<dyn ElectrsClientTrait>,
+        balance_history: BalanceHistoryStore,
+        xpub_watch: XpubWatchList,
+        is_ready: Arc<AtomicBool>,
+        shutdown: ShutdownCoordinator,
     ) -> Result<Self> {
         Ok(Self {
             client: nostr_state.client.clone(),
+            relay_latencies: nostr_state.relay_latencies.clone(),
+            published_events: nostr_state.published_events.clone(),
             keys,
+            pairing_manager,
             electrs_client,
+            balance_history,
+            xpub_watch,
+            is_ready,
+            shutdown_token: shutdown.token(),
+            shutdown,
+            xpub_scans: Arc::new(Mutex::new(HashMap::new())),
+            seen_requests: SeenRequests::new(SEEN_REQUEST_TTL),
+            request_limiter: RequestRateLimiter::new(
+                config::get_request_rate_limit(),
+                Duration::from_secs(60),
+            ),
+            ping_limiter: RequestRateLimiter::new(
+                PING_RATE_LIMIT_PER_MINUTE,
+                Duration::from_secs(60),
+            ),
+            xpub_addresses_limiter: RequestRateLimiter::new(
+                XPUB_ADDRESSES_RATE_LIMIT_PER_MINUTE,
+                Duration::from_secs(60),
+            ),
+            request_slots: Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS)),
+            low_priority_slots: Arc::new(Semaphore::new(MAX_CONCURRENT_LOW_PRIORITY)),
+            dust_fee: Mutex::new(None),
+            #[cfg(feature = "testing")]
+            outbox: None,
         })
     }
 
+    /// Capture responses as (recipient, JSON) instead of publishing them
+    /// (feature "testing")
+    #[cfg(feature = "testing")]
+    pub fn with_outbox(mut self, outbox: mpsc::UnboundedSender<(PublicKey, String)>) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Admit and handle one request event right away, skipping the queue
+    /// and its slots (feature "testing")
+    #[cfg(feature = "testing")]
+    pub async fn handle_notification(&self, event: Event, relay_url: RelayUrl) {
+        match self.admit(Box::new(event), relay_url).await {
+            Admission::Queue(_, request) => self.handle_incoming(request).await,
+            Admission::RateLimited(sender, req_id) => {
+                self.reject_rate_limited(sender, req_id).await
+            }
+            Admission::Drop => {}
+        }
+    }
+
+    /// Handle requests until the notification stream fails (Err) or the
+    /// shutdown token is cancelled (Ok, after in-flight requests drained)
     pub async fn start_listening(&self) -> Result<()> {
+        // Only requests p-tagged to THIS server
         let filter = Filter::new()
-            .kinds(vec![Kind::Custom(BALANCEBRIDGE_REQUEST_KIND)]);
+            .kind(Kind::Custom(BALANCEBRIDGE_REQUEST_KIND))
+            .custom_tag(
+                SingleLetterTag::lowercase(Alphabet::P),
+                self.keys.public_key().to_hex(),
+            );
 
         self.client.subscribe(filter, None).await?;
 
@@ -79,132 +786,2274 @@ impl NostrHandler {
             BALANCEBRIDGE_REQUEST_KIND
         );
 
-        let mut notifications = self.client.notifications();
+        // NIP-17 style: the same requests, gift-wrapped to hide the sender
+        let gift_wrap_filter = Filter::new()
+            .kind(Kind::GiftWrap)
+            .pubkey(self.keys.public_key());
+        self.client.subscribe(gift_wrap_filter, None).await?;
 
-        // IMPORTANT: never exit this loop on bad events
-        while let Ok(notification) = notifications.recv().await {
-            if let RelayPoolNotification::Event { event, .. } = notification {
-                if event.kind.as_u16() != BALANCEBRIDGE_REQUEST_KIND {
-                    continue;
-                }
+        // Follow the paired devices' NIP-65 relay lists so we also read from their relays
+        let paired = self.pairing_manager.paired_pubkeys()?;
+        if !paired.is_empty() {
+            let relay_list_filter = Filter::new()
+                .kind(Kind::RelayList)
+                .authors(paired.clone())
+                .limit(paired.len());
+            self.client.subscribe(relay_list_filter, None).await?;
+            info!("Subscribed to NIP-65 relay lists of {} paired device(s)", paired.len());
+        }
 
-                let from_pk = event.pubkey;
+        let mut notifications = self.client.notifications();
 
-                // 🔑 FIX: ignore events without req tag instead of crashing
-                let req_id = match extract_req_id(&event) {
-                    Some(v) => v,
-                    None => {
-                        warn!(
-                            "Ignoring BalanceBridge request without req tag (from={})",
-                            from_pk.to_hex()
-                        );
-                        continue;
-                    }
-                };
+        // Requests wait here until dispatch_queued starts them; started ones
+        // run concurrently in `running` (borrowing self, so no spawn needed)
+        let mut queue: BinaryHeap<QueuedRequest> = BinaryHeap::new();
+        let mut next_seq: u64 = 0;
+        let mut running: FuturesUnordered<BoxFuture<'_, ()>> = FuturesUnordered::new();
+        let mut failure = None;
 
-                let parsed: BitcoinLookupRequest =
-                    match serde_json::from_str(&event.content) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            warn!(
-                                "Invalid request JSON (from={} req={}): {}",
-                                from_pk.to_hex(),
-                                req_id,
-                                e
-                            );
-                            continue;
-                        }
-                    };
+        // IMPORTANT: never exit this loop on bad events (or on lag)
+        loop {
+            self.dispatch_queued(&mut queue, &mut running);
 
-                if parsed.req_type != "bitcoin_lookup" {
+            let received = tokio::select! {
+                received = notifications.recv() => received,
+                Some(()) = running.next(), if !running.is_empty() => continue,
+                _ = self.shutdown_token.cancelled() => break,
+            };
+
+            let notification = match received {
+                Ok(n) => n,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Nostr notifications lagged by {}; continuing", n);
                     continue;
                 }
+                Err(e) => {
+                    failure = Some(anyhow!("notifications recv failed: {e:?}"));
+                    break;
+                }
+            };
 
-                let address = parsed.query.clone();
-
-                info!(
-                    "Nostr lookup request: from={} req={} query={}",
-                    from_pk.to_hex(),
-                    req_id,
-                    address
-                );
+            if let RelayPoolNotification::Event {
+                relay_url, event, ..
+            } = notification
+            {
+                if event.kind == Kind::RelayList {
+                    self.handle_relay_list(&event).await;
+                    continue;
+                }
 
-                if let Err(e) = self
-                    .lookup_and_publish(from_pk, &req_id, address)
-                    .await
+                if event.kind != Kind::GiftWrap
+                    && event.kind.as_u16() != BALANCEBRIDGE_REQUEST_KIND
                 {
-                    error!(
-                        "Lookup failed: from={} req={} err={}",
-                        from_pk.to_hex(),
-                        req_id,
-                        e
-                    );
+                    continue;
+                }
+
+                match self.admit(event, relay_url).await {
+                    Admission::Queue(priority, request) if queue.len() >= MAX_QUEUED_REQUESTS => {
+                        warn!(
+                            "Request queue full ({}), turning away: from={} req={}",
+                            queue.len(),
+                            request.sender.to_hex(),
+                            request.req_id
+                        );
+                        // Paired devices learn to back off; a flood of
+                        // unpaired senders doesn't get a publish per event
+                        if priority != RequestPriority::Unpaired {
+                            running.push(Box::pin(
+                                self.reject_rate_limited(request.sender, request.req_id),
+                            ));
+                        } else {
+                            self.seen_requests.forget(&request.sender, &request.req_id);
+                        }
+                    }
+                    Admission::Queue(priority, request) => {
+                        queue.push(QueuedRequest {
+                            priority,
+                            seq: next_seq,
+                            request,
+                        });
+                        next_seq += 1;
+                    }
+                    Admission::RateLimited(sender, req_id) => {
+                        running.push(Box::pin(self.reject_rate_limited(sender, req_id)));
+                    }
+                    Admission::Drop => {}
                 }
             }
         }
 
-        Ok(())
+        if failure.is_none() {
+            info!("NostrHandler shutting down — draining in-flight requests");
+        }
+        if !queue.is_empty() {
+            warn!("Dropping {} queued request(s) that never started", queue.len());
+        }
+        let drained = timeout(NOSTR_DRAIN_TIMEOUT, async {
+            while running.next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            warn!(
+                "{} request(s) still in flight after {:?}",
+                running.len(),
+                NOSTR_DRAIN_TIMEOUT
+            );
+        }
+
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
-    async fn lookup_and_publish(
-        &self,
-        to_pubkey: PublicKey,
+    /// Start queued requests, highest priority first, while they get a slot.
+    /// High priority and unpaired ones need no slot; the first one that has
+    /// to wait blocks everything behind it (all of the same or lower priority).
+    fn dispatch_queued<'a>(
+        &'a self,
+        queue: &mut BinaryHeap<QueuedRequest>,
+        running: &mut FuturesUnordered<BoxFuture<'a, ()>>,
+    ) {
+        while let Some(next) = queue.peek() {
+            let permits = match next.priority {
+                RequestPriority::High | RequestPriority::Unpaired => (None, None),
+                RequestPriority::Normal => {
+                    match Arc::clone(&self.request_slots).try_acquire_owned() {
+                        Ok(slot) => (Some(slot), None),
+                        Err(_) => break,
+                    }
+                }
+                RequestPriority::Low => {
+                    let Ok(low) = Arc::clone(&self.low_priority_slots).try_acquire_owned() else {
+                        break;
+                    };
+                    let Ok(slot) = Arc::clone(&self.request_slots).try_acquire_owned() else {
+                        break;
+                    };
+                    (Some(slot), Some(low))
+                }
+            };
+
+            let Some(queued) = queue.pop() else { break };
+            running.push(Box::pin(async move {
+                let _permits = permits;
+                let _in_flight = self.shutdown.track();
+                self.handle_incoming(queued.request).await;
+            }));
+        }
+    }
+
+    async fn reject_rate_limited(&self, sender: PublicKey, req_id: String) {
+        warn!("Rate limited: from={} req={}", sender.to_hex(), req_id);
+        // rate_limited is retryable, and the retry carries the same req id
+        self.seen_requests.forget(&sender, &req_id);
+        self.reply_error(sender, &req_id, LookupError::RateLimited).await;
+    }
+
+    /// Authenticate a request event (kind 30078 or a gift wrap) and decide
+    /// what happens to it, before anything is decrypted for the queue.
+    ///
+    /// Signed events count only once their signature checks out, so a
+    /// forged pubkey can't use up or shadow someone else's requests. Content
+    /// of paired senders is decrypted here, once, to pick the priority;
+    /// unpaired senders are queued last without decrypting anything.
+    async fn admit(&self, event: Box<Event>, relay_url: RelayUrl) -> Admission {
+        let received_at = Instant::now();
+
+        let (sender, tags, created_at, content) = if event.kind == Kind::GiftWrap {
+            let Some(unwrapped) = self.handle_gift_wrap(&event).await else {
+                return Admission::Drop;
+            };
+            let rumor = unwrapped.rumor;
+            (
+                unwrapped.sender,
+                rumor.tags,
+                rumor.created_at,
+                RequestContent::Plain(rumor.content),
+            )
+        } else {
+            // Never route a response to a pubkey we haven't proven sent the event
+            if let Err(e) = verify_event_signature(&event) {
+                warn!(
+                    "Dropping BalanceBridge request with invalid signature (from={} id={}): {}",
+                    event.pubkey.to_hex(),
+                    event.id.to_hex(),
+                    e
+                );
+                return Admission::Drop;
+            }
+            let event = *event;
+            (
+                event.pubkey,
+                event.tags,
+                event.created_at,
+                RequestContent::Encrypted(event.content),
+            )
+        };
+
+        // 🔑 FIX: ignore events without req tag instead of crashing
+        let Some(req_id) = extract_req_id(&tags) else {
+            warn!(
+                "Ignoring BalanceBridge request without req tag (from={})",
+                sender.to_hex()
+            );
+            return Admission::Drop;
+        };
+
+        if !self.seen_requests.first_seen(&sender, &req_id, received_at) {
+            debug!(
+                "Skipping redelivered request: from={} req={}",
+                sender.to_hex(),
+                req_id
+            );
+            return Admission::Drop;
+        }
+
+        let paired = match self.pairing_manager.is_paired(&sender) {
+            Ok(paired) => paired,
+            Err(e) => {
+                error!("Failed to read pairing: {}", e);
+                false
+            }
+        };
+
+        let (priority, content) = if paired {
+            // Requests are NIP-44 encrypted to our key, so relays only see ciphertext
+            let plain = match content {
+                RequestContent::Plain(plain) => plain,
+                RequestContent::Encrypted(ciphertext) => {
+                    match decrypt_content(&self.keys, &sender, &ciphertext) {
+                        Ok(plain) => plain,
+                        Err(e) => {
+                            warn!(
+                                "Dropping BalanceBridge request that failed NIP-44 decryption (from={} req={}): {}",
+                                sender.to_hex(),
+                                req_id,
+                                e
+                            );
+                            return Admission::Drop;
+                        }
+                    }
+                }
+            };
+            (RequestPriority::of_content(&plain), RequestContent::Plain(plain))
+        } else {
+            (RequestPriority::Unpaired, content)
+        };
+
+        let own_limit = matches!(&content, RequestContent::Plain(plain) if has_own_rate_limit(plain));
+        if !own_limit && !self.request_limiter.check(&sender) {
+            return Admission::RateLimited(sender, req_id);
+        }
+
+        Admission::Queue(
+            priority,
+            IncomingRequest {
+                sender,
+                req_id,
+                tags,
+                created_at,
+                content,
+                relay_url,
+                received_at,
+            },
+        )
+    }
+
+    async fn handle_incoming(&self, request: IncomingRequest) {
+        let content = match request.content {
+            RequestContent::Plain(plain) => plain,
+            RequestContent::Encrypted(ciphertext) => {
+                match decrypt_content(&self.keys, &request.sender, &ciphertext) {
+                    Ok(plain) => plain,
+                    Err(e) => {
+                        warn!(
+                            "Dropping BalanceBridge request that failed NIP-44 decryption (from={} req={}): {}",
+                            request.sender.to_hex(),
+                            request.req_id,
+                            e
+                        );
+                        return;
+                    }
+                }
+            }
+        };
+
+        let envelope = RequestEnvelope {
+            pubkey: request.sender,
+            req_id: request.req_id,
+            tags: &request.tags,
+            created_at: request.created_at,
+            content: &content,
+        };
+        self.handle_request(envelope, request.relay_url.to_string(), request.received_at)
+            .await;
+    }
+
+    /// Add relays from a paired device's NIP-65 list as read-only relays
+    async fn handle_relay_list(&self, event: &Event) {
+        if !matches!(self.pairing_manager.is_paired(&event.pubkey), Ok(true)) {
+            return;
+        }
+
+        if let Err(e) = verify_event_signature(event) {
+            warn!("Dropping NIP-65 event with invalid signature: {}", e);
+            return;
+        }
+
+        for (url, marker) in relays::parse_nip65_event(event) {
+            // We read the device's relays; it decides where it writes
+            match self.client.add_read_relay(url.as_str()).await {
+                Ok(true) => {
+                    info!("Discovered relay via NIP-65: {} ({:?})", url, marker);
+                    if let Err(e) = self.client.connect_relay(url.as_str()).await {
+                        warn!("Failed to connect discovered relay {}: {}", url, e);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => warn!("Failed to add discovered relay {}: {}", url, e),
+            }
+        }
+    }
+
+    /// Unwrap a NIP-59 gift wrap (kind 1059) addressed to us. Returns the
+    /// sender and the inner rumor if it is a BalanceBridge request.
+    ///
+    /// The seal is signed by the sender and nostr-sdk checks that the rumor
+    /// claims the same author, so the sender is as authenticated as with a
+    /// plain signed request.
+    async fn handle_gift_wrap(&self, event: &Event) -> Option<UnwrappedGift> {
+        let unwrapped = match UnwrappedGift::from_gift_wrap(&self.keys, event).await {
+            Ok(u) => u,
+            Err(e) => {
+                warn!("Failed to unwrap gift wrap {}: {}", event.id.to_hex(), e);
+                return None;
+            }
+        };
+
+        if unwrapped.rumor.kind.as_u16() != BALANCEBRIDGE_REQUEST_KIND {
+            debug!("Ignoring gift-wrapped kind {}", unwrapped.rumor.kind.as_u16());
+            return None;
+        }
+        if unwrapped.rumor.pubkey != unwrapped.sender {
+            warn!(
+                "Dropping gift-wrapped request whose author differs from the seal (sender={})",
+                unwrapped.sender.to_hex()
+            );
+            return None;
+        }
+
+        Some(unwrapped)
+    }
+
+    /// Everything after authentication: HMAC, parsing, pairing, readiness, dispatch
+    async fn handle_request(
+        &self,
+        request: RequestEnvelope<'_>,
+        source_relay: String,
+        received_at: Instant,
+    ) {
+        let from_pk = request.pubkey;
+
+        metrics::metrics()
+            .requests_by_relay
+            .with_label_values(&[source_relay.as_str()])
+            .inc();
+
+        let req_id = request.req_id.clone();
+
+        // Optional HMAC layer on top of the event signature
+        match self.pairing_manager.get_shared_secret(&from_pk) {
+            Ok(Some(secret)) => {
+                if !Self::verify_request_hmac(&request, &secret) {
+                    warn!(
+                        "Dropping BalanceBridge request with bad HMAC (from={} req={})",
+                        from_pk.to_hex(),
+                        req_id
+                    );
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to read pairing shared secret: {}", e);
+                return;
+            }
+        }
+
+        let parsed: BitcoinLookupRequest =
+            match serde_json::from_str(request.content) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!(
+                        "Invalid request JSON (from={} req={}): {}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                    self.reply_error(from_pk, &req_id, LookupError::InvalidJson).await;
+                    return;
+                }
+            };
+
+        // Checked before pairing so an app that is too new learns why "pair" fails
+        let version = match protocol::negotiate_version(parsed.v) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!(
+                    "Unsupported request version: from={} req={} v={:?}",
+                    from_pk.to_hex(),
+                    req_id,
+                    parsed.v
+                );
+                self.reply_error(from_pk, &req_id, e).await;
+                return;
+            }
+        };
+        if parsed.v.is_some_and(|v| v < protocol::MIN_SUPPORTED_VERSION) {
+            info!(
+                "Answering outdated v{:?} request as v{}: from={} req={}",
+                parsed.v,
+                version,
+                from_pk.to_hex(),
+                req_id
+            );
+        }
+
+        // Only paired devices may query; "pair" (QR challenge) is how one gets paired
+        if parsed.req_type != "pair" {
+            match self.pairing_manager.is_paired(&from_pk) {
+                Ok(true) => {}
+                Ok(false) => {
+                    warn!(
+                        "Refusing {} request from unpaired pubkey: from={} req={}",
+                        parsed.req_type,
+                        from_pk.to_hex(),
+                        req_id
+                    );
+                    self.reply_error(from_pk, &req_id, LookupError::NotPaired).await;
+                    return;
+                }
+                Err(e) => {
+                    error!("Failed to read pairing: {}", e);
+                    return;
+                }
+            }
+        }
+
+        // After the pairing check: unpaired senders get nothing more than not_paired
+        if !self.is_ready.load(Ordering::Acquire) {
+            info!("Not ready yet, asking client to retry: from={} req={}", from_pk.to_hex(), req_id);
+            // The retry carries the same req id
+            self.seen_requests.forget(&from_pk, &req_id);
+            let response = NotReadyResponse {
+                req: req_id.clone(),
+                status: "not_ready",
+                retry_after_ms: NOT_READY_RETRY_AFTER_MS,
+            };
+            let result = match serde_json::to_string(&response) {
+                Ok(json) => self.publish_response(from_pk, &req_id, json).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                error!("Failed to send not_ready response: req={} err={}", req_id, e);
+            }
+            return;
+        }
+
+        match parsed.req_type.as_str() {
+            "bitcoin_lookup" => {
+                let address = parsed.query.clone();
+
+                info!(
+                    "Nostr lookup request: from={} req={} relay={} query={}",
+                    from_pk.to_hex(),
+                    req_id,
+                    source_relay,
+                    address
+                );
+
+                let gap_limit = match parsed.checked_gap_limit() {
+                    Some(gap_limit) if parsed.account.is_none_or(xpub::valid_account) => gap_limit,
+                    _ => {
+                        warn!(
+                            "Lookup params out of range (req={}): gap_limit={:?} account={:?}",
+                            req_id, parsed.gap_limit, parsed.account
+                        );
+                        self.reply_error(from_pk, &req_id, LookupError::InvalidParams).await;
+                        return;
+                    }
+                };
+                let page = XpubPageRequest {
+                    gap_limit,
+                    cursor: parsed.cursor,
+                    include_addresses: parsed.include_addresses,
+                };
+
+                let result = if address.trim().is_empty() {
+                    self.send_error(from_pk, &req_id, LookupError::EmptyQuery).await
+                } else if xpub::is_descriptor(&address) {
+                    match xpub::parse_descriptor(&address) {
+                        Ok(descriptor) => {
+                            self.xpub_lookup_and_publish(
+                                from_pk,
+                                &req_id,
+                                &descriptor.xpub,
+                                descriptor.address_type,
+                                page,
+                            )
+                            .await
+                        }
+                        Err(e) => {
+                            warn!("Bad descriptor (req={}): {}", req_id, e);
+                            self.send_error(from_pk, &req_id, LookupError::InvalidXpub).await
+                        }
+                    }
+                } else if let (Some(standard), Some(account)) = (&parsed.standard, parsed.account) {
+                    match standard.parse::<xpub::DerivationStandard>() {
+                        Ok(standard) => {
+                            let query = xpub::XpubQuery {
+                                xpub: address.trim().to_string(),
+                                standard,
+                                account,
+                            };
+                            self.standard_xpub_lookup_and_publish(
+                                from_pk,
+                                &req_id,
+                                query,
+                                page,
+                            )
+                            .await
+                        }
+                        Err(e) => {
+                            warn!("Bad derivation standard (req={}): {}", req_id, e);
+                            self.send_error(from_pk, &req_id, LookupError::InvalidQuery).await
+                        }
+                    }
+                } else if xpub::is_xpub(address.trim()) {
+                    // Bare keys: the prefix says the script type (zpub → bc1q…),
+                    // and with an account, the standard to check it against
+                    match xpub::prefix_address_type(address.trim()) {
+                        Ok(address_type) => match parsed.account {
+                            Some(account) => {
+                                let query = xpub::XpubQuery {
+                                    xpub: address.trim().to_string(),
+                                    standard: xpub::DerivationStandard::for_address_type(
+                                        address_type,
+                                    ),
+                                    account,
+                                };
+                                self.standard_xpub_lookup_and_publish(
+                                    from_pk,
+                                    &req_id,
+                                    query,
+                                    page,
+                                )
+                                .await
+                            }
+                            None => {
+                                self.xpub_lookup_and_publish(
+                                    from_pk,
+                                    &req_id,
+                                    address.trim(),
+                                    address_type,
+                                    page,
+                                )
+                                .await
+                            }
+                        },
+                        Err(e) => {
+                            warn!("Bad xpub prefix (req={}): {}", req_id, e);
+                            self.send_error(from_pk, &req_id, LookupError::InvalidXpub).await
+                        }
+                    }
+                } else if xpub::is_bitcoin_address(&address, self.electrs_client.network()) {
+                    self.lookup_and_publish(from_pk, &req_id, address, Some(source_relay.clone()))
+                        .await
+                } else if xpub::is_address_on_other_network(&address, self.electrs_client.network()) {
+                    warn!("Address is for another network (req={})", req_id);
+                    self.send_error(from_pk, &req_id, LookupError::WrongNetwork).await
+                } else {
+                    warn!("Query is not an address, key or descriptor (req={})", req_id);
+                    self.send_error(from_pk, &req_id, LookupError::InvalidQuery).await
+                };
+
+                if let Err(e) = result {
+                    error!(
+                        "Lookup failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "xpub_info" => {
+                info!(
+                    "Nostr xpub_info request: from={} req={}",
+                    from_pk.to_hex(),
+                    req_id
+                );
+
+                if let Err(e) = self.xpub_info_and_publish(from_pk, &req_id, &parsed.query).await {
+                    error!(
+                        "xpub_info failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "xpub_addresses" => {
+                if !self.xpub_addresses_limiter.check(&from_pk) {
+                    warn!("xpub_addresses rate limited: from={}", from_pk.to_hex());
+                    self.seen_requests.forget(&from_pk, &req_id);
+                    self.reply_error(from_pk, &req_id, LookupError::RateLimited).await;
+                    return;
+                }
+
+                let count = parsed
+                    .count
+                    .unwrap_or(XPUB_ADDRESSES_DEFAULT_COUNT)
+                    .clamp(1, XPUB_ADDRESSES_MAX_COUNT);
+
+                info!(
+                    "Nostr xpub_addresses request: from={} req={} count={}",
+                    from_pk.to_hex(),
+                    req_id,
+                    count
+                );
+
+                if let Err(e) = self
+                    .xpub_addresses_and_publish(from_pk, &req_id, &parsed.query, count)
+                    .await
+                {
+                    error!(
+                        "xpub_addresses failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "update_relays" => {
+                info!(
+                    "Nostr update_relays request: from={} req={} relays={:?}",
+                    from_pk.to_hex(),
+                    req_id,
+                    parsed.relays
+                );
+
+                if let Err(e) = self.update_relays_and_publish(from_pk, &req_id, parsed.relays).await {
+                    error!(
+                        "update_relays failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "balance_history" => {
+                let limit = parsed.limit.unwrap_or(history::DEFAULT_HISTORY_LIMIT);
+                let address = parsed.query.trim().to_string();
+
+                let result = match self.balance_history.get_history(&address, limit) {
+                    Ok(snapshots) => {
+                        let response = BalanceHistoryResponse {
+                            req: req_id.clone(),
+                            address,
+                            snapshots,
+                        };
+                        match serde_json::to_string(&response) {
+                            Ok(json) => self.publish_response(from_pk, &req_id, json).await,
+                            Err(e) => Err(e.into()),
+                        }
+                    }
+                    Err(e) => Err(e),
+                };
+
+                if let Err(e) = result {
+                    error!(
+                        "balance_history failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "address_validate" => {
+                // Local check only: no Electrs call, no rate limiter
+                let validation = xpub::validate_address(&parsed.query, self.electrs_client.network());
+
+                let response = AddressValidateResponse {
+                    req: req_id.clone(),
+                    validation,
+                };
+
+                let result = match serde_json::to_string(&response) {
+                    Ok(json) => self.publish_response(from_pk, &req_id, json).await,
+                    Err(e) => Err(e.into()),
+                };
+
+                if let Err(e) = result {
+                    error!(
+                        "address_validate failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "server_ping" => {
+                if !self.ping_limiter.check(&from_pk) {
+                    warn!("server_ping rate limited: from={}", from_pk.to_hex());
+                    self.seen_requests.forget(&from_pk, &req_id);
+                    self.reply_error(from_pk, &req_id, LookupError::RateLimited).await;
+                    return;
+                }
+
+                if let Err(e) = self.pong_and_publish(from_pk, &req_id, received_at).await {
+                    error!(
+                        "server_ping failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "server_stats" => {
+                info!(
+                    "Nostr server_stats request: from={} req={}",
+                    from_pk.to_hex(),
+                    req_id
+                );
+
+                if let Err(e) = self.server_stats_and_publish(from_pk, &req_id).await {
+                    error!(
+                        "server_stats failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "fee_histogram" => {
+                info!(
+                    "Nostr fee_histogram request: from={} req={}",
+                    from_pk.to_hex(),
+                    req_id
+                );
+
+                if let Err(e) = self.fee_histogram_and_publish(from_pk, &req_id).await {
+                    error!(
+                        "fee_histogram failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "block_height" => {
+                info!(
+                    "Nostr block_height request: from={} req={}",
+                    from_pk.to_hex(),
+                    req_id
+                );
+
+                if let Err(e) = self.block_height_and_publish(from_pk, &req_id).await {
+                    error!(
+                        "block_height failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "fee_estimate" => {
+                let target = parsed.target.unwrap_or(DEFAULT_FEE_TARGET_BLOCKS);
+
+                info!(
+                    "Nostr fee_estimate request: from={} req={} target={}",
+                    from_pk.to_hex(),
+                    req_id,
+                    target
+                );
+
+                if let Err(e) = self.fee_estimate_and_publish(from_pk, &req_id, target).await {
+                    error!(
+                        "fee_estimate failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "tx_detail" => {
+                let txid = parsed
+                    .txid
+                    .clone()
+                    .unwrap_or_else(|| parsed.query.clone())
+                    .trim()
+                    .to_string();
+                let address = parsed
+                    .address
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|a| !a.is_empty())
+                    .map(str::to_string);
+
+                info!(
+                    "Nostr tx_detail request: from={} req={} txid={}",
+                    from_pk.to_hex(),
+                    req_id,
+                    txid
+                );
+
+                if let Err(e) = self.tx_detail_and_publish(from_pk, &req_id, &txid, address).await {
+                    error!(
+                        "tx_detail failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "list_utxos" => {
+                let Some(gap_limit) = parsed.checked_gap_limit() else {
+                    warn!(
+                        "list_utxos gap_limit out of range (req={}): {:?}",
+                        req_id, parsed.gap_limit
+                    );
+                    self.reply_error(from_pk, &req_id, LookupError::InvalidParams).await;
+                    return;
+                };
+
+                info!(
+                    "Nostr list_utxos request: from={} req={} query={}",
+                    from_pk.to_hex(),
+                    req_id,
+                    parsed.query
+                );
+
+                if let Err(e) = self
+                    .list_utxos_and_publish(from_pk, &req_id, &parsed.query, gap_limit)
+                    .await
+                {
+                    error!(
+                        "list_utxos failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "bulk_balance" => {
+                let addresses: Vec<String> = if parsed.addresses.is_empty() {
+                    parsed
+                        .query
+                        .lines()
+                        .map(|l| l.trim().to_string())
+                        .filter(|l| !l.is_empty())
+                        .collect()
+                } else {
+                    parsed.addresses.iter().map(|a| a.trim().to_string()).collect()
+                };
+
+                info!(
+                    "Nostr bulk_balance request: from={} req={} addresses={}",
+                    from_pk.to_hex(),
+                    req_id,
+                    addresses.len()
+                );
+
+                if let Err(e) = self.bulk_balance_and_publish(from_pk, &req_id, addresses).await {
+                    error!(
+                        "bulk_balance failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "pair" => {
+                info!(
+                    "Nostr pair request: from={} req={}",
+                    from_pk.to_hex(),
+                    req_id
+                );
+
+                if let Err(e) = self
+                    .pair_and_publish(
+                        from_pk,
+                        &req_id,
+                        parsed.challenge,
+                        parsed.relays,
+                        parsed.shared_secret,
+                    )
+                    .await
+                {
+                    error!(
+                        "pair failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "watch_xpub" => {
+                info!(
+                    "Nostr watch_xpub request: from={} req={}",
+                    from_pk.to_hex(),
+                    req_id
+                );
+
+                if let Err(e) = self.watch_xpub_and_publish(from_pk, &req_id, &parsed.query).await {
+                    error!(
+                        "watch_xpub failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "unwatch_xpub" => {
+                info!(
+                    "Nostr unwatch_xpub request: from={} req={}",
+                    from_pk.to_hex(),
+                    req_id
+                );
+
+                if let Err(e) = self.unwatch_xpub_and_publish(from_pk, &req_id, &parsed.query).await
+                {
+                    error!(
+                        "unwatch_xpub failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            "unpair" => {
+                info!(
+                    "Nostr unpair request: from={} req={}",
+                    from_pk.to_hex(),
+                    req_id
+                );
+
+                if let Err(e) = self.unpair_and_publish(from_pk, &req_id).await {
+                    error!(
+                        "unpair failed: from={} req={} err={}",
+                        from_pk.to_hex(),
+                        req_id,
+                        e
+                    );
+                }
+            }
+            other => {
+                warn!(
+                    "Unknown request type: from={} req={} type={}",
+                    from_pk.to_hex(),
+                    req_id,
+                    other
+                );
+                self.reply_error(from_pk, &req_id, LookupError::InvalidType).await;
+            }
+        }
+    }
+
+    /// Liveness check without touching Electrs, so the client can measure
+    /// Nostr round-trip latency on its own
+    async fn pong_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        received_at: Instant,
+    ) -> Result<()> {
+        let response = ServerPongResponse {
+            resp_type: "server_pong",
+            req: req_id.to_string(),
+            server_time_utc: chrono::Utc::now().to_rfc3339(),
+            processing_time_ms: received_at.elapsed().as_millis() as u64,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Counters for the app's "Server Health" screen (paired device only, no Electrs calls)
+    async fn server_stats_and_publish(&self, to_pubkey: PublicKey, req_id: &str) -> Result<()> {
+        if !self.pairing_manager.is_paired(&to_pubkey)? {
+            return self.send_error(to_pubkey, req_id, LookupError::NotPaired).await;
+        }
+
+        let m = metrics::metrics();
+        let hits = m.electrs.cache_hits.get();
+        let lookups = hits + m.electrs.cache_misses.get();
+
+        let relay_connection_count = self
+            .client
+            .relays()
+            .await
+            .values()
+            .filter(|relay| relay.status() == RelayStatus::Connected)
+            .count() as u32;
+
+        let response = ServerStatsResponse {
+            resp_type: "server_stats",
+            req: req_id.to_string(),
+            uptime_secs: metrics::uptime().as_secs(),
+            requests_served: m.responses_published.get(),
+            errors_total: m.error_responses.get(),
+            electrs_calls: m.electrs.calls_total.get(),
+            electrs_errors: m.electrs.errors_total.get(),
+            cache_hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                hits as f64 / lookups as f64
+            },
+            relay_connection_count,
+            pairing_count: self.pairing_manager.pairing_count()? as u32,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    async fn fee_histogram_and_publish(&self, to_pubkey: PublicKey, req_id: &str) -> Result<()> {
+        let buckets = match self.electrs_client.get_fee_histogram().await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Electrs fee histogram failed: req={} err={}", req_id, e);
+                return self.send_error(to_pubkey, req_id, electrs_lookup_error(&e)).await;
+            }
+        };
+
+        let response = FeeHistogramResponse {
+            req: req_id.to_string(),
+            fast_fee: electrs::fee_rate_for_depth(&buckets, electrs::BLOCK_VSIZE),
+            normal_fee: electrs::fee_rate_for_depth(&buckets, 3 * electrs::BLOCK_VSIZE),
+            slow_fee: electrs::fee_rate_for_depth(&buckets, 6 * electrs::BLOCK_VSIZE),
+            buckets,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    async fn block_height_and_publish(&self, to_pubkey: PublicKey, req_id: &str) -> Result<()> {
+        let tip = match self.electrs_client.get_tip_height().await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Electrs tip lookup failed: req={} err={}", req_id, e);
+                return self.send_error(to_pubkey, req_id, electrs_lookup_error(&e)).await;
+            }
+        };
+
+        let response = BlockHeightResponse {
+            req: req_id.to_string(),
+            protocol_version: PROTOCOL_VERSION.to_string(),
+            height: tip.height,
+            header_hash: tip.header_hash,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    async fn fee_estimate_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        target: usize,
+    ) -> Result<()> {
+        if !(1..=MAX_FEE_TARGET_BLOCKS).contains(&target) {
+            return self.send_error(to_pubkey, req_id, LookupError::InvalidQuery).await;
+        }
+
+        let btc_per_kb = match self.electrs_client.estimate_fee(target).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Electrs fee estimate failed: req={} err={}", req_id, e);
+                return self.send_error(to_pubkey, req_id, electrs_lookup_error(&e)).await;
+            }
+        };
+        let Some(fee_rate) = electrs::btc_per_kb_to_sat_per_vb(btc_per_kb) else {
+            return self.send_error(to_pubkey, req_id, LookupError::FeeUnavailable).await;
+        };
+
+        let response = FeeEstimateResponse {
+            req: req_id.to_string(),
+            protocol_version: PROTOCOL_VERSION.to_string(),
+            target_blocks: target,
+            fee_rate_sat_vbyte: fee_rate,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Fee and net amount of one transaction; with an address, also its
+    /// confirmations (from the address history)
+    async fn tx_detail_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        txid: &str,
+        address: Option<String>,
+    ) -> Result<()> {
+        let Ok(txid) = bitcoin::Txid::from_str(txid) else {
+            return self.send_error(to_pubkey, req_id, LookupError::InvalidQuery).await;
+        };
+        let script = match address.as_deref().map(electrs::address_script).transpose() {
+            Ok(v) => v,
+            Err(_) => {
+                return self.send_error(to_pubkey, req_id, LookupError::InvalidAddress).await;
+            }
+        };
+
+        let tx = match self.electrs_client.get_transaction(txid).await {
+            Ok(Some(tx)) => tx,
+            Ok(None) => return self.send_error(to_pubkey, req_id, LookupError::TxNotFound).await,
+            Err(e) => {
+                warn!("Electrs transaction lookup failed: req={} err={}", req_id, e);
+                return self.send_error(to_pubkey, req_id, electrs_lookup_error(&e)).await;
+            }
+        };
+
+        // Transactions whose outputs this one spends, for the fee and the debit side
+        let mut prev_txids: Vec<_> = if tx.is_coinbase() {
+            Vec::new()
+        } else {
+            tx.input.iter().map(|i| i.previous_output.txid).collect()
+        };
+        prev_txids.sort();
+        prev_txids.dedup();
+        let prevouts = match self.electrs_client.get_transactions(&prev_txids).await {
+            Ok(txs) => prev_txids.into_iter().zip(txs).collect(),
+            Err(e) => {
+                warn!("Spent outputs unavailable, omitting fee (req={}): {}", req_id, e);
+                HashMap::new()
+            }
+        };
+        let amounts = electrs::tx_amounts(&tx, &prevouts, script.as_deref());
+
+        let confirmations = match &address {
+            Some(address) => self.tx_confirmations(address, txid).await,
+            None => None,
+        };
+
+        let response = TxDetailResponse {
+            req: req_id.to_string(),
+            protocol_version: PROTOCOL_VERSION.to_string(),
+            txid: txid.to_string(),
+            confirmations,
+            net_amount_sats: amounts.net_amount_sats,
+            fee_sats: amounts.fee_sats,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Confirmations of `txid` from the history of `address`; None if it's
+    /// not in that history or Electrs can't tell us
+    async fn tx_confirmations(&self, address: &str, txid: bitcoin::Txid) -> Option<u32> {
+        let history = self.electrs_client.get_address_history(address).await.ok()?;
+        let entry = history.into_iter().find(|e| e.txid == txid.to_string())?;
+        if entry.confirmed_height().is_none() {
+            return Some(0);
+        }
+        let tip = self.electrs_client.get_tip_height().await.ok()?;
+        Some(entry.confirmations(tip.height))
+    }
+
+    /// Balances of up to BULK_BALANCE_MAX_ADDRESSES addresses in one response
+    async fn bulk_balance_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        addresses: Vec<String>,
+    ) -> Result<()> {
+        if addresses.is_empty() || addresses.len() > BULK_BALANCE_MAX_ADDRESSES {
+            return self.send_error(to_pubkey, req_id, LookupError::InvalidQuery).await;
+        }
+        // Same checks as a single address lookup
+        let network = self.electrs_client.network();
+        if addresses.iter().any(|a| xpub::is_address_on_other_network(a, network)) {
+            return self.send_error(to_pubkey, req_id, LookupError::WrongNetwork).await;
+        }
+        if !addresses.iter().all(|a| xpub::is_bitcoin_address(a, network)) {
+            return self.send_error(to_pubkey, req_id, LookupError::InvalidAddress).await;
+        }
+
+        let balances = match self
+            .electrs_client
+            .get_balances_bounded(addresses.clone(), config::get_bulk_concurrency())
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Electrs bulk balance failed: req={} err={}", req_id, e);
+                return self.send_error(to_pubkey, req_id, electrs_lookup_error(&e)).await;
+            }
+        };
+
+        let response = BulkBalanceResponse {
+            req: req_id.to_string(),
+            balances: addresses
+                .into_iter()
+                .zip(balances)
+                .map(|(address, (confirmed, unconfirmed))| BulkBalanceEntry {
+                    address,
+                    confirmed_sat: confirmed,
+                    unconfirmed_sat: unconfirmed,
+                })
+                .collect(),
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// UTXOs of an address, or of every address of an xpub or descriptor
+    /// (both chains, up to the gap limit), for coin selection
+    async fn list_utxos_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        query: &str,
+        gap_limit: u32,
+    ) -> Result<()> {
+        let network = self.electrs_client.network();
+        let query = query.trim();
+
+        let addresses = if query.is_empty() {
+            return self.send_error(to_pubkey, req_id, LookupError::EmptyQuery).await;
+        } else if xpub::is_bitcoin_address(query, network) {
+            vec![query.to_string()]
+        } else if xpub::is_address_on_other_network(query, network) {
+            return self.send_error(to_pubkey, req_id, LookupError::WrongNetwork).await;
+        } else {
+            let key = if xpub::is_descriptor(query) {
+                xpub::parse_descriptor(query).map(|d| (d.xpub, d.address_type))
+            } else if xpub::is_xpub(query) {
+                xpub::prefix_address_type(query).map(|t| (query.to_string(), t))
+            } else {
+                return self.send_error(to_pubkey, req_id, LookupError::InvalidQuery).await;
+            };
+            let (xpub_str, address_type) = match key {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Bad xpub or descriptor (req={}): {}", req_id, e);
+                    return self.send_error(to_pubkey, req_id, LookupError::InvalidXpub).await;
+                }
+            };
+            if self.key_on_other_network(&xpub_str) {
+                return self.send_error(to_pubkey, req_id, LookupError::WrongNetwork).await;
+            }
+
+            // Unused addresses have no UTXOs; the scan already knows which they are
+            match xpub::used_addresses_until_gap(
+                &xpub_str,
+                address_type,
+                gap_limit,
+                self.electrs_client.as_ref(),
+            )
+            .await
+            {
+                Ok(used) => used,
+                Err(e) => {
+                    warn!("xpub address scan failed: req={} err={}", req_id, e);
+                    return self.send_error(to_pubkey, req_id, gap_scan_error(&e)).await;
+                }
+            }
+        };
+
+        let scripts = match addresses
+            .iter()
+            .map(|a| electrs::address_script(a))
+            .collect::<Result<Vec<_>>>()
+        {
+            Ok(v) => v,
+            Err(_) => return self.send_error(to_pubkey, req_id, LookupError::InvalidAddress).await,
+        };
+
+        // One round trip for every address
+        let mut utxos = match self.electrs_client.list_unspent(&scripts).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Electrs listunspent failed: req={} err={}", req_id, e);
+                return self.send_error(to_pubkey, req_id, electrs_lookup_error(&e)).await;
+            }
+        };
+
+        let limit = config::get_list_utxos_max();
+        if utxos.len() > limit {
+            warn!("More than {} UTXOs, refusing list_utxos (req={})", limit, req_id);
+            let error = LookupError::TooManyUtxos { limit };
+            return self.send_error(to_pubkey, req_id, error).await;
+        }
+
+        if utxos.iter().any(|u| u.confirmations.is_none()) {
+            match self.electrs_client.get_tip_height().await {
+                Ok(tip) => utxos = utxos.into_iter().map(|u| u.with_tip(tip.height)).collect(),
+                Err(e) => {
+                    warn!("Chain tip unavailable, omitting confirmations (req={}): {}", req_id, e)
+                }
+            }
+        }
+
+        let response = UtxoListResponse {
+            req: req_id.to_string(),
+            protocol_version: PROTOCOL_VERSION.to_string(),
+            total_sats: utxos.iter().fold(0u64, |total, u| total.saturating_add(u.value_sats)),
+            utxos,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Pair the sender, provided it echoes the (unexpired) challenge from the QR code
+    async fn pair_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        challenge: Option<String>,
+        relays: Vec<String>,
+        shared_secret: Option<String>,
+    ) -> Result<()> {
+        let valid = challenge
+            .as_deref()
+            .map(|c| self.pairing_manager.verify_challenge(c))
+            .unwrap_or(false);
+        if !valid {
+            warn!(
+                "Rejected pairing with missing, wrong or expired challenge: from={}",
+                to_pubkey.to_hex()
+            );
+            return self.send_error(to_pubkey, req_id, LookupError::InvalidQuery).await;
+        }
+        if let Some(Err(e)) = shared_secret.as_deref().map(pairing::validate_shared_secret) {
+            warn!(
+                "Rejected pairing with a bad shared secret: from={} err={}",
+                to_pubkey.to_hex(),
+                e
+            );
+            return self.send_error(to_pubkey, req_id, LookupError::InvalidQuery).await;
+        }
+
+        let mut relays = relays;
+        relays.truncate(pairing::MAX_RELAYS_PER_PAIRING);
+        self.pairing_manager.add_pairing(to_pubkey, relays, shared_secret)?;
+        self.pairing_manager.clear_challenge();
+
+        let response = PairResponse {
+            req: req_id.to_string(),
+            resp_type: "pair_response",
+            status: "ok",
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Device-initiated revocation (e.g. before selling the phone)
+    async fn unpair_and_publish(&self, to_pubkey: PublicKey, req_id: &str) -> Result<()> {
+        if !self.pairing_manager.revoke(&to_pubkey)? {
+            return self.send_error(to_pubkey, req_id, LookupError::NotPaired).await;
+        }
+
+        info!("Device unpaired itself: {}", to_pubkey.to_hex());
+        let unwatched = self.xpub_watch.remove_owner(&to_pubkey.to_hex())?;
+        if unwatched > 0 {
+            info!("Dropped {} watched xpub(s) of {}", unwatched, to_pubkey.to_hex());
+        }
+
+        let response = UnpairResponse {
+            req: req_id.to_string(),
+            resp_type: "unpair_response",
+            status: "ok",
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Add an xpub to the paired device's watch list (see `run_xpub_watcher`)
+    async fn watch_xpub_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        xpub_str: &str,
+    ) -> Result<()> {
+        if !self.pairing_manager.is_paired(&to_pubkey)? {
+            return self.send_error(to_pubkey, req_id, LookupError::NotPaired).await;
+        }
+
+        let xpub_str = xpub_str.trim();
+        if !xpub::is_xpub(xpub_str) || xpub::parse_xpub_info(xpub_str).is_err() {
+            return self.send_error(to_pubkey, req_id, LookupError::InvalidXpub).await;
+        }
+
+        // Refuse before the gap scan: a device at its limit gets no scans
+        let owner = to_pubkey.to_hex();
+        if self.xpub_watch.is_full_for(&owner, xpub_str) {
+            let error = LookupError::WatchLimitReached { limit: MAX_WATCHED_XPUBS_PER_DEVICE };
+            return self.send_error(to_pubkey, req_id, error).await;
+        }
+
+        // Baseline for the watcher; a failed scan just means the first
+        // watcher pass sets it instead
+        let snapshot = match self.scan_watched_xpub(xpub_str).await {
+            Ok(s) => Some(s),
+            Err(e) => {
+                warn!("Initial watch scan failed (req={}): {}", req_id, e);
+                None
+            }
+        };
+
+        let status = match self.xpub_watch.add(&owner, xpub_str, req_id, snapshot.clone())? {
+            WatchAdd::Added => "watching",
+            WatchAdd::AlreadyWatched => "already_watching",
+            WatchAdd::LimitReached => {
+                let error = LookupError::WatchLimitReached { limit: MAX_WATCHED_XPUBS_PER_DEVICE };
+                return self.send_error(to_pubkey, req_id, error).await;
+            }
+        };
+
+        let response = WatchXpubResponse {
+            req: req_id.to_string(),
+            resp_type: "watch_xpub_response",
+            status,
+            watched_count: self.xpub_watch.count_for(&owner),
+            snapshot,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Remove an xpub from the device's watch list
+    async fn unwatch_xpub_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        xpub_str: &str,
+    ) -> Result<()> {
+        let owner = to_pubkey.to_hex();
+        let status = if self.xpub_watch.remove(&owner, xpub_str.trim())? {
+            "removed"
+        } else {
+            "not_watched"
+        };
+
+        let response = UnwatchXpubResponse {
+            req: req_id.to_string(),
+            resp_type: "unwatch_xpub_response",
+            status,
+            watched_count: self.xpub_watch.count_for(&owner),
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Whether a watch entry's owner is still paired. Entries of devices
+    /// that are gone (unpaired, expired, or an unreadable owner) are dropped.
+    fn watch_owner_paired(&self, entry: &WatchedXpub) -> bool {
+        let paired = match PublicKey::from_hex(&entry.owner) {
+            Ok(owner) => match self.pairing_manager.is_paired(&owner) {
+                Ok(paired) => paired,
+                Err(e) => {
+                    // Can't tell; skip this pass but keep the entry
+                    warn!("Failed to read pairing for watched xpub (req={}): {}", entry.req_id, e);
+                    return false;
+                }
+            },
+            Err(_) => false,
+        };
+
+        if !paired {
+            info!("Dropping watched xpubs of unpaired device {}", entry.owner);
+            if let Err(e) = self.xpub_watch.remove_owner(&entry.owner) {
+                warn!("Failed to drop watched xpubs of {}: {}", entry.owner, e);
+            }
+        }
+        paired
+    }
+
+    /// Re-scan every watched xpub each XPUB_WATCH_INTERVAL_SECS and notify
+    /// the owner when the totals changed. Runs forever.
+    pub async fn run_xpub_watcher(&self) {
+        let interval = config::get_xpub_watch_interval();
+
+        loop {
+            tokio::time::sleep(interval).await;
+
+            if !self.is_ready.load(Ordering::Acquire) {
+                continue;
+            }
+
+            for entry in self.xpub_watch.entries() {
+                if !self.watch_owner_paired(&entry) {
+                    continue;
+                }
+
+                let current = match self.scan_watched_xpub(&entry.xpub).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        warn!("Watched xpub scan failed (req={}): {}", entry.req_id, e);
+                        continue;
+                    }
+                };
+
+                if let Some(previous) = &entry.last_snapshot {
+                    if previous.same_balance(&current) {
+                        continue;
+                    }
+                    if let Err(e) = self.publish_xpub_change(&entry, previous, &current).await {
+                        warn!("Failed to notify xpub change (req={}): {}", entry.req_id, e);
+                        continue;
+                    }
+                }
+
+                if let Err(e) = self.xpub_watch.update_snapshot(&entry.owner, &entry.xpub, current) {
+                    warn!("Failed to store xpub snapshot (req={}): {}", entry.req_id, e);
+                }
+            }
+        }
+    }
+
+    async fn publish_xpub_change(
+        &self,
+        entry: &WatchedXpub,
+        previous: &XpubSnapshot,
+        current: &XpubSnapshot,
+    ) -> Result<()> {
+        let to_pubkey = PublicKey::from_hex(&entry.owner)?;
+
+        info!(
+            "Watched xpub changed: req={} confirmed {} -> {} unconfirmed {} -> {}",
+            entry.req_id,
+            previous.total_confirmed,
+            current.total_confirmed,
+            previous.total_unconfirmed,
+            current.total_unconfirmed
+        );
+
+        let notification = XpubChangedNotification {
+            req: entry.req_id.clone(),
+            resp_type: "xpub_balance_changed",
+            xpub: entry.xpub.clone(),
+            previous: previous.clone(),
+            current: current.clone(),
+        };
+
+        let json = serde_json::to_string(&notification)?;
+        self.publish_response(to_pubkey, &entry.req_id, json).await
+    }
+
+    /// Totals over both chains, scanned up to the gap limit
+    async fn scan_watched_xpub(&self, xpub_str: &str) -> Result<XpubSnapshot> {
+        let scan = self
+            .electrs_client
+            .scan_xpub_parallel(xpub_str, config::get_gap_limit())
+            .await?;
+
+        Ok(XpubSnapshot {
+            total_confirmed: scan.confirmed,
+            total_unconfirmed: scan.unconfirmed,
+            address_count: scan.funded.len() as u32,
+            scanned_at: chrono::Utc::now().to_rfc3339(),
+        })
+    }
+
+    /// Persist a paired device's new relay list and start using those relays
+    async fn update_relays_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        relays: Vec<String>,
+    ) -> Result<()> {
+        if !self.pairing_manager.is_paired(&to_pubkey)? {
+            return self.send_error(to_pubkey, req_id, LookupError::NotPaired).await;
+        }
+
+        let relays: Vec<String> = relays
+            .into_iter()
+            .map(|r| r.trim().to_string())
+            .filter(|r| RelayUrl::parse(r).is_ok())
+            .collect();
+
+        if relays.is_empty() {
+            return self.send_error(to_pubkey, req_id, LookupError::InvalidQuery).await;
+        }
+
+        self.pairing_manager.update_relay_list(&to_pubkey, relays)?;
+        let stored = self.pairing_manager.get_relays_for_pubkey(&to_pubkey)?;
+
+        for relay in &stored {
+            match self.client.add_relay(relay.as_str()).await {
+                Ok(true) => {
+                    info!("Added relay from paired device: {}", relay);
+                    if let Err(e) = self.client.connect_relay(relay.as_str()).await {
+                        warn!("Failed to connect relay {}: {}", relay, e);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => warn!("Failed to add relay {}: {}", relay, e),
+            }
+        }
+
+        let mut merged = stored;
+        for url in self.client.relays().await.keys() {
+            let url = url.to_string();
+            if !merged.contains(&url) {
+                merged.push(url);
+            }
+        }
+
+        let response = UpdateRelaysResponse {
+            req: req_id.to_string(),
+            resp_type: "update_relays_response",
+            relays: merged,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Answer with the HD metadata of an xpub (no Electrs involved)
+    async fn xpub_info_and_publish(
+        &self,
+        to_pubkey: PublicKey,
         req_id: &str,
-        address: String,
+        xpub_str: &str,
     ) -> Result<()> {
-        let (confirmed, unconfirmed) = timeout(
-            Duration::from_secs(30),
-            self.electrs_client.get_address_balance(&address),
+        let info = match xpub::parse_xpub_info(xpub_str.trim()) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("xpub_info: invalid xpub (req={}): {}", req_id, e);
+                return self.send_error(to_pubkey, req_id, LookupError::InvalidXpub).await;
+            }
+        };
+
+        let response = XpubInfoResponse {
+            req: req_id.to_string(),
+            network: info.network.to_string(),
+            depth: info.depth,
+            parent_fingerprint: info.parent_fingerprint,
+            child_number: info.child_number,
+            chain_code: info.chain_code_hex,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Derive the first `count` receiving addresses locally (no Electrs) so the
+    /// client can check it has the right key
+    async fn xpub_addresses_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        xpub_str: &str,
+        count: u32,
+    ) -> Result<()> {
+        if self.key_on_other_network(xpub_str.trim()) {
+            warn!("xpub_addresses: key is for another network (req={})", req_id);
+            return self.send_error(to_pubkey, req_id, LookupError::WrongNetwork).await;
+        }
+
+        let network = self.electrs_client.network();
+        let addresses = match xpub::derive_addresses_typed(xpub_str.trim(), network, count) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("xpub_addresses: invalid xpub (req={}): {}", req_id, e);
+                return self.send_error(to_pubkey, req_id, LookupError::InvalidXpub).await;
+            }
+        };
+
+        let response = XpubAddressesResponse {
+            req: req_id.to_string(),
+            addresses,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Scan one page (XPUB_PAGE_SIZE addresses) of an xpub and publish it.
+    ///
+    /// Each chain is scanned until XPUB_GAP_LIMIT consecutive unused
+    /// addresses are seen. Without a cursor a new scan starts at m/0/0;
+    /// with one, the stored scan state for this requester+xpub is resumed.
+    async fn xpub_lookup_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        xpub_str: &str,
+        address_type: xpub::AddressType,
+        page: XpubPageRequest,
+    ) -> Result<()> {
+        let XpubPageRequest {
+            gap_limit,
+            cursor,
+            include_addresses,
+        } = page;
+
+        if self.key_on_other_network(xpub_str) {
+            warn!("xpub is for another network (req={})", req_id);
+            return self.send_error(to_pubkey, req_id, LookupError::WrongNetwork).await;
+        }
+
+        let scan_key = format!(
+            "{}:{}:{:?}:{}",
+            to_pubkey.to_hex(),
+            xpub_str,
+            address_type,
+            gap_limit
+        );
+
+        let resumed = {
+            let mut scans = self.xpub_scans.lock().unwrap();
+            scans.retain(|_, s| s.updated_at.elapsed() < XPUB_SCAN_TTL);
+
+            match &cursor {
+                None => Some(ScanState::new(gap_limit)),
+                Some(c) => scans.remove(&scan_key).filter(|s| &s.cursor() == c),
+            }
+        };
+
+        let mut state = match resumed {
+            Some(s) => s,
+            None => {
+                warn!("Unknown or expired xpub cursor (req={} cursor={:?})", req_id, cursor);
+                return self.send_error(to_pubkey, req_id, LookupError::InvalidQuery).await;
+            }
+        };
+
+        if cursor.is_none() {
+            self.publish_scan_estimate(to_pubkey, req_id, xpub_str, address_type).await;
+        }
+
+        let mut breakdown = Vec::new();
+        let mut scanned = 0;
+
+        while scanned < XPUB_PAGE_SIZE && !state.scan.is_done() {
+            let batch = XPUB_PAGE_SIZE - scanned;
+            let network = self.electrs_client.network();
+            let derived = match state.scan.next_window(xpub_str, network, address_type, batch) {
+                Ok(v) => v,
+                Err(e) if e.is::<xpub::ScanIndexLimit>() => {
+                    warn!("xpub scan gave up (req={}): {}", req_id, e);
+                    return self.send_error(to_pubkey, req_id, gap_scan_error(&e)).await;
+                }
+                Err(e) => {
+                    warn!("xpub derivation failed (req={}): {}", req_id, e);
+                    return self.send_error(to_pubkey, req_id, LookupError::InvalidXpub).await;
+                }
+            };
+
+            let scripts = match derived
+                .iter()
+                .map(|d| electrs::address_script(&d.address))
+                .collect::<Result<Vec<_>>>()
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("xpub derived an unusable address (req={}): {}", req_id, e);
+                    return self.send_error(to_pubkey, req_id, LookupError::InvalidXpub).await;
+                }
+            };
+
+            // One round trip for the whole batch instead of one per address
+            let histories = match self.electrs_client.batch_get_histories(&scripts).await {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Electrs history failed: req={} err={}", req_id, e);
+                    return self.send_error(to_pubkey, req_id, electrs_lookup_error(&e)).await;
+                }
+            };
+
+            // First pass: find the used addresses (and where the gap limit ends the chain)
+            let batch_chain = state.scan.chain;
+            let mut used = Vec::new();
+            let mut used_scripts = Vec::new();
+            for ((derived, history), script) in derived.into_iter().zip(histories).zip(scripts) {
+                scanned += 1;
+
+                let is_used = !history.is_empty();
+                if is_used {
+                    used.push(derived);
+                    used_scripts.push(script);
+                }
+                if state.scan.record(is_used) {
+                    break;
+                }
+            }
+
+            // Second pass: balances of the used addresses, again in one round trip
+            let balances = match self.electrs_client.get_balances_batch(&used_scripts).await {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Electrs balance failed: req={} err={}", req_id, e);
+                    return self.send_error(to_pubkey, req_id, electrs_lookup_error(&e)).await;
+                }
+            };
+
+            for (derived, (confirmed, unconfirmed)) in used.into_iter().zip(balances) {
+                state.confirmed = state.confirmed.saturating_add(confirmed);
+                state.unconfirmed = state.unconfirmed.saturating_add(unconfirmed);
+                if batch_chain == 0 {
+                    state.external_confirmed = state.external_confirmed.saturating_add(confirmed);
+                } else {
+                    state.internal_confirmed = state.internal_confirmed.saturating_add(confirmed);
+                }
+                breakdown.push(AddressBalance {
+                    path: derived.path,
+                    address: derived.address,
+                    confirmed,
+                    unconfirmed,
+                });
+            }
+        }
+
+        let addresses = include_addresses
+            .then(|| breakdown.iter().filter(|a| a.is_funded()).cloned().collect());
+        let has_more = !state.scan.is_done();
+        let next_cursor = has_more.then(|| state.cursor());
+
+        info!(
+            "xpub page OK: req={} used={} confirmed={} unconfirmed={} next={:?}",
+            req_id,
+            breakdown.len(),
+            state.confirmed,
+            state.unconfirmed,
+            next_cursor
+        );
+
+        let response = XpubLookupResponse {
+            req: req_id.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            confirmedBalance: state.confirmed,
+            unconfirmedBalance: state.unconfirmed,
+            confirmed_balance: state.confirmed,
+            unconfirmed_balance: state.unconfirmed,
+            external_confirmed_sat: state.external_confirmed,
+            internal_confirmed_sat: state.internal_confirmed,
+            address_breakdown: breakdown,
+            addresses,
+            next_cursor,
+            has_more,
+        };
+
+        if has_more {
+            state.updated_at = Instant::now();
+            self.xpub_scans.lock().unwrap().insert(scan_key, state);
+        }
+
+        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// A well-formed key whose version bytes belong to another network than
+    /// BITCOIN_NETWORK (e.g. a tpub on a mainnet node)
+    fn key_on_other_network(&self, xpub_str: &str) -> bool {
+        xpub::parse_xpub_info(xpub_str).is_ok()
+            && !xpub::key_matches_network(xpub_str, self.electrs_client.network())
+    }
+
+    /// xpub lookup for an `XpubQuery` (explicit standard + account)
+    async fn standard_xpub_lookup_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        query: xpub::XpubQuery,
+        page: XpubPageRequest,
+    ) -> Result<()> {
+        // One address per chain proves the xpub derives under `query.standard`;
+        // the paginated scan below goes through the rest
+        let network = self.electrs_client.network();
+        let address_type = match xpub::derive_addresses_for_standard(&query, network, 1) {
+            Ok(addresses) => addresses[0].1,
+            Err(e) => {
+                warn!("xpub does not match request (req={}): {}", req_id, e);
+                return self.send_error(to_pubkey, req_id, LookupError::InvalidXpub).await;
+            }
+        };
+        self.xpub_lookup_and_publish(to_pubkey, req_id, &query.xpub, address_type, page).await
+    }
+
+    /// Best effort: publish `{"status": "scanning", "estimated_address_count": N}`
+    async fn publish_scan_estimate(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        xpub_str: &str,
+        address_type: xpub::AddressType,
+    ) {
+        let estimated_address_count = match xpub::estimate_address_count_from_history(
+            xpub_str,
+            address_type,
+            self.electrs_client.as_ref(),
         )
         .await
-        .map_err(|_| anyhow!("Electrs balance timeout"))??;
+        {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("Address count estimate failed (req={}): {}", req_id, e);
+                return;
+            }
+        };
+
+        let response = ScanProgressResponse {
+            req: req_id.to_string(),
+            status: "scanning",
+            estimated_address_count,
+        };
+
+        let result = match serde_json::to_string(&response) {
+            Ok(json) => self.publish_response(to_pubkey, req_id, json).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            warn!("Failed to publish scan estimate (req={}): {}", req_id, e);
+        }
+    }
+
+    async fn lookup_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        address: String,
+        source_relay: Option<String>,
+    ) -> Result<()> {
+        match bitcoin::Address::from_str(address.trim()) {
+            Err(_) => {
+                return self.send_error(to_pubkey, req_id, LookupError::InvalidAddress).await;
+            }
+            Ok(a) if !a.is_valid_for_network(self.electrs_client.network()) => {
+                return self.send_error(to_pubkey, req_id, LookupError::WrongNetwork).await;
+            }
+            Ok(_) => {}
+        }
 
-        let txids = match timeout(
-            Duration::from_secs(20),
-            self.electrs_client.get_address_txs(&address),
+        let (confirmed, unconfirmed, utxo_values, used) = match timeout(
+            Duration::from_secs(30),
+            self.electrs_client.get_address_balance(&address, RetryStrategy::default()),
         )
         .await
         {
-            Ok(Ok(v)) => v,
-            _ => vec![],
+            Ok(Ok(ElectrsQueryResult::Found {
+                confirmed,
+                unconfirmed,
+                utxo_values,
+            })) => (confirmed, unconfirmed, utxo_values, true),
+            // Never used: a genuine zero, and no history worth fetching
+            Ok(Ok(ElectrsQueryResult::NotFound)) => (0, 0, Vec::new(), false),
+            Ok(Ok(ElectrsQueryResult::Error(e))) => {
+                warn!("Electrs rejected balance query: req={} err={}", req_id, e);
+                return self.send_error(to_pubkey, req_id, LookupError::InvalidAddress).await;
+            }
+            Ok(Err(e)) => {
+                warn!("Electrs balance failed: req={} err={}", req_id, e);
+                return self.send_error(to_pubkey, req_id, electrs_lookup_error(&e)).await;
+            }
+            Err(_) => {
+                return self.send_error(to_pubkey, req_id, LookupError::ElectrsTimeout).await;
+            }
         };
 
+        let history = if used {
+            match timeout(
+                Duration::from_secs(20),
+                self.electrs_client.get_address_history(&address),
+            )
+            .await
+            {
+                Ok(Ok(v)) => v,
+                _ => vec![],
+            }
+        } else {
+            vec![]
+        };
+
+        // One tip lookup per request: confirmations and the balance snapshot
+        let tip_height =
+            match timeout(Duration::from_secs(10), self.electrs_client.get_tip_height()).await {
+                Ok(Ok(tip)) => Some(tip.height),
+                _ => {
+                    warn!(
+                        "Chain tip unavailable; omitting confirmations and snapshot height (req={})",
+                        req_id
+                    );
+                    None
+                }
+            };
+
+        let (dust_utxo_count, dust_total_sat) = if utxo_values.is_empty() {
+            (0, 0)
+        } else {
+            let threshold = electrs::DustThreshold::for_script_type(
+                electrs::dust_script_type(address.trim()),
+                self.dust_fee_rate().await,
+            );
+            electrs::DustThreshold::count(&utxo_values, threshold)
+        };
+
+        let snapshot = BalanceSnapshot {
+            timestamp_utc: chrono::Utc::now().to_rfc3339(),
+            confirmed_sat: confirmed,
+            unconfirmed_sat: unconfirmed,
+            block_height: tip_height,
+        };
+        if let Err(e) = self.balance_history.record(address.trim(), snapshot) {
+            warn!("Failed to record balance snapshot (req={}): {}", req_id, e);
+        }
+
         info!(
             "Lookup OK: req={} confirmed={} unconfirmed={} txs={}",
             req_id,
             confirmed,
             unconfirmed,
-            txids.len()
+            history.len()
         );
 
         let response = BitcoinLookupResponse {
             req: req_id.to_string(),
+            protocol_version: PROTOCOL_VERSION,
             confirmedBalance: confirmed,
             unconfirmedBalance: unconfirmed,
-            confirmations: txids.len() as u64,
+            confirmations: history.len() as u64,
             amount: confirmed + unconfirmed,
+            source_relay,
 
             confirmed_balance: confirmed,
             unconfirmed_balance: unconfirmed,
-            transactions: txids
+            transactions: history
                 .into_iter()
-                .map(|txid| TransactionInfo { txid })
+                .map(|tx| TransactionInfo {
+                    height: tx.confirmed_height().unwrap_or(0),
+                    confirmations: match tx.confirmed_height() {
+                        Some(_) => tip_height.map(|tip| tx.confirmations(tip)),
+                        None => Some(0),
+                    },
+                    txid: tx.txid,
+                })
                 .collect(),
+            dust_utxo_count,
+            dust_total_sat,
         };
 
         let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// Next-block fee rate for dust accounting; the relay floor (1 sat/vB)
+    /// if Electrs can't tell us quickly. Fetched at most once per
+    /// DUST_FEE_RATE_TTL, so lookups don't each pay for a histogram call.
+    async fn dust_fee_rate(&self) -> f64 {
+        if let Some((rate, fetched_at)) = *self.dust_fee.lock().unwrap() {
+            if fetched_at.elapsed() < DUST_FEE_RATE_TTL {
+                return rate;
+            }
+        }
+
+        let rate = match timeout(Duration::from_secs(5), self.electrs_client.get_fee_histogram()).await {
+            Ok(Ok(buckets)) => electrs::fee_rate_for_depth(&buckets, electrs::BLOCK_VSIZE),
+            _ => 1.0,
+        };
+        *self.dust_fee.lock().unwrap() = Some((rate, Instant::now()));
+        rate
+    }
+
+    /// Check the `["hmac", "{hex}"]` tag: HMAC-SHA256 keyed with the pairing's
+    /// shared secret over `"{req_id}:{created_at}:{query}"`. `event` must
+    /// carry the decrypted content.
+    pub fn verify_hmac(event: &Event, shared_secret: &str) -> bool {
+        let Some(req_id) = extract_req_id(&event.tags) else {
+            return false;
+        };
+        let request = RequestEnvelope {
+            pubkey: event.pubkey,
+            req_id,
+            tags: &event.tags,
+            created_at: event.created_at,
+            content: &event.content,
+        };
+        Self::verify_request_hmac(&request, shared_secret)
+    }
+
+    fn verify_request_hmac(request: &RequestEnvelope<'_>, shared_secret: &str) -> bool {
+        use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+
+        let Ok(key) = hex::decode(shared_secret) else {
+            return false;
+        };
+        let Some(tag_hex) = request.tags.iter().find_map(|t| {
+            let v = t.clone().to_vec();
+            (v.len() >= 2 && v[0] == "hmac").then(|| v[1].to_string())
+        }) else {
+            return false;
+        };
+        let Ok(tag_mac) = hex::decode(tag_hex) else {
+            return false;
+        };
+
+        let query = serde_json::from_str::<BitcoinLookupRequest>(request.content)
+            .map(|r| r.query)
+            .unwrap_or_default();
+        let message = format!("{}:{}:{}", request.req_id, request.created_at.as_u64(), query);
+
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&key);
+        engine.input(message.as_bytes());
+        let expected = hmac::Hmac::<sha256::Hash>::from_engine(engine);
+        let expected = expected.as_byte_array();
+
+        // Constant-time comparison
+        tag_mac.len() == expected.len()
+            && tag_mac.iter().zip(expected).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+
+    /// Publish a typed error response for a failed request
+    async fn send_error(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        error: LookupError,
+    ) -> Result<()> {
+        warn!(
+            "Sending error response: to={} req={} code={}",
+            to_pubkey.to_hex(),
+            req_id,
+            error.code()
+        );
+        metrics::metrics().error_responses.inc();
+
+        let json = serde_json::to_string(&ErrorResponse::new(req_id, error))?;
+        self.publish_response(to_pubkey, req_id, json).await
+    }
+
+    /// `send_error` for paths that have nowhere to propagate a publish failure
+    async fn reply_error(&self, to_pubkey: PublicKey, req_id: &str, error: LookupError) {
+        if let Err(e) = self.send_error(to_pubkey, req_id, error).await {
+            error!("Failed to send error response: req={} err={}", req_id, e);
+        }
+    }
+
+    /// Sign once and send the same event to every relay in `relays` (the
+    /// whole pool if empty), waiting up to PUBLISH_CONFIRM_TIMEOUT for OKs.
+    /// `relays` comes fastest first: that one is sent to on its own before
+    /// the rest, so the phone most likely gets the response without waiting
+    /// on slower relays.
+    ///
+    /// A response accepted by a single flaky relay may never reach the
+    /// phone, so `Err` unless at least `required_confirmations` relays
+    /// accepted it.
+    async fn publish_to_all_relays(
+        &self,
+        builder: EventBuilder,
+        relays: &[String],
+        required_confirmations: usize,
+    ) -> Result<PublishResult> {
+        let event = self.client.sign_event_builder(builder).await?;
+        let mut result = PublishResult {
+            event_id: event.id,
+            confirmed_by: Vec::new(),
+            failed_on: Vec::new(),
+        };
+
+        let batches = match relays.split_first() {
+            Some((_, rest)) if !rest.is_empty() => vec![&relays[..1], rest],
+            _ => vec![relays],
+        };
+        let send = async {
+            for batch in batches {
+                let sent = if batch.is_empty() {
+                    self.client.send_event(&event).await?
+                } else {
+                    match self
+                        .client
+                        .send_event_to(batch.iter().map(|url| url.as_str()), &event)
+                        .await
+                    {
+                        Ok(output) => output,
+                        Err(e) => {
+                            result
+                                .failed_on
+                                .extend(batch.iter().map(|url| (url.clone(), e.to_string())));
+                            continue;
+                        }
+                    }
+                };
+                result
+                    .confirmed_by
+                    .extend(sent.success.iter().map(|url| url.to_string()));
+                result
+                    .failed_on
+                    .extend(sent.failed.iter().map(|(url, e)| (url.to_string(), e.to_string())));
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+
+        match timeout(PUBLISH_CONFIRM_TIMEOUT, send).await {
+            Ok(sent) => sent?,
+            // Whatever confirmed in time still counts
+            Err(_) => warn!(
+                "Publish of event {} not finished within {:?}",
+                event.id.to_hex(),
+                PUBLISH_CONFIRM_TIMEOUT
+            ),
+        }
+
+        for (url, e) in &result.failed_on {
+            warn!("Relay {} rejected event {}: {}", url, result.event_id.to_hex(), e);
+        }
+
+        if result.confirmed_by.len() < required_confirmations {
+            error!(
+                "Event {} confirmed by {} relay(s), {} required",
+                result.event_id.to_hex(),
+                result.confirmed_by.len(),
+                required_confirmations
+            );
+            return Err(anyhow!(
+                "event confirmed by {} of {} required relays",
+                result.confirmed_by.len(),
+                required_confirmations
+            ));
+        }
+
+        Ok(result)
+    }
+
+    /// Sign and publish a kind-30079 response, NIP-44 encrypted to the requester
+    async fn publish_response(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        json: String,
+    ) -> Result<()> {
+        #[cfg(feature = "testing")]
+        if let Some(outbox) = &self.outbox {
+            outbox.send((to_pubkey, json))?;
+            return Ok(());
+        }
 
         let tags = vec![
             Tag::parse(["p", to_pubkey.to_hex().as_str()])?,
             Tag::parse(["req", req_id])?,
         ];
 
-        let event = EventBuilder::new(
-            Kind::Custom(BALANCEBRIDGE_RESPONSE_KIND),
-            json,
-        )
-        .tags(tags)
-        .sign_with_keys(&self.keys)?;
+        // Big responses (long xpub breakdowns) go out gzipped
+        let content = if json.len() > config::get_compress_threshold() {
+            compress_response(req_id, &json)?
+        } else {
+            json
+        };
+        let content = encrypt_content(&self.keys, &to_pubkey, &content)?;
 
         info!(
             "Publishing response: kind={} to={} req={}",
@@ -213,16 +3062,202 @@ impl NostrHandler {
             req_id
         );
 
-        self.client.send_event(&event).await?;
+        let pool: Vec<String> = self
+            .client
+            .relays()
+            .await
+            .keys()
+            .map(|url| url.to_string())
+            .collect();
+
+        // Prefer the relays the recipient told us about (if we're connected to them)
+        let preferred: Vec<String> = match self.pairing_manager.get_relays_for_pubkey(&to_pubkey) {
+            Ok(theirs) => pool
+                .iter()
+                .filter(|url| {
+                    theirs.iter().any(|r| {
+                        RelayUrl::parse(r)
+                            .map(|r| r.to_string() == **url)
+                            .unwrap_or(false)
+                    })
+                })
+                .cloned()
+                .collect(),
+            Err(e) => {
+                warn!("Failed to read relays of {}: {}", to_pubkey.to_hex(), e);
+                Vec::new()
+            }
+        };
+        let urls = if preferred.is_empty() { pool } else { preferred };
+
+        // Fastest relay first: it is the most likely one to deliver quickly
+        let urls = {
+            let latencies = self.relay_latencies.read().unwrap();
+            relays::rank_relays(urls, &latencies)
+        };
+
+        Self::log_event_size(&content, &self.relay_limits(&urls).await);
+
+        let builder = EventBuilder::new(
+            Kind::Custom(BALANCEBRIDGE_RESPONSE_KIND),
+            content,
+        )
+        .tags(tags);
+
+        let result = self
+            .publish_to_all_relays(builder, &urls, PUBLISH_REQUIRED_CONFIRMATIONS)
+            .await?;
+
+        metrics::metrics().responses_published.inc();
+
+        let event_id = result.event_id;
+        info!(
+            "Published response: req={} event_id={} relays={}",
+            req_id,
+            event_id.to_hex(),
+            result.confirmed_by.len()
+        );
+
+        let mut published = self.published_events.lock().unwrap();
+        if published.len() >= MAX_PUBLISHED_EVENTS {
+            published.pop_front();
+        }
+        published.push_back((event_id, req_id.to_string(), Instant::now()));
 
         Ok(())
     }
+
+    /// NIP-11 `max_message_length` of each of `urls` (None: not advertised,
+    /// or the document hasn't been fetched)
+    async fn relay_limits(&self, urls: &[String]) -> HashMap<String, Option<u64>> {
+        let mut limits = HashMap::new();
+        for (url, relay) in self.client.relays().await {
+            let url = url.to_string();
+            if !urls.contains(&url) {
+                continue;
+            }
+            let limit = relay
+                .document()
+                .await
+                .limitation
+                .and_then(|l| l.max_message_length)
+                .and_then(|n| u64::try_from(n).ok());
+            limits.insert(url, limit);
+        }
+        limits
+    }
+
+    /// Warn about relays whose max_message_length `content` exceeds, so the
+    /// operator can tune the compress threshold or response sizes
+    fn log_event_size(content: &str, relay_limits: &HashMap<String, Option<u64>>) {
+        // Allow a third on top of the content for the event envelope and framing
+        let estimated = content.len() as u64 * 4 / 3;
+
+        let mut oversized = false;
+        for (url, limit) in relay_limits {
+            let Some(limit) = limit else { continue };
+            if estimated > *limit {
+                warn!(
+                    "Response of ~{} bytes exceeds max_message_length {} of {}",
+                    estimated, limit, url
+                );
+                oversized = true;
+            }
+        }
+
+        if oversized {
+            metrics::metrics().oversized_responses.inc();
+        }
+    }
 }
 
 /* -------------------- Helpers -------------------- */
 
-fn extract_req_id(event: &Event) -> Option<String> {
-    for t in event.tags.iter() {
+/// NIP-44 (v2) encrypt `content` from `keys` to `to`
+pub fn encrypt_content(keys: &Keys, to: &PublicKey, content: &str) -> Result<String> {
+    nip44::encrypt(keys.secret_key(), to, content, nip44::Version::V2)
+        .map_err(|e| anyhow!("NIP-44 encryption failed: {e}"))
+}
+
+/// NIP-44 decrypt a `payload` that `from` encrypted to `keys`
+pub fn decrypt_content(keys: &Keys, from: &PublicKey, payload: &str) -> Result<String> {
+    nip44::decrypt(keys.secret_key(), from, payload)
+        .map_err(|e| anyhow!("NIP-44 decryption failed: {e}"))
+}
+
+/// Envelope of a compressed response; `data` is the gzipped, base64-encoded JSON
+#[derive(Debug, Serialize)]
+struct CompressedResponse<'a> {
+    req: &'a str,
+    encoding: &'static str,
+    data: String,
+}
+
+/// Gzip + base64 `json` and wrap it in a `CompressedResponse`
+fn compress_response(req_id: &str, json: &str) -> Result<String> {
+    use bitcoin::base64::{engine::general_purpose::STANDARD, Engine as _};
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(json.as_bytes())?;
+    let compressed = encoder.finish()?;
+
+    let envelope = serde_json::to_string(&CompressedResponse {
+        req: req_id,
+        encoding: "gzip+base64",
+        data: STANDARD.encode(&compressed),
+    })?;
+
+    debug!(
+        "compressed response {}→{}B ({:.0}%)",
+        json.len(),
+        envelope.len(),
+        100.0 * envelope.len() as f64 / json.len() as f64
+    );
+
+    Ok(envelope)
+}
+
+/// Check the event id and Schnorr signature against the claimed pubkey.
+///
+/// Relays are untrusted, so a forged event could otherwise make us answer
+/// (and leak balances) to an arbitrary pubkey.
+pub fn verify_event_signature(event: &Event) -> Result<()> {
+    event
+        .verify()
+        .map_err(|e| anyhow!("event signature verification failed: {}", e))
+}
+
+/// `electrs_lookup_error`, except that a key with no gap within
+/// MAX_SCAN_INDEX addresses is the query's fault
+fn gap_scan_error(e: &anyhow::Error) -> LookupError {
+    if e.is::<xpub::ScanIndexLimit>() {
+        return LookupError::InvalidQuery;
+    }
+    electrs_lookup_error(e)
+}
+
+/// Map an Electrs failure onto the wire error code
+fn electrs_lookup_error(e: &anyhow::Error) -> LookupError {
+    if let Some(cooldown) = e.downcast_ref::<electrs::PendingCooldown>() {
+        return LookupError::ElectrsCoolingDown {
+            retry_after_ms: cooldown.remaining_ms,
+        };
+    }
+
+    let msg = e.to_string();
+    if msg.contains("cooling down") {
+        LookupError::ElectrsCoolingDown { retry_after_ms: 0 }
+    } else if msg.contains("timeout") {
+        LookupError::ElectrsTimeout
+    } else {
+        LookupError::ElectrsUnavailable
+    }
+}
+
+fn extract_req_id(tags: &Tags) -> Option<String> {
+    for t in tags.iter() {
         let v = t.clone().to_vec();
         if v.len() >= 2 && v[0] == "req" {
             return Some(v[1].to_string());
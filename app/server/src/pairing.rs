@@ -1,27 +1,72 @@
 //! Pairing management for Android app
 //!
-//! Stores and retrieves the paired Android app's public key and relay list.
+//! Stores and retrieves the public keys and relay lists of the paired
+//! Android apps (several phones may share one node).
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use nostr_sdk::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::fs;
+use fd_lock::RwLock;
+use std::fs::{self, File, OpenOptions};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::info;
 
 const PAIRING_FILENAME: &str = "android_pairing.json";
 
+/// Advisory lock serializing pairing file access (writers exclusive, readers shared)
+const LOCK_FILENAME: &str = ".pairing.lock";
+
+/// How long the challenge shown in the pairing QR code stays valid
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Maximum number of relays stored per pairing
+pub const MAX_RELAYS_PER_PAIRING: usize = 10;
+
 /// Pairing information for Android app
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AndroidPairing {
     pub android_pubkey: String,
     pub relays: Vec<String>,
+    /// Hex-encoded 32-byte secret for request HMACs (optional, high-security setups)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_secret: Option<String>,
+    /// Unknown for pairings made before this was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paired_at: Option<DateTime<Utc>>,
+    /// After this the device loses access (None = never), so a lost phone
+    /// eventually stops working on its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl AndroidPairing {
+    /// Not expired at `now`
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.map_or(true, |expires_at| now < expires_at)
+    }
+}
+
+/// A pairing's HMAC key must be 32 bytes, hex encoded
+pub fn validate_shared_secret(secret: &str) -> Result<()> {
+    let bytes = hex::decode(secret).context("Shared secret is not valid hex")?;
+    if bytes.len() != 32 {
+        anyhow::bail!("Shared secret must be 32 bytes, got {}", bytes.len());
+    }
+    Ok(())
 }
 
-/// Manages Android app pairing
+/// Manages Android app pairings
 #[derive(Clone)]
 pub struct PairingManager {
     pairing_path: PathBuf,
+    lock_path: PathBuf,
+    // Challenge from the current QR code and when it was issued (in memory only)
+    pending_challenge: Arc<Mutex<Option<(String, Instant)>>>,
+    // Lifetime given to new pairings (None = they never expire)
+    pairing_ttl: Option<chrono::Duration>,
 }
 
 impl PairingManager {
@@ -29,68 +74,283 @@ impl PairingManager {
     pub fn new(data_dir: impl AsRef<Path>) -> Result<Self> {
         let data_dir = data_dir.as_ref();
         let pairing_path = data_dir.join(PAIRING_FILENAME);
+        let lock_path = data_dir.join(LOCK_FILENAME);
 
         // Ensure data directory exists
         fs::create_dir_all(data_dir)
             .context("Failed to create data directory")?;
 
-        Ok(Self { pairing_path })
+        Ok(Self {
+            pairing_path,
+            lock_path,
+            pending_challenge: Arc::new(Mutex::new(None)),
+            pairing_ttl: None,
+        })
     }
 
-    /// Check if an Android app is paired
+    /// Make new pairings expire `ttl` after they were made (existing ones keep theirs)
+    pub fn with_pairing_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.pairing_ttl = ttl.and_then(|ttl| chrono::Duration::from_std(ttl).ok());
+        self
+    }
+
+    /// Check if at least one Android app is paired (and not expired)
     pub fn has_pairing(&self) -> bool {
-        self.pairing_path.exists()
+        self.pairing_count().map(|n| n > 0).unwrap_or(false)
     }
 
-    /// Get the paired Android pubkey
-    pub fn get_android_pubkey(&self) -> Result<Option<PublicKey>> {
-        if !self.has_pairing() {
-            return Ok(None);
-        }
+    /// Issue a fresh pairing challenge (32 random bytes, hex), replacing any previous one
+    pub fn generate_challenge(&self) -> String {
+        let bytes: [u8; 32] = rand::random();
+        let challenge = hex::encode(bytes);
+        *self.pending_challenge.lock().unwrap() = Some((challenge.clone(), Instant::now()));
+        challenge
+    }
 
-        let pairing = self.load_pairing()?;
-        let pubkey = PublicKey::from_hex(&pairing.android_pubkey)
-            .context("Invalid Android pubkey in pairing file")?;
+    /// Whether `challenge` is the pending one and hasn't expired
+    pub fn verify_challenge(&self, challenge: &str) -> bool {
+        match &*self.pending_challenge.lock().unwrap() {
+            Some((pending, issued_at)) => {
+                issued_at.elapsed() < CHALLENGE_TTL && pending == challenge
+            }
+            None => false,
+        }
+    }
 
-        Ok(Some(pubkey))
+    /// Forget the pending challenge (after a successful pairing)
+    pub fn clear_challenge(&self) {
+        *self.pending_challenge.lock().unwrap() = None;
     }
 
-    /// Get the relay list from pairing
-    pub fn get_relays(&self) -> Result<Vec<String>> {
-        if !self.has_pairing() {
+    /// Every paired device
+    pub fn list_pairings(&self) -> Result<Vec<AndroidPairing>> {
+        if !self.pairing_path.exists() {
             return Ok(Vec::new());
         }
 
-        let pairing = self.load_pairing()?;
-        Ok(pairing.relays)
+        let lock = self.lock()?;
+        let _guard = lock.read().context("Failed to lock pairing file")?;
+
+        self.read_pairings_file()
+    }
+
+    /// Pubkeys of every unexpired pairing (entries with an unparseable pubkey
+    /// are skipped)
+    pub fn paired_pubkeys(&self) -> Result<Vec<PublicKey>> {
+        let now = Utc::now();
+        Ok(self
+            .list_pairings()?
+            .iter()
+            .filter(|p| p.is_active(now))
+            .filter_map(|p| PublicKey::from_hex(&p.android_pubkey).ok())
+            .collect())
+    }
+
+    /// Whether `pubkey` is one of the paired devices and its pairing hasn't
+    /// expired (the only ones allowed to query)
+    pub fn is_paired(&self, pubkey: &PublicKey) -> Result<bool> {
+        self.is_active(pubkey, Utc::now())
+    }
+
+    /// Whether `pubkey` is paired and not expired at `now`
+    pub fn is_active(&self, pubkey: &PublicKey, now: DateTime<Utc>) -> Result<bool> {
+        Ok(self
+            .find_pairing(pubkey)?
+            .is_some_and(|pairing| pairing.is_active(now)))
+    }
+
+    /// Number of unexpired pairings
+    pub fn pairing_count(&self) -> Result<usize> {
+        let now = Utc::now();
+        Ok(self
+            .list_pairings()?
+            .iter()
+            .filter(|p| p.is_active(now))
+            .count())
+    }
+
+    /// Union of the relays of every unexpired pairing, deduplicated
+    pub fn get_all_known_relays(&self) -> Result<Vec<String>> {
+        let now = Utc::now();
+        let mut relays: Vec<String> = Vec::new();
+        for pairing in self.list_pairings()?.into_iter().filter(|p| p.is_active(now)) {
+            for relay in pairing.relays {
+                if !relays.contains(&relay) {
+                    relays.push(relay);
+                }
+            }
+        }
+        Ok(relays)
+    }
+
+    /// Relays of the pairing belonging to `pubkey` (empty if it isn't paired)
+    pub fn get_relays_for_pubkey(&self, pubkey: &PublicKey) -> Result<Vec<String>> {
+        Ok(self
+            .find_pairing(pubkey)?
+            .map(|p| p.relays)
+            .unwrap_or_default())
+    }
+
+    /// Get the HMAC shared secret of `pubkey`, if it is paired and one was set
+    pub fn get_shared_secret(&self, pubkey: &PublicKey) -> Result<Option<String>> {
+        Ok(self.find_pairing(pubkey)?.and_then(|p| p.shared_secret))
     }
 
-    /// Store pairing information (called when "hello / paired" is received)
-    pub fn store_pairing(&self, android_pubkey: PublicKey, relays: Vec<String>) -> Result<()> {
+    /// Pair a device (called when "pair" is received). Pairing an already
+    /// paired pubkey again replaces its entry; other devices are kept.
+    pub fn add_pairing(
+        &self,
+        android_pubkey: PublicKey,
+        relays: Vec<String>,
+        shared_secret: Option<String>,
+    ) -> Result<()> {
+        if let Some(secret) = &shared_secret {
+            validate_shared_secret(secret)?;
+        }
+
+        let now = Utc::now();
         let pairing = AndroidPairing {
             android_pubkey: android_pubkey.to_hex(),
             relays,
+            shared_secret,
+            paired_at: Some(now),
+            expires_at: self.pairing_ttl.map(|ttl| now + ttl),
         };
 
-        let json = serde_json::to_string_pretty(&pairing)
-            .context("Failed to serialize pairing")?;
+        let mut lock = self.lock()?;
+        let _guard = lock.write().context("Failed to lock pairing file")?;
 
-        fs::write(&self.pairing_path, json)
-            .context("Failed to write pairing file")?;
+        let mut pairings = self.read_pairings_file()?;
+        pairings.retain(|p| p.android_pubkey != pairing.android_pubkey);
+        pairings.push(pairing);
+        self.save_pairings(&pairings)?;
 
-        info!("Stored Android pairing: {}", android_pubkey.to_hex());
+        info!(
+            "Stored Android pairing: {} ({} device(s) paired)",
+            android_pubkey.to_hex(),
+            pairings.len()
+        );
 
         Ok(())
     }
 
-    fn load_pairing(&self) -> Result<AndroidPairing> {
+    /// Replace the relay list of an existing pairing (capped at MAX_RELAYS_PER_PAIRING)
+    pub fn update_relay_list(&self, pubkey: &PublicKey, relays: Vec<String>) -> Result<()> {
+        let mut lock = self.lock()?;
+        let _guard = lock.write().context("Failed to lock pairing file")?;
+
+        let mut pairings = self.read_pairings_file()?;
+        let hex = pubkey.to_hex();
+        let Some(pairing) = pairings.iter_mut().find(|p| p.android_pubkey == hex) else {
+            anyhow::bail!("Pubkey {} is not paired", hex);
+        };
+
+        let mut deduped: Vec<String> = Vec::new();
+        for relay in relays {
+            if !deduped.contains(&relay) {
+                deduped.push(relay);
+            }
+        }
+        deduped.truncate(MAX_RELAYS_PER_PAIRING);
+
+        pairing.relays = deduped;
+        info!("Updated relay list for {}: {:?}", hex, pairing.relays);
+
+        self.save_pairings(&pairings)
+    }
+
+    /// Remove the pairing of `pubkey`. Returns false if it wasn't paired.
+    pub fn remove_pairing(&self, pubkey: &PublicKey) -> Result<bool> {
+        let mut lock = self.lock()?;
+        let _guard = lock.write().context("Failed to lock pairing file")?;
+
+        let mut pairings = self.read_pairings_file()?;
+        let before = pairings.len();
+        pairings.retain(|p| p.android_pubkey != pubkey.to_hex());
+        if pairings.len() == before {
+            return Ok(false);
+        }
+
+        self.save_pairings(&pairings)?;
+
+        info!("Revoked Android pairing: {}", pubkey.to_hex());
+
+        Ok(true)
+    }
+
+    /// Revoke a device's access (the "unpair" request, or the operator).
+    /// Returns false if it wasn't paired.
+    pub fn revoke(&self, pubkey: &PublicKey) -> Result<bool> {
+        self.remove_pairing(pubkey)
+    }
+
+    /// Block until any pairing write in progress (another thread) has finished
+    pub fn flush(&self) -> Result<()> {
+        let mut lock = self.lock()?;
+        let _guard = lock.write().context("Failed to lock pairing file")?;
+        Ok(())
+    }
+
+    /// Open the lock file. Guards must not be held across an await.
+    ///
+    /// flock locks belong to the open file, so a nested lock() in the same
+    /// thread would deadlock: locked methods use the `*_file` helpers only.
+    fn lock(&self) -> Result<RwLock<File>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock_path)
+            .context("Failed to open pairing lock file")?;
+        Ok(RwLock::new(file))
+    }
+
+    /// Write the pairing file atomically (temp file + rename).
+    /// Caller holds the exclusive lock.
+    fn save_pairings(&self, pairings: &[AndroidPairing]) -> Result<()> {
+        let json = serde_json::to_string_pretty(pairings)
+            .context("Failed to serialize pairings")?;
+
+        let tmp_path = self.pairing_path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .context("Failed to write pairing file")?;
+        fs::rename(&tmp_path, &self.pairing_path)
+            .context("Failed to replace pairing file")?;
+
+        Ok(())
+    }
+
+    fn find_pairing(&self, pubkey: &PublicKey) -> Result<Option<AndroidPairing>> {
+        let hex = pubkey.to_hex();
+        Ok(self.list_pairings()?.into_iter().find(|p| p.android_pubkey == hex))
+    }
+
+    /// Read + parse the pairing file without locking (missing file = no pairings)
+    ///
+    /// Older versions stored a single pairing object; that format is read as
+    /// a one-entry list and rewritten as a list on the next change.
+    fn read_pairings_file(&self) -> Result<Vec<AndroidPairing>> {
+        if !self.pairing_path.exists() {
+            return Ok(Vec::new());
+        }
+
         let content = fs::read_to_string(&self.pairing_path)
             .context("Failed to read pairing file")?;
 
-        let pairing: AndroidPairing = serde_json::from_str(&content)
+        let stored: StoredPairings = serde_json::from_str(&content)
             .context("Invalid pairing file format")?;
 
-        Ok(pairing)
+        Ok(match stored {
+            StoredPairings::List(pairings) => pairings,
+            StoredPairings::Single(pairing) => vec![pairing],
+        })
     }
 }
 
+/// On-disk format of the pairing file: a list, or a single object (pre multi-device)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPairings {
+    List(Vec<AndroidPairing>),
+    Single(AndroidPairing),
+}
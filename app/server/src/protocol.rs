@@ -1,7 +1,38 @@
-use serde::{Deserialize, Serialize};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use thiserror::Error;
+
+pub use crate::error::LookupErrorCode;
+
+/// Wire protocol version reported in responses
+pub const PROTOCOL_VERSION: &str = "1.2";
+
+/// Oldest request version (`v`) the server still answers
+pub const MIN_SUPPORTED_VERSION: u32 = 1;
+
+/// Newest request version (`v`) the server understands
+pub const MAX_SUPPORTED_VERSION: u32 = 1;
+
+/// Request version assumed when a client sends no `v` (apps predating it)
+pub const DEFAULT_REQUEST_VERSION: u32 = 1;
+
+/// Pick the version to answer a request with.
+///
+/// Requests without `v` and versions older than MIN_SUPPORTED_VERSION are
+/// served in the oldest shape we still have; newer versions than
+/// MAX_SUPPORTED_VERSION get `unsupported_version` so the app can fall back.
+pub fn negotiate_version(requested: Option<u32>) -> Result<u32, LookupError> {
+    match requested.unwrap_or(DEFAULT_REQUEST_VERSION) {
+        v if v > MAX_SUPPORTED_VERSION => Err(LookupError::UnsupportedVersion { requested: v }),
+        v => Ok(v.max(MIN_SUPPORTED_VERSION)),
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BitcoinLookupRequest {
+    /// Request version, see `negotiate_version`
+    #[serde(default)]
+    pub v: Option<u32>,
     pub query: String,
 }
 
@@ -11,11 +42,22 @@ pub struct BitcoinLookupResponse {
     pub confirmed_balance: u64,
     pub unconfirmed_balance: u64,
     pub transactions: Vec<TransactionInfo>,
+    /// UTXOs below the dust threshold at the current fee rate, and their total
+    #[serde(default)]
+    pub dust_utxo_count: u32,
+    #[serde(default)]
+    pub dust_total_sat: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TransactionInfo {
     pub txid: String,
+    /// Block height, 0 while in the mempool
+    #[serde(default)]
+    pub height: u32,
+    /// 0 while in the mempool; None if the chain tip couldn't be fetched
+    #[serde(default)]
+    pub confirmations: Option<u32>,
 }
 
 impl BitcoinLookupResponse {
@@ -25,6 +67,265 @@ impl BitcoinLookupResponse {
             confirmed_balance: 0,
             unconfirmed_balance: 0,
             transactions: Vec::new(),
+            dust_utxo_count: 0,
+            dust_total_sat: 0,
+        }
+    }
+}
+
+/// Answer to a `block_height` request: the chain tip Electrs is at
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockHeightResponse {
+    pub req: String,
+    pub protocol_version: String,
+    pub height: u32,
+    /// Tip block hash, hex
+    pub header_hash: String,
+}
+
+/// Answer to a `tx_detail` request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TxDetailResponse {
+    pub req: String,
+    pub protocol_version: String,
+    pub txid: String,
+    /// 0 while in the mempool; None without an address or chain tip
+    pub confirmations: Option<u32>,
+    /// Credited to (positive) or debited from (negative) the queried address;
+    /// None without an address or when a spent output couldn't be fetched
+    pub net_amount_sats: Option<i64>,
+    /// None for coinbase or when a spent output couldn't be fetched
+    pub fee_sats: Option<u64>,
+}
+
+/// One unspent output in a `list_utxos` answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Utxo {
+    pub txid: String,
+    pub vout: u32,
+    pub value_sats: u64,
+    /// Block height, 0 while in the mempool
+    #[serde(default)]
+    pub height: u32,
+    /// 0 while in the mempool; None if the chain tip couldn't be fetched
+    pub confirmations: Option<u32>,
+    /// Address the output pays to
+    pub address: String,
+}
+
+impl Utxo {
+    /// Confirmations are filled in by `with_tip`, except for mempool outputs
+    pub fn new(txid: String, vout: u32, value_sats: u64, height: u32, address: String) -> Self {
+        Self {
+            txid,
+            vout,
+            value_sats,
+            height,
+            confirmations: (height == 0).then_some(0),
+            address,
+        }
+    }
+
+    /// Confirmations at chain tip `tip_height`
+    pub fn with_tip(mut self, tip_height: u32) -> Self {
+        if self.height > 0 {
+            self.confirmations = Some(tip_height.saturating_sub(self.height).saturating_add(1));
+        }
+        self
+    }
+}
+
+/// Answer to a `list_utxos` request: the UTXOs of an address or of every
+/// address of an xpub, up to LIST_UTXOS_MAX
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UtxoListResponse {
+    pub req: String,
+    pub protocol_version: String,
+    pub utxos: Vec<Utxo>,
+    pub total_sats: u64,
+}
+
+/// Answer to a `fee_estimate` request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeeEstimateResponse {
+    pub req: String,
+    pub protocol_version: String,
+    /// Confirmation target the estimate is for, in blocks
+    pub target_blocks: usize,
+    pub fee_rate_sat_vbyte: f64,
+}
+
+/// Typed error returned to the Android client
+///
+/// Serialized as `{ "code": "electrs_timeout", "message": "…", "retryable": true }`
+/// so the app can branch on `code` (a `LookupErrorCode`) instead of parsing
+/// free-form text; `message` is for humans and may change.
+/// `electrs_cooling_down` also carries `retry_after_ms` for a countdown, and
+/// `unsupported_version` carries `min_version`/`max_version`, and
+/// `too_many_utxos` carries `limit`.
+#[derive(Debug, Clone, Error)]
+pub enum LookupError {
+    #[error("device is not paired with this server")]
+    NotPaired,
+
+    #[error("too many requests, slow down")]
+    RateLimited,
+
+    #[error("request is not valid JSON")]
+    InvalidJson,
+
+    #[error("unknown request type")]
+    InvalidType,
+
+    #[error("query is empty")]
+    EmptyQuery,
+
+    #[error("invalid query")]
+    InvalidQuery,
+
+    #[error("gap_limit or account out of range")]
+    InvalidParams,
+
+    #[error("invalid Bitcoin address")]
+    InvalidAddress,
+
+    #[error("invalid extended public key")]
+    InvalidXpub,
+
+    #[error("address or key is for a different Bitcoin network than this node")]
+    WrongNetwork,
+
+    #[error(
+        "request version {requested} is newer than this server supports: \
+         v{min}..=v{max} are answered, requests without `v` are treated as v{default}, \
+         older versions get the v{min} response; retry with v{max} or update the server",
+        min = MIN_SUPPORTED_VERSION,
+        max = MAX_SUPPORTED_VERSION,
+        default = DEFAULT_REQUEST_VERSION
+    )]
+    UnsupportedVersion { requested: u32 },
+
+    #[error("transaction not found")]
+    TxNotFound,
+
+    #[error("not enough data to estimate a fee")]
+    FeeUnavailable,
+
+    #[error("more than {limit} unspent outputs; query single addresses instead")]
+    TooManyUtxos { limit: usize },
+
+    #[error("already watching {limit} xpubs; unwatch one first")]
+    WatchLimitReached { limit: usize },
+
+    #[error("Electrs is unavailable")]
+    ElectrsUnavailable,
+
+    #[error("Electrs is cooling down after a timeout, retry in {retry_after_ms}ms")]
+    ElectrsCoolingDown { retry_after_ms: u64 },
+
+    #[error("Electrs did not answer in time")]
+    ElectrsTimeout,
+
+    #[error("internal server error")]
+    InternalError,
+}
+
+impl LookupError {
+    /// Stable machine-readable code
+    pub fn error_code(&self) -> LookupErrorCode {
+        match self {
+            LookupError::NotPaired => LookupErrorCode::NotPaired,
+            LookupError::RateLimited => LookupErrorCode::RateLimited,
+            LookupError::InvalidJson => LookupErrorCode::InvalidJson,
+            LookupError::InvalidType => LookupErrorCode::InvalidType,
+            LookupError::EmptyQuery => LookupErrorCode::EmptyQuery,
+            LookupError::InvalidQuery => LookupErrorCode::InvalidQuery,
+            LookupError::InvalidParams => LookupErrorCode::InvalidParams,
+            LookupError::InvalidAddress => LookupErrorCode::InvalidAddress,
+            LookupError::InvalidXpub => LookupErrorCode::InvalidXpub,
+            LookupError::WrongNetwork => LookupErrorCode::WrongNetwork,
+            LookupError::UnsupportedVersion { .. } => LookupErrorCode::UnsupportedVersion,
+            LookupError::TxNotFound => LookupErrorCode::TxNotFound,
+            LookupError::FeeUnavailable => LookupErrorCode::FeeUnavailable,
+            LookupError::TooManyUtxos { .. } => LookupErrorCode::TooManyUtxos,
+            LookupError::WatchLimitReached { .. } => LookupErrorCode::WatchLimitReached,
+            LookupError::ElectrsUnavailable => LookupErrorCode::ElectrsUnavailable,
+            LookupError::ElectrsCoolingDown { .. } => LookupErrorCode::ElectrsCoolingDown,
+            LookupError::ElectrsTimeout => LookupErrorCode::ElectrsTimeout,
+            LookupError::InternalError => LookupErrorCode::InternalError,
+        }
+    }
+
+    /// `error_code` as the string sent on the wire
+    pub fn code(&self) -> &'static str {
+        self.error_code().as_str()
+    }
+
+    /// Whether the same request may succeed if sent again later
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            LookupError::RateLimited
+                | LookupError::FeeUnavailable
+                | LookupError::ElectrsUnavailable
+                | LookupError::ElectrsCoolingDown { .. }
+                | LookupError::ElectrsTimeout
+                | LookupError::InternalError
+        )
+    }
+}
+
+impl Serialize for LookupError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let retry_after_ms = match self {
+            LookupError::ElectrsCoolingDown { retry_after_ms } => Some(*retry_after_ms),
+            _ => None,
+        };
+
+        let version_range = matches!(self, LookupError::UnsupportedVersion { .. });
+        let limit = match self {
+            LookupError::TooManyUtxos { limit } | LookupError::WatchLimitReached { limit } => {
+                Some(*limit)
+            }
+            _ => None,
+        };
+
+        let len = 3
+            + usize::from(retry_after_ms.is_some())
+            + 2 * usize::from(version_range)
+            + usize::from(limit.is_some());
+        let mut state = serializer.serialize_struct("LookupError", len)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("retryable", &self.retryable())?;
+        if let Some(ms) = retry_after_ms {
+            state.serialize_field("retry_after_ms", &ms)?;
+        }
+        if version_range {
+            state.serialize_field("min_version", &MIN_SUPPORTED_VERSION)?;
+            state.serialize_field("max_version", &MAX_SUPPORTED_VERSION)?;
         }
+        if let Some(limit) = limit {
+            state.serialize_field("limit", &limit)?;
+        }
+        state.end()
     }
-}
\ No newline at end of file
+}
+
+/// Response sent instead of a result when a request fails
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub req: String,
+    pub protocol_version: &'static str,
+    pub error: LookupError,
+}
+
+impl ErrorResponse {
+    pub fn new(req: impl Into<String>, error: LookupError) -> Self {
+        Self {
+            req: req.into(),
+            protocol_version: PROTOCOL_VERSION,
+            error,
+        }
+    }
+}
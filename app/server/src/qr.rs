@@ -1,18 +1,68 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{schnorr, Keypair, Message, Secp256k1, XOnlyPublicKey};
+use image::codecs::png::PngEncoder;
+use image::{ColorType, Luma};
+use nostr_sdk::Keys;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use qrcode::QrCode;
+use std::collections::HashMap;
+use std::str::FromStr;
 use qrcode::render::svg;
 use serde::{Deserialize, Serialize};
 
 const APP_IDENTIFIER: &str = "umbrel-balancebridge";
 const VERSION: u32 = 1;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// URI the Android app registers, so a generic camera app can open it
+pub const DEEP_LINK_PREFIX: &str = "balancebridge://pair?";
+
+/// Deep-link parameters escape everything but RFC 3986 unreserved characters
+const DEEP_LINK_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// What the pairing QR encodes (PAIRING_QR_FORMAT)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QrEncoding {
+    /// The signed payload JSON, parsed by the app's own scanner
+    #[default]
+    Json,
+    /// A `balancebridge://pair?…` URI that launches the app
+    DeepLink,
+}
+
+impl FromStr for QrEncoding {
+    type Err = anyhow::Error;
+
+    /// "json", or "uri" / "deeplink" for the deep link
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(QrEncoding::Json),
+            "uri" | "deeplink" | "deep_link" => Ok(QrEncoding::DeepLink),
+            other => Err(anyhow!("Unknown pairing QR format '{}' (expected json or uri)", other)),
+        }
+    }
+}
+
+/// Minimum width and height of the served PNG pairing QR, in pixels
+pub const QR_PNG_SIZE: u32 = 512;
+
+/// Default UR fragment size for animated QR frames (keeps each frame small and easy to scan)
+pub const ANIMATED_QR_FRAME_BYTES: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PairingPayload {
     pub version: u32,
     pub app: String,
     #[serde(rename = "nodePubkey")]
     pub node_pubkey: String,
     pub relays: Vec<String>,
+    /// One-time pairing challenge, echoed back in the app's "pair" request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<String>,
 }
 
 impl PairingPayload {
@@ -22,28 +72,293 @@ impl PairingPayload {
             app: APP_IDENTIFIER.to_string(),
             node_pubkey,
             relays,
+            challenge: None,
         }
     }
 
+    pub fn with_challenge(mut self, challenge: String) -> Self {
+        self.challenge = Some(challenge);
+        self
+    }
+
     pub fn to_json(&self) -> Result<String> {
         serde_json::to_string(self)
             .context("Failed to serialize pairing payload")
     }
 
+    /// `balancebridge://pair?v=1&pubkey=…&relays=…&challenge=…`, with each
+    /// relay URL percent-encoded and the relays joined by ','
+    pub fn to_deep_link(&self) -> String {
+        let relays: Vec<String> = self.relays.iter().map(|r| deep_link_escape(r)).collect();
+
+        let mut link = format!(
+            "{}v={}&pubkey={}&relays={}",
+            DEEP_LINK_PREFIX,
+            self.version,
+            deep_link_escape(&self.node_pubkey),
+            relays.join(",")
+        );
+        if let Some(challenge) = &self.challenge {
+            link.push_str("&challenge=");
+            link.push_str(&deep_link_escape(challenge));
+        }
+        link
+    }
+
+    /// Inverse of `to_deep_link`; unknown parameters are ignored
+    pub fn from_deep_link(link: &str) -> Result<Self> {
+        let params = deep_link_params(link)?;
+        let param = |name: &str| {
+            params
+                .get(name)
+                .cloned()
+                .with_context(|| format!("Pairing link has no '{}'", name))
+        };
+
+        let version = param("v")?
+            .parse()
+            .context("Pairing link has an invalid version")?;
+        let relays = param("relays")?;
+        let relays = relays
+            .split(',')
+            .filter(|r| !r.is_empty())
+            .map(deep_link_unescape)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            version,
+            app: APP_IDENTIFIER.to_string(),
+            node_pubkey: deep_link_unescape(&param("pubkey")?)?,
+            relays,
+            challenge: params.get("challenge").map(|c| deep_link_unescape(c)).transpose()?,
+        })
+    }
+
+    /// Sign the payload JSON with the server key, so a scanned QR with a
+    /// tampered relay list (relay MITM) is rejected by the app
+    pub fn sign_with_keys(&self, keys: &Keys) -> Result<SignedPairingPayload> {
+        let message = payload_message(&self.to_json()?);
+
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &keys.secret_key().secret_bytes())
+            .context("Invalid server secret key")?;
+        let signature = secp.sign_schnorr_no_aux_rand(&message, &keypair);
+
+        Ok(SignedPairingPayload {
+            payload: self.clone(),
+            pubkey: keypair.x_only_public_key().0.to_string(),
+            signature: signature.to_string(),
+        })
+    }
+
     /// Generate QR code as SVG (stable, no image crate)
     pub fn generate_qr_svg(&self) -> Result<String> {
         let json = self.to_json()?;
+        render_svg(json.as_bytes())
+    }
+
+    /// Generate QR code as PNG bytes, at least `size` pixels wide, for
+    /// clients and tooling that can't rasterize SVG
+    pub fn generate_qr_image(&self, size: u32) -> Result<Vec<u8>> {
+        let json = self.to_json()?;
+        render_png(json.as_bytes(), size)
+    }
+
+    /// Split the payload into UR (Uniform Resource) fragments, one SVG frame each
+    ///
+    /// For payloads too large for a single QR code. The client cycles through
+    /// the frames and reassembles them with a standard UR decoder.
+    pub fn generate_animated_qr_frames(&self, frame_size_bytes: usize) -> Result<Vec<String>> {
+        render_animated(&self.to_json()?, frame_size_bytes)
+    }
+}
+
+/// Pairing payload plus a BIP-340 signature over its JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedPairingPayload {
+    #[serde(flatten)]
+    pub payload: PairingPayload,
+    /// x-only hex pubkey that signed (same as `nodePubkey`)
+    pub pubkey: String,
+    /// Schnorr signature (hex) over sha256 of the unsigned payload JSON
+    pub signature: String,
+}
+
+impl SignedPairingPayload {
+    /// Check the signature, and that the signer is the advertised node
+    pub fn verify(&self) -> Result<()> {
+        if self.pubkey != self.payload.node_pubkey {
+            return Err(anyhow!("Pairing payload signed by a different key"));
+        }
+
+        let pubkey = XOnlyPublicKey::from_str(&self.pubkey)
+            .context("Invalid pairing payload pubkey")?;
+        let signature = schnorr::Signature::from_str(&self.signature)
+            .context("Invalid pairing payload signature")?;
+        let message = payload_message(&self.payload.to_json()?);
+
+        Secp256k1::verification_only()
+            .verify_schnorr(&signature, &message, &pubkey)
+            .map_err(|e| anyhow!("Pairing payload signature check failed: {}", e))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .context("Failed to serialize signed pairing payload")
+    }
+
+    /// Same as `PairingPayload::generate_qr_svg`, with the signature included
+    pub fn generate_qr_svg(&self) -> Result<String> {
+        let json = self.to_json()?;
+        render_svg(json.as_bytes())
+    }
+
+    /// Same as `PairingPayload::to_deep_link`, plus `&sig=…`
+    pub fn to_deep_link(&self) -> String {
+        format!("{}&sig={}", self.payload.to_deep_link(), deep_link_escape(&self.signature))
+    }
+
+    /// Inverse of `to_deep_link`. Call `verify` before trusting the result.
+    pub fn from_deep_link(link: &str) -> Result<Self> {
+        let payload = PairingPayload::from_deep_link(link)?;
+        let signature = deep_link_params(link)?
+            .remove("sig")
+            .context("Pairing link is not signed")?;
+
+        Ok(Self {
+            pubkey: payload.node_pubkey.clone(),
+            signature: deep_link_unescape(&signature)?,
+            payload,
+        })
+    }
+
+    /// Same as `PairingPayload::generate_qr_image`, with the signature included
+    pub fn generate_qr_image(&self, size: u32) -> Result<Vec<u8>> {
+        let json = self.to_json()?;
+        render_png(json.as_bytes(), size)
+    }
 
-        let code = QrCode::new(json.as_bytes())
-            .context("Failed to generate QR code")?;
+    /// Same as `PairingPayload::generate_animated_qr_frames`, with the signature included
+    pub fn generate_animated_qr_frames(&self, frame_size_bytes: usize) -> Result<Vec<String>> {
+        render_animated(&self.to_json()?, frame_size_bytes)
+    }
+}
 
-        let svg = code
-            .render::<svg::Color>()
-            .min_dimensions(512, 512)
-            .dark_color(svg::Color("#000000"))
-            .light_color(svg::Color("#ffffff"))
-            .build();
+/// Everything served by the pairing endpoints, rebuilt on /pairing/refresh
+#[derive(Debug, Clone)]
+pub struct PairingQr {
+    pub json: String,
+    pub deep_link: String,
+    /// The JSON or the deep link, as `encoding` chose
+    pub svg: String,
+    pub png: Vec<u8>,
+    /// Always the JSON (UR-encoded)
+    pub frames: Vec<String>,
+}
 
-        Ok(svg)
+impl PairingQr {
+    /// Signed payload for `keys` + `relays` + `challenge`, rendered every way we serve it
+    pub fn build(
+        keys: &Keys,
+        relays: Vec<String>,
+        challenge: String,
+        encoding: QrEncoding,
+    ) -> Result<Self> {
+        let payload = PairingPayload::new(keys.public_key().to_hex(), relays)
+            .with_challenge(challenge)
+            .sign_with_keys(keys)?;
+
+        let json = payload.to_json()?;
+        let deep_link = payload.to_deep_link();
+        let qr_data = match encoding {
+            QrEncoding::Json => json.as_bytes(),
+            QrEncoding::DeepLink => deep_link.as_bytes(),
+        };
+
+        Ok(Self {
+            svg: render_svg(qr_data)?,
+            png: render_png(qr_data, QR_PNG_SIZE)?,
+            frames: payload.generate_animated_qr_frames(ANIMATED_QR_FRAME_BYTES)?,
+            json,
+            deep_link,
+        })
     }
 }
+
+fn deep_link_escape(value: &str) -> String {
+    utf8_percent_encode(value, DEEP_LINK_ESCAPE).to_string()
+}
+
+fn deep_link_unescape(value: &str) -> Result<String> {
+    Ok(percent_decode_str(value)
+        .decode_utf8()
+        .context("Pairing link is not valid UTF-8")?
+        .into_owned())
+}
+
+/// Raw (still escaped) query parameters of a `balancebridge://pair?` link
+fn deep_link_params(link: &str) -> Result<HashMap<String, String>> {
+    let query = link
+        .trim()
+        .strip_prefix(DEEP_LINK_PREFIX)
+        .with_context(|| format!("Pairing link must start with {}", DEEP_LINK_PREFIX))?;
+
+    Ok(query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect())
+}
+
+fn payload_message(json: &str) -> Message {
+    Message::from_digest(sha256::Hash::hash(json.as_bytes()).to_byte_array())
+}
+
+fn render_animated(json: &str, frame_size_bytes: usize) -> Result<Vec<String>> {
+    let mut encoder = ur::Encoder::bytes(json.as_bytes(), frame_size_bytes)
+        .map_err(|e| anyhow!("Failed to create UR encoder: {:?}", e))?;
+
+    let count = encoder.fragment_count();
+    let mut frames = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let part = encoder
+            .next_part()
+            .map_err(|e| anyhow!("Failed to encode UR part: {:?}", e))?;
+        // Uppercase lets the QR use the denser alphanumeric mode
+        frames.push(render_svg(part.to_uppercase().as_bytes())?);
+    }
+
+    Ok(frames)
+}
+
+fn render_svg(data: &[u8]) -> Result<String> {
+    let code = QrCode::new(data)
+        .context("Failed to generate QR code")?;
+
+    let svg = code
+        .render::<svg::Color>()
+        .min_dimensions(512, 512)
+        .dark_color(svg::Color("#000000"))
+        .light_color(svg::Color("#ffffff"))
+        .build();
+
+    Ok(svg)
+}
+
+fn render_png(data: &[u8], size: u32) -> Result<Vec<u8>> {
+    let code = QrCode::new(data)
+        .context("Failed to generate QR code")?;
+
+    let image = code
+        .render::<Luma<u8>>()
+        .min_dimensions(size, size)
+        .build();
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .encode(image.as_raw(), image.width(), image.height(), ColorType::L8)
+        .context("Failed to encode QR code as PNG")?;
+
+    Ok(png)
+}
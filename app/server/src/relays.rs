@@ -2,11 +2,55 @@
 //! 
 //! Manages the list of public Nostr relays to use.
 
+use anyhow::{anyhow, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use nostr_sdk::{Client, Event, Kind, RelayStatus, RelayUrl};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
-use tracing::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+const RELAYS_FILENAME: &str = "relays.json";
+const RELAY_HEALTH_FILENAME: &str = "relay_health.json";
+
+/// How often the relay health scores are written to disk
+pub const RELAY_HEALTH_SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Weight of the newest probe in the moving averages
+const RELAY_HEALTH_SMOOTHING: f64 = 0.2;
+
+/// How often relay round-trip times are re-measured
+pub const LATENCY_PROBE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Give up on a relay that hasn't answered within this time
+const LATENCY_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the pool's connection status is checked for failover
+pub const RELAY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Drop a relay from the pool after this long unreachable (RELAY_DROP_AFTER_MINS)
+pub const DEFAULT_RELAY_DROP_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Last measured round-trip time per relay URL
+pub type RelayLatencies = Arc<RwLock<HashMap<String, Duration>>>;
+
+/// Read/write marker of a NIP-65 relay entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayReadWrite {
+    Read,
+    Write,
+    /// No marker: the relay is used for both
+    ReadWrite,
+}
 
 /// Default list of public Nostr relays
-fn default_relays() -> Vec<String> {
+pub fn default_relays() -> Vec<String> {
     vec![
         "wss://relay.damus.io".to_string(),
         "wss://nostr.wine".to_string(),
@@ -16,22 +60,26 @@ fn default_relays() -> Vec<String> {
     ]
 }
 
+/// Relays from the NOSTR_RELAYS environment variable (comma-separated), if set and non-empty
+pub fn env_relays() -> Option<Vec<String>> {
+    let relays_env = env::var("NOSTR_RELAYS").ok()?;
+    let relays: Vec<String> = relays_env
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    (!relays.is_empty()).then_some(relays)
+}
+
 /// Get the list of relays to use
 /// 
 /// Reads from NOSTR_RELAYS environment variable (comma-separated).
 /// Falls back to default list if env var is not set.
 pub fn get_relays() -> Vec<String> {
-    if let Ok(relays_env) = env::var("NOSTR_RELAYS") {
-        let relays: Vec<String> = relays_env
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
-        
-        if !relays.is_empty() {
-            info!("Using relays from NOSTR_RELAYS: {:?}", relays);
-            return relays;
-        }
+    if let Some(relays) = env_relays() {
+        info!("Using relays from NOSTR_RELAYS: {:?}", relays);
+        return relays;
     }
     
     let defaults = default_relays();
@@ -39,3 +87,528 @@ pub fn get_relays() -> Vec<String> {
     defaults
 }
 
+/// A relay URL fit for the configured list: `wss://` or `ws://`, normalized
+pub fn validate_relay_url(url: &str) -> Result<String> {
+    let url = url.trim();
+    if !url.starts_with("wss://") && !url.starts_with("ws://") {
+        anyhow::bail!("Invalid relay URL '{}': expected wss:// or ws://", url);
+    }
+    RelayUrl::parse(url)
+        .map(|u| u.to_string())
+        .map_err(|e| anyhow!("Invalid relay URL '{}': {}", url, e))
+}
+
+/// Relay list edited at runtime (POST /relays, POST /relay/add, DELETE /relay/:url),
+/// stored in `data_dir/relays.json` so the changes survive restarts
+#[derive(Debug, Clone)]
+pub struct PersistentRelayList {
+    data_dir: PathBuf,
+    /// Used until something is stored: the configured relays
+    /// (command line, NOSTR_RELAYS or the defaults)
+    fallback: Vec<String>,
+}
+
+impl PersistentRelayList {
+    pub fn new(data_dir: impl Into<PathBuf>, fallback: Vec<String>) -> Self {
+        Self {
+            data_dir: data_dir.into(),
+            fallback,
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.data_dir.join(RELAYS_FILENAME)
+    }
+
+    /// Stored list, or the configured relays if nothing (readable) was stored yet
+    pub fn load(&self) -> Vec<String> {
+        match self.read() {
+            Ok(Some(relays)) => relays,
+            Ok(None) => self.fallback.clone(),
+            Err(e) => {
+                warn!("Ignoring unreadable {}: {}", RELAYS_FILENAME, e);
+                self.fallback.clone()
+            }
+        }
+    }
+
+    /// Replace the stored list (temp file + rename)
+    pub fn save(&self, relays: &[String]) -> Result<()> {
+        fs::create_dir_all(&self.data_dir)
+            .context("Failed to create data directory")?;
+
+        let json = serde_json::to_string_pretty(relays)
+            .context("Failed to serialize relay list")?;
+
+        let path = self.path();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .context("Failed to write relay list")?;
+        fs::rename(&tmp_path, &path)
+            .context("Failed to replace relay list")?;
+
+        Ok(())
+    }
+
+    /// Replace the whole list. Every URL must pass `validate_relay_url`, or
+    /// nothing is stored; duplicates are dropped. Returns the stored list.
+    pub fn replace(&self, relays: &[String]) -> Result<Vec<String>> {
+        let mut validated: Vec<String> = Vec::with_capacity(relays.len());
+        for url in relays {
+            let url = validate_relay_url(url)?;
+            if !validated.contains(&url) {
+                validated.push(url);
+            }
+        }
+        if validated.is_empty() {
+            anyhow::bail!("Relay list must not be empty");
+        }
+
+        self.save(&validated)?;
+        Ok(validated)
+    }
+
+    fn read(&self) -> Result<Option<Vec<String>>> {
+        let path = self.path();
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path)
+            .context("Failed to read relay list")?;
+        let relays = serde_json::from_str(&content)
+            .context("Invalid relay list format")?;
+        Ok(Some(relays))
+    }
+}
+
+/// Probe history of one relay, kept across restarts in `data_dir/relay_health.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayHealthStatus {
+    pub url: String,
+    /// Moving average of probe outcomes, 0.0 (always fails) to 1.0
+    pub success_rate: f64,
+    /// Moving average round trip of the successful probes
+    pub avg_latency_ms: f64,
+    /// RFC 3339
+    pub last_checked: String,
+}
+
+/// Relay health: scores fed by the latency probes, plus the pool's up/down
+/// tracking, so a dead relay stops taking responses the phone will never see
+/// and comes back once it recovers
+///
+/// The scores are loaded on startup so the historically healthy relays are
+/// preferred before the first probe round has finished.
+#[derive(Debug, Clone)]
+pub struct RelayHealthMonitor {
+    path: PathBuf,
+    statuses: Arc<RwLock<HashMap<String, RelayHealthStatus>>>,
+    drop_after: Duration,
+    reachability: Arc<RwLock<HashMap<String, RelayDownState>>>,
+}
+
+impl RelayHealthMonitor {
+    /// A missing or unreadable file just means no history (equal weights)
+    pub fn new(data_dir: impl AsRef<Path>) -> Self {
+        let path = data_dir.as_ref().join(RELAY_HEALTH_FILENAME);
+
+        let statuses = match Self::read(&path) {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                warn!("Ignoring unreadable {}: {}", RELAY_HEALTH_FILENAME, e);
+                Vec::new()
+            }
+        };
+
+        Self {
+            path,
+            statuses: Arc::new(RwLock::new(
+                statuses.into_iter().map(|s| (s.url.clone(), s)).collect(),
+            )),
+            drop_after: DEFAULT_RELAY_DROP_AFTER,
+            reachability: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Drop a relay from the pool after this long unreachable
+    pub fn with_drop_after(mut self, drop_after: Duration) -> Self {
+        self.drop_after = drop_after;
+        self
+    }
+
+    fn read(path: &Path) -> Result<Vec<RelayHealthStatus>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(path)
+            .context("Failed to read relay health")?;
+        serde_json::from_str(&content)
+            .context("Invalid relay health format")
+    }
+
+    /// Fold one probe outcome (round trip or failure) into the relay's averages
+    pub fn record(&self, url: &str, latency: Option<Duration>) {
+        let outcome = if latency.is_some() { 1.0 } else { 0.0 };
+        let now = chrono::Utc::now().to_rfc3339();
+
+        let mut statuses = self.statuses.write().unwrap();
+        match statuses.get_mut(url) {
+            Some(status) => {
+                status.success_rate += RELAY_HEALTH_SMOOTHING * (outcome - status.success_rate);
+                if let Some(latency) = latency {
+                    let ms = latency.as_secs_f64() * 1000.0;
+                    status.avg_latency_ms += RELAY_HEALTH_SMOOTHING * (ms - status.avg_latency_ms);
+                }
+                status.last_checked = now;
+            }
+            None => {
+                statuses.insert(
+                    url.to_string(),
+                    RelayHealthStatus {
+                        url: url.to_string(),
+                        success_rate: outcome,
+                        avg_latency_ms: latency.map_or(0.0, |l| l.as_secs_f64() * 1000.0),
+                        last_checked: now,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Most reliable first, then fastest. Relays without history keep their
+    /// configured order, between the reliable ones and the failing ones.
+    pub fn rank(&self, mut relays: Vec<String>) -> Vec<String> {
+        let statuses = self.statuses.read().unwrap();
+        let score = |url: &String| match statuses.get(url) {
+            Some(s) if s.success_rate > 0.0 => (s.success_rate, s.avg_latency_ms),
+            Some(_) => (0.0, f64::MAX),
+            None => (0.5, f64::MAX),
+        };
+
+        relays.sort_by(|a, b| {
+            let (a_rate, a_ms) = score(a);
+            let (b_rate, b_ms) = score(b);
+            b_rate.total_cmp(&a_rate).then(a_ms.total_cmp(&b_ms))
+        });
+        relays
+    }
+
+    /// Write the scores (temp file + rename)
+    pub fn save(&self) -> Result<()> {
+        let mut statuses: Vec<RelayHealthStatus> =
+            self.statuses.read().unwrap().values().cloned().collect();
+        statuses.sort_by(|a, b| a.url.cmp(&b.url));
+
+        let json = serde_json::to_string_pretty(&statuses)
+            .context("Failed to serialize relay health")?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .context("Failed to write relay health")?;
+        fs::rename(&tmp_path, &self.path)
+            .context("Failed to replace relay health")?;
+
+        Ok(())
+    }
+
+    /// Save the scores every RELAY_HEALTH_SAVE_INTERVAL
+    pub fn spawn_persist(&self) {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(RELAY_HEALTH_SAVE_INTERVAL).await;
+                if let Err(e) = monitor.save() {
+                    warn!("Failed to save relay health: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Record one check of `url`. The last relay still in the pool is never
+    /// dropped: an unreachable relay beats no relay at all.
+    pub fn observe(&self, url: &str, reachable: bool, now: Instant) -> RelayTransition {
+        let mut states = self.reachability.write().unwrap();
+        let in_pool = states.values().filter(|s| !s.dropped).count();
+        let state = states.entry(url.to_string()).or_default();
+
+        match (reachable, &state.down_since) {
+            (true, None) => RelayTransition::Unchanged,
+            (true, Some(_)) => {
+                state.down_since = None;
+                if std::mem::take(&mut state.dropped) {
+                    RelayTransition::Readd
+                } else {
+                    RelayTransition::CameUp
+                }
+            }
+            (false, None) => {
+                state.down_since = Some((now, chrono::Utc::now().to_rfc3339()));
+                RelayTransition::WentDown
+            }
+            (false, Some((since, _))) => {
+                let expired = now.saturating_duration_since(*since) >= self.drop_after;
+                if !state.dropped && expired && in_pool > 1 {
+                    state.dropped = true;
+                    RelayTransition::Drop
+                } else {
+                    RelayTransition::Unchanged
+                }
+            }
+        }
+    }
+
+    /// Relays taken out of the pool, to be probed directly
+    pub fn dropped(&self) -> Vec<String> {
+        let states = self.reachability.read().unwrap();
+        states
+            .iter()
+            .filter(|(_, s)| s.dropped)
+            .map(|(url, _)| url.clone())
+            .collect()
+    }
+
+    /// Stop tracking a relay (removed over HTTP: never re-add it)
+    pub fn forget(&self, url: &str) {
+        let normalized = RelayUrl::parse(url).map(|u| u.to_string()).ok();
+        let forgotten = |known: &String| known == url || Some(known) == normalized.as_ref();
+        self.reachability.write().unwrap().retain(|known, _| !forgotten(known));
+        self.statuses.write().unwrap().retain(|known, _| !forgotten(known));
+    }
+
+    /// Every tracked relay, by URL, with its probe scores
+    pub fn snapshot(&self) -> Vec<RelayReachability> {
+        let states = self.reachability.read().unwrap();
+        let statuses = self.statuses.read().unwrap();
+        let mut relays: Vec<RelayReachability> = states
+            .iter()
+            .map(|(url, s)| RelayReachability {
+                url: url.clone(),
+                connected: s.down_since.is_none(),
+                dropped: s.dropped,
+                down_since: s.down_since.as_ref().map(|(_, at)| at.clone()),
+                success_rate: statuses.get(url).map(|h| h.success_rate),
+                avg_latency_ms: statuses.get(url).map(|h| h.avg_latency_ms),
+            })
+            .collect();
+        relays.sort_by(|a, b| a.url.cmp(&b.url));
+        relays
+    }
+}
+
+/// What `RelayHealthMonitor::observe` made of one reachability check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayTransition {
+    Unchanged,
+    WentDown,
+    CameUp,
+    /// Unreachable for the whole drop-after period: remove it from the pool
+    Drop,
+    /// A dropped relay answered again: add it back
+    Readd,
+}
+
+/// One relay as reported by GET /health
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayReachability {
+    pub url: String,
+    pub connected: bool,
+    /// Removed from the pool until it answers again
+    pub dropped: bool,
+    /// RFC 3339; only while unreachable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub down_since: Option<String>,
+    /// Probe scores, once the relay has been probed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub success_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_latency_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RelayDownState {
+    down_since: Option<(Instant, String)>,
+    dropped: bool,
+}
+
+/// Every RELAY_CHECK_INTERVAL: read the pool's connection status, probe the
+/// dropped relays directly, and drop or re-add relays as `health` decides
+pub fn spawn_relay_failover(client: Arc<Client>, health: RelayHealthMonitor) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(RELAY_CHECK_INTERVAL).await;
+
+            let mut checks: Vec<(String, bool)> = client
+                .relays()
+                .await
+                .iter()
+                .map(|(url, relay)| (url.to_string(), relay.status() == RelayStatus::Connected))
+                .collect();
+            for url in health.dropped() {
+                let reachable = measure_relay_latency(&url).await.is_ok();
+                checks.push((url, reachable));
+            }
+
+            let now = Instant::now();
+            for (url, reachable) in checks {
+                match health.observe(&url, reachable, now) {
+                    RelayTransition::Unchanged => {}
+                    RelayTransition::WentDown => warn!("Relay {} is unreachable", url),
+                    RelayTransition::CameUp => info!("Relay {} is reachable again", url),
+                    RelayTransition::Drop => {
+                        warn!(
+                            "Dropping relay {} after {}s unreachable",
+                            url,
+                            health.drop_after.as_secs()
+                        );
+                        if let Err(e) = client.remove_relay(&url).await {
+                            warn!("Failed to remove relay {} from pool: {}", url, e);
+                        }
+                    }
+                    RelayTransition::Readd => {
+                        info!("Relay {} recovered; adding it back", url);
+                        match client.add_relay(&url).await {
+                            Ok(_) => {
+                                if let Err(e) = client.connect_relay(&url).await {
+                                    warn!("Failed to connect to relay {}: {}", url, e);
+                                }
+                            }
+                            Err(e) => warn!("Failed to add relay {} to pool: {}", url, e),
+                        }
+                    }
+                }
+            }
+
+            let relays = health.snapshot();
+            let up = relays.iter().filter(|r| r.connected).count();
+            debug!("Relay health: {} of {} reachable", up, relays.len());
+        }
+    });
+}
+
+/// Extract the relays announced in a NIP-65 relay list event (kind 10002)
+///
+/// Each `["r", <url>, <optional "read"/"write">]` tag becomes one entry;
+/// invalid URLs and unknown markers are skipped.
+pub fn parse_nip65_event(event: &Event) -> Vec<(String, RelayReadWrite)> {
+    if event.kind != Kind::RelayList {
+        return Vec::new();
+    }
+
+    let mut relays = Vec::new();
+    for t in event.tags.iter() {
+        let v = t.clone().to_vec();
+        if v.len() < 2 || v[0] != "r" {
+            continue;
+        }
+
+        let url = v[1].trim().to_string();
+        if RelayUrl::parse(&url).is_err() {
+            continue;
+        }
+
+        let marker = match v.get(2).map(|m| m.as_str()) {
+            None | Some("") => RelayReadWrite::ReadWrite,
+            Some("read") => RelayReadWrite::Read,
+            Some("write") => RelayReadWrite::Write,
+            Some(_) => continue,
+        };
+
+        relays.push((url, marker));
+    }
+
+    relays
+}
+
+/// Time from sending `["REQ", "ping", {"limit": 1}]` to the relay's first reply
+///
+/// Opens a dedicated WebSocket (not the nostr-sdk pool connection), so the
+/// number includes nothing but the relay round trip after the handshake.
+pub async fn measure_relay_latency(url: &str) -> Result<Duration> {
+    let probe = async {
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await?;
+
+        let started = Instant::now();
+        ws.send(Message::text(r#"["REQ","ping",{"limit":1}]"#)).await?;
+
+        let latency = loop {
+            match ws.next().await {
+                Some(Ok(Message::Text(_))) | Some(Ok(Message::Binary(_))) => break started.elapsed(),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+                None => return Err(anyhow!("connection closed before any reply")),
+            }
+        };
+
+        let _ = ws.send(Message::text(r#"["CLOSE","ping"]"#)).await;
+        let _ = ws.close(None).await;
+
+        Ok(latency)
+    };
+
+    tokio::time::timeout(LATENCY_PROBE_TIMEOUT, probe)
+        .await
+        .map_err(|_| anyhow!("relay {} did not answer within {:?}", url, LATENCY_PROBE_TIMEOUT))?
+}
+
+/// Measure every relay now and then every LATENCY_PROBE_INTERVAL, logging the
+/// ranking and feeding the outcomes to `health`
+pub fn spawn_latency_monitor(
+    relays: Vec<String>,
+    latencies: RelayLatencies,
+    health: RelayHealthMonitor,
+) {
+    tokio::spawn(async move {
+        loop {
+            let mut measured = HashMap::new();
+            for url in &relays {
+                match measure_relay_latency(url).await {
+                    Ok(latency) => {
+                        health.record(url, Some(latency));
+                        measured.insert(url.clone(), latency);
+                    }
+                    Err(e) => {
+                        health.record(url, None);
+                        warn!("Relay latency probe failed for {}: {}", url, e);
+                    }
+                }
+            }
+
+            let ranking: Vec<String> = rank_relays(relays.clone(), &measured)
+                .into_iter()
+                .map(|url| match measured.get(&url) {
+                    Some(latency) => format!("{} ({}ms)", url, latency.as_millis()),
+                    None => format!("{} (unreachable)", url),
+                })
+                .collect();
+            info!("Relay latency ranking: {}", ranking.join(", "));
+
+            *latencies.write().unwrap() = measured;
+
+            tokio::time::sleep(LATENCY_PROBE_INTERVAL).await;
+        }
+    });
+}
+
+/// Sort relays fastest first; relays without a measurement go last
+pub fn rank_relays(mut relays: Vec<String>, latencies: &HashMap<String, Duration>) -> Vec<String> {
+    relays.sort_by_key(|url| latencies.get(url).copied().unwrap_or(Duration::MAX));
+    relays
+}
+
+/// Exponential backoff with random jitter for reconnect/restart loops
+///
+/// `base_ms * 2^attempt` (capped at `max_ms`) plus `0..base_ms` of jitter, so
+/// many servers that lost the same relay at once don't all come back together.
+pub fn jitter_delay(attempt: u32, base_ms: u64, max_ms: u64) -> Duration {
+    let backoff = base_ms
+        .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX))
+        .min(max_ms);
+    let jitter = if base_ms > 0 {
+        rand::thread_rng().gen_range(0..base_ms)
+    } else {
+        0
+    };
+    Duration::from_millis(backoff.saturating_add(jitter))
+}
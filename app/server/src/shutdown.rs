@@ -0,0 +1,116 @@
+//! Graceful shutdown
+//!
+//! Umbrel stops an app with SIGTERM and kills it 30 seconds later. The
+//! coordinator cancels a shared token (watched by the HTTP server and the
+//! Nostr loop) and counts Nostr requests still being answered.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+/// Whole shutdown budget: Umbrel's SIGTERM → SIGKILL window
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long in-flight requests get to finish
+pub const IN_FLIGHT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Cancelled once shutdown starts
+pub type ShutdownToken = CancellationToken;
+
+#[derive(Clone)]
+pub struct ShutdownCoordinator {
+    token: ShutdownToken,
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Counts one request as in flight until dropped
+pub struct InFlightGuard {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> Self {
+        Self {
+            token: ShutdownToken::new(),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Tell every waiter to shut down
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    /// Resolves once `trigger` is called (immediately if it already was)
+    pub fn wait(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let token = self.token.clone();
+        async move { token.cancelled().await }
+    }
+
+    /// The token `trigger` cancels, for loops that `select!` on it
+    pub fn token(&self) -> ShutdownToken {
+        self.token.clone()
+    }
+
+    /// Mark a request as in flight for the lifetime of the guard
+    pub fn track(&self) -> InFlightGuard {
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard {
+            in_flight: Arc::clone(&self.in_flight),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Wait until no request is in flight. Returns false on timeout.
+    pub async fn wait_for_in_flight(&self, timeout: Duration) -> bool {
+        let drained = async {
+            while self.in_flight() > 0 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        };
+        tokio::time::timeout(timeout, drained).await.is_ok()
+    }
+}
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves on SIGTERM (Umbrel stopping the app) or Ctrl-C
+pub async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Failed to install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
@@ -0,0 +1,36 @@
+//! Startup banner
+//!
+//! Logs the effective configuration in one place so a misconfigured
+//! deployment is obvious from the Umbrel app logs (`grep '\[CONFIG\]'`).
+
+use nostr_sdk::prelude::*;
+use tracing::info;
+
+use crate::config::ServerConfig;
+
+const GIT_COMMIT: Option<&str> = option_env!("GIT_COMMIT");
+
+pub fn print_banner(config: &ServerConfig, pubkey: &PublicKey, paired: bool) {
+    let npub = pubkey
+        .to_bech32()
+        .unwrap_or_else(|_| "<bech32 encoding failed>".to_string());
+
+    info!("[CONFIG] ==== BalanceBridge server ====");
+    info!("[CONFIG] version: {}", env!("CARGO_PKG_VERSION"));
+    info!("[CONFIG] git commit: {}", GIT_COMMIT.unwrap_or("unknown"));
+    info!("[CONFIG] data dir: {}", config.data_dir.display());
+    info!(
+        "[CONFIG] electrs: {} (tls={})",
+        config.electrs_addr,
+        config.electrs_tls()
+    );
+    info!("[CONFIG] relays: {}", config.relays.join(", "));
+    info!("[CONFIG] gap limit: {}", config.gap_limit);
+    info!("[CONFIG] listen address: {}", config.listen_addr);
+    info!(
+        "[CONFIG] pairing: {}",
+        if paired { "paired" } else { "unpaired" }
+    );
+    info!("[CONFIG] server pubkey (hex): {}", pubkey.to_hex());
+    info!("[CONFIG] server pubkey (npub): {}", npub);
+}
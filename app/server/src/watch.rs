@@ -0,0 +1,179 @@
+//! Watched xpubs
+//!
+//! A paired device can ask the server to keep an eye on an xpub; the
+//! watcher in `NostrHandler` re-scans every entry periodically and notifies
+//! the device when the totals change. Entries live in
+//! `data_dir/xpub_watch.json` so they survive restarts.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const WATCH_FILENAME: &str = "xpub_watch.json";
+
+/// Watched xpubs allowed per paired device
+pub const MAX_WATCHED_XPUBS_PER_DEVICE: usize = 5;
+
+/// Totals of one watch scan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XpubSnapshot {
+    pub total_confirmed: u64,
+    pub total_unconfirmed: u64,
+    /// Addresses with a non-zero balance
+    pub address_count: u32,
+    pub scanned_at: String,
+}
+
+impl XpubSnapshot {
+    /// Same balances, ignoring when they were taken
+    pub fn same_balance(&self, other: &XpubSnapshot) -> bool {
+        self.total_confirmed == other.total_confirmed
+            && self.total_unconfirmed == other.total_unconfirmed
+            && self.address_count == other.address_count
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchedXpub {
+    /// Hex pubkey of the device that asked
+    pub owner: String,
+    pub xpub: String,
+    /// Request id of the watch_xpub request, reused as the `req` tag of notifications
+    pub req_id: String,
+    pub last_snapshot: Option<XpubSnapshot>,
+}
+
+/// Outcome of `XpubWatchList::add`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAdd {
+    Added,
+    AlreadyWatched,
+    LimitReached,
+}
+
+/// Watched xpubs of every device, persisted on each change
+#[derive(Clone)]
+pub struct XpubWatchList {
+    path: PathBuf,
+    entries: Arc<Mutex<Vec<WatchedXpub>>>,
+}
+
+impl XpubWatchList {
+    pub fn new(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let path = data_dir.as_ref().join(WATCH_FILENAME);
+
+        let entries = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .context("Failed to read xpub watch list")?;
+            serde_json::from_str(&content)
+                .context("Invalid xpub watch list format")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            entries: Arc::new(Mutex::new(entries)),
+        })
+    }
+
+    /// Start watching `xpub` for `owner` (at most MAX_WATCHED_XPUBS_PER_DEVICE)
+    pub fn add(
+        &self,
+        owner: &str,
+        xpub: &str,
+        req_id: &str,
+        snapshot: Option<XpubSnapshot>,
+    ) -> Result<WatchAdd> {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.iter().any(|e| e.owner == owner && e.xpub == xpub) {
+            return Ok(WatchAdd::AlreadyWatched);
+        }
+        if entries.iter().filter(|e| e.owner == owner).count() >= MAX_WATCHED_XPUBS_PER_DEVICE {
+            return Ok(WatchAdd::LimitReached);
+        }
+
+        entries.push(WatchedXpub {
+            owner: owner.to_string(),
+            xpub: xpub.to_string(),
+            req_id: req_id.to_string(),
+            last_snapshot: snapshot,
+        });
+        self.save(&entries)?;
+
+        Ok(WatchAdd::Added)
+    }
+
+    /// Whether `add` would refuse `xpub` for `owner` because of the per-device limit
+    pub fn is_full_for(&self, owner: &str, xpub: &str) -> bool {
+        let entries = self.entries.lock().unwrap();
+        !entries.iter().any(|e| e.owner == owner && e.xpub == xpub)
+            && entries.iter().filter(|e| e.owner == owner).count() >= MAX_WATCHED_XPUBS_PER_DEVICE
+    }
+
+    /// Stop watching `xpub` for `owner`; false if it wasn't watched
+    pub fn remove(&self, owner: &str, xpub: &str) -> Result<bool> {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|e| !(e.owner == owner && e.xpub == xpub));
+        if entries.len() == before {
+            return Ok(false);
+        }
+        self.save(&entries)?;
+        Ok(true)
+    }
+
+    /// Drop every entry of `owner` (its pairing is gone); returns how many
+    pub fn remove_owner(&self, owner: &str) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|e| e.owner != owner);
+        let removed = before - entries.len();
+        if removed > 0 {
+            self.save(&entries)?;
+        }
+        Ok(removed)
+    }
+
+    /// Number of xpubs `owner` is watching
+    pub fn count_for(&self, owner: &str) -> usize {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.owner == owner)
+            .count()
+    }
+
+    /// Copy of every entry (for the watcher, which must not hold the lock across awaits)
+    pub fn entries(&self) -> Vec<WatchedXpub> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Store the latest scan of an entry (no-op if it was removed meanwhile)
+    pub fn update_snapshot(&self, owner: &str, xpub: &str, snapshot: XpubSnapshot) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.iter_mut().find(|e| e.owner == owner && e.xpub == xpub) else {
+            return Ok(());
+        };
+        entry.last_snapshot = Some(snapshot);
+        self.save(&entries)
+    }
+
+    /// Write the list atomically (temp file + rename). Caller holds the lock.
+    fn save(&self, entries: &[WatchedXpub]) -> Result<()> {
+        let json = serde_json::to_string_pretty(entries)
+            .context("Failed to serialize xpub watch list")?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .context("Failed to write xpub watch list")?;
+        fs::rename(&tmp_path, &self.path)
+            .context("Failed to replace xpub watch list")?;
+
+        Ok(())
+    }
+}
@@ -5,96 +5,638 @@
 use anyhow::{Context, Result};
 use bitcoin::bip32::{DerivationPath, Xpub};
 use bitcoin::secp256k1::Secp256k1;
-use bitcoin::Network;
+use bitcoin::{Network, NetworkKind};
 use std::str::FromStr;
 use tracing::{info, warn};
 
+/// Number of addresses derived per chain when the caller doesn't specify one
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// Largest gap limit a request may ask for
+pub const MAX_GAP_LIMIT: u32 = 200;
+
+/// Largest account index a request may ask for
+pub const MAX_ACCOUNT: u32 = 100;
+
+/// Whether a requested gap limit is within 1..=MAX_GAP_LIMIT
+pub fn valid_gap_limit(gap_limit: u32) -> bool {
+    (1..=MAX_GAP_LIMIT).contains(&gap_limit)
+}
+
+/// Whether a requested account index is within 0..=MAX_ACCOUNT
+pub fn valid_account(account: u32) -> bool {
+    account <= MAX_ACCOUNT
+}
+
+/// Addresses per batched history call in `derive_addresses_until_gap`
+const GAP_SCAN_WINDOW: u32 = 20;
+
+/// Highest child index a gap scan derives on one chain. A key whose used
+/// addresses run past this is refused instead of scanned without end.
+pub const MAX_SCAN_INDEX: u32 = 10_000;
+
+/// Script type of the addresses derived from a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressType {
+    /// Legacy `1…` (BIP44)
+    P2PKH,
+    /// P2WPKH wrapped in P2SH, `3…` (BIP49)
+    P2shSegwit,
+    /// P2WPKH, `bc1q…` (BIP84)
+    NativeSegwit,
+    /// Key-path-only P2TR, `bc1p…` (BIP86)
+    Taproot,
+}
+
+/// BIP that defines an account's path and address type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivationStandard {
+    /// m/44'/coin'/account' — P2PKH
+    Bip44,
+    /// m/49'/coin'/account' — P2SH-P2WPKH
+    Bip49,
+    /// m/84'/coin'/account' — P2WPKH
+    Bip84,
+    /// m/86'/coin'/account' — P2TR
+    Bip86,
+}
+
+impl DerivationStandard {
+    /// The standard whose accounts use `address_type` (ypub → BIP49, …)
+    pub fn for_address_type(address_type: AddressType) -> Self {
+        match address_type {
+            AddressType::P2PKH => DerivationStandard::Bip44,
+            AddressType::P2shSegwit => DerivationStandard::Bip49,
+            AddressType::NativeSegwit => DerivationStandard::Bip84,
+            AddressType::Taproot => DerivationStandard::Bip86,
+        }
+    }
+
+    pub fn address_type(self) -> AddressType {
+        match self {
+            DerivationStandard::Bip44 => AddressType::P2PKH,
+            DerivationStandard::Bip49 => AddressType::P2shSegwit,
+            DerivationStandard::Bip84 => AddressType::NativeSegwit,
+            DerivationStandard::Bip86 => AddressType::Taproot,
+        }
+    }
+}
+
+impl FromStr for DerivationStandard {
+    type Err = anyhow::Error;
+
+    /// "bip84", "BIP84" or just "84"
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        match s.strip_prefix("bip").unwrap_or(&s) {
+            "44" => Ok(DerivationStandard::Bip44),
+            "49" => Ok(DerivationStandard::Bip49),
+            "84" => Ok(DerivationStandard::Bip84),
+            "86" => Ok(DerivationStandard::Bip86),
+            other => anyhow::bail!("Unknown derivation standard '{}'", other),
+        }
+    }
+}
+
+/// An account-level xpub together with how it was derived
+/// (e.g. exported by a hardware wallet at m/84'/0'/0')
+#[derive(Debug, Clone)]
+pub struct XpubQuery {
+    pub xpub: String,
+    pub standard: DerivationStandard,
+    pub account: u32,
+}
+
+impl XpubQuery {
+    /// The account index can't be re-derived from an account xpub (it is a
+    /// hardened step above it), but a depth-3 xpub records it: reject a mismatch
+    pub fn check_account(&self) -> Result<()> {
+        let xpub = Xpub::from_str(&normalize_to_xpub(&self.xpub)?)
+            .context("Failed to parse extended public key")?;
+
+        if xpub.depth == 3 {
+            let expected = bitcoin::bip32::ChildNumber::from_hardened_idx(self.account)
+                .context("Account index out of range")?;
+            if xpub.child_number != expected {
+                anyhow::bail!(
+                    "xpub is for account {}, request says {}'",
+                    xpub.child_number,
+                    self.account
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Receiving then change addresses (`gap_limit` each) with the address type of
+/// `query.standard`
+pub fn derive_addresses_for_standard(
+    query: &XpubQuery,
+    network: Network,
+    gap_limit: u32,
+) -> Result<Vec<(String, AddressType)>> {
+    query.check_account()?;
+
+    let address_type = query.standard.address_type();
+    let mut addresses = Vec::with_capacity(2 * gap_limit as usize);
+    for chain in [0, 1] {
+        for derived in derive_chain_range(&query.xpub, network, address_type, chain, 0, gap_limit)? {
+            addresses.push((derived.address, address_type));
+        }
+    }
+
+    Ok(addresses)
+}
+
+/// Addresses of an xpub, split by chain
+#[derive(Debug, Clone, Default)]
+pub struct DerivedAddressSets {
+    /// m/0/* (receiving)
+    pub external: Vec<String>,
+    /// m/1/* (change)
+    pub internal: Vec<String>,
+}
+
 /// Derive addresses from an extended public key
 ///
-/// Supports xpub (mainnet), ypub/zpub (SegWit), tpub (testnet)
-/// Derives both external (receiving) and internal (change) addresses
-/// with a gap limit of 20 for each chain.
-pub fn derive_addresses(xpub_str: &str, gap_limit: u32) -> Result<Vec<String>> {
-    info!("Deriving addresses from xpub with gap_limit={}", gap_limit);
+/// Supports xpub (mainnet), ypub/zpub (SegWit), tpub (testnet); addresses
+/// are encoded for `network`, which the key must belong to. Derives exactly `gap_limit` external (receiving) and internal (change)
+/// addresses; `derive_addresses_until_gap` follows wallets past that.
+pub fn derive_addresses(xpub_str: &str, network: Network, gap_limit: u32) -> Result<Vec<String>> {
+    let sets = derive_addresses_split(xpub_str, network, gap_limit)?;
 
-    // Determine network from xpub prefix
-    let network = detect_network(xpub_str)?;
+    let mut addresses = sets.external;
+    addresses.extend(sets.internal);
 
-    // Parse the xpub using bitcoin crate
-    let xpub = Xpub::from_str(xpub_str)
-        .context("Failed to parse extended public key")?;
+    info!("Derived {} addresses from xpub", addresses.len());
+
+    Ok(addresses)
+}
+
+/// Like `derive_addresses`, but keeps receiving and change addresses apart
+pub fn derive_addresses_split(
+    xpub_str: &str,
+    network: Network,
+    gap_limit: u32,
+) -> Result<DerivedAddressSets> {
+    info!("Deriving addresses from xpub with gap_limit={}", gap_limit);
+
+    // Script type follows from the prefix (zpub → bc1q…)
+    let (_, address_type) = detect_network(xpub_str)?;
+    let xpub = parse_xpub_for_network(xpub_str, network)?;
 
     // Create secp256k1 context for key operations
     let secp = Secp256k1::new();
 
-    let mut addresses = Vec::new();
+    let mut sets = DerivedAddressSets::default();
 
     // Derive external (receiving) addresses: m/0/0, m/0/1, ..., m/0/(gap_limit-1)
-    info!("Deriving external (receiving) addresses");
-    for i in 0..gap_limit {
-        let path_str = format!("m/0/{}", i);
-        let path = DerivationPath::from_str(&path_str)
-            .context("Failed to create derivation path")?;
-        
-        match derive_address_from_path(&xpub, &path, network, &secp) {
-            Ok(addr) => {
-                addresses.push(addr);
-            }
-            Err(e) => {
-                warn!("Failed to derive address at path {}: {}", path_str, e);
-                break; // Stop if derivation fails
+    // then internal (change) addresses: m/1/0, m/1/1, ..., m/1/(gap_limit-1)
+    for (chain, addresses) in [(0, &mut sets.external), (1, &mut sets.internal)] {
+        info!(
+            "Deriving {} addresses",
+            if chain == 0 { "external (receiving)" } else { "internal (change)" }
+        );
+        for i in 0..gap_limit {
+            let path_str = format!("m/{}/{}", chain, i);
+            let path = DerivationPath::from_str(&path_str)
+                .context("Failed to create derivation path")?;
+
+            match derive_address_from_path(&xpub, &path, network, address_type, &secp) {
+                Ok(addr) => {
+                    addresses.push(addr);
+                }
+                Err(e) => {
+                    warn!("Failed to derive address at path {}: {}", path_str, e);
+                    break; // Stop if derivation fails
+                }
             }
         }
     }
 
-    // Derive internal (change) addresses: m/1/0, m/1/1, ..., m/1/(gap_limit-1)
-    info!("Deriving internal (change) addresses");
-    for i in 0..gap_limit {
-        let path_str = format!("m/1/{}", i);
-        let path = DerivationPath::from_str(&path_str)
-            .context("Failed to create derivation path")?;
-        
-        match derive_address_from_path(&xpub, &path, network, &secp) {
-            Ok(addr) => {
-                addresses.push(addr);
-            }
-            Err(e) => {
-                warn!("Failed to derive address at path {}: {}", path_str, e);
-                break; // Stop if derivation fails
+    Ok(sets)
+}
+
+/// A gap scan reached MAX_SCAN_INDEX on a chain without finding the gap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanIndexLimit {
+    pub chain: u32,
+}
+
+impl std::fmt::Display for ScanIndexLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no gap found on chain {} within {} addresses",
+            self.chain, MAX_SCAN_INDEX
+        )
+    }
+}
+
+impl std::error::Error for ScanIndexLimit {}
+
+/// Position of a gap-limit scan: chain 0 (external) then chain 1 (change),
+/// each until `gap_limit` consecutive addresses have no history.
+///
+/// The one walk shared by `derive_addresses_until_gap` and the handler's
+/// paginated xpub scan, which keeps it between pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GapScan {
+    /// 0 = external, 1 = change, 2 = done
+    pub chain: u32,
+    pub next_index: u32,
+    consecutive_unused: u32,
+    gap_limit: u32,
+}
+
+impl GapScan {
+    pub fn new(gap_limit: u32) -> Self {
+        Self {
+            chain: 0,
+            next_index: 0,
+            consecutive_unused: 0,
+            gap_limit: gap_limit.max(1),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.chain > 1
+    }
+
+    /// Path of the next address, e.g. "m/0/21"
+    pub fn cursor(&self) -> String {
+        format!("m/{}/{}", self.chain, self.next_index)
+    }
+
+    /// Up to `count` addresses from the current position, stopping at
+    /// MAX_SCAN_INDEX. `ScanIndexLimit` once the chain is at the cap.
+    pub fn next_window(
+        &self,
+        xpub_str: &str,
+        network: Network,
+        address_type: AddressType,
+        count: u32,
+    ) -> Result<Vec<DerivedAddress>> {
+        if self.next_index >= MAX_SCAN_INDEX {
+            return Err(ScanIndexLimit { chain: self.chain }.into());
+        }
+        let count = count.min(MAX_SCAN_INDEX - self.next_index);
+        derive_chain_range(xpub_str, network, address_type, self.chain, self.next_index, count)
+    }
+
+    /// Step past the next address. True if that completed the gap, which
+    /// moves the scan to the start of the next chain.
+    pub fn record(&mut self, used: bool) -> bool {
+        self.next_index += 1;
+        self.consecutive_unused = if used { 0 } else { self.consecutive_unused + 1 };
+
+        if self.consecutive_unused < self.gap_limit {
+            return false;
+        }
+        self.chain += 1;
+        self.next_index = 0;
+        self.consecutive_unused = 0;
+        true
+    }
+}
+
+/// An address passed by a gap scan
+struct ScannedAddress {
+    chain: u32,
+    address: String,
+    /// Has history
+    used: bool,
+}
+
+/// Walk both chains until `gap_limit` consecutive addresses have no history
+///
+/// Works in windows of GAP_SCAN_WINDOW addresses with one batched history
+/// call each, so wallets with more than `gap_limit` used addresses are fully
+/// covered. Fails with `ScanIndexLimit` if a chain has no gap within
+/// MAX_SCAN_INDEX addresses.
+async fn scan_until_gap(
+    xpub_str: &str,
+    address_type: AddressType,
+    gap_limit: u32,
+    electrs: &dyn crate::electrs::ElectrsClientTrait,
+) -> Result<Vec<ScannedAddress>> {
+    let mut scan = GapScan::new(gap_limit);
+    let mut scanned = Vec::new();
+
+    while !scan.is_done() {
+        let window = scan.next_window(xpub_str, electrs.network(), address_type, GAP_SCAN_WINDOW)?;
+        let scripts = window
+            .iter()
+            .map(|d| crate::electrs::address_script(&d.address))
+            .collect::<Result<Vec<_>>>()?;
+
+        let histories = electrs.batch_get_histories(&scripts).await?;
+        if histories.len() != scripts.len() {
+            anyhow::bail!(
+                "Electrs returned {} histories for {} scripts",
+                histories.len(),
+                scripts.len()
+            );
+        }
+
+        for (derived, history) in window.into_iter().zip(histories) {
+            let used = !history.is_empty();
+            scanned.push(ScannedAddress {
+                chain: scan.chain,
+                address: derived.address,
+                used,
+            });
+            if scan.record(used) {
+                break;
             }
         }
     }
 
-    info!("Derived {} addresses from xpub", addresses.len());
+    info!(
+        "Gap-limit scan: {} addresses, {} used (gap_limit={})",
+        scanned.len(),
+        scanned.iter().filter(|a| a.used).count(),
+        gap_limit
+    );
+
+    Ok(scanned)
+}
+
+/// Every address of a gap scan (see `scan_until_gap`), split by chain.
+/// The trailing unused addresses are included.
+pub async fn derive_addresses_until_gap(
+    xpub_str: &str,
+    address_type: AddressType,
+    gap_limit: u32,
+    electrs: &dyn crate::electrs::ElectrsClientTrait,
+) -> Result<DerivedAddressSets> {
+    let mut sets = DerivedAddressSets::default();
+    for scanned in scan_until_gap(xpub_str, address_type, gap_limit, electrs).await? {
+        match scanned.chain {
+            0 => sets.external.push(scanned.address),
+            _ => sets.internal.push(scanned.address),
+        }
+    }
+    Ok(sets)
+}
+
+/// Only the addresses of a gap scan that have history, both chains
+pub async fn used_addresses_until_gap(
+    xpub_str: &str,
+    address_type: AddressType,
+    gap_limit: u32,
+    electrs: &dyn crate::electrs::ElectrsClientTrait,
+) -> Result<Vec<String>> {
+    Ok(scan_until_gap(xpub_str, address_type, gap_limit, electrs)
+        .await?
+        .into_iter()
+        .filter(|a| a.used)
+        .map(|a| a.address)
+        .collect())
+}
+
+/// An address together with the derivation path it came from
+#[derive(Debug, Clone)]
+pub struct DerivedAddress {
+    /// Relative to the xpub, e.g. "m/0/3"
+    pub path: String,
+    pub address: String,
+}
+
+/// Derive `count` consecutive addresses on one chain (0 = external, 1 = change),
+/// starting at `start`. Used by paginated scans that resume mid-chain.
+pub fn derive_chain_range(
+    xpub_str: &str,
+    network: Network,
+    address_type: AddressType,
+    chain: u32,
+    start: u32,
+    count: u32,
+) -> Result<Vec<DerivedAddress>> {
+    let xpub = parse_xpub_for_network(xpub_str, network)?;
+    let secp = Secp256k1::new();
+
+    let mut addresses = Vec::with_capacity(count as usize);
+    for i in start..start.saturating_add(count) {
+        let path_str = format!("m/{}/{}", chain, i);
+        let path = DerivationPath::from_str(&path_str)
+            .context("Failed to create derivation path")?;
+
+        let address = derive_address_from_path(&xpub, &path, network, address_type, &secp)?;
+        addresses.push(DerivedAddress {
+            path: path_str,
+            address,
+        });
+    }
 
     Ok(addresses)
 }
 
-/// Detect Bitcoin network from xpub prefix
-fn detect_network(xpub_str: &str) -> Result<Network> {
-    let prefix = xpub_str.get(0..4).unwrap_or("");
-    
+/// A derived address together with its script type
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TypedAddress {
+    pub path: String,
+    pub address: String,
+    /// "p2pkh", "p2wpkh", "p2tr", …
+    #[serde(rename = "type")]
+    pub address_type: String,
+}
+
+/// First `count` receiving addresses (m/0/0 …) with their script type.
+/// Purely local, no Electrs.
+pub fn derive_addresses_typed(
+    xpub_str: &str,
+    network: Network,
+    count: u32,
+) -> Result<Vec<TypedAddress>> {
+    derive_chain_range(xpub_str, network, prefix_address_type(xpub_str)?, 0, 0, count)?
+        .into_iter()
+        .map(|d| {
+            let address_type = bitcoin::Address::from_str(&d.address)?
+                .assume_checked()
+                .address_type()
+                .map(|t| t.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            Ok(TypedAddress {
+                path: d.path,
+                address: d.address,
+                address_type,
+            })
+        })
+        .collect()
+}
+
+/// Rough number of used receiving addresses, from 5 sampled indices
+///
+/// Checks m/0/{0, gap/4, gap/2, 3*gap/4, gap-1} in one batched history call
+/// and interpolates between the last used sample and the next unused one.
+/// Only meant for progress reporting before a real gap-limit scan.
+pub async fn estimate_address_count_from_history(
+    xpub_str: &str,
+    address_type: AddressType,
+    electrs: &dyn crate::electrs::ElectrsClientTrait,
+) -> Result<u32> {
+    let gap = DEFAULT_GAP_LIMIT;
+    let samples = [0, gap / 4, gap / 2, 3 * gap / 4, gap - 1];
+
+    let mut scripts = Vec::with_capacity(samples.len());
+    for index in samples {
+        let derived = derive_chain_range(xpub_str, electrs.network(), address_type, 0, index, 1)?;
+        let address = &derived
+            .first()
+            .context("Failed to derive sample address")?
+            .address;
+        scripts.push(crate::electrs::address_script(address)?);
+    }
+
+    let histories = electrs.batch_get_histories(&scripts).await?;
+
+    let Some(last_used) = histories.iter().rposition(|h| !h.is_empty()) else {
+        return Ok(0);
+    };
+
+    // Every sample used: the wallet is at least this big
+    let Some(next_unused) = samples.get(last_used + 1) else {
+        return Ok(gap);
+    };
+
+    // Used addresses end somewhere between the two samples; take the middle
+    let midpoint = (samples[last_used] + next_unused) / 2;
+    Ok(midpoint + 1)
+}
+
+/// HD wallet metadata encoded in an extended public key
+#[derive(Debug, Clone)]
+pub struct XpubInfo {
+    pub network: Network,
+    /// 0 = root key, 3 = account level (e.g. m/44'/0'/0'), anything else is unusual
+    pub depth: u8,
+    pub parent_fingerprint: String,
+    pub child_number: u32,
+    pub chain_code_hex: String,
+}
+
+/// Parse an extended public key and return its HD metadata
+///
+/// Purely local: no address derivation and no Electrs calls.
+pub fn parse_xpub_info(xpub_str: &str) -> Result<XpubInfo> {
+    let xpub = Xpub::from_str(&normalize_to_xpub(xpub_str)?)
+        .context("Failed to parse extended public key")?;
+
+    let network = match xpub.network {
+        NetworkKind::Main => Network::Bitcoin,
+        NetworkKind::Test => Network::Testnet,
+    };
+
+    if xpub.depth != 0 && xpub.depth != 3 {
+        warn!("Unusual xpub depth {} (expected 0 or 3)", xpub.depth);
+    }
+
+    Ok(XpubInfo {
+        network,
+        depth: xpub.depth,
+        parent_fingerprint: xpub.parent_fingerprint.to_string(),
+        child_number: u32::from(xpub.child_number),
+        chain_code_hex: hex::encode(xpub.chain_code.as_bytes()),
+    })
+}
+
+/// SLIP-0132 version bytes of mainnet extended public keys (xpub, ypub, zpub, Ypub, Zpub)
+const MAINNET_PUB_VERSIONS: [[u8; 4]; 5] = [
+    [0x04, 0x88, 0xB2, 0x1E],
+    [0x04, 0x9D, 0x7C, 0xB2],
+    [0x04, 0xB2, 0x47, 0x46],
+    [0x02, 0x95, 0xB4, 0x3F],
+    [0x02, 0xAA, 0x7E, 0xD3],
+];
+
+/// SLIP-0132 version bytes of testnet extended public keys (tpub, upub, vpub, Upub, Vpub)
+const TESTNET_PUB_VERSIONS: [[u8; 4]; 5] = [
+    [0x04, 0x35, 0x87, 0xCF],
+    [0x04, 0x4A, 0x52, 0x62],
+    [0x04, 0x5F, 0x1C, 0xF6],
+    [0x02, 0x42, 0x89, 0xEF],
+    [0x02, 0x57, 0x54, 0x83],
+];
+
+/// Rewrite any SLIP-0132 extended public key (ypub, zpub, vpub, …) as a plain
+/// xpub (mainnet) or tpub (testnet), which is all `Xpub::from_str` accepts.
+///
+/// The version bytes are the only thing that changes, so the script type they
+/// implied is lost: callers must pass the intended address kind separately.
+pub fn normalize_to_xpub(s: &str) -> Result<String> {
+    let mut data = bitcoin::base58::decode_check(s.trim())
+        .context("Extended public key is not valid base58check")?;
+    if data.len() != 78 {
+        anyhow::bail!("Extended public key has {} bytes, expected 78", data.len());
+    }
+
+    let version: [u8; 4] = data[0..4].try_into().expect("length checked");
+    let canonical = if MAINNET_PUB_VERSIONS.contains(&version) {
+        MAINNET_PUB_VERSIONS[0]
+    } else if TESTNET_PUB_VERSIONS.contains(&version) {
+        TESTNET_PUB_VERSIONS[0]
+    } else {
+        anyhow::bail!("Unknown extended public key version {}", hex::encode(version));
+    };
+
+    data[0..4].copy_from_slice(&canonical);
+    Ok(bitcoin::base58::encode_check(&data))
+}
+
+/// Detect Bitcoin network and address type from the SLIP-0132 prefix
+///
+/// xpub/tpub → P2PKH, ypub/upub → P2SH-P2WPKH, zpub/vpub → P2WPKH. The
+/// multisig prefixes (Ypub, Zpub, …) are derived as single-sig P2PKH.
+fn detect_network(xpub_str: &str) -> Result<(Network, AddressType)> {
+    let prefix = xpub_str.trim().get(0..4).unwrap_or("");
+
     match prefix {
-        "xpub" => Ok(Network::Bitcoin),
-        "tpub" => Ok(Network::Testnet),
-        "ypub" | "zpub" => {
-            // ypub/zpub can be mainnet or testnet, default to mainnet
-            // In a real implementation, you might want to check more carefully
-            Ok(Network::Bitcoin)
-        }
+        "xpub" => Ok((Network::Bitcoin, AddressType::P2PKH)),
+        "ypub" => Ok((Network::Bitcoin, AddressType::P2shSegwit)),
+        "zpub" => Ok((Network::Bitcoin, AddressType::NativeSegwit)),
+        "tpub" | "Upub" | "Vpub" => Ok((Network::Testnet, AddressType::P2PKH)),
+        "upub" => Ok((Network::Testnet, AddressType::P2shSegwit)),
+        "vpub" => Ok((Network::Testnet, AddressType::NativeSegwit)),
         _ => {
             // Default to mainnet, but warn
             warn!("Unknown xpub prefix '{}', defaulting to mainnet", prefix);
-            Ok(Network::Bitcoin)
+            Ok((Network::Bitcoin, AddressType::P2PKH))
         }
     }
 }
 
+/// Whether an extended key may be used on `network`: mainnet keys on mainnet,
+/// testnet keys (tpub, vpub, …) on testnet, signet and regtest
+pub fn key_matches_network(xpub_str: &str, network: Network) -> bool {
+    parse_xpub_for_network(xpub_str, network).is_ok()
+}
+
+fn parse_xpub_for_network(xpub_str: &str, network: Network) -> Result<Xpub> {
+    let xpub = Xpub::from_str(&normalize_to_xpub(xpub_str)?)
+        .context("Failed to parse extended public key")?;
+    if xpub.network != NetworkKind::from(network) {
+        anyhow::bail!(
+            "Extended public key is not for {} (BITCOIN_NETWORK)",
+            network_name(network)
+        );
+    }
+    Ok(xpub)
+}
+
+/// Address type implied by an extended key's prefix (see `detect_network`)
+pub fn prefix_address_type(xpub_str: &str) -> Result<AddressType> {
+    detect_network(xpub_str).map(|(_, address_type)| address_type)
+}
+
 /// Derive a single address from xpub and derivation path
 fn derive_address_from_path(
     xpub: &Xpub,
     path: &DerivationPath,
     network: Network,
+    address_type: AddressType,
     secp: &Secp256k1<bitcoin::secp256k1::All>,
 ) -> Result<String> {
     // Derive the public key at this path
@@ -108,27 +650,271 @@ fn derive_address_from_path(
     // bitcoin::PublicKey::new() takes secp256k1::PublicKey
     let bitcoin_pubkey = bitcoin::PublicKey::new(secp_pubkey);
 
-    // Convert to Bitcoin address (P2PKH for legacy, P2WPKH for SegWit)
-    // For now, use P2PKH - in production you'd detect address type from xpub prefix
-    let address = bitcoin::Address::p2pkh(&bitcoin_pubkey, network);
+    // BIP32 child keys are always compressed
+    let compressed = bitcoin::CompressedPublicKey(secp_pubkey);
+
+    let address = match address_type {
+        AddressType::P2PKH => bitcoin::Address::p2pkh(&bitcoin_pubkey, network),
+        AddressType::P2shSegwit => bitcoin::Address::p2shwpkh(&compressed, network),
+        AddressType::NativeSegwit => bitcoin::Address::p2wpkh(&compressed, network),
+        AddressType::Taproot => {
+            let (internal_key, _parity) = secp_pubkey.x_only_public_key();
+            bitcoin::Address::p2tr(secp, internal_key, None, network)
+        }
+    };
     
     Ok(address.to_string())
 }
 
-/// Check if a string looks like an extended public key
+/// Address type stated by an output descriptor's script function
+///
+/// Sparrow, Specter and Bitcoin Core export e.g. `tr([d34db33f/86'/0'/0']xpub…/0/*)`,
+/// where the function name, not the key's version bytes, says what to derive.
+pub fn detect_address_type_from_descriptor(descriptor: &str) -> Option<AddressType> {
+    let descriptor = descriptor.trim();
+    if descriptor.starts_with("pkh(") {
+        Some(AddressType::P2PKH)
+    } else if descriptor.starts_with("sh(wpkh(") {
+        Some(AddressType::P2shSegwit)
+    } else if descriptor.starts_with("wpkh(") {
+        Some(AddressType::NativeSegwit)
+    } else if descriptor.starts_with("tr(") {
+        Some(AddressType::Taproot)
+    } else {
+        None
+    }
+}
+
+/// Whether a query is an output descriptor rather than a bare key or address
+pub fn is_descriptor(query: &str) -> bool {
+    detect_address_type_from_descriptor(query).is_some()
+}
+
+/// Characters a descriptor may contain, in BIP380 checksum order
+const DESCRIPTOR_INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}\
+IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+
+const DESCRIPTOR_CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn descriptor_polymod(c: u64, val: u64) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+    for (i, g) in GENERATOR.iter().enumerate() {
+        if c0 & (1 << i) != 0 {
+            c ^= g;
+        }
+    }
+    c
+}
+
+/// BIP380 checksum of a descriptor given without its `#…` suffix; `None` if
+/// it contains characters no descriptor can
+pub fn descriptor_checksum(descriptor: &str) -> Option<String> {
+    let mut c = 1u64;
+    let mut cls = 0u64;
+    let mut clscount = 0;
+    for ch in descriptor.chars() {
+        let pos = DESCRIPTOR_INPUT_CHARSET.find(ch)? as u64;
+        c = descriptor_polymod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = descriptor_polymod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = descriptor_polymod(c, cls);
+    }
+    for _ in 0..8 {
+        c = descriptor_polymod(c, 0);
+    }
+    c ^= 1;
+
+    Some(
+        (0..8)
+            .map(|j| DESCRIPTOR_CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+/// A single-key descriptor reduced to what address derivation needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedDescriptor {
+    pub address_type: AddressType,
+    /// The extended public key, without origin or derivation steps
+    pub xpub: String,
+    /// `fingerprint/path` from the `[…]` key origin, if present
+    pub origin: Option<String>,
+}
+
+/// Parse a `pkh(…)`, `sh(wpkh(…))`, `wpkh(…)` or `tr(…)` descriptor over one
+/// extended key. A trailing `#checksum` is verified when present; the key may
+/// be followed by `/0/*`, `/1/*` or `/<0;1>/*`, all of which scan both chains.
+pub fn parse_descriptor(descriptor: &str) -> Result<ParsedDescriptor> {
+    let descriptor = descriptor.trim();
+    let body = match descriptor.split_once('#') {
+        Some((body, checksum)) => {
+            let expected = descriptor_checksum(body)
+                .context("Descriptor contains invalid characters")?;
+            if checksum != expected {
+                anyhow::bail!("Descriptor checksum mismatch: expected #{}", expected);
+            }
+            body
+        }
+        None => descriptor,
+    };
+
+    let address_type = detect_address_type_from_descriptor(body)
+        .context("Unsupported descriptor: expected pkh(), sh(wpkh()), wpkh() or tr()")?;
+    let (open, close) = match address_type {
+        AddressType::P2PKH => ("pkh(", ")"),
+        AddressType::P2shSegwit => ("sh(wpkh(", "))"),
+        AddressType::NativeSegwit => ("wpkh(", ")"),
+        AddressType::Taproot => ("tr(", ")"),
+    };
+    let inner = body
+        .strip_prefix(open)
+        .and_then(|rest| rest.strip_suffix(close))
+        .context("Malformed descriptor: unbalanced parentheses")?;
+    if inner.contains(['(', ')', ',']) {
+        anyhow::bail!("Only single-key descriptors are supported");
+    }
+
+    let (origin, key_expr) = match inner.strip_prefix('[') {
+        Some(rest) => {
+            let (origin, key_expr) = rest
+                .split_once(']')
+                .context("Malformed descriptor: unterminated key origin")?;
+            (Some(origin.to_string()), key_expr)
+        }
+        None => (None, inner),
+    };
+    let (key, steps) = match key_expr.split_once('/') {
+        Some((key, steps)) => (key, Some(steps)),
+        None => (key_expr, None),
+    };
+
+    if !is_xpub(key) {
+        anyhow::bail!("Descriptor key is not a valid extended public key");
+    }
+
+    match steps {
+        None | Some("0/*") | Some("1/*") | Some("<0;1>/*") => {}
+        Some(other) => anyhow::bail!(
+            "Unsupported derivation steps /{} (expected /0/*, /1/* or /<0;1>/*)",
+            other
+        ),
+    }
+
+    Ok(ParsedDescriptor {
+        address_type,
+        xpub: key.to_string(),
+        origin,
+    })
+}
+
+/// Check if a string is an extended public key (any SLIP-0132 prefix, either
+/// network): base58check, 78 bytes and a key that parses
 pub fn is_xpub(query: &str) -> bool {
-    query.starts_with("xpub")
-        || query.starts_with("ypub")
-        || query.starts_with("zpub")
-        || query.starts_with("tpub")
+    normalize_to_xpub(query)
+        .ok()
+        .is_some_and(|key| Xpub::from_str(&key).is_ok())
+}
+
+/// Check if a string is a valid Bitcoin address on `network`
+///
+/// Parses the full address (checksum, witness program), so `bc1garbage` is
+/// rejected. Use `is_bech32_address` / `is_taproot_address` for the kind.
+pub fn is_bitcoin_address(query: &str, network: Network) -> bool {
+    bitcoin::Address::from_str(query.trim()).is_ok_and(|a| a.is_valid_for_network(network))
+}
+
+/// A valid address, but for another network than `network` (bc1… on testnet)
+pub fn is_address_on_other_network(query: &str, network: Network) -> bool {
+    bitcoin::Address::from_str(query.trim()).is_ok_and(|a| !a.is_valid_for_network(network))
+}
+
+/// Check if a string looks like a SegWit v0 (Bech32, P2WPKH/P2WSH) address
+pub fn is_bech32_address(query: &str) -> bool {
+    let lower = query.to_ascii_lowercase();
+    lower.starts_with("bc1q") || lower.starts_with("tb1q")
+}
+
+/// Check if a string looks like a Taproot (Bech32m, P2TR) address
+pub fn is_taproot_address(query: &str) -> bool {
+    let lower = query.to_ascii_lowercase();
+    lower.starts_with("bc1p") || lower.starts_with("tb1p")
 }
 
-/// Check if a string looks like a Bitcoin address
-pub fn is_bitcoin_address(query: &str) -> bool {
-    // Basic check - starts with 1, 3, bc1, or tb1
-    query.starts_with('1')
-        || query.starts_with('3')
-        || query.starts_with("bc1")
-        || query.starts_with("tb1")
+/// Result of a local (no Electrs) address check
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AddressValidation {
+    /// Parses AND belongs to the expected network
+    pub valid: bool,
+    /// "p2pkh", "p2sh", "p2wpkh", "p2wsh", "p2tr", …
+    pub address_type: Option<String>,
+    /// Network the address encodes ("mainnet", "testnet", "regtest")
+    pub network: Option<String>,
 }
 
+/// Parse and classify an address locally
+pub fn validate_address(query: &str, expected: Network) -> AddressValidation {
+    let unchecked = match bitcoin::Address::from_str(query.trim()) {
+        Ok(a) => a,
+        Err(_) => {
+            return AddressValidation {
+                valid: false,
+                address_type: None,
+                network: None,
+            }
+        }
+    };
+
+    // Signet shares testnet's encoding, so it reports as "testnet"
+    let network = [Network::Bitcoin, Network::Testnet, Network::Regtest]
+        .into_iter()
+        .find(|n| unchecked.is_valid_for_network(*n));
+
+    let valid = unchecked.is_valid_for_network(expected);
+    let address_type = unchecked
+        .assume_checked()
+        .address_type()
+        .map(|t| t.to_string());
+
+    AddressValidation {
+        valid,
+        address_type,
+        network: network.map(|n| network_name(n).to_string()),
+    }
+}
+
+/// Network for a BITCOIN_NETWORK value ("mainnet", "testnet", "signet", "regtest")
+pub fn network_from_name(name: &str) -> Option<Network> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "mainnet" | "bitcoin" | "main" => Some(Network::Bitcoin),
+        "testnet" | "testnet3" | "test" => Some(Network::Testnet),
+        "signet" => Some(Network::Signet),
+        "regtest" => Some(Network::Regtest),
+        _ => None,
+    }
+}
+
+/// Human-facing network name used on the wire
+pub fn network_name(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "mainnet",
+        Network::Testnet => "testnet",
+        Network::Signet => "signet",
+        Network::Regtest => "regtest",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,71 @@
+//! include_addresses: funded addresses of an xpub query with their paths
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use balancebridge_server::electrs::mock::MockElectrsClient;
+use balancebridge_server::xpub::{derive_chain_range, AddressType};
+use bitcoin::Network;
+use common::Harness;
+use nostr_sdk::Keys;
+use serde_json::{json, Value};
+
+// Account key of the "abandon … about" test mnemonic (m/84h/0h/0h)
+const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+fn address(chain: u32, index: u32) -> String {
+    derive_chain_range(BIP84_XPUB, Network::Bitcoin, AddressType::NativeSegwit, chain, index, 1)
+        .unwrap()
+        .remove(0)
+        .address
+}
+
+/// Final answer to an xpub bitcoin_lookup (after the scan estimate)
+async fn lookup(name: &str, request: Value) -> Value {
+    // m/0/1 used and emptied, m/0/3 and m/1/0 funded
+    let used = |a: String| (a, vec!["aa".repeat(32)]);
+    let electrs = MockElectrsClient::new(
+        HashMap::from([(address(0, 3), (1_500, 0)), (address(1, 0), (0, 700))]),
+        HashMap::from([used(address(0, 1)), used(address(0, 3)), used(address(1, 0))]),
+    );
+    let mut harness = Harness::start(name, Arc::new(electrs)).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    harness.send(harness.request(&phone, "r1", request)).await;
+
+    let (_, response) = harness.responses().pop().unwrap();
+    response
+}
+
+#[tokio::test]
+async fn funded_addresses_come_with_their_paths() {
+    let request = json!({
+        "type": "bitcoin_lookup",
+        "query": BIP84_XPUB,
+        "gap_limit": 5,
+        "include_addresses": true,
+    });
+    let response = lookup("breakdown-paths", request).await;
+
+    assert_eq!(
+        response["addresses"],
+        json!([
+            { "path": "m/0/3", "address": address(0, 3), "confirmed": 1_500, "unconfirmed": 0 },
+            { "path": "m/1/0", "address": address(1, 0), "confirmed": 0, "unconfirmed": 700 },
+        ])
+    );
+    // The emptied address stays in the full breakdown only
+    assert_eq!(response["address_breakdown"].as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn addresses_are_left_out_unless_asked_for() {
+    let request = json!({ "type": "bitcoin_lookup", "query": BIP84_XPUB, "gap_limit": 5 });
+    let response = lookup("breakdown-off", request).await;
+
+    assert!(response.get("addresses").is_none());
+    assert_eq!(response["confirmed_balance"], 1_500);
+}
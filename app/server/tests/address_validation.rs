@@ -0,0 +1,69 @@
+//! Queries are parsed, not prefix-sniffed: malformed addresses and keys are
+//! told apart from valid ones on the wrong network
+
+use balancebridge_server::xpub::{
+    is_address_on_other_network, is_bitcoin_address, is_xpub, validate_address,
+};
+use bitcoin::Network;
+
+const P2PKH: &str = "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA";
+const P2SH: &str = "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf";
+const P2WPKH: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+const P2WSH: &str = "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3";
+const P2TR: &str = "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr";
+const TESTNET_P2WPKH: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+
+const BIP84_ZPUB: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+
+/// Same string with the last character changed, so the checksum no longer matches
+fn corrupt(s: &str) -> String {
+    let last = if s.ends_with('q') { 'p' } else { 'q' };
+    format!("{}{}", &s[..s.len() - 1], last)
+}
+
+#[test]
+fn every_address_type_parses() {
+    for address in [P2PKH, P2SH, P2WPKH, P2WSH, P2TR] {
+        assert!(is_bitcoin_address(address, Network::Bitcoin), "{}", address);
+        assert!(validate_address(address, Network::Bitcoin).valid, "{}", address);
+    }
+    assert_eq!(
+        validate_address(P2TR, Network::Bitcoin).address_type.as_deref(),
+        Some("p2tr")
+    );
+}
+
+#[test]
+fn corrupted_checksums_are_rejected() {
+    for address in [P2PKH, P2SH, P2WPKH, P2WSH, P2TR] {
+        let bad = corrupt(address);
+        assert!(!is_bitcoin_address(&bad, Network::Bitcoin), "{}", bad);
+        assert!(!is_address_on_other_network(&bad, Network::Bitcoin), "{}", bad);
+    }
+}
+
+#[test]
+fn prefix_alone_is_not_an_address() {
+    for query in ["bc1garbage", "bc1q", "1", "3abc", "tb1qqqqq", "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fy", ""] {
+        assert!(!is_bitcoin_address(query, Network::Bitcoin), "{}", query);
+        assert!(!is_address_on_other_network(query, Network::Bitcoin), "{}", query);
+    }
+}
+
+#[test]
+fn wrong_network_is_not_malformed() {
+    assert!(!is_bitcoin_address(TESTNET_P2WPKH, Network::Bitcoin));
+    assert!(is_address_on_other_network(TESTNET_P2WPKH, Network::Bitcoin));
+    assert!(is_address_on_other_network(P2WPKH, Network::Testnet));
+    assert!(is_bitcoin_address(TESTNET_P2WPKH, Network::Testnet));
+}
+
+#[test]
+fn xpubs_are_parsed() {
+    assert!(is_xpub(BIP84_ZPUB));
+    assert!(!is_xpub(&corrupt(BIP84_ZPUB)));
+    assert!(!is_xpub("xpubgarbage"));
+    assert!(!is_xpub(&BIP84_ZPUB[..100]));
+    // An address is never a key
+    assert!(!is_xpub(P2PKH));
+}
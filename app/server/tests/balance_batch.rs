@@ -0,0 +1,102 @@
+//! get_balances_batch: many scripts' balances in one batched round trip
+
+mod common;
+
+use balancebridge_server::electrs::{address_script, ElectrsClient};
+use bitcoin::ScriptBuf;
+use common::{FakeAddress, FakeElectrs};
+use serde_json::json;
+
+const FUNDED: &str = "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA";
+const PENDING: &str = "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf";
+const FRESH: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+const TX_A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+const TX_B: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+fn funded_and_pending() -> Vec<FakeAddress> {
+    vec![
+        FakeAddress {
+            address: FUNDED,
+            history: json!([{ "tx_hash": TX_A, "height": 800_000 }]),
+            utxos: json!([
+                { "tx_hash": TX_A, "tx_pos": 0, "height": 800_000, "value": 30_000 },
+                { "tx_hash": TX_A, "tx_pos": 1, "height": 800_000, "value": 20_000 },
+            ]),
+        },
+        FakeAddress {
+            address: PENDING,
+            history: json!([{ "tx_hash": TX_B, "height": 0, "fee": 141 }]),
+            utxos: json!([{ "tx_hash": TX_B, "tx_pos": 0, "height": 0, "value": 7_000 }]),
+        },
+    ]
+}
+
+fn scripts() -> Vec<ScriptBuf> {
+    [FRESH, FUNDED, PENDING]
+        .iter()
+        .map(|a| address_script(a).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn balances_come_back_in_script_order() {
+    let server = FakeElectrs::with_addresses(funded_and_pending());
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+
+    let balances = client.get_balances_batch(&scripts()).await.unwrap();
+
+    assert_eq!(balances, vec![(0, 0), (50_000, 0), (0, 7_000)]);
+    // Only listunspent, once per script and all in one batch, no history fast-path
+    assert_eq!(server.listunspent_calls(), 3);
+    assert_eq!(server.batched_calls(), 3);
+    assert_eq!(server.scripthash_calls(), 3);
+}
+
+#[tokio::test]
+async fn rejected_batch_falls_back_to_one_call_per_script() {
+    let server = FakeElectrs::with_addresses(funded_and_pending()).rejecting_batches();
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+
+    let balances = client.get_balances_batch(&scripts()).await.unwrap();
+
+    assert_eq!(balances, vec![(0, 0), (50_000, 0), (0, 7_000)]);
+    // The three batched requests were refused, then each script went on its own
+    assert_eq!(server.batched_calls(), 3);
+    assert_eq!(server.listunspent_calls(), 3);
+    assert_eq!(server.scripthash_calls(), 6);
+}
+
+#[tokio::test]
+async fn empty_batch_skips_electrs() {
+    let server = FakeElectrs::start(false);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+
+    assert!(client.get_balances_batch(&[]).await.unwrap().is_empty());
+    assert_eq!(server.listunspent_calls(), 0);
+}
+
+#[tokio::test]
+async fn cached_balances_are_not_fetched_again() {
+    let server = FakeElectrs::with_addresses(vec![FakeAddress {
+        address: FUNDED,
+        history: json!([{ "tx_hash": TX_A, "height": 800_000 }]),
+        utxos: json!([{ "tx_hash": TX_A, "tx_pos": 0, "height": 800_000, "value": 30_000 }]),
+    }]);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+    let funded = address_script(FUNDED).unwrap();
+    let fresh = address_script(FRESH).unwrap();
+
+    client.get_balances_batch(&[funded.clone()]).await.unwrap();
+    assert_eq!(server.listunspent_calls(), 1);
+
+    // Only the uncached script reaches Electrs
+    let balances = client.get_balances_batch(&[fresh, funded]).await.unwrap();
+    assert_eq!(balances, vec![(0, 0), (30_000, 0)]);
+    assert_eq!(server.listunspent_calls(), 2);
+
+    // The batch result also answers single-address lookups
+    let bounded = client.get_balances_bounded(vec![FUNDED.to_string()], 2).await.unwrap();
+    assert_eq!(bounded, vec![(30_000, 0)]);
+    assert_eq!(server.scripthash_calls(), 2);
+}
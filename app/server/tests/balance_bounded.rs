@@ -0,0 +1,61 @@
+//! get_balances_bounded: per-address lookups, several round trips in flight at once
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use balancebridge_server::electrs::ElectrsClient;
+use balancebridge_server::xpub::{derive_chain_range, AddressType};
+use bitcoin::Network;
+use common::FakeElectrs;
+
+// Account key of the "abandon … about" test mnemonic (m/84h/0h/0h)
+const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+// Well above the client's 100ms spacing between calls, which caps the speedup
+const ROUND_TRIP: Duration = Duration::from_millis(400);
+const ADDRESSES: u32 = 6;
+
+/// `ADDRESSES` unfunded receive addresses from `start`: one history call each
+fn addresses(start: u32) -> Vec<String> {
+    derive_chain_range(BIP84_XPUB, Network::Bitcoin, AddressType::NativeSegwit, 0, start, ADDRESSES)
+        .unwrap()
+        .into_iter()
+        .map(|derived| derived.address)
+        .collect()
+}
+
+#[tokio::test]
+async fn concurrent_lookups_share_round_trips() {
+    let server = FakeElectrs::start(false).with_round_trip(ROUND_TRIP);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap().with_max_inflight(4);
+    // Each run gets its own addresses so neither is answered from the cache
+    let one_at_a_time = addresses(0);
+    let four_at_a_time = addresses(ADDRESSES);
+
+    let started = Instant::now();
+    let balances = client.get_balances_bounded(one_at_a_time, 1).await.unwrap();
+    let sequential = started.elapsed();
+    assert_eq!(balances, vec![(0, 0); ADDRESSES as usize]);
+
+    let started = Instant::now();
+    let balances = client.get_balances_bounded(four_at_a_time, 4).await.unwrap();
+    let concurrent = started.elapsed();
+    assert_eq!(balances, vec![(0, 0); ADDRESSES as usize]);
+
+    assert_eq!(server.scripthash_calls(), 2 * ADDRESSES as usize);
+    assert!(sequential >= ROUND_TRIP * ADDRESSES, "sequential took {:?}", sequential);
+    assert!(concurrent < sequential / 2, "{:?} vs {:?} sequential", concurrent, sequential);
+}
+
+#[tokio::test]
+async fn concurrency_is_capped_by_the_inflight_gate() {
+    let server = FakeElectrs::start(false).with_round_trip(ROUND_TRIP);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap().with_max_inflight(1);
+
+    // Asking for four at a time still runs them one by one
+    let started = Instant::now();
+    client.get_balances_bounded(addresses(0), 4).await.unwrap();
+
+    assert!(started.elapsed() >= ROUND_TRIP * ADDRESSES);
+}
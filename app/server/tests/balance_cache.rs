@@ -0,0 +1,93 @@
+//! Electrs balance cache: hits, misses, expiry and LRU eviction
+
+use std::time::{Duration, Instant};
+
+use balancebridge_server::electrs::address_script;
+use balancebridge_server::electrs::cache::{BalanceCache, CachedBalance};
+use balancebridge_server::electrs::ElectrsQueryResult;
+
+const TTL: Duration = Duration::from_secs(30);
+
+fn script(address: &str) -> electrum_client::bitcoin::ScriptBuf {
+    address_script(address).unwrap()
+}
+
+fn balance(confirmed: u64, fetched_at: Instant) -> CachedBalance {
+    CachedBalance {
+        confirmed,
+        unconfirmed: 0,
+        utxo_values: vec![confirmed],
+        used: true,
+        fetched_at,
+    }
+}
+
+const A: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+const B: &str = "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el";
+const C: &str = "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA";
+
+#[test]
+fn fresh_entry_is_a_hit() {
+    let cache = BalanceCache::new(TTL, 10);
+    let now = Instant::now();
+    cache.insert(script(A), balance(1_000, now));
+
+    let hit = cache.get(&script(A), now + Duration::from_secs(29)).unwrap();
+    assert_eq!(hit.confirmed, 1_000);
+}
+
+#[test]
+fn unknown_script_is_a_miss() {
+    let cache = BalanceCache::new(TTL, 10);
+    cache.insert(script(A), balance(1_000, Instant::now()));
+
+    assert!(cache.get(&script(B), Instant::now()).is_none());
+}
+
+#[test]
+fn entry_expires_after_ttl() {
+    let cache = BalanceCache::new(TTL, 10);
+    let now = Instant::now();
+    cache.insert(script(A), balance(1_000, now));
+
+    assert!(cache.get(&script(A), now + TTL).is_none());
+    // Expired entries are dropped, not just hidden
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn full_cache_evicts_least_recently_used() {
+    let cache = BalanceCache::new(TTL, 2);
+    let now = Instant::now();
+    cache.insert(script(A), balance(1, now));
+    cache.insert(script(B), balance(2, now));
+
+    // A is now more recent than B
+    assert!(cache.get(&script(A), now).is_some());
+    cache.insert(script(C), balance(3, now));
+
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(&script(A), now).is_some());
+    assert!(cache.get(&script(B), now).is_none());
+    assert!(cache.get(&script(C), now).is_some());
+}
+
+#[test]
+fn zero_ttl_disables_the_cache() {
+    let cache = BalanceCache::new(Duration::ZERO, 10);
+    let now = Instant::now();
+    cache.insert(script(A), balance(1, now));
+
+    assert!(cache.get(&script(A), now).is_none());
+}
+
+#[test]
+fn never_used_and_error_answers() {
+    let now = Instant::now();
+
+    let empty = CachedBalance::from_result(&ElectrsQueryResult::NotFound, now).unwrap();
+    assert!(matches!(ElectrsQueryResult::from(empty), ElectrsQueryResult::NotFound));
+
+    let error = ElectrsQueryResult::Error(anyhow::anyhow!("protocol error"));
+    assert!(CachedBalance::from_result(&error, now).is_none());
+}
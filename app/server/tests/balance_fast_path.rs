@@ -0,0 +1,91 @@
+//! Balance lookup: empty history short-circuits, everything else asks for UTXOs
+
+mod common;
+
+use balancebridge_server::electrs::{ElectrsClient, ElectrsQueryResult, RetryStrategy};
+use common::{FakeAddress, FakeElectrs};
+use serde_json::json;
+
+const FRESH: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+const CONFIRMED: &str = "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA";
+const UNCONFIRMED: &str = "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf";
+const SPENT: &str = "bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh";
+
+const TX_A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+const TX_B: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+fn server() -> FakeElectrs {
+    FakeElectrs::with_addresses(vec![
+        FakeAddress {
+            address: CONFIRMED,
+            history: json!([{ "tx_hash": TX_A, "height": 800_000 }]),
+            utxos: json!([{ "tx_hash": TX_A, "tx_pos": 0, "height": 800_000, "value": 50_000 }]),
+        },
+        FakeAddress {
+            address: UNCONFIRMED,
+            history: json!([{ "tx_hash": TX_B, "height": 0, "fee": 141 }]),
+            utxos: json!([{ "tx_hash": TX_B, "tx_pos": 1, "height": 0, "value": 12_000 }]),
+        },
+        FakeAddress {
+            address: SPENT,
+            history: json!([
+                { "tx_hash": TX_A, "height": 800_000 },
+                { "tx_hash": TX_B, "height": 800_010 },
+            ]),
+            utxos: json!([]),
+        },
+    ])
+}
+
+async fn lookup(server: &FakeElectrs, address: &str) -> ElectrsQueryResult {
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+    client
+        .get_address_balance(address, RetryStrategy::default())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn fresh_address_skips_listunspent() {
+    let server = server();
+
+    assert!(matches!(lookup(&server, FRESH).await, ElectrsQueryResult::NotFound));
+    assert_eq!(server.listunspent_calls(), 0);
+}
+
+#[tokio::test]
+async fn confirmed_only() {
+    let server = server();
+
+    let result = lookup(&server, CONFIRMED).await;
+    assert_eq!(result.balance(), Some((50_000, 0)));
+}
+
+#[tokio::test]
+async fn unconfirmed_only_is_not_empty() {
+    let server = server();
+
+    let result = lookup(&server, UNCONFIRMED).await;
+    assert!(matches!(result, ElectrsQueryResult::Found { .. }));
+    assert_eq!(result.balance(), Some((0, 12_000)));
+}
+
+#[tokio::test]
+async fn fully_spent_is_found_and_empty() {
+    let server = server();
+
+    let result = lookup(&server, SPENT).await;
+    match result {
+        ElectrsQueryResult::Found {
+            confirmed,
+            unconfirmed,
+            utxo_values,
+        } => {
+            assert_eq!((confirmed, unconfirmed), (0, 0));
+            assert!(utxo_values.is_empty());
+        }
+        other => panic!("expected Found, got {:?}", other),
+    }
+    // History, then a single listunspent; no per-transaction fetches
+    assert_eq!(server.listunspent_calls(), 1);
+}
@@ -0,0 +1,33 @@
+//! `block_height`: chain tip from Electrs and its response shape
+
+mod common;
+
+use balancebridge_server::electrs::ElectrsClient;
+use balancebridge_server::protocol::{BlockHeightResponse, PROTOCOL_VERSION};
+use common::{FakeElectrs, FAKE_TIP_HEIGHT, GENESIS_HASH};
+
+#[tokio::test]
+async fn tip_height_is_a_positive_integer() {
+    let server = FakeElectrs::start(false);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+
+    let tip = client.get_tip_height().await.unwrap();
+
+    assert!(tip.height > 0);
+    assert_eq!(tip.height, FAKE_TIP_HEIGHT);
+    assert_eq!(tip.header_hash, GENESIS_HASH);
+}
+
+#[test]
+fn response_carries_height_and_hash() {
+    let response = BlockHeightResponse {
+        req: "r1".to_string(),
+        protocol_version: PROTOCOL_VERSION.to_string(),
+        height: FAKE_TIP_HEIGHT,
+        header_hash: GENESIS_HASH.to_string(),
+    };
+
+    let json: serde_json::Value = serde_json::to_value(&response).unwrap();
+    assert_eq!(json["height"].as_u64(), Some(u64::from(FAKE_TIP_HEIGHT)));
+    assert_eq!(json["header_hash"], GENESIS_HASH);
+}
@@ -0,0 +1,370 @@
+//! Shared helpers for the integration tests
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use balancebridge_server::electrs::ElectrsClientTrait;
+use balancebridge_server::history::BalanceHistoryStore;
+use balancebridge_server::nostr::NostrState;
+use balancebridge_server::nostr_handler::{
+    encrypt_content, NostrHandler, BALANCEBRIDGE_REQUEST_KIND,
+};
+use balancebridge_server::pairing::PairingManager;
+use balancebridge_server::shutdown::ShutdownCoordinator;
+use balancebridge_server::watch::XpubWatchList;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::Address;
+use nostr_sdk::{Event, EventBuilder, Keys, Kind, PublicKey, RelayUrl, Tag};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+/// Bitcoin genesis block header, served as the tip by `blockchain.headers.subscribe`
+pub const GENESIS_HEADER_HEX: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+pub const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+
+/// Fresh (not yet created) directory under the system temp dir
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("balancebridge-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Tip height the fake server reports
+pub const FAKE_TIP_HEIGHT: u32 = 840_000;
+
+/// History and UTXOs `FakeElectrs::with_addresses` serves for one address,
+/// in Electrum's `get_history` / `listunspent` JSON shape
+pub struct FakeAddress {
+    pub address: &'static str,
+    pub history: Value,
+    pub utxos: Value,
+}
+
+/// Minimal line-delimited JSON-RPC Electrum server on 127.0.0.1.
+///
+/// Addresses have an empty history unless given to `with_addresses`. With
+/// `drop_first_history`, the first `blockchain.scripthash.get_history`
+/// closes the socket instead of answering; `with_slow_history` delays every
+/// history answer instead.
+///
+/// Electrum batches arrive as several request lines in one write, which is
+/// how the server tells them apart from one-at-a-time calls:
+/// `rejecting_batches` answers every batched request with an error
+/// (concurrent calls that happen to arrive together look the same), and
+/// `with_round_trip` delays every answer by that long after its request
+/// arrived, like a remote server would.
+pub struct FakeElectrs {
+    pub addr: String,
+    connections: Arc<AtomicUsize>,
+    state: Arc<FakeState>,
+}
+
+struct FakeState {
+    dropped: AtomicBool,
+    /// (history, utxos) by Electrum script hash
+    scripts: HashMap<String, (Value, Value)>,
+    history_delay: Duration,
+    listunspent_calls: AtomicUsize,
+    scripthash_calls: AtomicUsize,
+    batched_calls: AtomicUsize,
+    reject_batches: AtomicBool,
+    round_trip_micros: AtomicU64,
+}
+
+impl FakeElectrs {
+    pub fn start(drop_first_history: bool) -> Self {
+        Self::spawn(drop_first_history, HashMap::new(), Duration::ZERO)
+    }
+
+    pub fn with_slow_history(delay: Duration) -> Self {
+        Self::spawn(false, HashMap::new(), delay)
+    }
+
+    pub fn with_addresses(addresses: Vec<FakeAddress>) -> Self {
+        let scripts = addresses
+            .into_iter()
+            .map(|a| (electrum_script_hash(a.address), (a.history, a.utxos)))
+            .collect();
+        Self::spawn(false, scripts, Duration::ZERO)
+    }
+
+    fn spawn(
+        drop_first_history: bool,
+        scripts: HashMap<String, (Value, Value)>,
+        history_delay: Duration,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let state = Arc::new(FakeState {
+            dropped: AtomicBool::new(!drop_first_history),
+            scripts,
+            history_delay,
+            listunspent_calls: AtomicUsize::new(0),
+            scripthash_calls: AtomicUsize::new(0),
+            batched_calls: AtomicUsize::new(0),
+            reject_batches: AtomicBool::new(false),
+            round_trip_micros: AtomicU64::new(0),
+        });
+
+        let counter = Arc::clone(&connections);
+        let shared = Arc::clone(&state);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                counter.fetch_add(1, Ordering::SeqCst);
+                let state = Arc::clone(&shared);
+                thread::spawn(move || serve(stream, &state));
+            }
+        });
+
+        Self {
+            addr,
+            connections,
+            state,
+        }
+    }
+
+    /// Answer every request that arrives as part of a batch with an error
+    pub fn rejecting_batches(self) -> Self {
+        self.state.reject_batches.store(true, Ordering::SeqCst);
+        self
+    }
+
+    /// Answer each request `delay` after it arrived
+    pub fn with_round_trip(self, delay: Duration) -> Self {
+        let micros = delay.as_micros().try_into().unwrap_or(u64::MAX);
+        self.state.round_trip_micros.store(micros, Ordering::SeqCst);
+        self
+    }
+
+    /// TCP connections accepted so far (the client's preflight included)
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// `blockchain.scripthash.listunspent` requests answered so far
+    pub fn listunspent_calls(&self) -> usize {
+        self.state.listunspent_calls.load(Ordering::SeqCst)
+    }
+
+    /// `blockchain.scripthash.*` requests (of any kind) received so far
+    pub fn scripthash_calls(&self) -> usize {
+        self.state.scripthash_calls.load(Ordering::SeqCst)
+    }
+
+    /// Requests received as part of a batch (answered or rejected) so far
+    pub fn batched_calls(&self) -> usize {
+        self.state.batched_calls.load(Ordering::SeqCst)
+    }
+}
+
+/// sha256 of the output script, byte-reversed, as Electrum keys addresses
+fn electrum_script_hash(address: &str) -> String {
+    let script = Address::from_str(address)
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    hex::encode(hash)
+}
+
+fn serve(stream: TcpStream, state: &FakeState) {
+    let mut writer = stream.try_clone().unwrap();
+    let (lines, requests) = std::sync::mpsc::channel();
+
+    // Read ahead, so a round trip delays each request from when it arrived
+    // and pipelined calls overlap like they would on a real server
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        loop {
+            // Lines already buffered came in the same write as the previous one
+            let follows_previous = !reader.buffer().is_empty();
+            let mut line = String::new();
+            if !matches!(reader.read_line(&mut line), Ok(n) if n > 0) {
+                return;
+            }
+            let batched = follows_previous || !reader.buffer().is_empty();
+            if lines.send((Instant::now(), batched, line)).is_err() {
+                return;
+            }
+        }
+    });
+
+    answer(&mut writer, requests, state);
+    // Also ends the reader thread
+    let _ = writer.shutdown(Shutdown::Both);
+}
+
+/// Answer each request read by `serve` until the connection should close
+fn answer(
+    writer: &mut TcpStream,
+    requests: std::sync::mpsc::Receiver<(Instant, bool, String)>,
+    state: &FakeState,
+) {
+    for (arrived, batched, line) in requests {
+        let Ok(request) = serde_json::from_str::<Value>(&line) else {
+            return;
+        };
+
+        let method = request["method"].as_str().unwrap_or_default();
+        if method.starts_with("blockchain.scripthash.") {
+            state.scripthash_calls.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let round_trip = Duration::from_micros(state.round_trip_micros.load(Ordering::SeqCst));
+        thread::sleep(round_trip.saturating_sub(arrived.elapsed()));
+
+        if batched {
+            state.batched_calls.fetch_add(1, Ordering::SeqCst);
+            if state.reject_batches.load(Ordering::SeqCst) {
+                let error =
+                    json!({ "code": -32600, "message": "batch requests are not supported" });
+                let response = json!({ "jsonrpc": "2.0", "id": request["id"], "error": error });
+                if writeln!(writer, "{}", response).is_err() {
+                    return;
+                }
+                continue;
+            }
+        }
+
+        let script = state.scripts.get(request["params"][0].as_str().unwrap_or_default());
+
+        let result = match request["method"].as_str() {
+            Some("blockchain.scripthash.get_history") => {
+                if !state.dropped.swap(true, Ordering::SeqCst) {
+                    // `serve` shuts both halves of the socket down
+                    return;
+                }
+                thread::sleep(state.history_delay);
+                script.map_or_else(|| json!([]), |(history, _)| history.clone())
+            }
+            Some("blockchain.scripthash.listunspent") => {
+                state.listunspent_calls.fetch_add(1, Ordering::SeqCst);
+                script.map_or_else(|| json!([]), |(_, utxos)| utxos.clone())
+            }
+            Some("blockchain.headers.subscribe") => json!({
+                "height": FAKE_TIP_HEIGHT,
+                "hex": GENESIS_HEADER_HEX,
+            }),
+            Some("server.features") => json!({
+                "genesis_hash": GENESIS_HASH,
+                "hosts": {},
+                "protocol_max": "1.4",
+                "protocol_min": "1.4",
+                "pruning": null,
+                "server_version": "fake-electrs 0.1",
+                "hash_function": "sha256",
+            }),
+            _ => Value::Null,
+        };
+
+        let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+/// A ready `NostrHandler` with no relays: requests are fed in by hand and
+/// responses are captured instead of published
+pub struct Harness {
+    pub handler: NostrHandler,
+    pub server: Keys,
+    pub pairing: PairingManager,
+    pub watch: XpubWatchList,
+    pub dir: PathBuf,
+    responses: mpsc::UnboundedReceiver<(PublicKey, String)>,
+}
+
+impl Harness {
+    pub async fn start(name: &str, electrs: Arc<dyn ElectrsClientTrait>) -> Self {
+        let dir = scratch_dir(name);
+        let server = Keys::generate();
+        let pairing = PairingManager::new(&dir).unwrap();
+        let watch = XpubWatchList::new(&dir).unwrap();
+        let (outbox, responses) = mpsc::unbounded_channel();
+
+        let handler = NostrHandler::new(
+            NostrState::new(server.clone(), Vec::new()).await.unwrap(),
+            server.clone(),
+            pairing.clone(),
+            electrs,
+            BalanceHistoryStore::new(&dir).unwrap(),
+            watch.clone(),
+            Arc::new(AtomicBool::new(true)),
+            ShutdownCoordinator::new(),
+        )
+        .await
+        .unwrap()
+        .with_outbox(outbox);
+
+        Self {
+            handler,
+            server,
+            pairing,
+            watch,
+            dir,
+            responses,
+        }
+    }
+
+    /// Pair `device` the way a finished "pair" request would
+    pub fn pair(&self, device: &Keys) {
+        self.pairing.add_pairing(device.public_key(), Vec::new(), None).unwrap();
+    }
+
+    /// A signed kind 30078 request from `from`, content NIP-44 encrypted to the server
+    pub fn request(&self, from: &Keys, req_id: &str, body: Value) -> Event {
+        self.request_with_tags(from, req_id, body, Vec::new())
+    }
+
+    pub fn request_with_tags(
+        &self,
+        from: &Keys,
+        req_id: &str,
+        body: Value,
+        extra: Vec<Tag>,
+    ) -> Event {
+        let content = encrypt_content(from, &self.server.public_key(), &body.to_string()).unwrap();
+        let mut tags = vec![
+            Tag::parse(["p", self.server.public_key().to_hex().as_str()]).unwrap(),
+            Tag::parse(["req", req_id]).unwrap(),
+        ];
+        tags.extend(extra);
+        EventBuilder::new(Kind::Custom(BALANCEBRIDGE_REQUEST_KIND), content)
+            .tags(tags)
+            .sign_with_keys(from)
+            .unwrap()
+    }
+
+    pub async fn send(&self, event: Event) {
+        let relay = RelayUrl::parse("wss://relay.example").unwrap();
+        self.handler.handle_notification(event, relay).await;
+    }
+
+    /// Responses captured so far, as JSON, with their recipient
+    pub fn responses(&mut self) -> Vec<(PublicKey, Value)> {
+        let mut out = Vec::new();
+        while let Ok((to, json)) = self.responses.try_recv() {
+            out.push((to, serde_json::from_str(&json).unwrap()));
+        }
+        out
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
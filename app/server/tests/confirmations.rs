@@ -0,0 +1,31 @@
+//! Confirmation counts from Electrs history heights
+
+use balancebridge_server::electrs::TxHistoryEntry;
+
+fn tx(height: i32) -> TxHistoryEntry {
+    TxHistoryEntry {
+        txid: "00".repeat(32),
+        height,
+    }
+}
+
+#[test]
+fn tip_block_has_one_confirmation() {
+    assert_eq!(tx(840_000).confirmations(840_000), 1);
+    assert_eq!(tx(839_995).confirmations(840_000), 6);
+}
+
+#[test]
+fn mempool_transactions_have_none() {
+    // 0: in mempool; -1: in mempool with unconfirmed parents
+    for height in [0, -1] {
+        assert_eq!(tx(height).confirmed_height(), None);
+        assert_eq!(tx(height).confirmations(840_000), 0);
+    }
+}
+
+#[test]
+fn stale_tip_never_underflows() {
+    // Tip fetched before the block that confirmed the transaction
+    assert_eq!(tx(840_001).confirmations(840_000), 1);
+}
@@ -0,0 +1,85 @@
+//! Output descriptors as lookup queries: checksum, script type and key
+
+use balancebridge_server::xpub::{
+    derive_chain_range, descriptor_checksum, is_descriptor, parse_descriptor, AddressType,
+};
+use bitcoin::Network;
+
+// Account keys of the "abandon … about" test mnemonic
+const WPKH: &str = "wpkh([73c5da0a/84h/0h/0h]xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)#qf45pmyh";
+const SH_WPKH: &str = "sh(wpkh([73c5da0a/49h/0h/0h]xpub6C6nQwHaWbSrzs5tZ1q7m5R9cPK9eYpNMFesiXsYrgc1P8bvLLAet9JfHjYXKjToD8cBRswJXXbbFpXgwsswVPAZzKMa1jUp2kVkGVUaJa7/0/*))#vu666hnq";
+const PKH: &str = "pkh([73c5da0a/44'/0'/0']xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj/0/*)#8w4z8fed";
+
+fn first_receive_address(descriptor: &str) -> String {
+    let parsed = parse_descriptor(descriptor).unwrap();
+    derive_chain_range(&parsed.xpub, Network::Bitcoin, parsed.address_type, 0, 0, 1)
+        .unwrap()
+        .remove(0)
+        .address
+}
+
+#[test]
+fn checksum_matches_bip380() {
+    assert_eq!(descriptor_checksum("raw(deadbeef)").as_deref(), Some("89f8spxm"));
+    for descriptor in [WPKH, SH_WPKH, PKH] {
+        let (body, checksum) = descriptor.split_once('#').unwrap();
+        assert_eq!(descriptor_checksum(body).as_deref(), Some(checksum));
+    }
+}
+
+#[test]
+fn script_function_picks_the_address_kind() {
+    assert_eq!(parse_descriptor(WPKH).unwrap().address_type, AddressType::NativeSegwit);
+    assert_eq!(parse_descriptor(SH_WPKH).unwrap().address_type, AddressType::P2shSegwit);
+    assert_eq!(parse_descriptor(PKH).unwrap().address_type, AddressType::P2PKH);
+
+    assert_eq!(
+        first_receive_address(WPKH),
+        "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+    );
+    assert_eq!(first_receive_address(SH_WPKH), "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf");
+    assert_eq!(first_receive_address(PKH), "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA");
+}
+
+#[test]
+fn origin_is_kept_apart_from_the_key() {
+    let parsed = parse_descriptor(WPKH).unwrap();
+    assert_eq!(parsed.origin.as_deref(), Some("73c5da0a/84h/0h/0h"));
+    assert!(parsed.xpub.starts_with("xpub6CatWdiZ"));
+    assert!(!parsed.xpub.contains('/'));
+}
+
+#[test]
+fn checksum_is_optional() {
+    let (body, _) = SH_WPKH.split_once('#').unwrap();
+    assert_eq!(parse_descriptor(body).unwrap(), parse_descriptor(SH_WPKH).unwrap());
+}
+
+#[test]
+fn bad_checksum_is_rejected() {
+    let (body, _) = WPKH.split_once('#').unwrap();
+    assert!(parse_descriptor(&format!("{}#qf45pmyy", body)).is_err());
+    // Same checksum, one key character changed
+    assert!(parse_descriptor(&WPKH.replacen("xpub6CatWdiZ", "xpub6CatWdiY", 1)).is_err());
+}
+
+#[test]
+fn unsupported_shapes_are_rejected() {
+    let key = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    for descriptor in [
+        format!("wsh(multi(1,{}/0/*))", key),
+        format!("wpkh({}/0/*", key),
+        format!("wpkh({}/0h/*)", key),
+        "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)".to_string(),
+    ] {
+        assert!(parse_descriptor(&descriptor).is_err(), "{}", descriptor);
+    }
+}
+
+#[test]
+fn descriptors_are_told_apart_from_keys() {
+    assert!(is_descriptor(WPKH));
+    assert!(is_descriptor(PKH));
+    assert!(!is_descriptor("xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V"));
+    assert!(!is_descriptor("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"));
+}
@@ -0,0 +1,62 @@
+//! Circuit breaker state reported by /health/electrs
+
+mod common;
+
+use std::time::Duration;
+
+use balancebridge_server::electrs::{
+    BreakerState, ElectrsClient, ElectrsTimeouts, RetryStrategy,
+};
+use common::FakeElectrs;
+
+const ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+const NO_RETRY: RetryStrategy = RetryStrategy {
+    max_retries: 0,
+    reconnect_before_retry: false,
+};
+
+#[tokio::test]
+async fn healthy_after_a_successful_call() {
+    let server = FakeElectrs::start(false);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+    assert_eq!(client.breaker_state(), BreakerState::Healthy);
+
+    client.get_address_balance(ADDRESS, NO_RETRY).await.unwrap();
+    assert_eq!(client.breaker_state(), BreakerState::Healthy);
+}
+
+#[tokio::test]
+async fn timeout_opens_the_breaker_until_the_cooldown_ends() {
+    let server = FakeElectrs::with_slow_history(Duration::from_secs(2));
+    let client = ElectrsClient::new(server.addr.clone())
+        .unwrap()
+        .with_timeouts(ElectrsTimeouts {
+            balance: Duration::from_millis(200),
+            history: Duration::from_millis(200),
+            // Doubled: the only attempt is also the last one
+            cooldown: Duration::from_millis(300),
+        });
+
+    assert!(client.get_address_balance(ADDRESS, NO_RETRY).await.is_err());
+    match client.breaker_state() {
+        BreakerState::CoolingDown { remaining_ms } => {
+            assert!(remaining_ms > 0 && remaining_ms <= 600, "{}", remaining_ms)
+        }
+        other => panic!("expected CoolingDown, got {:?}", other),
+    }
+    assert!(!client.health().breaker.is_healthy());
+
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    assert_eq!(client.breaker_state(), BreakerState::Healthy);
+}
+
+#[test]
+fn breaker_serializes_for_operators() {
+    let open = serde_json::to_value(BreakerState::CoolingDown { remaining_ms: 4200 }).unwrap();
+    assert_eq!(open["state"], "cooling_down");
+    assert_eq!(open["remaining_ms"], 4200);
+
+    assert_eq!(serde_json::to_value(BreakerState::Down).unwrap()["state"], "down");
+    assert_eq!(serde_json::to_value(BreakerState::Healthy).unwrap()["state"], "healthy");
+}
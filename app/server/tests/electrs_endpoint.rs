@@ -0,0 +1,37 @@
+//! ELECTRS_ADDR parsing (plaintext and TLS endpoints)
+
+use balancebridge_server::electrs::ElectrsEndpoint;
+
+#[test]
+fn bare_host_port_is_plaintext() {
+    let e = ElectrsEndpoint::parse("electrs:50001").unwrap();
+    assert_eq!(e.host, "electrs");
+    assert_eq!(e.port, 50001);
+    assert!(!e.tls);
+    assert_eq!(e.url(), "tcp://electrs:50001");
+}
+
+#[test]
+fn schemes_select_tls() {
+    assert!(!ElectrsEndpoint::parse("tcp://10.21.21.10:50001").unwrap().tls);
+
+    let e = ElectrsEndpoint::parse("ssl://fulcrum.local:50002").unwrap();
+    assert!(e.tls);
+    assert_eq!(e.host_port(), "fulcrum.local:50002");
+    assert_eq!(e.url(), "ssl://fulcrum.local:50002");
+}
+
+#[test]
+fn ipv6_host_keeps_brackets() {
+    let e = ElectrsEndpoint::parse("ssl://[::1]:50002").unwrap();
+    assert_eq!(e.host, "[::1]");
+    assert_eq!(e.port, 50002);
+}
+
+#[test]
+fn malformed_addresses_name_the_accepted_formats() {
+    for addr in ["electrs", "http://electrs:50001", "ssl://:50002", "electrs:port"] {
+        let err = ElectrsEndpoint::parse(addr).unwrap_err().to_string();
+        assert!(err.contains("ssl://host:port"), "{}: {}", addr, err);
+    }
+}
@@ -0,0 +1,24 @@
+//! ELECTRS_ADDR and its deprecated alias ELECTRS_URL
+//!
+//! One test only: it changes process-wide environment variables.
+
+use std::env;
+
+use balancebridge_server::config::{get_electrs_addr, uses_deprecated_electrs_var};
+
+#[test]
+fn electrs_url_is_a_deprecated_alias() {
+    env::remove_var("ELECTRS_ADDR");
+    env::remove_var("ELECTRS_URL");
+    assert_eq!(get_electrs_addr(), "electrs:50001");
+    assert!(!uses_deprecated_electrs_var());
+
+    env::set_var("ELECTRS_URL", "10.21.21.10:50001");
+    assert_eq!(get_electrs_addr(), "10.21.21.10:50001");
+    assert!(uses_deprecated_electrs_var());
+
+    // The canonical name wins when both are set
+    env::set_var("ELECTRS_ADDR", "ssl://electrs.local:50002");
+    assert_eq!(get_electrs_addr(), "ssl://electrs.local:50002");
+    assert!(!uses_deprecated_electrs_var());
+}
@@ -0,0 +1,35 @@
+//! ElectrsClient recovers after Electrs drops the TCP connection
+
+mod common;
+
+use balancebridge_server::electrs::{ElectrsClient, ElectrsQueryResult, RetryStrategy};
+use common::FakeElectrs;
+
+const ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+#[tokio::test]
+async fn history_call_survives_dropped_connection() {
+    let server = FakeElectrs::start(true);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+    let before = server.connections();
+
+    let used = client.has_transactions(ADDRESS).await.unwrap();
+
+    assert!(!used);
+    assert!(server.connections() > before, "expected a fresh connection after the drop");
+}
+
+#[tokio::test]
+async fn balance_call_survives_dropped_connection() {
+    let server = FakeElectrs::start(true);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+    let before = server.connections();
+
+    let result = client
+        .get_address_balance(ADDRESS, RetryStrategy::default())
+        .await
+        .unwrap();
+
+    assert!(matches!(result, ElectrsQueryResult::NotFound));
+    assert!(server.connections() > before, "expected a fresh connection after the drop");
+}
@@ -0,0 +1,110 @@
+//! Configured Electrs timeouts fire and start the cooldown
+
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use balancebridge_server::electrs::{
+    ElectrsClient, ElectrsTimeouts, PendingCooldown, RetryStrategy,
+};
+use common::{FakeElectrs, Harness};
+use nostr_sdk::Keys;
+use serde_json::json;
+
+const ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+/// Long enough to miss the short timeouts below, short enough that the
+/// blocked lookup thread doesn't hold up the test runtime's shutdown
+const SLOW: Duration = Duration::from_secs(2);
+
+fn short_timeouts() -> ElectrsTimeouts {
+    ElectrsTimeouts {
+        balance: Duration::from_millis(300),
+        history: Duration::from_millis(300),
+        cooldown: Duration::from_secs(5),
+    }
+}
+
+#[test]
+fn defaults_match_the_documented_values() {
+    let defaults = ElectrsTimeouts::default();
+    assert_eq!(defaults.balance, Duration::from_secs(90));
+    assert_eq!(defaults.history, Duration::from_secs(45));
+    assert_eq!(defaults.cooldown, Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn short_balance_timeout_fires() {
+    let server = FakeElectrs::with_slow_history(SLOW);
+    let client = ElectrsClient::new(server.addr.clone())
+        .unwrap()
+        .with_timeouts(short_timeouts());
+    let no_retry = RetryStrategy {
+        max_retries: 0,
+        reconnect_before_retry: false,
+    };
+
+    let started = Instant::now();
+    let err = client.get_address_balance(ADDRESS, no_retry).await.unwrap_err();
+    assert!(started.elapsed() < SLOW, "took {:?}", started.elapsed());
+    assert!(err.to_string().contains("timeout"), "{}", err);
+
+    // The timeout starts the cooldown: the next call fails fast
+    let err = client.get_address_balance(ADDRESS, no_retry).await.unwrap_err();
+    assert!(err.downcast_ref::<PendingCooldown>().is_some(), "{}", err);
+}
+
+#[tokio::test]
+async fn cooling_down_error_tells_the_phone_when_to_retry() {
+    let server = FakeElectrs::with_slow_history(SLOW);
+    let client = ElectrsClient::new(server.addr.clone())
+        .unwrap()
+        .with_timeouts(short_timeouts());
+    let mut harness = Harness::start("cooldown-error", Arc::new(client)).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    // The first lookup times out and starts the cooldown
+    let lookup = json!({ "type": "bitcoin_lookup", "query": ADDRESS });
+    harness.send(harness.request(&phone, "r1", lookup.clone())).await;
+    harness.send(harness.request(&phone, "r2", lookup)).await;
+
+    let responses = harness.responses();
+    let error = &responses[1].1["error"];
+    assert_eq!(responses[1].1["req"], "r2");
+    assert_eq!(error["code"], "electrs_cooling_down");
+    assert_eq!(error["retryable"], true);
+    let retry_after_ms = error["retry_after_ms"].as_u64().unwrap();
+    assert!(retry_after_ms > 0 && retry_after_ms <= 10_000, "{}", retry_after_ms);
+}
+
+#[tokio::test]
+async fn short_history_timeout_fires() {
+    let server = FakeElectrs::with_slow_history(SLOW);
+    let client = ElectrsClient::new(server.addr.clone())
+        .unwrap()
+        .with_timeouts(short_timeouts());
+
+    let started = Instant::now();
+    let err = client.get_address_txs(ADDRESS).await.unwrap_err();
+    assert!(started.elapsed() < SLOW, "took {:?}", started.elapsed());
+    assert!(err.to_string().contains("timeout"), "{}", err);
+}
+
+#[test]
+fn zero_durations_keep_the_defaults() {
+    let server = FakeElectrs::start(false);
+    let client = ElectrsClient::new(server.addr.clone())
+        .unwrap()
+        .with_timeouts(ElectrsTimeouts {
+            balance: Duration::ZERO,
+            history: Duration::from_secs(5),
+            cooldown: Duration::ZERO,
+        });
+
+    let timeouts = client.timeouts();
+    assert_eq!(timeouts.balance, Duration::from_secs(90));
+    assert_eq!(timeouts.history, Duration::from_secs(5));
+    assert_eq!(timeouts.cooldown, Duration::from_secs(10));
+}
@@ -0,0 +1,91 @@
+//! Error `code` strings are part of the wire protocol and must not change
+
+use balancebridge_server::protocol::{ErrorResponse, LookupError, LookupErrorCode};
+
+/// The documented code of each variant. No wildcard arm: a new variant
+/// doesn't compile until its code is written down here.
+fn documented(code: LookupErrorCode) -> &'static str {
+    match code {
+        LookupErrorCode::NotPaired => "not_paired",
+        LookupErrorCode::RateLimited => "rate_limited",
+        LookupErrorCode::InvalidJson => "invalid_json",
+        LookupErrorCode::InvalidType => "invalid_type",
+        LookupErrorCode::EmptyQuery => "empty_query",
+        LookupErrorCode::InvalidQuery => "invalid_query",
+        LookupErrorCode::InvalidParams => "invalid_params",
+        LookupErrorCode::InvalidAddress => "invalid_address",
+        LookupErrorCode::InvalidXpub => "invalid_xpub",
+        LookupErrorCode::WrongNetwork => "wrong_network",
+        LookupErrorCode::UnsupportedVersion => "unsupported_version",
+        LookupErrorCode::TxNotFound => "tx_not_found",
+        LookupErrorCode::FeeUnavailable => "fee_unavailable",
+        LookupErrorCode::TooManyUtxos => "too_many_utxos",
+        LookupErrorCode::WatchLimitReached => "watch_limit_reached",
+        LookupErrorCode::ElectrsUnavailable => "electrs_unavailable",
+        LookupErrorCode::ElectrsCoolingDown => "electrs_cooling_down",
+        LookupErrorCode::ElectrsTimeout => "electrs_timeout",
+        LookupErrorCode::InternalError => "internal_error",
+    }
+}
+
+#[test]
+fn every_code_serializes_to_its_documented_string() {
+    let errors = [
+        LookupError::NotPaired,
+        LookupError::RateLimited,
+        LookupError::InvalidJson,
+        LookupError::InvalidType,
+        LookupError::EmptyQuery,
+        LookupError::InvalidQuery,
+        LookupError::InvalidParams,
+        LookupError::InvalidAddress,
+        LookupError::InvalidXpub,
+        LookupError::WrongNetwork,
+        LookupError::UnsupportedVersion { requested: 99 },
+        LookupError::TxNotFound,
+        LookupError::FeeUnavailable,
+        LookupError::TooManyUtxos { limit: 500 },
+        LookupError::WatchLimitReached { limit: 5 },
+        LookupError::ElectrsUnavailable,
+        LookupError::ElectrsCoolingDown { retry_after_ms: 1_000 },
+        LookupError::ElectrsTimeout,
+        LookupError::InternalError,
+    ];
+    for error in errors {
+        let code = error.error_code();
+        assert_eq!(code.as_str(), documented(code));
+        assert_eq!(serde_json::to_value(code).unwrap(), documented(code));
+        assert_eq!(serde_json::to_value(&error).unwrap()["code"], documented(code));
+    }
+}
+
+#[test]
+fn error_response_carries_code_and_message() {
+    let json = serde_json::to_value(ErrorResponse::new("r1", LookupError::InvalidJson)).unwrap();
+
+    assert_eq!(json["req"], "r1");
+    assert_eq!(json["error"]["code"], "invalid_json");
+    assert_eq!(json["error"]["message"], "request is not valid JSON");
+    assert_eq!(json["error"]["retryable"], false);
+}
+
+#[test]
+fn request_errors_are_not_retryable() {
+    for error in [
+        LookupError::InvalidJson,
+        LookupError::InvalidType,
+        LookupError::EmptyQuery,
+        LookupError::NotPaired,
+    ] {
+        assert!(!error.retryable(), "{}", error.code());
+    }
+    assert!(LookupError::ElectrsTimeout.retryable());
+    assert_eq!(LookupError::ElectrsTimeout.error_code(), LookupErrorCode::ElectrsTimeout);
+}
+
+#[test]
+fn internal_errors_do_not_leak_details() {
+    let json = serde_json::to_value(LookupError::InternalError).unwrap();
+    assert_eq!(json["code"], "internal_error");
+    assert_eq!(json["message"], "internal server error");
+}
@@ -0,0 +1,68 @@
+//! Request events whose signature doesn't check out are dropped unanswered
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use balancebridge_server::electrs::mock::MockElectrsClient;
+use common::Harness;
+use nostr_sdk::{Event, Keys};
+use serde_json::json;
+
+const ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+async fn harness(name: &str) -> Harness {
+    let electrs = MockElectrsClient::new(HashMap::new(), HashMap::new());
+    Harness::start(name, Arc::new(electrs)).await
+}
+
+#[tokio::test]
+async fn forged_pubkey_is_dropped() {
+    let mut harness = harness("sig-forged").await;
+    let phone = Keys::generate();
+    let attacker = Keys::generate();
+    harness.pair(&phone);
+
+    // Signed by the attacker, claiming to come from the paired phone
+    let lookup = json!({ "type": "bitcoin_lookup", "query": ADDRESS });
+    let signed = harness.request(&attacker, "r1", lookup);
+    let forged = Event::new(
+        signed.id,
+        phone.public_key(),
+        signed.created_at,
+        signed.kind,
+        signed.tags.to_vec(),
+        signed.content.clone(),
+        signed.sig,
+    );
+    harness.send(forged).await;
+
+    assert!(harness.responses().is_empty());
+}
+
+#[tokio::test]
+async fn tampered_content_is_dropped() {
+    let mut harness = harness("sig-tampered").await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    let lookup = json!({ "type": "bitcoin_lookup", "query": ADDRESS });
+    let signed = harness.request(&phone, "r1", lookup);
+    let other = harness.request(&phone, "r1", json!({ "type": "server_ping" }));
+    let tampered = Event::new(
+        signed.id,
+        signed.pubkey,
+        signed.created_at,
+        signed.kind,
+        signed.tags.to_vec(),
+        other.content,
+        signed.sig,
+    );
+    harness.send(tampered).await;
+
+    assert!(harness.responses().is_empty());
+    // The genuine event still goes through
+    harness.send(signed).await;
+    assert_eq!(harness.responses().len(), 1);
+}
@@ -0,0 +1,23 @@
+//! `fee_estimate`: Electrum's BTC/kB answer converted to sat/vB
+
+use balancebridge_server::electrs::btc_per_kb_to_sat_per_vb;
+
+fn assert_close(actual: Option<f64>, expected: f64) {
+    let actual = actual.expect("expected an estimate");
+    assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+}
+
+#[test]
+fn btc_per_kb_converts_to_sat_per_vb() {
+    // 1,000 sat per 1,000 vbytes
+    assert_close(btc_per_kb_to_sat_per_vb(0.00001), 1.0);
+    assert_close(btc_per_kb_to_sat_per_vb(0.00012345), 12.345);
+    assert_close(btc_per_kb_to_sat_per_vb(0.001), 100.0);
+}
+
+#[test]
+fn insufficient_data_is_unavailable() {
+    assert_eq!(btc_per_kb_to_sat_per_vb(-1.0), None);
+    assert_eq!(btc_per_kb_to_sat_per_vb(0.0), None);
+    assert_eq!(btc_per_kb_to_sat_per_vb(f64::NAN), None);
+}
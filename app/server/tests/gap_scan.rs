@@ -0,0 +1,97 @@
+//! Gap-limit scans: where they stop, and the cap on how far they go
+
+use std::collections::HashMap;
+
+use balancebridge_server::electrs::mock::MockElectrsClient;
+use balancebridge_server::electrs::ElectrsClientTrait;
+use balancebridge_server::xpub::{
+    derive_addresses_until_gap, derive_chain_range, AddressType, GapScan, ScanIndexLimit,
+    MAX_SCAN_INDEX,
+};
+use bitcoin::Network;
+
+// Account key of the "abandon … about" test mnemonic (m/84h/0h/0h)
+const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+fn receiving(index: u32) -> String {
+    derive_chain_range(BIP84_XPUB, Network::Bitcoin, AddressType::NativeSegwit, 0, index, 1)
+        .unwrap()
+        .remove(0)
+        .address
+}
+
+#[tokio::test]
+async fn scan_stops_after_gap_limit_unused() {
+    // m/0/0 and m/0/2 used; with a gap of 2, m/0/3 and m/0/4 end the chain
+    let txs = HashMap::from([
+        (receiving(0), vec!["aa".repeat(32)]),
+        (receiving(2), vec!["bb".repeat(32)]),
+    ]);
+    let electrs = MockElectrsClient::new(HashMap::new(), txs);
+
+    let sets = derive_addresses_until_gap(BIP84_XPUB, AddressType::NativeSegwit, 2, &electrs)
+        .await
+        .unwrap();
+
+    let expected: Vec<_> = (0..5).map(receiving).collect();
+    assert_eq!(sets.external, expected);
+    assert_eq!(sets.internal.len(), 2);
+}
+
+#[tokio::test]
+async fn parallel_scan_totals_the_funded_addresses() {
+    let txs = HashMap::from([
+        (receiving(0), vec!["aa".repeat(32)]),
+        (receiving(2), vec!["bb".repeat(32)]),
+    ]);
+    let balances = HashMap::from([(receiving(2), (4_000, 500))]);
+    let electrs = MockElectrsClient::new(balances, txs);
+
+    let scan = electrs.scan_xpub_parallel(BIP84_XPUB, 2).await.unwrap();
+
+    assert_eq!((scan.confirmed, scan.unconfirmed), (4_000, 500));
+    assert_eq!((scan.external_confirmed, scan.internal_confirmed), (4_000, 0));
+    assert_eq!(scan.funded.len(), 1);
+    assert_eq!(scan.funded[0].address, receiving(2));
+}
+
+#[test]
+fn gap_scan_moves_on_to_the_change_chain() {
+    let mut scan = GapScan::new(3);
+
+    assert!(!scan.record(true));
+    assert!(!scan.record(false));
+    assert!(!scan.record(false));
+    assert_eq!(scan.cursor(), "m/0/3");
+    assert!(scan.record(false));
+    assert_eq!(scan.cursor(), "m/1/0");
+
+    for _ in 0..2 {
+        assert!(!scan.record(false));
+    }
+    assert!(scan.record(false));
+    assert!(scan.is_done());
+}
+
+#[test]
+fn gap_scan_stops_at_the_index_cap() {
+    let mut scan = GapScan::new(20);
+    for _ in 0..MAX_SCAN_INDEX - 5 {
+        scan.record(true);
+    }
+
+    // Only what is left below the cap
+    let window = scan
+        .next_window(BIP84_XPUB, Network::Bitcoin, AddressType::NativeSegwit, 20)
+        .unwrap();
+    assert_eq!(window.len(), 5);
+    assert_eq!(window[0].path, format!("m/0/{}", MAX_SCAN_INDEX - 5));
+
+    for _ in 0..5 {
+        scan.record(true);
+    }
+    let err = scan
+        .next_window(BIP84_XPUB, Network::Bitcoin, AddressType::NativeSegwit, 20)
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<ScanIndexLimit>(), Some(&ScanIndexLimit { chain: 0 }));
+}
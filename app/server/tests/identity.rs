@@ -0,0 +1,100 @@
+//! The Nostr identity lives in the configured data dir and survives restarts
+
+mod common;
+
+use balancebridge_server::identity::{parse_nsec, IdentityManager};
+use common::scratch_dir;
+use nostr_sdk::ToBech32;
+
+#[test]
+fn first_run_key_is_reloaded_on_the_second() {
+    let dir = scratch_dir("identity-reload");
+
+    let first = IdentityManager::load_or_create(&dir, None).unwrap();
+    assert_eq!(first.key_path(), dir.join("nostr_secret.hex"));
+    assert!(first.key_path().exists());
+
+    let second = IdentityManager::load_or_create(&dir, None).unwrap();
+    assert_eq!(second.public_key_hex(), first.public_key_hex());
+    assert_eq!(
+        second.keys().secret_key().to_secret_hex(),
+        first.keys().secret_key().to_secret_hex()
+    );
+}
+
+#[test]
+fn separate_data_dirs_get_separate_identities() {
+    let a = IdentityManager::load_or_create(scratch_dir("identity-a"), None).unwrap();
+    let b = IdentityManager::load_or_create(scratch_dir("identity-b"), None).unwrap();
+
+    assert_ne!(a.public_key_hex(), b.public_key_hex());
+}
+
+#[test]
+fn damaged_key_file_is_refused() {
+    let dir = scratch_dir("identity-damaged");
+    let identity = IdentityManager::load_or_create(&dir, None).unwrap();
+    std::fs::write(identity.key_path(), "abcd").unwrap();
+
+    assert!(IdentityManager::load_or_create(&dir, None).is_err());
+}
+
+#[test]
+fn nsec_is_imported_on_first_run_only() {
+    let dir = scratch_dir("identity-import");
+    let imported = nostr_sdk::Keys::generate();
+    let nsec = imported.secret_key().to_bech32().unwrap();
+
+    let first = IdentityManager::load_or_create(&dir, Some(&nsec)).unwrap();
+    assert_eq!(first.public_key_hex(), imported.public_key().to_hex());
+
+    // A different nsec later on doesn't rotate the persisted key
+    let other = nostr_sdk::Keys::generate().secret_key().to_bech32().unwrap();
+    let second = IdentityManager::load_or_create(&dir, Some(&other)).unwrap();
+    assert_eq!(second.public_key_hex(), first.public_key_hex());
+}
+
+#[test]
+fn malformed_nsec_fails_without_echoing_it() {
+    let dir = scratch_dir("identity-bad-nsec");
+    let nsec = nostr_sdk::Keys::generate().secret_key().to_bech32().unwrap();
+    let corrupted = format!("{}x", &nsec[..nsec.len() - 1]);
+
+    for bad in [corrupted.as_str(), "npub1xyz", "deadbeef"] {
+        let err = IdentityManager::load_or_create(&dir, Some(bad)).err().unwrap();
+        assert!(!format!("{:#}", err).contains(bad), "{}", bad);
+    }
+    assert!(parse_nsec(&nsec).is_ok());
+    assert!(!dir.join("nostr_secret.hex").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn key_file_is_created_owner_only() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = scratch_dir("identity-mode");
+    let identity = IdentityManager::load_or_create(&dir, None).unwrap();
+
+    let mode = std::fs::metadata(identity.key_path()).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(IdentityManager::verify_key_file(identity.key_path()).is_ok());
+}
+
+#[cfg(unix)]
+#[test]
+fn readable_key_file_is_tightened_on_load() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = scratch_dir("identity-tighten");
+    let identity = IdentityManager::load_or_create(&dir, None).unwrap();
+    std::fs::set_permissions(identity.key_path(), std::fs::Permissions::from_mode(0o644)).unwrap();
+    let err = IdentityManager::verify_key_file(identity.key_path()).unwrap_err();
+    assert!(err.to_string().contains("mode 644"), "{}", err);
+
+    let reloaded = IdentityManager::load_or_create(&dir, None).unwrap();
+    assert_eq!(reloaded.public_key_hex(), identity.public_key_hex());
+    let mode = std::fs::metadata(identity.key_path()).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(IdentityManager::verify_key_file(identity.key_path()).is_ok());
+}
@@ -0,0 +1,156 @@
+//! list_utxos: typed UTXOs for coin selection, capped by LIST_UTXOS_MAX
+
+mod common;
+
+use std::sync::Arc;
+
+use balancebridge_server::electrs::{address_script, ElectrsClient};
+use balancebridge_server::protocol::{LookupError, Utxo, UtxoListResponse};
+use common::{FakeAddress, FakeElectrs, Harness, FAKE_TIP_HEIGHT};
+use nostr_sdk::Keys;
+use serde_json::json;
+
+const FUNDED: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+const FRESH: &str = "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA";
+
+const TX_A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+const TX_B: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+// Account key of the "abandon … about" test mnemonic (m/84h/0h/0h); FUNDED is its m/0/0
+const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+const RECEIVE_1: &str = "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g";
+const CHANGE_0: &str = "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el";
+
+#[tokio::test]
+async fn unspent_outputs_come_back_typed() {
+    let server = FakeElectrs::with_addresses(vec![FakeAddress {
+        address: FUNDED,
+        history: json!([
+            { "tx_hash": TX_A, "height": 800_000 },
+            { "tx_hash": TX_B, "height": 0, "fee": 141 },
+        ]),
+        utxos: json!([
+            { "tx_hash": TX_A, "tx_pos": 1, "height": 800_000, "value": 30_000 },
+            { "tx_hash": TX_B, "tx_pos": 0, "height": 0, "value": 7_000 },
+        ]),
+    }]);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+
+    let utxos = client.list_unspent(&[address_script(FUNDED).unwrap()]).await.unwrap();
+
+    assert_eq!(
+        utxos,
+        vec![
+            Utxo::new(TX_A.to_string(), 1, 30_000, 800_000, FUNDED.to_string()),
+            Utxo::new(TX_B.to_string(), 0, 7_000, 0, FUNDED.to_string()),
+        ]
+    );
+    // Mempool outputs need no tip; mined ones wait for `with_tip`
+    assert_eq!(utxos[0].confirmations, None);
+    assert_eq!(utxos[1].confirmations, Some(0));
+    assert!(client
+        .list_unspent(&[address_script(FRESH).unwrap()])
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn confirmations_count_from_the_tip() {
+    let mined = Utxo::new(TX_A.to_string(), 0, 1_000, 800_000, FUNDED.to_string());
+    assert_eq!(mined.clone().with_tip(800_000).confirmations, Some(1));
+    assert_eq!(mined.with_tip(FAKE_TIP_HEIGHT).confirmations, Some(40_001));
+
+    let pending = Utxo::new(TX_B.to_string(), 0, 1_000, 0, FUNDED.to_string());
+    assert_eq!(pending.with_tip(FAKE_TIP_HEIGHT).confirmations, Some(0));
+}
+
+#[test]
+fn response_uses_the_documented_fields() {
+    let utxo =
+        Utxo::new(TX_A.to_string(), 2, 30_000, 800_000, FUNDED.to_string()).with_tip(800_009);
+    let response = UtxoListResponse {
+        req: "r1".to_string(),
+        protocol_version: "1.2".to_string(),
+        total_sats: utxo.value_sats,
+        utxos: vec![utxo],
+    };
+
+    let json = serde_json::to_value(&response).unwrap();
+    assert_eq!(json["total_sats"], 30_000);
+    let entry = &json["utxos"][0];
+    assert_eq!(entry["txid"], TX_A);
+    assert_eq!(entry["vout"], 2);
+    assert_eq!(entry["value_sats"], 30_000);
+    assert_eq!(entry["confirmations"], 10);
+    assert_eq!(entry["address"], FUNDED);
+}
+
+#[test]
+fn too_many_utxos_names_the_limit() {
+    let error = LookupError::TooManyUtxos { limit: 500 };
+    assert!(!error.retryable());
+
+    let json = serde_json::to_value(&error).unwrap();
+    assert_eq!(json["code"], "too_many_utxos");
+    assert_eq!(json["limit"], 500);
+    assert!(json["message"].as_str().unwrap().contains("500"));
+}
+
+/// One test, since LIST_UTXOS_MAX is process-wide
+#[tokio::test]
+async fn xpub_request_lists_used_addresses_up_to_the_cap() {
+    std::env::set_var("LIST_UTXOS_MAX", "1");
+    let phone = Keys::generate();
+    let request = json!({ "type": "list_utxos", "query": BIP84_XPUB, "gap_limit": 5 });
+
+    // m/0/0 holds one output, m/0/1 was used and emptied
+    let electrs = FakeElectrs::with_addresses(vec![
+        FakeAddress {
+            address: FUNDED,
+            history: json!([{ "tx_hash": TX_A, "height": 800_000 }]),
+            utxos: json!([{ "tx_hash": TX_A, "tx_pos": 1, "height": 800_000, "value": 30_000 }]),
+        },
+        FakeAddress {
+            address: RECEIVE_1,
+            history: json!([{ "tx_hash": TX_A, "height": 800_000 }]),
+            utxos: json!([]),
+        },
+    ]);
+    let client = Arc::new(ElectrsClient::new(electrs.addr.clone()).unwrap());
+    let mut harness = Harness::start("list-utxos-xpub", client).await;
+    harness.pair(&phone);
+
+    harness.send(harness.request(&phone, "r1", request.clone())).await;
+
+    let json = &harness.responses()[0].1;
+    assert_eq!(json["total_sats"], 30_000);
+    assert_eq!(json["utxos"].as_array().unwrap().len(), 1);
+    assert_eq!(json["utxos"][0]["address"], FUNDED);
+    assert_eq!(json["utxos"][0]["confirmations"], FAKE_TIP_HEIGHT - 800_000 + 1);
+    // Only the two used addresses are listed, not the whole gap
+    assert_eq!(electrs.listunspent_calls(), 2);
+
+    // One more output, on the change chain: over the cap of 1
+    let electrs = FakeElectrs::with_addresses(vec![
+        FakeAddress {
+            address: FUNDED,
+            history: json!([{ "tx_hash": TX_A, "height": 800_000 }]),
+            utxos: json!([{ "tx_hash": TX_A, "tx_pos": 1, "height": 800_000, "value": 30_000 }]),
+        },
+        FakeAddress {
+            address: CHANGE_0,
+            history: json!([{ "tx_hash": TX_B, "height": 0 }]),
+            utxos: json!([{ "tx_hash": TX_B, "tx_pos": 0, "height": 0, "value": 7_000 }]),
+        },
+    ]);
+    let client = Arc::new(ElectrsClient::new(electrs.addr.clone()).unwrap());
+    let mut harness = Harness::start("list-utxos-cap", client).await;
+    harness.pair(&phone);
+
+    harness.send(harness.request(&phone, "r2", request)).await;
+
+    let json = &harness.responses()[0].1;
+    assert_eq!(json["error"]["code"], "too_many_utxos");
+    assert_eq!(json["error"]["limit"], 1);
+}
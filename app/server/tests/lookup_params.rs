@@ -0,0 +1,36 @@
+//! Per-request gap limit and account bounds
+
+use balancebridge_server::protocol::LookupError;
+use balancebridge_server::xpub::{
+    valid_account, valid_gap_limit, AddressType, DerivationStandard, MAX_ACCOUNT, MAX_GAP_LIMIT,
+};
+
+#[test]
+fn gap_limit_bounds() {
+    assert!(!valid_gap_limit(0));
+    assert!(valid_gap_limit(1));
+    assert!(valid_gap_limit(MAX_GAP_LIMIT));
+    assert!(!valid_gap_limit(MAX_GAP_LIMIT + 1));
+}
+
+#[test]
+fn account_bounds() {
+    assert!(valid_account(0));
+    assert!(valid_account(MAX_ACCOUNT));
+    assert!(!valid_account(MAX_ACCOUNT + 1));
+}
+
+#[test]
+fn bare_xpub_prefix_picks_the_standard() {
+    assert_eq!(DerivationStandard::for_address_type(AddressType::P2PKH), DerivationStandard::Bip44);
+    assert_eq!(
+        DerivationStandard::for_address_type(AddressType::NativeSegwit),
+        DerivationStandard::Bip84
+    );
+}
+
+#[test]
+fn out_of_range_params_have_their_own_code() {
+    assert_eq!(LookupError::InvalidParams.code(), "invalid_params");
+    assert!(!LookupError::InvalidParams.retryable());
+}
@@ -0,0 +1,92 @@
+//! BITCOIN_NETWORK: keys and addresses must belong to the configured chain
+
+use balancebridge_server::config::get_bitcoin_network;
+use balancebridge_server::protocol::LookupError;
+use balancebridge_server::xpub::{
+    derive_addresses, derive_chain_range, is_bitcoin_address, key_matches_network,
+    network_from_name, validate_address, AddressType,
+};
+use bitcoin::bip32::{Xpriv, Xpub};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::Network;
+
+const BIP84_ZPUB: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+
+fn tpub() -> String {
+    let xpriv = Xpriv::new_master(Network::Testnet, &[7; 32]).expect("32-byte seed");
+    Xpub::from_priv(&Secp256k1::new(), &xpriv).to_string()
+}
+
+fn first_segwit_address(key: &str, network: Network) -> String {
+    derive_chain_range(key, network, AddressType::NativeSegwit, 0, 0, 1)
+        .unwrap()
+        .remove(0)
+        .address
+}
+
+#[test]
+fn network_names() {
+    assert_eq!(network_from_name("mainnet"), Some(Network::Bitcoin));
+    assert_eq!(network_from_name("testnet"), Some(Network::Testnet));
+    assert_eq!(network_from_name("Signet"), Some(Network::Signet));
+    assert_eq!(network_from_name("regtest"), Some(Network::Regtest));
+    assert_eq!(network_from_name("liquid"), None);
+}
+
+#[test]
+fn testnet_keys_derive_for_the_configured_chain() {
+    let key = tpub();
+    assert!(first_segwit_address(&key, Network::Testnet).starts_with("tb1q"));
+    assert!(first_segwit_address(&key, Network::Signet).starts_with("tb1q"));
+    assert!(first_segwit_address(&key, Network::Regtest).starts_with("bcrt1q"));
+}
+
+#[test]
+fn keys_for_another_chain_are_rejected() {
+    assert!(key_matches_network(BIP84_ZPUB, Network::Bitcoin));
+    assert!(!key_matches_network(BIP84_ZPUB, Network::Testnet));
+    assert!(derive_addresses(BIP84_ZPUB, Network::Signet, 1).is_err());
+
+    assert!(key_matches_network(&tpub(), Network::Regtest));
+    assert!(derive_addresses(&tpub(), Network::Bitcoin, 1).is_err());
+}
+
+#[test]
+fn address_prefixes_follow_the_network() {
+    let mainnet = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+    let testnet = first_segwit_address(&tpub(), Network::Testnet);
+    let regtest = first_segwit_address(&tpub(), Network::Regtest);
+
+    assert!(is_bitcoin_address(mainnet, Network::Bitcoin));
+    assert!(is_bitcoin_address("1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA", Network::Bitcoin));
+    assert!(!is_bitcoin_address(&testnet, Network::Bitcoin));
+
+    assert!(is_bitcoin_address(&testnet, Network::Signet));
+    assert!(!is_bitcoin_address(mainnet, Network::Testnet));
+
+    assert!(is_bitcoin_address(&regtest, Network::Regtest));
+    assert!(!is_bitcoin_address(&testnet, Network::Regtest));
+}
+
+#[test]
+fn mainnet_address_is_wrong_on_testnet() {
+    let validation = validate_address("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu", Network::Testnet);
+    assert!(!validation.valid);
+    assert_eq!(validation.network.as_deref(), Some("mainnet"));
+
+    assert_eq!(LookupError::WrongNetwork.code(), "wrong_network");
+    assert!(!LookupError::WrongNetwork.retryable());
+}
+
+/// One test, since BITCOIN_NETWORK is process-wide
+#[test]
+fn unknown_network_is_a_startup_error() {
+    std::env::set_var("BITCOIN_NETWORK", "testnet4x");
+    assert!(get_bitcoin_network().is_err());
+
+    std::env::set_var("BITCOIN_NETWORK", "signet");
+    assert_eq!(get_bitcoin_network().unwrap(), Network::Signet);
+
+    std::env::remove_var("BITCOIN_NETWORK");
+    assert_eq!(get_bitcoin_network().unwrap(), Network::Bitcoin);
+}
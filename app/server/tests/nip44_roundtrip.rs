@@ -0,0 +1,37 @@
+//! Requests and responses are NIP-44 encrypted between the phone and the server.
+
+use balancebridge_server::nostr_handler::{decrypt_content, encrypt_content};
+use nostr_sdk::Keys;
+
+#[test]
+fn request_and_response_round_trip() {
+    let server = Keys::generate();
+    let phone = Keys::generate();
+
+    let request = r#"{"type":"bitcoin_lookup","query":"bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"}"#;
+    let encrypted = encrypt_content(&phone, &server.public_key(), request).unwrap();
+    assert_ne!(encrypted, request);
+    assert_eq!(decrypt_content(&server, &phone.public_key(), &encrypted).unwrap(), request);
+
+    let response = r#"{"req":"1","confirmed_balance":0}"#;
+    let encrypted = encrypt_content(&server, &phone.public_key(), response).unwrap();
+    assert_eq!(decrypt_content(&phone, &server.public_key(), &encrypted).unwrap(), response);
+}
+
+#[test]
+fn plaintext_is_rejected() {
+    let server = Keys::generate();
+    let phone = Keys::generate();
+
+    assert!(decrypt_content(&server, &phone.public_key(), r#"{"type":"server_ping"}"#).is_err());
+}
+
+#[test]
+fn third_party_cannot_decrypt() {
+    let server = Keys::generate();
+    let phone = Keys::generate();
+    let eavesdropper = Keys::generate();
+
+    let encrypted = encrypt_content(&phone, &server.public_key(), "secret").unwrap();
+    assert!(decrypt_content(&eavesdropper, &phone.public_key(), &encrypted).is_err());
+}
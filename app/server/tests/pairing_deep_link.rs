@@ -0,0 +1,85 @@
+//! balancebridge://pair?… deep links in the pairing QR
+
+use balancebridge_server::qr::{
+    PairingPayload, PairingQr, QrEncoding, SignedPairingPayload, DEEP_LINK_PREFIX,
+};
+use nostr_sdk::Keys;
+
+const PUBKEY: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+#[test]
+fn link_carries_version_pubkey_and_relays() {
+    let payload = PairingPayload::new(
+        PUBKEY.to_string(),
+        vec!["wss://nos.lol".to_string(), "wss://relay.damus.io".to_string()],
+    );
+
+    assert_eq!(
+        payload.to_deep_link(),
+        format!(
+            "{}v=1&pubkey={}&relays=wss%3A%2F%2Fnos.lol,wss%3A%2F%2Frelay.damus.io",
+            DEEP_LINK_PREFIX, PUBKEY
+        )
+    );
+}
+
+#[test]
+fn special_characters_in_relays_round_trip() {
+    let relays = vec![
+        "wss://relay.example.com:7777/path?token=a&b=c,d".to_string(),
+        "wss://umbrel.local/nostr#frag".to_string(),
+        "wss://relay.example.com/über space+plus".to_string(),
+    ];
+    let payload = PairingPayload::new(PUBKEY.to_string(), relays.clone())
+        .with_challenge("c/h=a&l+l".to_string());
+
+    let link = payload.to_deep_link();
+    // Nothing in a parameter value can be mistaken for a separator
+    let query = link.strip_prefix(DEEP_LINK_PREFIX).unwrap();
+    assert_eq!(query.matches('&').count(), 3);
+    assert!(query.is_ascii());
+
+    let parsed = PairingPayload::from_deep_link(&link).unwrap();
+    assert_eq!(parsed.relays, relays);
+    assert_eq!(parsed.node_pubkey, PUBKEY);
+    assert_eq!(parsed.challenge.as_deref(), Some("c/h=a&l+l"));
+    assert_eq!(parsed.to_json().unwrap(), payload.to_json().unwrap());
+}
+
+#[test]
+fn signed_link_still_verifies() {
+    let keys = Keys::generate();
+    let signed = PairingPayload::new(keys.public_key().to_hex(), vec!["wss://nos.lol".to_string()])
+        .with_challenge("abc".to_string())
+        .sign_with_keys(&keys)
+        .unwrap();
+
+    let parsed = SignedPairingPayload::from_deep_link(&signed.to_deep_link()).unwrap();
+    parsed.verify().unwrap();
+
+    let tampered = signed.to_deep_link().replace("nos.lol", "evil.example");
+    assert!(SignedPairingPayload::from_deep_link(&tampered)
+        .unwrap()
+        .verify()
+        .is_err());
+}
+
+#[test]
+fn other_links_are_rejected() {
+    assert!(PairingPayload::from_deep_link("https://example.com/pair?v=1").is_err());
+    assert!(PairingPayload::from_deep_link(&format!("{}v=1", DEEP_LINK_PREFIX)).is_err());
+}
+
+#[test]
+fn qr_format_is_selectable() {
+    assert_eq!("json".parse::<QrEncoding>().unwrap(), QrEncoding::Json);
+    assert_eq!("URI".parse::<QrEncoding>().unwrap(), QrEncoding::DeepLink);
+    assert!("png".parse::<QrEncoding>().is_err());
+
+    let keys = Keys::generate();
+    let json = PairingQr::build(&keys, vec![], "c".to_string(), QrEncoding::Json).unwrap();
+    let link = PairingQr::build(&keys, vec![], "c".to_string(), QrEncoding::DeepLink).unwrap();
+    assert!(link.deep_link.starts_with(DEEP_LINK_PREFIX));
+    // Same payload either way; only the QR content differs
+    assert_ne!(json.svg, link.svg);
+}
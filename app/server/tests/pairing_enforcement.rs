@@ -0,0 +1,228 @@
+//! Only paired, unexpired devices are allowed to query the server.
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use balancebridge_server::electrs::ElectrsClient;
+use balancebridge_server::pairing::PairingManager;
+use chrono::Utc;
+use common::{scratch_dir, FakeElectrs, Harness};
+use nostr_sdk::Keys;
+use serde_json::json;
+
+#[test]
+fn nobody_is_paired_before_pairing() {
+    let dir = scratch_dir("unpaired");
+    let manager = PairingManager::new(&dir).unwrap();
+
+    assert!(!manager.is_paired(&Keys::generate().public_key()).unwrap());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn only_the_paired_pubkey_is_accepted() {
+    let dir = scratch_dir("paired");
+    let manager = PairingManager::new(&dir).unwrap();
+    let phone = Keys::generate().public_key();
+    let stranger = Keys::generate().public_key();
+
+    manager.add_pairing(phone, Vec::new(), None).unwrap();
+
+    assert!(manager.is_paired(&phone).unwrap());
+    assert!(!manager.is_paired(&stranger).unwrap());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn several_devices_can_be_paired() {
+    let dir = scratch_dir("multi");
+    let manager = PairingManager::new(&dir).unwrap();
+    let first = Keys::generate().public_key();
+    let second = Keys::generate().public_key();
+
+    manager.add_pairing(first, Vec::new(), None).unwrap();
+    manager.add_pairing(second, Vec::new(), None).unwrap();
+    // Pairing again replaces the entry instead of adding a duplicate
+    manager.add_pairing(first, vec!["wss://relay.example".to_string()], None).unwrap();
+
+    assert_eq!(manager.pairing_count().unwrap(), 2);
+    assert!(manager.is_paired(&first).unwrap());
+    assert!(manager.is_paired(&second).unwrap());
+
+    assert!(manager.revoke(&first).unwrap());
+    assert!(!manager.is_paired(&first).unwrap());
+    assert!(manager.is_paired(&second).unwrap());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn single_pairing_file_is_migrated() {
+    let dir = scratch_dir("legacy");
+    let phone = Keys::generate().public_key();
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("android_pairing.json"),
+        format!(r#"{{"android_pubkey":"{}","relays":[]}}"#, phone.to_hex()),
+    )
+    .unwrap();
+
+    let manager = PairingManager::new(&dir).unwrap();
+    assert!(manager.is_paired(&phone).unwrap());
+
+    let other = Keys::generate().public_key();
+    manager.add_pairing(other, Vec::new(), None).unwrap();
+    assert_eq!(manager.list_pairings().unwrap().len(), 2);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn pairing_expires_after_ttl() {
+    let dir = scratch_dir("expiry");
+    let manager = PairingManager::new(&dir)
+        .unwrap()
+        .with_pairing_ttl(Some(Duration::from_secs(30 * 24 * 60 * 60)));
+    let phone = Keys::generate().public_key();
+
+    manager.add_pairing(phone, Vec::new(), None).unwrap();
+
+    let now = Utc::now();
+    assert!(manager.is_active(&phone, now).unwrap());
+    assert!(manager.is_active(&phone, now + chrono::Duration::days(29)).unwrap());
+    assert!(!manager.is_active(&phone, now + chrono::Duration::days(31)).unwrap());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn expired_pairings_are_not_listed_as_paired() {
+    let dir = scratch_dir("expired-listing");
+    let expired = Keys::generate().public_key();
+    let active = Keys::generate().public_key();
+    std::fs::create_dir_all(&dir).unwrap();
+    let stored = json!([
+        {
+            "android_pubkey": expired.to_hex(),
+            "relays": ["wss://expired.example"],
+            "expires_at": (Utc::now() - chrono::Duration::days(1)).to_rfc3339(),
+        },
+        { "android_pubkey": active.to_hex(), "relays": ["wss://active.example"] },
+    ]);
+    std::fs::write(dir.join("android_pairing.json"), stored.to_string()).unwrap();
+
+    let manager = PairingManager::new(&dir).unwrap();
+    assert_eq!(manager.paired_pubkeys().unwrap(), [active]);
+    assert_eq!(manager.get_all_known_relays().unwrap(), ["wss://active.example"]);
+    assert_eq!(manager.pairing_count().unwrap(), 1);
+
+    // Once the last active one is gone, nothing counts as paired
+    assert!(manager.remove_pairing(&active).unwrap());
+    assert!(!manager.has_pairing());
+    assert_eq!(manager.pairing_count().unwrap(), 0);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn pairing_without_ttl_never_expires() {
+    let dir = scratch_dir("no-expiry");
+    let manager = PairingManager::new(&dir).unwrap();
+    let phone = Keys::generate().public_key();
+
+    manager.add_pairing(phone, Vec::new(), None).unwrap();
+
+    let far_future = Utc::now() + chrono::Duration::days(10 * 365);
+    assert!(manager.is_active(&phone, far_future).unwrap());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn revoked_device_loses_access() {
+    let dir = scratch_dir("revoke");
+    let manager = PairingManager::new(&dir).unwrap();
+    let phone = Keys::generate().public_key();
+
+    manager.add_pairing(phone, Vec::new(), None).unwrap();
+    assert!(manager.revoke(&phone).unwrap());
+
+    assert!(!manager.is_active(&phone, Utc::now()).unwrap());
+    // Revoking twice is a no-op
+    assert!(!manager.revoke(&phone).unwrap());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn unpaired_request_is_refused_without_a_lookup() {
+    let electrs = FakeElectrs::start(false);
+    let client = Arc::new(ElectrsClient::new(electrs.addr.clone()).unwrap());
+    let mut harness = Harness::start("handler-unpaired", client).await;
+    let stranger = Keys::generate();
+
+    let request = json!({
+        "type": "bitcoin_lookup",
+        "query": "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+    });
+    harness.send(harness.request(&stranger, "r1", request)).await;
+
+    let responses = harness.responses();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].0, stranger.public_key());
+    assert_eq!(responses[0].1["error"]["code"], "not_paired");
+    assert_eq!(electrs.scripthash_calls(), 0);
+}
+
+#[tokio::test]
+async fn unpaired_device_is_refused_afterwards() {
+    let electrs = FakeElectrs::start(false);
+    let client = Arc::new(ElectrsClient::new(electrs.addr.clone()).unwrap());
+    let mut harness = Harness::start("handler-unpair", client).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    harness.send(harness.request(&phone, "r1", json!({ "type": "unpair" }))).await;
+    let lookup = json!({
+        "type": "bitcoin_lookup",
+        "query": "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+    });
+    harness.send(harness.request(&phone, "r2", lookup)).await;
+
+    let responses = harness.responses();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0].1["type"], "unpair_response");
+    assert_eq!(responses[0].1["status"], "ok");
+    assert_eq!(responses[1].1["req"], "r2");
+    assert_eq!(responses[1].1["error"]["code"], "not_paired");
+    assert!(!harness.pairing.is_paired(&phone.public_key()).unwrap());
+    assert_eq!(electrs.scripthash_calls(), 0);
+}
+
+#[test]
+fn concurrent_pairings_are_all_kept() {
+    let dir = scratch_dir("pairing-concurrent");
+    let phones: Vec<Keys> = (0..20).map(|_| Keys::generate()).collect();
+
+    // Two managers on the same directory, like the HTTP side and the handler
+    std::thread::scope(|scope| {
+        for half in phones.chunks(10) {
+            let manager = PairingManager::new(&dir).unwrap();
+            scope.spawn(move || {
+                for phone in half {
+                    manager.add_pairing(phone.public_key(), Vec::new(), None).unwrap();
+                }
+            });
+        }
+    });
+
+    let manager = PairingManager::new(&dir).unwrap();
+    assert_eq!(manager.pairing_count().unwrap(), phones.len());
+    for phone in &phones {
+        assert!(manager.is_paired(&phone.public_key()).unwrap());
+    }
+}
@@ -0,0 +1,44 @@
+//! Request version negotiation: newer clients are refused, older ones served
+
+use balancebridge_server::protocol::{
+    negotiate_version, ErrorResponse, LookupError, MAX_SUPPORTED_VERSION, MIN_SUPPORTED_VERSION,
+};
+
+#[test]
+fn missing_version_is_served() {
+    assert_eq!(negotiate_version(None).unwrap(), MIN_SUPPORTED_VERSION);
+}
+
+#[test]
+fn supported_versions_are_answered_as_sent() {
+    for v in MIN_SUPPORTED_VERSION..=MAX_SUPPORTED_VERSION {
+        assert_eq!(negotiate_version(Some(v)).unwrap(), v);
+    }
+}
+
+#[test]
+fn older_versions_degrade_to_the_oldest_supported() {
+    assert_eq!(negotiate_version(Some(0)).unwrap(), MIN_SUPPORTED_VERSION);
+}
+
+#[test]
+fn newer_version_is_unsupported() {
+    let err = negotiate_version(Some(MAX_SUPPORTED_VERSION + 1)).unwrap_err();
+    assert_eq!(err.code(), "unsupported_version");
+    assert!(!err.retryable());
+}
+
+#[test]
+fn error_carries_the_supported_range() {
+    let error = LookupError::UnsupportedVersion {
+        requested: MAX_SUPPORTED_VERSION + 1,
+    };
+    let json = serde_json::to_value(ErrorResponse::new("r1", error)).unwrap();
+
+    assert_eq!(json["error"]["code"], "unsupported_version");
+    assert_eq!(json["error"]["min_version"], MIN_SUPPORTED_VERSION);
+    assert_eq!(json["error"]["max_version"], MAX_SUPPORTED_VERSION);
+    let message = json["error"]["message"].as_str().unwrap();
+    assert!(message.contains(&format!("v{}..=v{}", MIN_SUPPORTED_VERSION, MAX_SUPPORTED_VERSION)));
+    assert!(message.contains("without `v`"));
+}
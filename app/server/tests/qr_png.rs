@@ -0,0 +1,35 @@
+//! Pairing QR as PNG (GET /qr.png)
+
+use balancebridge_server::qr::{PairingPayload, PairingQr, QrEncoding};
+use nostr_sdk::Keys;
+
+const PNG_MAGIC: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn payload() -> PairingPayload {
+    PairingPayload::new(
+        Keys::generate().public_key().to_hex(),
+        vec!["wss://nos.lol".to_string()],
+    )
+}
+
+#[test]
+fn qr_image_is_a_png() {
+    let png = payload().generate_qr_image(256).unwrap();
+    assert!(png.starts_with(&PNG_MAGIC));
+}
+
+#[test]
+fn signed_payload_renders_the_same_way() {
+    let keys = Keys::generate();
+    let signed = PairingPayload::new(keys.public_key().to_hex(), vec![])
+        .sign_with_keys(&keys)
+        .unwrap();
+    assert!(signed.generate_qr_image(256).unwrap().starts_with(&PNG_MAGIC));
+}
+
+#[test]
+fn served_pairing_qr_includes_the_png() {
+    let qr = PairingQr::build(&Keys::generate(), vec![], "challenge".to_string(), QrEncoding::Json)
+        .unwrap();
+    assert!(qr.png.starts_with(&PNG_MAGIC));
+}
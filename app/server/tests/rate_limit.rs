@@ -0,0 +1,117 @@
+//! Per-pubkey token bucket over all requests
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use balancebridge_server::electrs::mock::MockElectrsClient;
+use balancebridge_server::nostr_handler::RequestRateLimiter;
+use common::Harness;
+use nostr_sdk::Keys;
+use serde_json::json;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[test]
+fn eleventh_request_in_a_window_is_rejected() {
+    let limiter = RequestRateLimiter::new(10, WINDOW);
+    let phone = Keys::generate().public_key();
+    let start = Instant::now();
+
+    for i in 0..10 {
+        assert!(limiter.check_at(&phone, start + Duration::from_millis(i)), "request {}", i + 1);
+    }
+    assert!(!limiter.check_at(&phone, start + Duration::from_millis(10)));
+}
+
+#[test]
+fn allowed_again_after_the_window() {
+    let limiter = RequestRateLimiter::new(10, WINDOW);
+    let phone = Keys::generate().public_key();
+    let start = Instant::now();
+
+    for _ in 0..10 {
+        assert!(limiter.check_at(&phone, start));
+    }
+    assert!(!limiter.check_at(&phone, start + Duration::from_secs(1)));
+
+    // One token back every six seconds, all ten after a full window
+    assert!(limiter.check_at(&phone, start + Duration::from_secs(7)));
+    for _ in 0..10 {
+        assert!(limiter.check_at(&phone, start + Duration::from_secs(7) + WINDOW));
+    }
+}
+
+#[test]
+fn pubkeys_have_separate_buckets() {
+    let limiter = RequestRateLimiter::new(1, WINDOW);
+    let start = Instant::now();
+    let flooding = Keys::generate().public_key();
+    let other = Keys::generate().public_key();
+
+    assert!(limiter.check_at(&flooding, start));
+    assert!(!limiter.check_at(&flooding, start));
+    assert!(limiter.check_at(&other, start));
+}
+
+#[test]
+fn idle_buckets_are_cleaned_up() {
+    let limiter = RequestRateLimiter::new(10, WINDOW);
+    let start = Instant::now();
+    let idle = Keys::generate().public_key();
+    let active = Keys::generate().public_key();
+
+    limiter.check_at(&idle, start);
+    limiter.check_at(&active, start + WINDOW / 2);
+    assert_eq!(limiter.tracked(), 2);
+
+    limiter.cleanup(start + WINDOW);
+    assert_eq!(limiter.tracked(), 1);
+}
+
+#[tokio::test]
+async fn xpub_addresses_keep_their_own_budget() {
+    // Account key of the "abandon … about" test mnemonic (m/84h/0h/0h)
+    const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    let electrs = MockElectrsClient::new(HashMap::new(), HashMap::new());
+    let mut harness = Harness::start("rate-xpub-addresses", Arc::new(electrs)).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    // Past the shared limit of 10, within xpub_addresses' own 20
+    for i in 0..15 {
+        let request = json!({ "type": "xpub_addresses", "query": BIP84_XPUB });
+        harness.send(harness.request(&phone, &format!("r{}", i), request)).await;
+    }
+
+    let responses = harness.responses();
+    assert_eq!(responses.len(), 15);
+    assert!(responses.iter().all(|(_, json)| json.get("error").is_none()));
+}
+
+#[tokio::test]
+async fn retry_after_rate_limited_is_answered() {
+    const ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+    let electrs = MockElectrsClient::new(HashMap::new(), HashMap::new());
+    let mut harness = Harness::start("rate-retry", Arc::new(electrs)).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    let lookup = || json!({ "type": "bitcoin_lookup", "query": ADDRESS });
+    for i in 0..10 {
+        harness.send(harness.request(&phone, &format!("r{}", i), lookup())).await;
+    }
+    let limited = harness.request(&phone, "r10", lookup());
+    harness.send(limited.clone()).await;
+    // The retry carries the same req id; it must not be dropped as a redelivery
+    harness.send(limited).await;
+
+    let responses = harness.responses();
+    assert_eq!(responses.len(), 12);
+    for (_, json) in &responses[10..] {
+        assert_eq!(json["req"], "r10");
+        assert_eq!(json["error"]["code"], "rate_limited");
+    }
+}
@@ -0,0 +1,58 @@
+//! POST /relays: the relay list is validated as a whole and persisted
+
+mod common;
+
+use balancebridge_server::relays::{validate_relay_url, PersistentRelayList};
+use common::scratch_dir;
+
+fn fallback() -> Vec<String> {
+    vec!["wss://relay.damus.io".to_string()]
+}
+
+#[test]
+fn only_websocket_urls_are_relays() {
+    assert!(validate_relay_url("wss://nos.lol").is_ok());
+    assert!(validate_relay_url("ws://umbrel.local:4848").is_ok());
+
+    for url in ["https://nos.lol", "nos.lol", "wss://", ""] {
+        assert!(validate_relay_url(url).is_err(), "{}", url);
+    }
+}
+
+#[test]
+fn replaced_list_survives_a_restart() {
+    let dir = scratch_dir("relays-replace");
+    let list = PersistentRelayList::new(&dir, fallback());
+
+    let stored = list
+        .replace(&["wss://nos.lol".to_string(), "wss://relay.primal.net".to_string()])
+        .unwrap();
+    assert_eq!(stored.len(), 2);
+
+    let reloaded = PersistentRelayList::new(&dir, fallback());
+    assert_eq!(reloaded.load(), stored);
+}
+
+#[test]
+fn one_bad_url_rejects_the_whole_list() {
+    let dir = scratch_dir("relays-reject");
+    let list = PersistentRelayList::new(&dir, fallback());
+
+    assert!(list
+        .replace(&["wss://nos.lol".to_string(), "https://example.com".to_string()])
+        .is_err());
+    assert_eq!(list.load(), fallback());
+}
+
+#[test]
+fn empty_list_is_rejected_and_duplicates_dropped() {
+    let dir = scratch_dir("relays-dedupe");
+    let list = PersistentRelayList::new(&dir, fallback());
+
+    assert!(list.replace(&[]).is_err());
+
+    let stored = list
+        .replace(&["wss://nos.lol".to_string(), " wss://nos.lol ".to_string()])
+        .unwrap();
+    assert_eq!(stored.len(), 1);
+}
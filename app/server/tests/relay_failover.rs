@@ -0,0 +1,116 @@
+//! Relay failover: relays unreachable for the drop-after period leave the
+//! pool and come back once they answer again
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use balancebridge_server::relays::{RelayHealthMonitor, RelayTransition};
+use common::scratch_dir;
+
+const DROP_AFTER: Duration = Duration::from_secs(300);
+const DAMUS: &str = "wss://relay.damus.io";
+const NOS: &str = "wss://nos.lol";
+
+fn failover_with_two_relays(start: Instant) -> RelayHealthMonitor {
+    let failover = RelayHealthMonitor::new(scratch_dir("failover")).with_drop_after(DROP_AFTER);
+    failover.observe(DAMUS, true, start);
+    failover.observe(NOS, true, start);
+    failover
+}
+
+#[test]
+fn dropped_after_the_whole_period_down() {
+    let start = Instant::now();
+    let failover = failover_with_two_relays(start);
+
+    assert_eq!(failover.observe(DAMUS, false, start), RelayTransition::WentDown);
+    assert_eq!(
+        failover.observe(DAMUS, false, start + DROP_AFTER - Duration::from_secs(1)),
+        RelayTransition::Unchanged
+    );
+    assert_eq!(failover.observe(DAMUS, false, start + DROP_AFTER), RelayTransition::Drop);
+    // Only dropped once
+    assert_eq!(
+        failover.observe(DAMUS, false, start + 2 * DROP_AFTER),
+        RelayTransition::Unchanged
+    );
+    assert_eq!(failover.dropped(), [DAMUS]);
+}
+
+#[test]
+fn recovered_relay_is_readded() {
+    let start = Instant::now();
+    let failover = failover_with_two_relays(start);
+
+    failover.observe(DAMUS, false, start);
+    failover.observe(DAMUS, false, start + DROP_AFTER);
+    assert_eq!(
+        failover.observe(DAMUS, true, start + 2 * DROP_AFTER),
+        RelayTransition::Readd
+    );
+    assert!(failover.dropped().is_empty());
+}
+
+#[test]
+fn short_outage_is_only_logged() {
+    let start = Instant::now();
+    let failover = failover_with_two_relays(start);
+
+    assert_eq!(failover.observe(NOS, false, start), RelayTransition::WentDown);
+    assert_eq!(
+        failover.observe(NOS, true, start + Duration::from_secs(30)),
+        RelayTransition::CameUp
+    );
+}
+
+#[test]
+fn last_relay_is_never_dropped() {
+    let start = Instant::now();
+    let failover = failover_with_two_relays(start);
+
+    failover.observe(DAMUS, false, start);
+    failover.observe(NOS, false, start);
+    assert_eq!(failover.observe(DAMUS, false, start + DROP_AFTER), RelayTransition::Drop);
+    assert_eq!(
+        failover.observe(NOS, false, start + DROP_AFTER),
+        RelayTransition::Unchanged
+    );
+}
+
+#[test]
+fn health_lists_every_relay() {
+    let start = Instant::now();
+    let failover = failover_with_two_relays(start);
+    failover.observe(NOS, false, start);
+
+    let json = serde_json::to_value(failover.snapshot()).unwrap();
+    assert_eq!(json[0]["url"], NOS);
+    assert_eq!(json[0]["connected"], false);
+    assert!(json[0]["down_since"].is_string());
+    assert_eq!(json[1]["url"], DAMUS);
+    assert_eq!(json[1]["connected"], true);
+    assert!(json[1].get("down_since").is_none());
+}
+
+#[test]
+fn forgotten_relay_is_not_tracked() {
+    let start = Instant::now();
+    let failover = failover_with_two_relays(start);
+
+    failover.forget(DAMUS);
+    assert_eq!(failover.snapshot().len(), 1);
+}
+
+#[test]
+fn health_carries_the_probe_scores() {
+    let start = Instant::now();
+    let failover = failover_with_two_relays(start);
+    failover.record(DAMUS, Some(Duration::from_millis(80)));
+
+    let json = serde_json::to_value(failover.snapshot()).unwrap();
+    assert_eq!(json[1]["url"], DAMUS);
+    assert_eq!(json[1]["success_rate"], 1.0);
+    assert_eq!(json[1]["avg_latency_ms"], 80.0);
+    assert!(json[0].get("success_rate").is_none());
+}
@@ -0,0 +1,88 @@
+//! The same request delivered by several relays is only handled once
+
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use balancebridge_server::electrs::ElectrsClient;
+use balancebridge_server::nostr_handler::SeenRequests;
+use common::{FakeAddress, FakeElectrs, Harness};
+use nostr_sdk::Keys;
+use serde_json::json;
+
+const TTL: Duration = Duration::from_secs(600);
+
+#[test]
+fn second_delivery_is_skipped() {
+    let seen = SeenRequests::new(TTL);
+    let phone = Keys::generate().public_key();
+    let now = Instant::now();
+
+    let handled = (0..3)
+        .filter(|_| seen.first_seen(&phone, "req-1", now))
+        .count();
+    assert_eq!(handled, 1);
+}
+
+#[test]
+fn req_ids_are_per_sender() {
+    let seen = SeenRequests::new(TTL);
+    let now = Instant::now();
+    let phone = Keys::generate().public_key();
+    let tablet = Keys::generate().public_key();
+
+    assert!(seen.first_seen(&phone, "req-1", now));
+    assert!(seen.first_seen(&phone, "req-2", now));
+    assert!(seen.first_seen(&tablet, "req-1", now));
+}
+
+#[test]
+fn remembered_only_for_the_ttl() {
+    let seen = SeenRequests::new(TTL);
+    let phone = Keys::generate().public_key();
+    let start = Instant::now();
+
+    assert!(seen.first_seen(&phone, "req-1", start));
+    assert!(!seen.first_seen(&phone, "req-1", start + TTL - Duration::from_secs(1)));
+    assert!(seen.first_seen(&phone, "req-1", start + TTL));
+}
+
+#[test]
+fn forgotten_request_runs_again() {
+    let seen = SeenRequests::new(TTL);
+    let phone = Keys::generate().public_key();
+    let now = Instant::now();
+
+    assert!(seen.first_seen(&phone, "req-1", now));
+    seen.forget(&phone, "req-1");
+    assert!(seen.first_seen(&phone, "req-1", now));
+}
+
+#[tokio::test]
+async fn redelivered_event_is_looked_up_once() {
+    const FUNDED: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+    const TX_A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    let electrs = FakeElectrs::with_addresses(vec![FakeAddress {
+        address: FUNDED,
+        history: json!([{ "tx_hash": TX_A, "height": 0 }]),
+        utxos: json!([{ "tx_hash": TX_A, "tx_pos": 0, "height": 0, "value": 9_000 }]),
+    }]);
+    let client = Arc::new(ElectrsClient::new(electrs.addr.clone()).unwrap());
+    let mut harness = Harness::start("handler-dedup", client).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    let event = harness.request(&phone, "r1", json!({ "type": "bitcoin_lookup", "query": FUNDED }));
+    harness.send(event.clone()).await;
+    let calls = electrs.scripthash_calls();
+    // The same event again, as a second relay would deliver it
+    harness.send(event).await;
+
+    assert!(calls > 0);
+    assert_eq!(electrs.scripthash_calls(), calls);
+    assert_eq!(electrs.listunspent_calls(), 1);
+    let responses = harness.responses();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].1["unconfirmed_balance"], 9_000);
+}
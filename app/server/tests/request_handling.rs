@@ -0,0 +1,86 @@
+//! Requests fed through NostrHandler end to end, answered by MockElectrsClient
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use balancebridge_server::electrs::mock::MockElectrsClient;
+use balancebridge_server::history::BalanceHistoryStore;
+use common::Harness;
+use nostr_sdk::Keys;
+use serde_json::json;
+
+const FUNDED: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+const TX_A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+fn electrs() -> Arc<MockElectrsClient> {
+    Arc::new(MockElectrsClient::new(
+        HashMap::from([(FUNDED.to_string(), (50_000, 2_500))]),
+        HashMap::from([(FUNDED.to_string(), vec![TX_A.to_string()])]),
+    ))
+}
+
+#[tokio::test]
+async fn paired_lookup_is_answered() {
+    let mut harness = Harness::start("handler-lookup", electrs()).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    let request = json!({ "type": "bitcoin_lookup", "query": FUNDED });
+    harness.send(harness.request(&phone, "r1", request)).await;
+
+    let responses = harness.responses();
+    assert_eq!(responses.len(), 1);
+    let (to, json) = &responses[0];
+    assert_eq!(*to, phone.public_key());
+    assert_eq!(json["req"], "r1");
+    assert_eq!(json["confirmed_balance"], 50_000);
+    assert_eq!(json["unconfirmed_balance"], 2_500);
+    assert_eq!(json["transactions"][0]["txid"], TX_A);
+}
+
+#[tokio::test]
+async fn balance_snapshot_records_the_chain_tip() {
+    let mut harness = Harness::start("handler-snapshot", electrs()).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    let request = json!({ "type": "bitcoin_lookup", "query": FUNDED });
+    harness.send(harness.request(&phone, "r1", request)).await;
+    assert_eq!(harness.responses().len(), 1);
+
+    let history = BalanceHistoryStore::new(&harness.dir).unwrap();
+    let snapshots = history.get_history(FUNDED, 1).unwrap();
+    assert_eq!(snapshots[0].confirmed_sat, 50_000);
+    // The mock's default tip
+    assert_eq!(snapshots[0].block_height, Some(840_000));
+}
+
+#[tokio::test]
+async fn garbage_content_gets_invalid_json() {
+    let mut harness = Harness::start("handler-garbage", electrs()).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    harness.send(harness.request(&phone, "r1", json!("not a request"))).await;
+
+    let responses = harness.responses();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].1["error"]["code"], "invalid_json");
+}
+
+#[tokio::test]
+async fn bulk_balance_with_a_testnet_address_is_wrong_network() {
+    let mut harness = Harness::start("handler-bulk-network", electrs()).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+
+    // BIP173 testnet vector, on a mainnet node
+    let addresses = [FUNDED, "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"];
+    let request = json!({ "type": "bulk_balance", "addresses": addresses });
+    harness.send(harness.request(&phone, "r1", request)).await;
+
+    let responses = harness.responses();
+    assert_eq!(responses[0].1["error"]["code"], "wrong_network");
+}
@@ -0,0 +1,108 @@
+//! A device paired with a shared secret must HMAC every request
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use balancebridge_server::electrs::mock::MockElectrsClient;
+use balancebridge_server::nostr_handler::{encrypt_content, BALANCEBRIDGE_REQUEST_KIND};
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use common::Harness;
+use nostr_sdk::{Event, EventBuilder, Keys, Kind, Tag, Timestamp};
+use serde_json::json;
+
+const SECRET: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+const ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+/// Pair `phone` through a real "pair" request carrying `SECRET`
+async fn paired_with_secret(name: &str, phone: &Keys) -> Harness {
+    let electrs = MockElectrsClient::new(HashMap::new(), HashMap::new());
+    let mut harness = Harness::start(name, Arc::new(electrs)).await;
+
+    let challenge = harness.pairing.generate_challenge();
+    let pair = json!({ "type": "pair", "challenge": challenge, "shared_secret": SECRET });
+    harness.send(harness.request(phone, "pair-1", pair)).await;
+
+    let responses = harness.responses();
+    assert_eq!(responses[0].1["status"], "ok");
+    assert_eq!(
+        harness.pairing.get_shared_secret(&phone.public_key()).unwrap().as_deref(),
+        Some(SECRET)
+    );
+    harness
+}
+
+/// A lookup of `ADDRESS`, with an `["hmac", …]` tag keyed by `key` if given
+fn lookup(harness: &Harness, phone: &Keys, req_id: &str, key: Option<&str>) -> Event {
+    let created_at = Timestamp::now();
+    let body = json!({ "type": "bitcoin_lookup", "query": ADDRESS }).to_string();
+    let server = harness.server.public_key();
+
+    let mut tags = vec![
+        Tag::parse(["p", server.to_hex().as_str()]).unwrap(),
+        Tag::parse(["req", req_id]).unwrap(),
+    ];
+    if let Some(key) = key {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&hex::decode(key).unwrap());
+        engine.input(format!("{}:{}:{}", req_id, created_at.as_u64(), ADDRESS).as_bytes());
+        let mac = hmac::Hmac::<sha256::Hash>::from_engine(engine);
+        tags.push(Tag::parse(["hmac", hex::encode(mac.as_byte_array()).as_str()]).unwrap());
+    }
+
+    EventBuilder::new(
+        Kind::Custom(BALANCEBRIDGE_REQUEST_KIND),
+        encrypt_content(phone, &server, &body).unwrap(),
+    )
+    .tags(tags)
+    .custom_created_at(created_at)
+    .sign_with_keys(phone)
+    .unwrap()
+}
+
+#[tokio::test]
+async fn request_without_hmac_is_rejected() {
+    let phone = Keys::generate();
+    let mut harness = paired_with_secret("hmac-missing", &phone).await;
+
+    harness.send(lookup(&harness, &phone, "r1", None)).await;
+
+    assert!(harness.responses().is_empty());
+}
+
+#[tokio::test]
+async fn request_with_wrong_key_is_rejected() {
+    let phone = Keys::generate();
+    let mut harness = paired_with_secret("hmac-wrong", &phone).await;
+    let other_key = "02".repeat(32);
+
+    harness.send(lookup(&harness, &phone, "r1", Some(&other_key))).await;
+
+    assert!(harness.responses().is_empty());
+}
+
+#[tokio::test]
+async fn request_with_valid_hmac_is_answered() {
+    let phone = Keys::generate();
+    let mut harness = paired_with_secret("hmac-valid", &phone).await;
+
+    harness.send(lookup(&harness, &phone, "r1", Some(SECRET))).await;
+
+    let responses = harness.responses();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].1["req"], "r1");
+}
+
+#[tokio::test]
+async fn malformed_secret_fails_pairing() {
+    let electrs = MockElectrsClient::new(HashMap::new(), HashMap::new());
+    let mut harness = Harness::start("hmac-malformed", Arc::new(electrs)).await;
+    let phone = Keys::generate();
+
+    let challenge = harness.pairing.generate_challenge();
+    let pair = json!({ "type": "pair", "challenge": challenge, "shared_secret": "abcd" });
+    harness.send(harness.request(&phone, "pair-1", pair)).await;
+
+    assert_eq!(harness.responses()[0].1["error"]["code"], "invalid_query");
+    assert!(!harness.pairing.is_paired(&phone.public_key()).unwrap());
+}
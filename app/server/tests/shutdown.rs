@@ -0,0 +1,68 @@
+//! Shutdown plumbing, driven by triggering the coordinator by hand
+
+use std::time::Duration;
+
+use axum::routing::get;
+use axum::Router;
+use balancebridge_server::shutdown::ShutdownCoordinator;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+const SOON: Duration = Duration::from_secs(2);
+
+#[tokio::test]
+async fn trigger_wakes_every_waiter() {
+    let coordinator = ShutdownCoordinator::new();
+    let waiter = tokio::spawn(coordinator.wait());
+    let token = coordinator.token();
+
+    coordinator.trigger();
+
+    timeout(SOON, waiter).await.unwrap().unwrap();
+    assert!(token.is_cancelled());
+    // Late waiters don't hang either
+    timeout(SOON, coordinator.wait()).await.unwrap();
+}
+
+#[tokio::test]
+async fn http_server_stops_on_trigger() {
+    let coordinator = ShutdownCoordinator::new();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let app = Router::new().route("/", get(|| async { "ok" }));
+    let stopped = coordinator.wait();
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(stopped)
+            .await
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!server.is_finished());
+
+    coordinator.trigger();
+    timeout(SOON, server).await.unwrap().unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn in_flight_requests_are_waited_for() {
+    let coordinator = ShutdownCoordinator::new();
+    let guard = coordinator.track();
+    assert_eq!(coordinator.in_flight(), 1);
+
+    coordinator.trigger();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        drop(guard);
+    });
+
+    assert!(coordinator.wait_for_in_flight(SOON).await);
+    assert_eq!(coordinator.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn stuck_request_times_out() {
+    let coordinator = ShutdownCoordinator::new();
+    let _stuck = coordinator.track();
+
+    assert!(!coordinator.wait_for_in_flight(Duration::from_millis(300)).await);
+}
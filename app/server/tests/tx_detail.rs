@@ -0,0 +1,83 @@
+//! `tx_detail`: fee and net amount of a transaction for one address
+
+use std::collections::HashMap;
+
+use balancebridge_server::electrs::{address_script, tx_amounts};
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::Hash;
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+
+const A: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+const B: &str = "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el";
+
+fn tx(inputs: Vec<OutPoint>, outputs: Vec<(&str, u64)>) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .into_iter()
+            .map(|previous_output| TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs
+            .into_iter()
+            .map(|(address, sats)| TxOut {
+                value: Amount::from_sat(sats),
+                script_pubkey: address_script(address).unwrap(),
+            })
+            .collect(),
+    }
+}
+
+/// A funds a payment of 30,000 to B, with 19,000 change back to A
+fn payment() -> (Transaction, HashMap<bitcoin::Txid, Transaction>) {
+    let earlier = OutPoint::new(bitcoin::Txid::from_byte_array([7; 32]), 0);
+    let funding = tx(vec![earlier], vec![(A, 50_000)]);
+    let payment = tx(
+        vec![OutPoint::new(funding.compute_txid(), 0)],
+        vec![(B, 30_000), (A, 19_000)],
+    );
+    let prevouts = HashMap::from([(funding.compute_txid(), funding)]);
+    (payment, prevouts)
+}
+
+#[test]
+fn fee_is_inputs_minus_outputs() {
+    let (payment, prevouts) = payment();
+    assert_eq!(tx_amounts(&payment, &prevouts, None).fee_sats, Some(1_000));
+}
+
+#[test]
+fn net_amount_is_signed_per_address() {
+    let (payment, prevouts) = payment();
+    let a = address_script(A).unwrap();
+    let b = address_script(B).unwrap();
+
+    assert_eq!(tx_amounts(&payment, &prevouts, Some(&a)).net_amount_sats, Some(-31_000));
+    assert_eq!(tx_amounts(&payment, &prevouts, Some(&b)).net_amount_sats, Some(30_000));
+}
+
+#[test]
+fn unknown_spent_outputs_leave_amounts_empty() {
+    let (payment, _) = payment();
+    let a = address_script(A).unwrap();
+
+    let amounts = tx_amounts(&payment, &HashMap::new(), Some(&a));
+    assert_eq!(amounts.fee_sats, None);
+    assert_eq!(amounts.net_amount_sats, None);
+}
+
+#[test]
+fn coinbase_has_no_fee() {
+    let coinbase = tx(vec![OutPoint::null()], vec![(A, 312_500_000)]);
+    let a = address_script(A).unwrap();
+
+    let amounts = tx_amounts(&coinbase, &HashMap::new(), Some(&a));
+    assert_eq!(amounts.fee_sats, None);
+    assert_eq!(amounts.net_amount_sats, Some(312_500_000));
+}
@@ -0,0 +1,40 @@
+//! preload_scripts: history-only warm-up of known addresses
+
+mod common;
+
+use std::time::Duration;
+
+use balancebridge_server::electrs::ElectrsClient;
+use common::{FakeAddress, FakeElectrs};
+use serde_json::json;
+
+const USED: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+const FRESH: &str = "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA";
+const TX_A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+#[test]
+fn counts_active_addresses_without_listing_utxos() {
+    let server = FakeElectrs::with_addresses(vec![FakeAddress {
+        address: USED,
+        history: json!([{ "tx_hash": TX_A, "height": 800_000 }]),
+        utxos: json!([{ "tx_hash": TX_A, "tx_pos": 0, "height": 800_000, "value": 1_000 }]),
+    }]);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+    let addresses = vec![USED.to_string(), FRESH.to_string()];
+
+    let active = client.preload_scripts(&addresses, Duration::from_secs(30)).unwrap();
+
+    assert_eq!(active, 1);
+    assert_eq!(server.scripthash_calls(), 2);
+    assert_eq!(server.listunspent_calls(), 0);
+}
+
+#[test]
+fn fails_only_if_every_address_failed() {
+    let server = FakeElectrs::start(false);
+    let client = ElectrsClient::new(server.addr.clone()).unwrap();
+
+    assert!(client.preload_scripts(&["not an address".to_string()], Duration::from_secs(30)).is_err());
+    let mixed = vec!["not an address".to_string(), FRESH.to_string()];
+    assert_eq!(client.preload_scripts(&mixed, Duration::from_secs(30)).unwrap(), 0);
+}
@@ -0,0 +1,150 @@
+//! Address type follows the key prefix. Vectors: account keys of the
+//! "abandon … about" mnemonic from BIP44/49/84/86.
+
+use balancebridge_server::electrs::address_script;
+use balancebridge_server::xpub::{
+    derive_addresses, derive_addresses_for_standard, derive_chain_range, normalize_to_xpub,
+    prefix_address_type, AddressType, DerivationStandard, XpubQuery,
+};
+use bitcoin::Network;
+
+const BIP44_XPUB: &str = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+const BIP49_YPUB: &str = "ypub6Ww3ibxVfGzLrAH1PNcjyAWenMTbbAosGNB6VvmSEgytSER9azLDWCxoJwW7Ke7icmizBMXrzBx9979FfaHxHcrArf3zbeJJJUZPf663zsP";
+const BIP84_ZPUB: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+const BIP86_XPUB: &str = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+
+/// Address and script_pubkey (hex) of `chain/index`
+fn derive(xpub: &str, address_type: AddressType, chain: u32, index: u32) -> (String, String) {
+    let address = derive_chain_range(xpub, Network::Bitcoin, address_type, chain, index, 1)
+        .unwrap()
+        .remove(0)
+        .address;
+    let script = address_script(&address).unwrap().to_hex_string();
+    (address, script)
+}
+
+#[test]
+fn xpub_derives_p2pkh() {
+    assert_eq!(prefix_address_type(BIP44_XPUB).unwrap(), AddressType::P2PKH);
+    assert_eq!(
+        derive_addresses(BIP44_XPUB, Network::Bitcoin, 1).unwrap(),
+        ["1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA", "1J3J6EvPrv8q6AC3VCjWV45Uf3nssNMRtH"]
+    );
+}
+
+#[test]
+fn ypub_derives_p2sh_p2wpkh() {
+    assert_eq!(prefix_address_type(BIP49_YPUB).unwrap(), AddressType::P2shSegwit);
+    assert_eq!(
+        derive_addresses(BIP49_YPUB, Network::Bitcoin, 1).unwrap(),
+        ["37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf", "34K56kSjgUCUSD8GTtuF7c9Zzwokbs6uZ7"]
+    );
+}
+
+#[test]
+fn zpub_derives_p2wpkh() {
+    assert_eq!(prefix_address_type(BIP84_ZPUB).unwrap(), AddressType::NativeSegwit);
+    assert_eq!(
+        derive_addresses(BIP84_ZPUB, Network::Bitcoin, 1).unwrap(),
+        [
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+            "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el"
+        ]
+    );
+}
+
+#[test]
+fn p2wpkh_vectors_and_scripts() {
+    assert_eq!(
+        derive(BIP84_XPUB, AddressType::NativeSegwit, 0, 0),
+        (
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu".to_string(),
+            "0014c0cebcd6c3d3ca8c75dc5ec62ebe55330ef910e2".to_string()
+        )
+    );
+    assert_eq!(
+        derive(BIP84_XPUB, AddressType::NativeSegwit, 1, 0),
+        (
+            "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el".to_string(),
+            "00143e34985dca6fddc9fb369940e4c7d8e2873f529c".to_string()
+        )
+    );
+}
+
+#[test]
+fn p2tr_vectors_and_scripts() {
+    assert_eq!(
+        derive(BIP86_XPUB, AddressType::Taproot, 0, 0),
+        (
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr".to_string(),
+            "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c".to_string()
+        )
+    );
+    assert_eq!(
+        derive(BIP86_XPUB, AddressType::Taproot, 0, 1),
+        (
+            "bc1p4qhjn9zdvkux4e44uhx8tc55attvtyu358kutcqkudyccelu0was9fqzwh".to_string(),
+            "5120a82f29944d65b86ae6b5e5cc75e294ead6c59391a1edc5e016e3498c67fc7bbb".to_string()
+        )
+    );
+    assert_eq!(
+        derive(BIP86_XPUB, AddressType::Taproot, 1, 0),
+        (
+            "bc1p3qkhfews2uk44qtvauqyr2ttdsw7svhkl9nkm9s9c3x4ax5h60wqwruhk7".to_string(),
+            "5120882d74e5d0572d5a816cef0041a96b6c1de832f6f9676d9605c44d5e9a97d3dc".to_string()
+        )
+    );
+}
+
+#[test]
+fn ypub_and_zpub_normalize_to_their_xpub() {
+    let xpub = normalize_to_xpub(BIP49_YPUB).unwrap();
+    assert_eq!(
+        xpub,
+        "xpub6C6nQwHaWbSrzs5tZ1q7m5R9cPK9eYpNMFesiXsYrgc1P8bvLLAet9JfHjYXKjToD8cBRswJXXbbFpXgwsswVPAZzKMa1jUp2kVkGVUaJa7"
+    );
+    // Same keys: with the address type passed in, the addresses don't change
+    assert_eq!(
+        vec![
+            derive(&xpub, AddressType::P2shSegwit, 0, 0).0,
+            derive(&xpub, AddressType::P2shSegwit, 1, 0).0,
+        ],
+        derive_addresses(BIP49_YPUB, Network::Bitcoin, 1).unwrap()
+    );
+
+    assert_eq!(normalize_to_xpub(BIP84_ZPUB).unwrap(), BIP84_XPUB);
+    assert_eq!(normalize_to_xpub(BIP84_XPUB).unwrap(), BIP84_XPUB);
+}
+
+#[test]
+fn standard_picks_the_address_type() {
+    let query = |xpub: &str, standard, account| XpubQuery {
+        xpub: xpub.to_string(),
+        standard,
+        account,
+    };
+
+    // BIP84: m/0/0 then m/1/0, both P2WPKH
+    let bip84 = query(BIP84_XPUB, DerivationStandard::Bip84, 0);
+    let addresses = derive_addresses_for_standard(&bip84, Network::Bitcoin, 1).unwrap();
+    assert_eq!(
+        addresses,
+        vec![
+            derive(BIP84_XPUB, AddressType::NativeSegwit, 0, 0).0,
+            derive(BIP84_XPUB, AddressType::NativeSegwit, 1, 0).0,
+        ]
+        .into_iter()
+        .map(|a| (a, AddressType::NativeSegwit))
+        .collect::<Vec<_>>()
+    );
+
+    let bip86 = query(BIP86_XPUB, DerivationStandard::Bip86, 0);
+    let addresses = derive_addresses_for_standard(&bip86, Network::Bitcoin, 2).unwrap();
+    assert_eq!(addresses.len(), 4);
+    assert!(addresses.iter().all(|(a, t)| a.starts_with("bc1p") && *t == AddressType::Taproot));
+
+    // The account-0 key can't be account 1
+    let wrong_account = query(BIP84_XPUB, DerivationStandard::Bip84, 1);
+    assert!(derive_addresses_for_standard(&wrong_account, Network::Bitcoin, 1).is_err());
+}
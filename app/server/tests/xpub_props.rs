@@ -0,0 +1,43 @@
+//! Property tests for the xpub/address helpers: arbitrary input must never
+//! panic, and valid keys must always derive both chains.
+
+use balancebridge_server::xpub::{derive_addresses, is_bitcoin_address, is_xpub};
+use bitcoin::bip32::{Xpriv, Xpub};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::Network;
+use proptest::prelude::*;
+
+/// Mainnet xpub of a random master seed
+fn valid_xpub() -> impl Strategy<Value = String> {
+    prop::array::uniform32(any::<u8>()).prop_map(|seed| {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Bitcoin, &seed).expect("32-byte seed");
+        Xpub::from_priv(&secp, &xpriv).to_string()
+    })
+}
+
+proptest! {
+    #[test]
+    fn is_xpub_never_panics(s in any::<String>()) {
+        let _ = is_xpub(&s);
+    }
+
+    #[test]
+    fn is_bitcoin_address_never_panics(s in any::<String>()) {
+        let _ = is_bitcoin_address(&s, Network::Bitcoin);
+    }
+
+    #[test]
+    fn valid_xpub_derives_both_chains(xpub in valid_xpub()) {
+        prop_assert!(is_xpub(&xpub));
+        let addresses = derive_addresses(&xpub, Network::Bitcoin, 1).unwrap();
+        prop_assert!(addresses.len() >= 2);
+    }
+
+    #[test]
+    fn xpub_lookalike_is_rejected(s in "(xpub|ypub|zpub|tpub)[1-9A-HJ-NP-Za-km-z]{0,120}") {
+        // A random string passing base58check is a 1-in-2^32 event
+        prop_assert!(!is_xpub(&s));
+        prop_assert!(derive_addresses(&s, Network::Bitcoin, 1).is_err());
+    }
+}
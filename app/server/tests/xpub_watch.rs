@@ -0,0 +1,108 @@
+//! Watched xpubs: the per-device limit, removal by request, and cleanup when a
+//! device goes away
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use balancebridge_server::electrs::mock::MockElectrsClient;
+use balancebridge_server::electrs::ElectrsClient;
+use balancebridge_server::watch::{WatchAdd, XpubWatchList, MAX_WATCHED_XPUBS_PER_DEVICE};
+use common::{scratch_dir, FakeElectrs, Harness};
+use nostr_sdk::Keys;
+use serde_json::json;
+
+// Account key of the "abandon … about" test mnemonic (m/84h/0h/0h)
+const XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+const OTHER_XPUB: &str = "xpub-other";
+
+async fn harness(name: &str) -> Harness {
+    let electrs = MockElectrsClient::new(HashMap::new(), HashMap::new());
+    Harness::start(name, Arc::new(electrs)).await
+}
+
+#[test]
+fn remove_only_touches_the_given_entry() {
+    let dir = scratch_dir("watch-remove");
+    std::fs::create_dir_all(&dir).unwrap();
+    let list = XpubWatchList::new(&dir).unwrap();
+
+    assert_eq!(list.add("phone", XPUB, "w1", None).unwrap(), WatchAdd::Added);
+    assert_eq!(list.add("phone", OTHER_XPUB, "w2", None).unwrap(), WatchAdd::Added);
+    assert_eq!(list.add("tablet", XPUB, "w3", None).unwrap(), WatchAdd::Added);
+
+    assert!(list.remove("phone", XPUB).unwrap());
+    assert!(!list.remove("phone", XPUB).unwrap());
+    assert_eq!(list.count_for("phone"), 1);
+    assert_eq!(list.count_for("tablet"), 1);
+
+    assert_eq!(list.remove_owner("phone").unwrap(), 1);
+    assert_eq!(list.remove_owner("phone").unwrap(), 0);
+    // Persisted: a fresh load sees the same list
+    let reloaded = XpubWatchList::new(&dir).unwrap();
+    assert_eq!(reloaded.count_for("phone"), 0);
+    assert_eq!(reloaded.count_for("tablet"), 1);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn unwatch_request_removes_the_xpub() {
+    let mut harness = harness("watch-unwatch").await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+    let owner = phone.public_key().to_hex();
+    harness.watch.add(&owner, XPUB, "w1", None).unwrap();
+
+    let unwatch = json!({ "type": "unwatch_xpub", "query": XPUB });
+    harness.send(harness.request(&phone, "r1", unwatch.clone())).await;
+    harness.send(harness.request(&phone, "r2", unwatch)).await;
+
+    let responses = harness.responses();
+    assert_eq!(responses[0].1["type"], "unwatch_xpub_response");
+    assert_eq!(responses[0].1["status"], "removed");
+    assert_eq!(responses[0].1["watched_count"], 0);
+    assert_eq!(responses[1].1["status"], "not_watched");
+    assert_eq!(harness.watch.count_for(&owner), 0);
+}
+
+#[tokio::test]
+async fn unpairing_drops_the_device_watch_list() {
+    let mut harness = harness("watch-unpair").await;
+    let phone = Keys::generate();
+    let tablet = Keys::generate();
+    harness.pair(&phone);
+    harness.pair(&tablet);
+    harness.watch.add(&phone.public_key().to_hex(), XPUB, "w1", None).unwrap();
+    harness.watch.add(&tablet.public_key().to_hex(), XPUB, "w2", None).unwrap();
+
+    harness.send(harness.request(&phone, "r1", json!({ "type": "unpair" }))).await;
+
+    assert_eq!(harness.responses()[0].1["status"], "ok");
+    assert_eq!(harness.watch.count_for(&phone.public_key().to_hex()), 0);
+    assert_eq!(harness.watch.count_for(&tablet.public_key().to_hex()), 1);
+}
+
+#[tokio::test]
+async fn watch_limit_is_refused_before_any_scan() {
+    let server = FakeElectrs::start(false);
+    let electrs = ElectrsClient::new(server.addr.clone()).unwrap();
+    let mut harness = Harness::start("watch-limit", Arc::new(electrs)).await;
+    let phone = Keys::generate();
+    harness.pair(&phone);
+    let owner = phone.public_key().to_hex();
+    for i in 0..MAX_WATCHED_XPUBS_PER_DEVICE {
+        harness.watch.add(&owner, &format!("xpub-{}", i), "w", None).unwrap();
+    }
+
+    let watch = json!({ "type": "watch_xpub", "query": XPUB });
+    harness.send(harness.request(&phone, "r1", watch)).await;
+
+    let error = &harness.responses()[0].1["error"];
+    assert_eq!(error["code"], "watch_limit_reached");
+    assert_eq!(error["retryable"], false);
+    assert_eq!(error["limit"], MAX_WATCHED_XPUBS_PER_DEVICE);
+    assert_eq!(server.scripthash_calls(), 0);
+    assert_eq!(harness.watch.count_for(&owner), MAX_WATCHED_XPUBS_PER_DEVICE);
+}